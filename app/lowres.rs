@@ -1,22 +1,117 @@
-use clap::{Parser, ValueEnum};
-use image::{DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage, imageops::FilterType};
-use rayon::prelude::*;
-use std::fmt::{self, Display};
-use std::{fs::File, io::BufWriter, path::PathBuf};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The CLI shares its processing core with the desktop app.
+#[path = "../src-tauri/src/lowres/mod.rs"]
+mod lowres;
+mod rpc;
+
+use lowres::batch::{BatchItem, BatchReport, CollisionAction};
+use lowres::heatmap::HeatmapMetric;
+use lowres::icons::IconFormat;
+use lowres::keyframes::Keyframes;
+use lowres::limits::SizeLimits;
+use lowres::manifest::ManifestStatus;
+use lowres::metrics::Metrics;
+use lowres::proof::{PageSize, ProofLayout};
+use lowres::sequence::{FrameRange, SequencePattern};
+use lowres::shots::ShotList;
+use lowres::{
+    AutoMask, Backend, Banding, BandingCheck, BlockOutput, BlockSize, BlockStat, ChannelSpace,
+    ColorMatch, DefaultSize, Dither, Guide, LegibilityGuard, Length, LowresConfig, LowresError,
+    OnCollision, OutputSpec, Palette, PercentRegion, PixelateChannels, PngCompression,
+    ProcessReport, Region, RegionSpec, Resample, ResizeMode, Style, Upscaler,
+};
 
 type Result<T> = anyhow::Result<T>;
 
 /// Convert an image to a low-resolution or pixelated PNG and tag DPI.
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct Args {
-    /// Input image path (jpg, png, etc.)
-    #[arg(short, long)]
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Output image path (png recommended, e.g., out.png)
-    #[arg(short, long)]
-    output: PathBuf,
+    /// Input image path (jpg, png, etc.), http(s) URL, or - for standard input;
+    /// give several to process a batch, or a numbered sequence pattern such as
+    /// frame_%04d.png or frame_####.png
+    #[arg(short, long, num_args = 1.., required_unless_present = "rpc")]
+    input: Vec<PathBuf>,
+
+    /// Output image path (png recommended, e.g., out.png), or - to write the PNG
+    /// to standard output; a pattern such as out_%04d.png for a sequence input
+    #[arg(short, long, required_unless_present_any = ["rpc", "out_dir", "explain", "audit"])]
+    output: Option<PathBuf>,
+
+    /// Batch mode: write `<stem>_lowres.png` for every input into this directory
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    /// With a sequence input, process only these frames: START-END or one frame
+    /// [default: every frame found]
+    #[arg(long)]
+    frames: Option<FrameRange>,
+
+    /// With a sequence input, a JSON file of settings keyed to frame numbers
+    /// and eased in between (e.g. block 32 at frame 0 to 2 at frame 120)
+    #[arg(long, value_name = "FILE")]
+    keyframes: Option<PathBuf>,
+
+    /// With a sequence input, a CSV or OpenTimelineIO shot list giving frame
+    /// ranges their own config; --keyframes applies on top
+    #[arg(long, value_name = "FILE")]
+    shot_list: Option<PathBuf>,
+
+    /// What to do with outputs that already exist: overwrite, skip,
+    /// rename-increment (`<name>_1.png`, …) or fail
+    #[arg(long, alias = "on-conflict", default_value_t = OnCollision::Overwrite)]
+    on_collision: OnCollision,
+
+    /// In batch mode, name outputs from these tokens: {stem}, {ext}, {block},
+    /// {width} and {height} (of the output) [default: {stem}_lowres.{ext}]
+    #[arg(long)]
+    output_template: Option<String>,
+
+    /// In batch mode, write a SHA-256 manifest of the produced outputs here
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// In batch mode, lay the produced outputs onto PDF proof sheets here,
+    /// labelled with file name, size and DPI
+    #[arg(long, value_name = "PDF")]
+    proof: Option<PathBuf>,
+
+    /// Images per proof page, as COLUMNSxROWS
+    #[arg(long, value_name = "COLSxROWS", default_value = "3x4", value_parser = lowres::proof::parse_grid)]
+    proof_grid: (u32, u32),
+
+    /// Paper size of proof pages: a4 or letter
+    #[arg(long, default_value_t = PageSize::A4)]
+    proof_page: PageSize,
+
+    /// Refuse to write anything into a directory holding an input (batches need --out-dir)
+    #[arg(long)]
+    no_touch_source: bool,
+
+    /// In batch, sequence and watch mode, POST each run's results as JSON to
+    /// this URL when it completes or fails; in watch mode, one per file
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Upload the outputs to s3://bucket/prefix, with the AWS credentials in
+    /// the environment, or PUT them under an http(s) URL (needs the `upload`
+    /// feature)
+    #[arg(long, value_name = "URL")]
+    upload: Option<String>,
+
+    /// Crop the source to a region before processing: X,Y,WxH in source pixels,
+    /// or X%,Y%,W%,H% of the source's size
+    #[arg(long)]
+    crop: Option<RegionSpec>,
 
     /// Target width in pixels (resize mode)
     #[arg(long)]
@@ -26,282 +121,2227 @@ struct Args {
     #[arg(long)]
     height: Option<u32>,
 
-    /// Resize behavior (ignored if --block is set)
-    #[arg(long, value_enum, default_value_t = ResizeMode::Auto)]
+    /// Export several sizes from one decode, e.g. 16,32,64x32: writes
+    /// <output stem>_16.png, _32.png, … next to --output, or all of them into
+    /// one icon when --output ends in .ico or .icns [icon default: the
+    /// format's usual sizes]
+    #[arg(long, value_delimiter = ',')]
+    sizes: Vec<OutputSpec>,
+
+    /// Resize relative to the source instead of to a fixed size, e.g. 25% or 0.25
+    /// (ignored if --width or --height is set)
+    #[arg(long, value_parser = lowres::parse_scale)]
+    scale: Option<f32>,
+
+    /// Resize behavior: auto, exact, contain, cover or pad (ignored if --block is set)
+    #[arg(long, default_value_t = ResizeMode::Auto)]
     mode: ResizeMode,
 
-    /// Resampling filter for normal resize (ignored if --block is set)
-    #[arg(long, value_enum, default_value_t = Resample::Nearest)]
+    /// Size when no --width, --height, --print-* or --scale is given: WxH,
+    /// `source` to keep the source size, or `error` to refuse [default: 64x64]
+    #[arg(long)]
+    default_size: Option<DefaultSize>,
+
+    /// Canvas color for --mode pad: #rrggbb, #rrggbbaa or transparent
+    #[arg(long)]
+    background: Option<String>,
+
+    /// Resampling filter for normal resize: nearest, triangle, catmullrom, gaussian or lanczos3
+    /// (ignored if --block is set)
+    #[arg(long, default_value_t = Resample::Nearest)]
     filter: Resample,
 
+    /// Keep the source size: ignore --width/--height/--scale, --upscale and --max-edge
+    #[arg(long)]
+    no_resize: bool,
+
+    /// Ignore --block; with --no-resize, only retag, recolor and strip or copy metadata
+    #[arg(long)]
+    no_pixelate: bool,
+
     /// Pixelation block size in *source pixels*. If set, we pixelate and keep original WxH.
-    /// e.g. --block 8 makes ~8×8 squares.
+    /// e.g. --block 8 makes ~8×8 squares, --block 8x2 makes 8-wide, 2-tall blocks.
+    #[arg(long)]
+    block: Option<BlockSize>,
+
+    /// How each block's color is chosen: mean, median, dominant, darkest or lightest
+    #[arg(long, default_value_t = BlockStat::Mean)]
+    block_stat: BlockStat,
+
+    /// Average and resample in linear light for correct color mixing
+    #[arg(long)]
+    linear_light: bool,
+
+    /// With --block, pixelate only this region (X,Y,WxH, or X%,Y%,W%,H% of the
+    /// size after --crop); repeat for several
+    #[arg(long = "region", requires = "block")]
+    regions: Vec<RegionSpec>,
+
+    /// With --block, also pixelate the regions saved in this region set file
+    /// (see `lowres save-regions`)
+    #[arg(long, value_name = "FILE", requires = "block")]
+    regions_file: Option<PathBuf>,
+
+    /// With --regions-file, only the regions of this named set; repeat for
+    /// several [default: every set in the file]
+    #[arg(long = "region-set", value_name = "NAME", requires = "regions_file")]
+    region_sets: Vec<String>,
+
+    /// With --block, pixelate only the automatically detected subject or background
+    /// (needs the `segmentation` feature)
+    #[arg(long)]
+    auto_mask: Option<AutoMask>,
+
+    /// With --region or --regions-file: check the pixelated regions with text
+    /// recognition and `warn` while text still reads, or `enlarge` the blocks
+    /// until it doesn't (needs the `ocr` feature)
     #[arg(long)]
-    block: Option<u32>,
+    legibility_guard: Option<LegibilityGuard>,
+
+    /// With --block: `full` keeps the source WxH, `small` writes one pixel per block
+    #[arg(long, default_value_t = BlockOutput::Full)]
+    block_output: BlockOutput,
+
+    /// With --block: pixelate `all` channels, only `luma` (keeping each pixel's
+    /// color) or only `chroma` (keeping each pixel's brightness)
+    #[arg(long, default_value_t = PixelateChannels::All)]
+    pixelate_channels: PixelateChannels,
 
     /// Downscale filter for pixelation (averages colors per block). Upscale is always Nearest.
-    #[arg(long, value_enum, default_value_t = Resample::Triangle)]
+    #[arg(long, default_value_t = Resample::Triangle)]
     pixel_down_filter: Resample,
 
-    /// DPI to set in the output metadata (default 300)
-    #[arg(long, default_value_t = 300)]
-    dpi: u32,
-}
-
-#[derive(Clone, Debug, Copy, ValueEnum, PartialEq, Eq)]
-enum Resample {
-    Nearest,
-    Triangle,
-    CatmullRom,
-    Gaussian,
-    Lanczos3,
-}
-impl From<Resample> for FilterType {
-    fn from(r: Resample) -> Self {
-        match r {
-            Resample::Nearest => FilterType::Nearest,
-            Resample::Triangle => FilterType::Triangle,
-            Resample::CatmullRom => FilterType::CatmullRom,
-            Resample::Gaussian => FilterType::Gaussian,
-            Resample::Lanczos3 => FilterType::Lanczos3,
-        }
-    }
-}
-impl Display for Resample {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            Resample::Nearest => "nearest",
-            Resample::Triangle => "triangle",
-            Resample::CatmullRom => "catmullrom",
-            Resample::Gaussian => "gaussian",
-            Resample::Lanczos3 => "lanczos3",
-        };
-        write!(f, "{}", s)
+    /// DPI to set in the output metadata [default: the source's, else 300]
+    #[arg(long)]
+    dpi: Option<u32>,
+
+    /// Print width (e.g. 4in, 10cm, 90mm); the pixel width is computed from --dpi
+    #[arg(long)]
+    print_width: Option<Length>,
+
+    /// Print height (e.g. 6in); the pixel height is computed from --dpi
+    #[arg(long)]
+    print_height: Option<Length>,
+
+    /// Banding control: `posterize` brightness into hard steps (retro skies), or
+    /// `deband` gradients before colors are reduced
+    #[arg(long)]
+    banding: Option<Banding>,
+
+    /// Brightness steps for --banding posterize
+    #[arg(long, default_value_t = 8)]
+    banding_levels: u32,
+
+    /// Check the output for banding in gradients: `report` where it shows, or
+    /// also `dither` it away when colors are reduced without dithering
+    #[arg(long)]
+    banding_check: Option<BandingCheck>,
+
+    /// Enlarge the result by an integer factor, e.g. after --block-output small
+    #[arg(long)]
+    upscale: Option<u32>,
+
+    /// Upscale algorithm: nearest (any factor) or scale2x (2, 3 or 4)
+    #[arg(long, default_value_t = Upscaler::Nearest)]
+    upscaler: Upscaler,
+
+    /// Scale the result down so its longest edge is at most this many pixels
+    #[arg(long)]
+    max_edge: Option<u32>,
+
+    /// Keep shrinking the result until the PNG is at most this many bytes
+    #[arg(long)]
+    max_bytes: Option<u64>,
+
+    /// PNG compression: fast, default or best (smallest files, slower)
+    /// [default: fast, or best with --max-bytes]
+    #[arg(long, value_name = "LEVEL")]
+    png_compression: Option<PngCompression>,
+
+    /// Choose the PNG filter row by row for smaller files at a slower encode
+    #[arg(long)]
+    png_adaptive_filter: bool,
+
+    /// Write Adam7-interlaced PNGs that show coarsely while they load (still
+    /// PNG output only)
+    #[arg(long)]
+    png_interlace: bool,
+
+    /// Snap colors to a built-in palette: gameboy, nes, cga, pico8, c64, mono,
+    /// riso or newsprint
+    #[arg(long)]
+    palette: Option<Palette>,
+
+    /// Snap colors to a palette file: GIMP .gpl, Photoshop .act or a list of hex colors
+    #[arg(long)]
+    palette_file: Option<PathBuf>,
+
+    /// Reduce the output to N colors with median cut (ignored if --palette is set)
+    #[arg(long)]
+    colors: Option<u32>,
+
+    /// In a batch, build one --colors palette from every input and use it for all
+    /// outputs; it is saved as shared_palette.hex beside them
+    #[arg(long, requires = "colors")]
+    shared_palette: bool,
+
+    /// Match colors to a reference image's, so a batch shares one look
+    #[arg(long, value_name = "REFERENCE")]
+    match_colors: Option<PathBuf>,
+
+    /// How --match-colors works: histogram (per channel) or reinhard (mean and spread)
+    #[arg(long, default_value_t = ColorMatch::Histogram)]
+    match_method: ColorMatch,
+
+    /// Dither between palette colors: ordered, floyd-steinberg or atkinson
+    #[arg(long)]
+    dither: Option<Dither>,
+
+    /// Add monochrome film grain, from 0 to 1, before colors are reduced
+    #[arg(long)]
+    grain: Option<f32>,
+
+    /// Apply a look: mac (1-bit), cga, riso or newspaper. Sets palette, dither,
+    /// block and grain unless they are given
+    #[arg(long)]
+    style: Option<Style>,
+
+    /// Copy EXIF (minus orientation), XMP and copyright/author from the source
+    #[arg(long)]
+    keep_metadata: bool,
+
+    /// Privacy mode: write no metadata at all, not even DPI or sRGB tags
+    #[arg(long, conflicts_with = "keep_metadata")]
+    strip_metadata: bool,
+
+    /// Copy only the source's keywords and caption (XMP or IPTC) into the
+    /// output's XMP
+    #[arg(long, conflicts_with = "strip_metadata")]
+    keep_keywords: bool,
+
+    /// Add keywords to the output's XMP, e.g. --keyword lowres-proof
+    #[arg(
+        long = "keyword",
+        value_delimiter = ',',
+        value_name = "KEYWORD",
+        conflicts_with = "strip_metadata"
+    )]
+    keywords: Vec<String>,
+
+    /// Write the alpha channel as a grayscale <stem>_matte.png next to each
+    /// output, and the output itself as RGB (separate fill and matte passes)
+    #[arg(long)]
+    matte: bool,
+
+    /// Write <stem>.json next to each output recording its source and the
+    /// source's SHA-256, the resolved config, timings, size and color stats
+    #[arg(long)]
+    sidecar: bool,
+
+    /// In animated output, merge a frame into the one before it when no channel
+    /// differs by more than this; identical frames are always merged [default: 0]
+    #[arg(long, value_name = "LEVELS")]
+    frame_tolerance: Option<u8>,
+
+    /// Email-safe preset: ≤ 1600px, ≤ 500 KB, sRGB, stripped metadata
+    #[arg(long)]
+    email_safe: bool,
+
+    /// For small machines: run on one thread (unless --threads is given) and read
+    /// large sources in strips, trading speed for memory. The decoded source and the
+    /// full-size output are still held at once; --tiled streams the output
+    #[arg(long)]
+    low_memory: bool,
+
+    /// For very large images: pixelate a strip of blocks at a time and stream
+    /// rows to the PNG encoder, never holding the whole image (pixelation only)
+    #[arg(long)]
+    tiled: bool,
+
+    /// Where to pixelate: cpu, or gpu (mean blocks on a GPU adapter, falling
+    /// back to the CPU without one) [default: cpu]
+    #[arg(long, value_name = "BACKEND")]
+    backend: Option<Backend>,
+
+    /// Worker threads [default: one per core]
+    #[arg(long)]
+    threads: Option<std::num::NonZeroUsize>,
+
+    /// Run in the background: on half the cores (unless --threads is given),
+    /// at low OS priority, so long batches leave the machine responsive
+    #[arg(long)]
+    low_priority: bool,
+
+    /// Refuse input files larger than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    max_file_bytes: Option<u64>,
+
+    /// Refuse inputs whose header declares more megapixels than this, before
+    /// decoding them
+    #[arg(long, value_name = "MP")]
+    max_input_mp: Option<f64>,
+
+    /// Refuse to render outputs of more megapixels than this
+    #[arg(long, value_name = "MP")]
+    max_output_mp: Option<f64>,
+
+    /// Analyze the input and fill in suggested settings for any left unset
+    #[arg(long)]
+    auto: bool,
+
+    /// Print the fully resolved pipeline (every default filled in, stages in
+    /// order) as JSON instead of processing; save it for --pipeline-file
+    #[arg(long)]
+    explain: bool,
+
+    /// Print what each input would become (output file, size, block grid and
+    /// megapixels) from its headers alone, without processing anything
+    #[arg(
+        long,
+        conflicts_with_all = ["explain", "compare", "guides", "heatmap", "explore", "sprites", "auto", "sizes"]
+    )]
+    dry_run: bool,
+
+    /// Process with the pipeline written by --explain (or a saved config),
+    /// replacing all processing flags
+    #[arg(long)]
+    pipeline_file: Option<PathBuf>,
+
+    /// Write a captioned original/resized/pixelated/quantized comparison figure
+    /// to --output instead of the processed image
+    #[arg(long)]
+    compare: bool,
+
+    /// Write a preview of the input with composition guides (thirds, golden,
+    /// center) over the region the output keeps, after --crop and cover mode,
+    /// to --output instead of the processed image
+    #[arg(long, value_delimiter = ',', value_name = "GUIDES")]
+    guides: Vec<Guide>,
+
+    /// Write a heatmap of how many distinct colors (colors) or how much color
+    /// variance (variance) each block of the input holds, at the --block size,
+    /// to --output instead of the processed image
+    #[arg(long, value_name = "METRIC")]
+    heatmap: Option<HeatmapMetric>,
+
+    /// Print how much of each --region (or the whole input) pixelating at
+    /// --block keeps and how much interpolating the blocks recovers, with a
+    /// redaction strength from 0 to 100 and the smallest safe block size,
+    /// instead of processing
+    #[arg(long, conflicts_with_all = ["explain", "compare", "guides", "heatmap", "explore", "sprites", "dry_run"])]
+    audit: bool,
+
+    /// Render N variations with random block size, colors, dithering and grain
+    /// (for settings not given) onto a contact sheet at --output, and save each
+    /// one's pipeline as <output stem>_<n>.json for --pipeline-file
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..=64))]
+    explore: Option<u32>,
+
+    /// Seed for --explore; a run with the same seed and flags gives the same
+    /// variations [default: from the clock, and printed]
+    #[arg(long, requires = "explore")]
+    seed: Option<u64>,
+
+    /// Split a sprite sheet into one PNG per connected non-transparent region,
+    /// plus a JSON index of bounding boxes, in the --output directory
+    #[arg(long)]
+    sprites: bool,
+
+    /// With --sprites, run each sprite through the other processing flags
+    #[arg(long, requires = "sprites")]
+    process_sprites: bool,
+
+    /// Print the result as JSON instead of messages: the config used, and each
+    /// input's output path, original and final size, timings or error
+    #[arg(
+        long,
+        conflicts_with_all = ["explain", "compare", "guides", "heatmap", "explore", "sprites"]
+    )]
+    json: bool,
+
+    /// Serve newline-delimited JSON-RPC on stdin/stdout instead of processing
+    /// one file; takes no other flags but --metrics
+    #[arg(long)]
+    rpc: bool,
+
+    /// With --rpc or watch, serve Prometheus metrics at http://ADDR/metrics:
+    /// jobs processed, stage latencies and errors, e.g. 127.0.0.1:9464
+    #[arg(long, value_name = "ADDR")]
+    metrics: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Resize images to a size, without pixelating them
+    Resize(ResizeTask),
+    /// Pixelate images into blocks, keeping their size
+    Pixelate(PixelateTask),
+    /// Reduce the colors of images, keeping their size
+    Quantize(QuantizeTask),
+    /// Process many images into a folder, with the processing flags given
+    /// before `batch` or a --pipeline-file
+    Batch(BatchTask),
+    /// Print the JSON Schema of the processing config
+    Schema,
+    /// Show an image's size and the lowres settings embedded in it, if any
+    Info {
+        /// Image path
+        file: PathBuf,
+    },
+    /// Save regions as a named set in a region set file, for --regions-file,
+    /// replacing a set of that name; without --region, remove the set
+    SaveRegions {
+        /// Region set file, created if missing
+        file: PathBuf,
+        /// Name of the set, e.g. "bottom-right timestamp"
+        name: String,
+        /// A region of the set (X,Y,WxH); repeat for several
+        #[arg(long = "region")]
+        regions: Vec<Region>,
+    },
+    /// Set the DPI of PNG files without re-encoding them (in place unless --out-dir is given)
+    Retag {
+        /// DPI to write
+        #[arg(long)]
+        dpi: u32,
+        /// Write the retagged copies here instead of replacing the files
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// PNG files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Apply JPEGs' EXIF orientation losslessly and reset the tag (in place unless
+    /// --out-dir is given). Partial edge blocks on mirrored sides are trimmed.
+    Rotate {
+        /// Write the rotated copies here instead of replacing the files
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// JPEG files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Check the files listed in a SHA-256 manifest written with --manifest
+    Verify {
+        /// Manifest path
+        manifest: PathBuf,
+    },
+    /// Write each channel of an image as a grayscale PNG, `<stem>_<channel>.png`
+    SplitChannels {
+        /// Channels to split into: rgb or ycbcr (alpha is added if the image has it)
+        #[arg(long, default_value_t = ChannelSpace::Rgb)]
+        space: ChannelSpace,
+        /// Write the channel files here instead of next to each image
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+        /// Image files
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Merge grayscale channel images, in order, back into one image
+    MergeChannels {
+        /// Channels the images hold: rgb or ycbcr
+        #[arg(long, default_value_t = ChannelSpace::Rgb)]
+        space: ChannelSpace,
+        /// Output PNG path
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Three channel images, or four with alpha
+        #[arg(required = true)]
+        channels: Vec<PathBuf>,
+    },
+    /// Round-trip small test images through every decoder, encoder and
+    /// optional feature, and report what this build supports
+    Selftest {
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Add "Send to lowres" to the Explorer context menu of images and to the
+    /// Send To menu, opening the selected files in the desktop app (Windows)
+    IntegrateShell {
+        /// The desktop app's executable
+        #[arg(long, required_unless_present = "remove")]
+        app: Option<PathBuf>,
+        /// Remove the entries instead
+        #[arg(long, conflicts_with = "app")]
+        remove: bool,
+        /// Print the context menu entries as a .reg file instead of adding them
+        #[arg(long, requires = "app")]
+        print: bool,
+    },
+    /// Add a Finder Quick Action for images that opens them in the desktop
+    /// app, or processes them with this command line and a preset (macOS)
+    IntegrateFinder {
+        /// Open the files in this desktop app bundle
+        #[arg(long, conflicts_with = "cli", required_unless_present_any = ["cli", "remove"])]
+        app: Option<PathBuf>,
+        /// Process the files with this lowres executable and the FLAGS
+        #[arg(long, requires = "flags")]
+        cli: Option<PathBuf>,
+        /// Name of the Quick Action in Finder's menus
+        #[arg(long, default_value = lowres::services::DEFAULT_NAME)]
+        name: String,
+        /// Write the workflow into this folder instead of installing it, for
+        /// an installer to ship
+        #[arg(long, conflicts_with = "remove")]
+        out_dir: Option<PathBuf>,
+        /// Remove the Quick Action instead
+        #[arg(long, conflicts_with_all = ["app", "cli"])]
+        remove: bool,
+        /// The preset: processing flags the action runs with, after --, e.g.
+        /// -- --block 8 --palette pico8, or -- --pipeline-file look.json
+        #[arg(last = true, requires = "cli")]
+        flags: Vec<String>,
+    },
+    /// Process every image that appears or changes in a folder, with the
+    /// processing flags given before `watch`, until interrupted
+    Watch {
+        /// Folder to watch
+        dir: PathBuf,
+        /// Folder the outputs go into
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Milliseconds a file must go unchanged before it is processed
+        #[arg(
+            long,
+            value_name = "MS",
+            default_value_t = lowres::watch::DEFAULT_QUIET.as_millis() as u64
+        )]
+        debounce: u64,
+    },
+}
+
+// The task subcommands offer only the flags that matter for their task, and
+// then run as those flags would at the top level.
+
+/// Inputs and outputs of a task subcommand.
+#[derive(clap::Args, Debug)]
+struct TaskIo {
+    /// Input images, http(s) URLs or - for standard input; several make a batch
+    #[arg(required = true)]
+    input: Vec<PathBuf>,
+
+    /// Output image path, or - to write the PNG to standard output
+    #[arg(short, long, required_unless_present = "out_dir")]
+    output: Option<PathBuf>,
+
+    /// Write `<stem>_lowres.png` for every input into this directory
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    /// What to do with outputs that already exist: overwrite, skip,
+    /// rename-increment (`<name>_1.png`, …) or fail
+    #[arg(long, default_value_t = OnCollision::Overwrite)]
+    on_collision: OnCollision,
+
+    /// Print what each input would become instead of processing it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Output metadata options of a task subcommand.
+#[derive(clap::Args, Debug)]
+struct TaskTags {
+    /// DPI to set in the output metadata [default: the source's, else 300]
+    #[arg(long)]
+    dpi: Option<u32>,
+
+    /// Copy EXIF (minus orientation), XMP and copyright/author from the source
+    #[arg(long)]
+    keep_metadata: bool,
+
+    /// Write no metadata at all, not even DPI or sRGB tags
+    #[arg(long, conflicts_with = "keep_metadata")]
+    strip_metadata: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ResizeTask {
+    #[command(flatten)]
+    io: TaskIo,
+
+    /// Target width in pixels
+    #[arg(long)]
+    width: Option<u32>,
+
+    /// Target height in pixels
+    #[arg(long)]
+    height: Option<u32>,
+
+    /// Resize relative to the source, e.g. 25% or 0.25 (ignored if --width or
+    /// --height is set)
+    #[arg(long, value_parser = lowres::parse_scale)]
+    scale: Option<f32>,
+
+    /// Scale down so the longest edge is at most this many pixels
+    #[arg(long)]
+    max_edge: Option<u32>,
+
+    /// How to fit --width and --height: auto, exact, contain, cover or pad
+    #[arg(long, default_value_t = ResizeMode::Auto)]
+    mode: ResizeMode,
+
+    /// Canvas color for --mode pad: #rrggbb, #rrggbbaa or transparent
+    #[arg(long)]
+    background: Option<String>,
+
+    /// Resampling filter: nearest, triangle, catmullrom, gaussian or lanczos3
+    #[arg(long, default_value_t = Resample::Nearest)]
+    filter: Resample,
+
+    /// Resample in linear light for correct color mixing
+    #[arg(long)]
+    linear_light: bool,
+
+    /// Crop the source to a region first: X,Y,WxH in source pixels, or
+    /// X%,Y%,W%,H% of the source's size
+    #[arg(long)]
+    crop: Option<RegionSpec>,
+
+    #[command(flatten)]
+    tags: TaskTags,
+}
+
+#[derive(clap::Args, Debug)]
+struct PixelateTask {
+    #[command(flatten)]
+    io: TaskIo,
+
+    /// Block size in source pixels, e.g. 8 or 8x2
+    #[arg(long)]
+    block: BlockSize,
+
+    /// How each block's color is chosen: mean, median, dominant, darkest or lightest
+    #[arg(long, default_value_t = BlockStat::Mean)]
+    block_stat: BlockStat,
+
+    /// `full` keeps the source size, `small` writes one pixel per block
+    #[arg(long, default_value_t = BlockOutput::Full)]
+    block_output: BlockOutput,
+
+    /// Pixelate `all` channels, only `luma` or only `chroma`
+    #[arg(long, default_value_t = PixelateChannels::All)]
+    pixelate_channels: PixelateChannels,
+
+    /// Pixelate only this region (X,Y,WxH, or X%,Y%,W%,H% of the size after
+    /// --crop); repeat for several
+    #[arg(long = "region")]
+    regions: Vec<RegionSpec>,
+
+    /// Also pixelate the regions saved in this region set file (see `lowres
+    /// save-regions`)
+    #[arg(long, value_name = "FILE")]
+    regions_file: Option<PathBuf>,
+
+    /// With --regions-file, only the regions of this named set; repeat for
+    /// several [default: every set in the file]
+    #[arg(long = "region-set", value_name = "NAME", requires = "regions_file")]
+    region_sets: Vec<String>,
+
+    /// Pixelate only the automatically detected subject or background (needs
+    /// the `segmentation` feature)
+    #[arg(long)]
+    auto_mask: Option<AutoMask>,
+
+    /// Check the pixelated regions with text recognition: `warn` while text
+    /// still reads, or `enlarge` the blocks until it doesn't (needs the `ocr`
+    /// feature)
+    #[arg(long)]
+    legibility_guard: Option<LegibilityGuard>,
+
+    /// Filter that averages each block's colors
+    #[arg(long, default_value_t = Resample::Triangle)]
+    pixel_down_filter: Resample,
+
+    /// Average in linear light for correct color mixing
+    #[arg(long)]
+    linear_light: bool,
+
+    /// Enlarge the result by an integer factor, e.g. after --block-output small
+    #[arg(long)]
+    upscale: Option<u32>,
+
+    /// Upscale algorithm: nearest (any factor) or scale2x (2, 3 or 4)
+    #[arg(long, default_value_t = Upscaler::Nearest)]
+    upscaler: Upscaler,
+
+    /// Crop the source to a region first: X,Y,WxH in source pixels, or
+    /// X%,Y%,W%,H% of the source's size
+    #[arg(long)]
+    crop: Option<RegionSpec>,
+
+    #[command(flatten)]
+    tags: TaskTags,
+}
+
+#[derive(clap::Args, Debug)]
+struct QuantizeTask {
+    #[command(flatten)]
+    io: TaskIo,
+
+    /// Snap colors to a built-in palette: gameboy, nes, cga, pico8, c64, mono,
+    /// riso or newsprint
+    #[arg(long, required_unless_present_any = ["palette_file", "colors"])]
+    palette: Option<Palette>,
+
+    /// Snap colors to a palette file: GIMP .gpl, Photoshop .act or a list of hex colors
+    #[arg(long)]
+    palette_file: Option<PathBuf>,
+
+    /// Reduce to N colors with median cut (ignored if --palette is set)
+    #[arg(long)]
+    colors: Option<u32>,
+
+    /// Dither between palette colors: ordered, floyd-steinberg or atkinson
+    #[arg(long)]
+    dither: Option<Dither>,
+
+    /// Match colors to a reference image's first
+    #[arg(long, value_name = "REFERENCE")]
+    match_colors: Option<PathBuf>,
+
+    /// How --match-colors works: histogram (per channel) or reinhard (mean and spread)
+    #[arg(long, default_value_t = ColorMatch::Histogram)]
+    match_method: ColorMatch,
+
+    /// `posterize` brightness into hard steps, or `deband` gradients first
+    #[arg(long)]
+    banding: Option<Banding>,
+
+    /// Brightness steps for --banding posterize
+    #[arg(long, default_value_t = 8)]
+    banding_levels: u32,
+
+    /// Add monochrome film grain, from 0 to 1, before colors are reduced
+    #[arg(long)]
+    grain: Option<f32>,
+
+    #[command(flatten)]
+    tags: TaskTags,
+}
+
+#[derive(clap::Args, Debug)]
+struct BatchTask {
+    /// Input images
+    #[arg(required = true)]
+    input: Vec<PathBuf>,
+
+    /// Directory the outputs go into
+    #[arg(short, long)]
+    out_dir: PathBuf,
+
+    /// Name outputs from these tokens: {stem}, {ext}, {block}, {width} and
+    /// {height} (of the output) [default: {stem}_lowres.{ext}]
+    #[arg(long)]
+    output_template: Option<String>,
+
+    /// What to do with outputs that already exist: overwrite, skip,
+    /// rename-increment (`<name>_1.png`, …) or fail
+    #[arg(long, default_value_t = OnCollision::Overwrite)]
+    on_collision: OnCollision,
+
+    /// Write a SHA-256 manifest of the produced outputs here
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Lay the produced outputs onto PDF proof sheets here
+    #[arg(long, value_name = "PDF")]
+    proof: Option<PathBuf>,
+
+    /// POST the results as JSON to this URL when the batch completes or fails
+    #[arg(long, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Upload the outputs to s3://bucket/prefix or PUT them under an http(s) URL
+    #[arg(long, value_name = "URL")]
+    upload: Option<String>,
+
+    /// Images per proof page, as COLUMNSxROWS
+    #[arg(long, value_name = "COLSxROWS", value_parser = lowres::proof::parse_grid)]
+    proof_grid: Option<(u32, u32)>,
+
+    /// Paper size of proof pages: a4 or letter
+    #[arg(long)]
+    proof_page: Option<PageSize>,
+
+    /// Refuse to write into a directory holding an input
+    #[arg(long)]
+    no_touch_source: bool,
+
+    /// Process with a pipeline written by --explain, or a saved config
+    #[arg(long)]
+    pipeline_file: Option<PathBuf>,
+
+    /// Print what each input would become instead of processing it
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl TaskIo {
+    fn apply(self, args: &mut Args) {
+        args.input = self.input;
+        args.output = self.output;
+        args.out_dir = self.out_dir;
+        args.on_collision = self.on_collision;
+        args.dry_run = self.dry_run;
     }
 }
 
-#[derive(Clone, Debug, Copy, ValueEnum, PartialEq, Eq)]
-enum ResizeMode {
-    /// If one of width/height is missing, preserve aspect. If both provided, use them.
-    Auto,
-    /// Force exact width×height (may distort); both required.
-    Exact,
+impl TaskTags {
+    fn apply(self, args: &mut Args) {
+        args.dpi = self.dpi;
+        args.keep_metadata = self.keep_metadata;
+        args.strip_metadata = self.strip_metadata;
+    }
 }
-impl Display for ResizeMode {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            ResizeMode::Auto => "auto",
-            ResizeMode::Exact => "exact",
-        };
-        write!(f, "{}", s)
+
+impl ResizeTask {
+    fn apply(self, args: &mut Args) {
+        self.io.apply(args);
+        self.tags.apply(args);
+        args.block = None;
+        args.width = self.width;
+        args.height = self.height;
+        args.scale = self.scale;
+        args.max_edge = self.max_edge;
+        args.mode = self.mode;
+        args.background = self.background;
+        args.filter = self.filter;
+        args.linear_light = self.linear_light;
+        args.crop = self.crop;
+    }
+}
+
+impl PixelateTask {
+    fn apply(self, args: &mut Args) {
+        self.io.apply(args);
+        self.tags.apply(args);
+        args.block = Some(self.block);
+        args.block_stat = self.block_stat;
+        args.block_output = self.block_output;
+        args.pixelate_channels = self.pixelate_channels;
+        args.regions = self.regions;
+        args.regions_file = self.regions_file;
+        args.region_sets = self.region_sets;
+        args.auto_mask = self.auto_mask;
+        args.legibility_guard = self.legibility_guard;
+        args.pixel_down_filter = self.pixel_down_filter;
+        args.linear_light = self.linear_light;
+        args.upscale = self.upscale;
+        args.upscaler = self.upscaler;
+        args.crop = self.crop;
+    }
+}
+
+impl QuantizeTask {
+    fn apply(self, args: &mut Args) {
+        self.io.apply(args);
+        self.tags.apply(args);
+        args.no_resize = true;
+        args.no_pixelate = true;
+        args.palette = self.palette;
+        args.palette_file = self.palette_file;
+        args.colors = self.colors;
+        args.dither = self.dither;
+        args.match_colors = self.match_colors;
+        args.match_method = self.match_method;
+        args.banding = self.banding;
+        args.banding_levels = self.banding_levels;
+        args.grain = self.grain;
+    }
+}
+
+impl BatchTask {
+    fn apply(self, args: &mut Args) {
+        args.input = self.input;
+        args.out_dir = Some(self.out_dir);
+        args.output_template = self.output_template;
+        args.on_collision = self.on_collision;
+        args.manifest = self.manifest;
+        args.proof = self.proof;
+        args.webhook = self.webhook.or(args.webhook.take());
+        args.upload = self.upload.or(args.upload.take());
+        args.proof_grid = self.proof_grid.unwrap_or(args.proof_grid);
+        args.proof_page = self.proof_page.unwrap_or(args.proof_page);
+        args.no_touch_source = self.no_touch_source;
+        args.pipeline_file = self.pipeline_file.or(args.pipeline_file.take());
+        args.dry_run |= self.dry_run;
+    }
+}
+
+/// Exit codes by error kind, so scripts can tell bad input from bad usage.
+fn exit_code(error: &LowresError) -> i32 {
+    match error {
+        LowresError::Other(_) => 1,
+        LowresError::InvalidConfig(_) => 2,
+        LowresError::Io(_) => 3,
+        LowresError::UnsupportedFormat(_) | LowresError::Decode(_) => 4,
+        LowresError::TooLarge(_) => 5,
     }
 }
 
 fn main() {
     if let Err(e) = run() {
-        eprintln!("error: {:#}", e);
-        std::process::exit(1);
+        let error = LowresError::from(e);
+        eprintln!("error: {}", error);
+        std::process::exit(exit_code(&error));
     }
 }
 
 fn run() -> Result<()> {
-    let args = Args::parse();
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    match args.command.take() {
+        Some(Command::Resize(task)) => task.apply(&mut args),
+        Some(Command::Pixelate(task)) => task.apply(&mut args),
+        Some(Command::Quantize(task)) => task.apply(&mut args),
+        Some(Command::Batch(task)) => task.apply(&mut args),
+        command => args.command = command,
+    }
+    match &args.command {
+        Some(Command::Schema) => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&lowres::config_schema())?
+            );
+            return Ok(());
+        }
+        Some(Command::Info { file }) => return info(file),
+        Some(Command::SaveRegions {
+            file,
+            name,
+            regions,
+        }) => return save_regions(file, name, regions),
+        Some(Command::Retag {
+            dpi,
+            out_dir,
+            files,
+        }) => return retag(files, out_dir.as_deref(), *dpi),
+        Some(Command::Rotate { out_dir, files }) => return rotate(files, out_dir.as_deref()),
+        Some(Command::Verify { manifest }) => return verify(manifest),
+        Some(Command::SplitChannels {
+            space,
+            out_dir,
+            files,
+        }) => return split_channels(files, out_dir.as_deref(), *space),
+        Some(Command::MergeChannels {
+            space,
+            output,
+            channels,
+        }) => {
+            lowres::merge_channels(channels, *space, output)?;
+            println!("Wrote {:?}.", output);
+            return Ok(());
+        }
+        Some(Command::Selftest { json }) => return selftest(*json),
+        Some(Command::IntegrateShell { app, remove, print }) => {
+            return integrate_shell(app.as_deref(), *remove, *print)
+        }
+        Some(Command::IntegrateFinder {
+            app,
+            cli,
+            name,
+            out_dir,
+            remove,
+            flags,
+        }) => {
+            let target = match (app, cli) {
+                (Some(app), _) => Some(lowres::services::Target::App(app)),
+                (_, Some(cli)) => Some(lowres::services::Target::Cli { cli, flags }),
+                _ => None,
+            };
+            return integrate_finder(name, target, out_dir.as_deref(), *remove);
+        }
+        Some(
+            Command::Resize(_) | Command::Pixelate(_) | Command::Quantize(_) | Command::Batch(_),
+        ) => unreachable!("task commands are applied to the flags above"),
+        Some(Command::Watch { .. }) | None => {}
+    }
+    if args.rpc {
+        // Like clap's `exclusive`, which would refuse --metrics too. Only
+        // arguments are checked, as the matches' ids include their groups.
+        let others = matches.subcommand().is_some()
+            || Args::command().get_arguments().any(|arg| {
+                let id = arg.get_id().as_str();
+                !matches!(id, "rpc" | "metrics")
+                    && matches.value_source(id) == Some(ValueSource::CommandLine)
+            });
+        if others {
+            Args::command()
+                .error(
+                    ErrorKind::ArgumentConflict,
+                    "--rpc can't be used with other arguments than --metrics",
+                )
+                .exit();
+        }
+        let metrics = serve_metrics(args.metrics.as_deref())?;
+        return rpc::serve(metrics.as_deref());
+    }
+
+    let mut regions: Vec<Region> = args.regions.iter().filter_map(|r| r.pixels()).collect();
+    let percent_regions: Vec<PercentRegion> =
+        args.regions.iter().filter_map(|r| r.percent()).collect();
+    if let Some(file) = &args.regions_file {
+        let sets = lowres::region_sets::read_region_sets(file)?;
+        regions.extend(lowres::region_sets::select_regions(
+            &sets,
+            &args.region_sets,
+        )?);
+    }
+    let mut config = LowresConfig {
+        crop: args.crop.and_then(RegionSpec::pixels),
+        percent_crop: args.crop.and_then(RegionSpec::percent),
+        width: args.width,
+        height: args.height,
+        scale: args.scale,
+        mode: Some(args.mode),
+        default_size: args.default_size,
+        background: args.background,
+        filter: Some(args.filter),
+        no_resize: args.no_resize.then_some(true),
+        no_pixelate: args.no_pixelate.then_some(true),
+        block_width: args.block.map(|b| b.width),
+        block_height: args.block.map(|b| b.height),
+        block_stat: Some(args.block_stat),
+        block_output: Some(args.block_output),
+        pixelate_channels: Some(args.pixelate_channels),
+        regions: (!regions.is_empty()).then_some(regions),
+        percent_regions: (!percent_regions.is_empty()).then_some(percent_regions),
+        auto_mask: args.auto_mask,
+        legibility_guard: args.legibility_guard,
+        linear_light: args.linear_light.then_some(true),
+        pixel_down_filter: Some(args.pixel_down_filter),
+        dpi: args.dpi,
+        print_width: args.print_width,
+        print_height: args.print_height,
+        banding: args.banding,
+        banding_levels: Some(args.banding_levels),
+        banding_check: args.banding_check,
+        upscale: args.upscale,
+        upscaler: Some(args.upscaler),
+        max_edge: args.max_edge,
+        max_bytes: args.max_bytes,
+        png_compression: args.png_compression,
+        png_adaptive_filter: args.png_adaptive_filter.then_some(true),
+        png_interlace: args.png_interlace.then_some(true),
+        palette: args.palette,
+        palette_file: args.palette_file,
+        colors: args.colors,
+        shared_palette: args.shared_palette.then_some(true),
+        match_method: args.match_colors.is_some().then_some(args.match_method),
+        match_colors: args.match_colors,
+        dither: args.dither,
+        grain: args.grain,
+        style: args.style,
+        keep_metadata: args.keep_metadata.then_some(true),
+        strip_metadata: args.strip_metadata.then_some(true),
+        keep_keywords: args.keep_keywords.then_some(true),
+        add_keywords: (!args.keywords.is_empty()).then_some(args.keywords),
+        email_safe: Some(args.email_safe),
+        low_memory: args.low_memory.then_some(true),
+        tiled: args.tiled.then_some(true),
+        backend: args.backend,
+        output_template: args.output_template,
+        sizes: (!args.sizes.is_empty()).then_some(args.sizes),
+        matte: args.matte.then_some(true),
+        sidecar: args.sidecar.then_some(true),
+        frame_tolerance: args.frame_tolerance,
+        ..Default::default()
+    };
+    if let Some(path) = &args.pipeline_file {
+        config = lowres::pipeline::read_pipeline(path)?;
+    }
+    let icon = args.output.as_deref().and_then(IconFormat::of);
+    if let Some(icon) = icon {
+        icon.check(config.sizes.get_or_insert_with(|| icon.default_sizes()))?;
+    }
+    config.validate()?;
+    if let Some(url) = &args.webhook {
+        lowres::webhook::check_url(url)?;
+    }
+    check_upload(args.upload.as_deref())?;
+    if args.metrics.is_some() && !matches!(args.command, Some(Command::Watch { .. })) {
+        anyhow::bail!("--metrics serves the long-running modes: --rpc and watch");
+    }
+    lowres::limits::set(SizeLimits {
+        max_file_bytes: args.max_file_bytes,
+        max_input_megapixels: args.max_input_mp,
+        max_output_megapixels: args.max_output_mp,
+    });
+    if let Some(Command::Watch {
+        dir,
+        output,
+        debounce,
+    }) = &args.command
+    {
+        if args.upload.is_some() {
+            anyhow::bail!("--upload works on batches, sequences and single images, not watch");
+        }
+        let metrics = serve_metrics(args.metrics.as_deref())?;
+        return watch(
+            dir,
+            output,
+            &config,
+            *debounce,
+            args.webhook.as_deref(),
+            metrics.as_deref(),
+            args.json,
+        );
+    }
+    // --threads sizes the pool; --low-memory alone drops it to one worker.
+    let threads = args.threads.map(|n| n.get()).or(config.threads);
+    let low_priority = args.low_priority || config.low_priority == Some(true);
+    let low_memory = config.low_memory == Some(true);
+    if threads.is_some() || low_priority || low_memory {
+        lowres::pool::pool_builder(threads, low_priority, low_memory).build_global()?;
+    }
+
+    // Kept until the run ends; dropping it deletes the saved copies.
+    let fetched = fetch_inputs(&mut args.input)?;
+    if fetched.0.is_some() && args.input.len() > 1 && args.out_dir.is_none() {
+        anyhow::bail!("A batch with URL or standard input inputs needs --out-dir");
+    }
+
+    let sequence = match args.input.as_slice() {
+        [input] if !input.exists() => SequencePattern::parse(input)?,
+        _ => None,
+    };
+    if let Some(input) = sequence {
+        if args.auto
+            || args.sprites
+            || args.compare
+            || !args.guides.is_empty()
+            || args.heatmap.is_some()
+            || args.explore.is_some()
+            || args.audit
+        {
+            anyhow::bail!(
+                "--auto, --sprites, --compare, --guides, --heatmap, --explore and --audit work on a single image"
+            );
+        }
+        if config.sizes.is_some() {
+            anyhow::bail!("--sizes works on a single image");
+        }
+        if args.dry_run {
+            anyhow::bail!("--dry-run works on images, not sequences");
+        }
+        if args.explain {
+            return explain(&config);
+        }
+        let output = match (&args.output, &args.out_dir) {
+            (Some(output), _) => SequencePattern::parse(output)?.ok_or_else(|| {
+                anyhow::anyhow!("--output must be a sequence pattern too, e.g. out_%04d.png")
+            })?,
+            // The input's own numbering, as PNGs in --out-dir.
+            (None, Some(dir)) => {
+                let name = PathBuf::from(input.to_string()).with_extension("png");
+                let name = name.file_name().unwrap_or_default();
+                SequencePattern::parse(&dir.join(name))?
+                    .ok_or_else(|| anyhow::anyhow!("--out-dir must not contain '%' or '#'"))?
+            }
+            (None, None) => anyhow::bail!("--output or --out-dir is required"),
+        };
+        if args.no_touch_source {
+            let first = input.frames_on_disk()?.first().map(|&n| input.path(n));
+            let dest = output.path(0);
+            let dest_dir = dest.parent().unwrap_or(Path::new("."));
+            lowres::ensure_outside_sources(&Vec::from_iter(first), dest_dir)?;
+        }
+        let shots = args.shot_list.as_deref().map(ShotList::load).transpose()?;
+        let keyframes = args.keyframes.as_deref().map(Keyframes::load).transpose()?;
+        let config_at = |n| {
+            let shot = match &shots {
+                Some(shots) => shots.config_at(&config, n)?,
+                None => config.clone(),
+            };
+            match &keyframes {
+                Some(keyframes) => keyframes.config_at(&shot, n),
+                None => Ok(shot),
+            }
+        };
+        let report = lowres::sequence::process_sequence(
+            &input,
+            &output,
+            args.frames,
+            config_at,
+            args.on_collision,
+        );
+        let report = notify(args.webhook.as_deref(), report)?;
+        return finish_batch(
+            &report,
+            args.manifest.as_deref(),
+            args.upload.as_deref(),
+            args.json.then_some(&config),
+        );
+    }
+    if args.frames.is_some() || args.keyframes.is_some() || args.shot_list.is_some() {
+        anyhow::bail!("--frames, --keyframes and --shot-list need a sequence pattern as --input");
+    }
 
-    let img = load_image(&args.input)?;
-    let (orig_w, orig_h) = img.dimensions();
+    if args.out_dir.is_some() || args.input.len() > 1 {
+        if args.auto
+            || args.sprites
+            || args.compare
+            || !args.guides.is_empty()
+            || args.heatmap.is_some()
+            || args.explore.is_some()
+            || args.audit
+        {
+            anyhow::bail!(
+                "--auto, --sprites, --compare, --guides, --heatmap, --explore and --audit work on a single input"
+            );
+        }
+        if config.sizes.is_some() {
+            anyhow::bail!("--sizes works on a single input");
+        }
+        if args.explain {
+            return explain(&config);
+        }
+        if args.no_touch_source {
+            let out_dir = args
+                .out_dir
+                .as_deref()
+                .ok_or_else(|| anyhow::anyhow!("--no-touch-source needs --out-dir for a batch"))?;
+            lowres::ensure_outside_sources(&args.input, out_dir)?;
+        }
+        if args.dry_run {
+            return dry_run(
+                &args.input,
+                args.out_dir.as_deref(),
+                None,
+                &config,
+                args.json,
+            );
+        }
+        let proof = args.proof.as_deref().map(|path| {
+            let layout = ProofLayout {
+                columns: args.proof_grid.0,
+                rows: args.proof_grid.1,
+                page: args.proof_page,
+            };
+            (path, layout)
+        });
+        let report = lowres::process_batch(
+            &args.input,
+            args.out_dir.as_deref(),
+            &config,
+            args.on_collision,
+        );
+        let report = notify(args.webhook.as_deref(), report)?;
+        return finish_proofed_batch(
+            &report,
+            &config,
+            args.manifest.as_deref(),
+            args.upload.as_deref(),
+            proof,
+            args.json,
+        );
+    }
 
-    let (out_img, final_w, final_h) = if let Some(block) = args.block {
-        // --- Pixelation path (keeps original WxH) ---
-        let down = (args.pixel_down_filter).into();
-        let rgba = pixelate(&img, block, down)?;
-        let dims = rgba.dimensions();
-        (rgba, dims.0, dims.1)
+    if args.proof.is_some() {
+        anyhow::bail!("--proof needs a batch: several inputs or --out-dir");
+    }
+    if args.webhook.is_some() {
+        anyhow::bail!("--webhook needs a batch, a sequence or watch");
+    }
+    let input = args
+        .input
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("--input is required"))?;
+    if args.explain {
+        if args.auto {
+            config = config.with_suggestions(lowres::analyze(&input)?.recommended);
+        }
+        return explain(&config);
+    }
+    if args.audit {
+        return audit(&input, &config, args.json);
+    }
+    let output = args
+        .output
+        .ok_or_else(|| anyhow::anyhow!("--output is required"))?;
+    // `--output -` streams one PNG to stdout; everything else printed goes to stderr.
+    let to_stdout = output.as_os_str() == "-";
+    if to_stdout
+        && (args.sprites
+            || args.explore.is_some()
+            || config.sizes.is_some()
+            || config.matte == Some(true)
+            || config.sidecar == Some(true))
+    {
+        anyhow::bail!(
+            "--output - writes one PNG; it can't be combined with --sprites, --explore, \
+--sizes, --matte or --sidecar"
+        );
+    }
+    if to_stdout && args.json {
+        anyhow::bail!("--output - and --json both write to standard output");
+    }
+    if args.upload.is_some()
+        && (to_stdout
+            || args.sprites
+            || args.compare
+            || !args.guides.is_empty()
+            || args.heatmap.is_some()
+            || args.explore.is_some())
+    {
+        anyhow::bail!(
+            "--upload publishes processed images; it can't be combined with --output -, \
+--sprites, --compare, --guides, --heatmap or --explore"
+        );
+    }
+    if args.dry_run {
+        let output = Some(output.as_path()).filter(|_| !to_stdout);
+        return dry_run(&[input], None, output, &config, args.json);
+    }
+    if config.sizes.is_some()
+        && (args.auto || args.sprites || args.compare || args.explore.is_some())
+    {
+        anyhow::bail!("--sizes can't be combined with --auto, --sprites, --compare or --explore");
+    }
+    if args.no_touch_source && !to_stdout {
+        // --sprites writes into --output as a directory; everything else writes a file.
+        let dest_dir = if args.sprites {
+            output.as_path()
+        } else {
+            output.parent().unwrap_or(Path::new("."))
+        };
+        lowres::ensure_outside_sources(std::slice::from_ref(&input), dest_dir)?;
+    }
+    // --sizes claims each sized output as it writes it.
+    let mut claimed = CollisionAction::Created;
+    let output = if args.sprites || config.sizes.is_some() || to_stdout {
+        output
     } else {
-        // --- Plain resize path ---
-        let (tw, th) = pick_target_size(&img, args.width, args.height, args.mode)?;
-        let filter: FilterType = args.filter.into();
-        let resized = resize_image(&img, tw, th, filter, args.mode)?;
-        // Convert to RGBA8 for the encoder only once
-        let rgba = resized.to_rgba8();
-        (rgba, tw, th)
+        match lowres::batch::claim_output(&output, args.on_collision) {
+            (output, CollisionAction::Skipped) => {
+                if args.json {
+                    let item = BatchItem {
+                        input,
+                        output,
+                        action: CollisionAction::Skipped,
+                        report: None,
+                        error: None,
+                    };
+                    return print_json(&BatchReport::from(item), &config, None);
+                }
+                println!("Skipped {:?}: {:?} exists.", input, output);
+                return Ok(());
+            }
+            (output, CollisionAction::Refused) => {
+                anyhow::bail!("Output {:?} already exists", output)
+            }
+            (output, action) => {
+                claimed = action;
+                output
+            }
+        }
     };
 
-    write_png_with_dpi(&args.output, out_img, args.dpi)?;
+    if args.auto {
+        let analysis = lowres::analyze(&input)?;
+        for warning in &analysis.warnings {
+            eprintln!("warning: {}", warning);
+        }
+        config = config.with_suggestions(analysis.recommended);
+    }
+    if args.sprites {
+        let config = args.process_sprites.then_some(config);
+        let sheet = lowres::extract_sprites(&input, &output, config)?;
+        println!(
+            "Wrote {} sprites and their index to {:?}.",
+            sheet.sprites.len(),
+            output
+        );
+        return Ok(());
+    }
+    let shown = if to_stdout {
+        "standard output".to_string()
+    } else {
+        format!("{:?}", output)
+    };
+    if args.compare {
+        let png = lowres::render_comparison(&input, config)?;
+        write_png(&output, &png)?;
+        status(
+            to_stdout,
+            format_args!("Wrote comparison figure {}.", shown),
+        );
+        return Ok(());
+    }
+    if !args.guides.is_empty() {
+        let png = lowres::render_guides(&input, &config, &args.guides)?;
+        write_png(&output, &png)?;
+        status(
+            to_stdout,
+            format_args!("Wrote composition guides {}.", shown),
+        );
+        return Ok(());
+    }
+    if let Some(metric) = args.heatmap {
+        let (png, stats) = lowres::heatmap::render_heatmap(&input, &config, metric)?;
+        write_png(&output, &png)?;
+        status(
+            to_stdout,
+            format_args!(
+                "Wrote {} heatmap {}: {}x{} blocks of {}x{} hold {} to {} (median {}).",
+                metric,
+                shown,
+                stats.columns,
+                stats.rows,
+                stats.block_width,
+                stats.block_height,
+                stats.min.round(),
+                stats.max.round(),
+                stats.median.round()
+            ),
+        );
+        return Ok(());
+    }
+    if let Some(count) = args.explore {
+        return explore(&input, &output, &config, count, args.seed);
+    }
+    let json = args.json.then_some(&config);
+    if let Some(icon) = icon {
+        let item = lowres::icons::process_icon(&input, &output, icon, &config, args.on_collision);
+        return finish_batch(
+            &BatchReport::from(item),
+            args.manifest.as_deref(),
+            args.upload.as_deref(),
+            json,
+        );
+    }
+    if config.sizes.is_some() {
+        let report = lowres::batch::process_sizes(&input, &output, &config, args.on_collision)?;
+        return finish_batch(
+            &report,
+            args.manifest.as_deref(),
+            args.upload.as_deref(),
+            json,
+        );
+    }
+    let block = config
+        .block_size()
+        .filter(|_| !config.no_pixelate.unwrap_or(false));
+    let mode = config.mode.unwrap_or(ResizeMode::Auto);
+    let filter = config.filter.unwrap_or(Resample::Nearest);
+    let pixel_down_filter = config.pixel_down_filter.unwrap_or(Resample::Triangle);
 
-    println!(
-        "Wrote {:?} at {}x{} pixels with {} DPI metadata (mode={}, block={}, filters: resize={}, pixel_down={}). \
+    let used = args.json.then(|| config.clone());
+    let report = if to_stdout {
+        let (png, report) = lowres::render_png(&input, config, &mut |_| {})?;
+        write_png(&output, &png)?;
+        report
+    } else {
+        lowres::process_image(input.clone(), output.clone(), config)?
+    };
+    if let Some(config) = used {
+        publish(args.upload.as_deref(), std::slice::from_ref(&output), false)?;
+        let item = BatchItem {
+            input,
+            output,
+            action: claimed,
+            report: Some(report),
+            error: None,
+        };
+        return print_json(&BatchReport::from(item), &config, None);
+    }
+    let tags = match report.dpi {
+        Some(dpi) => format!("{} DPI metadata", dpi),
+        None => "no metadata".to_string(),
+    };
+
+    status(
+        to_stdout,
+        format_args!(
+        "Wrote {} at {}x{} pixels with {} (mode={}, block={}, filters: resize={}, pixel_down={}). \
 Original: {}x{}.",
-        args.output,
-        final_w,
-        final_h,
-        args.dpi,
-        args.mode,
-        args.block
+        shown,
+        report.width,
+        report.height,
+        tags,
+        mode,
+        block
             .map(|b| b.to_string())
             .unwrap_or_else(|| "-".into()),
-        args.filter,
-        args.pixel_down_filter,
-        orig_w,
-        orig_h
+        filter,
+        pixel_down_filter,
+        report.original_width,
+        report.original_height
+        ),
+    );
+    if report.matte.is_some() {
+        println!("Wrote matte {:?}.", lowres::matte_path(&output));
+    }
+    if report.sidecar.is_some() {
+        println!("Wrote sidecar {:?}.", lowres::sidecar_path(&output));
+    }
+    for note in report_notes(&report) {
+        eprintln!("{}", note);
+    }
+    let t = &report.timings;
+    status(
+        to_stdout,
+        format_args!(
+            "Timings: decode {:.1} ms, transform {:.1} ms, quantize {:.1} ms, encode {:.1} ms.",
+            t.decode_ms, t.transform_ms, t.quantize_ms, t.encode_ms
+        ),
     );
+    publish(args.upload.as_deref(), std::slice::from_ref(&output), true)
+}
+
+/// Print a status line, to stderr when stdout carries the output image.
+fn status(to_stdout: bool, line: std::fmt::Arguments) {
+    if to_stdout {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Write an encoded PNG to `output`, or to stdout if it is `-`.
+fn write_png(output: &Path, png: &[u8]) -> Result<()> {
+    if output.as_os_str() == "-" {
+        let mut stdout = std::io::stdout().lock();
+        stdout.write_all(png)?;
+        return Ok(stdout.flush()?);
+    }
+    std::fs::write(output, png).map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", output, e))
+}
+
+/// The temporary directory URL and standard input inputs were saved into,
+/// removed on drop.
+struct Fetched(Option<PathBuf>);
+
+impl Drop for Fetched {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.0 {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Replace each http(s) URL among `inputs` with a downloaded local copy, and
+/// `-` with what standard input holds, each in its own directory so that
+/// files with the same name don't collide.
+fn fetch_inputs(inputs: &mut [PathBuf]) -> Result<Fetched> {
+    let mut fetched = Fetched(None);
+    let mut read_stdin = false;
+    for (i, input) in inputs.iter_mut().enumerate() {
+        let Some(name) = input
+            .to_str()
+            .filter(|s| *s == "-" || lowres::remote::is_url(s))
+        else {
+            continue;
+        };
+        let dir = fetched
+            .0
+            .get_or_insert_with(|| {
+                std::env::temp_dir().join(format!("lowres_inputs_{}", std::process::id()))
+            })
+            .join(i.to_string());
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+        *input = if name == "-" {
+            if std::mem::replace(&mut read_stdin, true) {
+                anyhow::bail!("Standard input can only be read once");
+            }
+            let mut data = Vec::new();
+            std::io::stdin().lock().read_to_end(&mut data)?;
+            lowres::remote::save_image(&data, &dir, "stdin")
+                .map_err(|e| e.context("Standard input"))?
+        } else {
+            eprintln!("Downloading {}...", name);
+            lowres::remote::download(name, &dir)?
+        };
+    }
+    Ok(fetched)
+}
+
+/// `finish_batch`, first writing the batch's outputs onto proof sheets if
+/// asked to.
+fn finish_proofed_batch(
+    report: &BatchReport,
+    config: &LowresConfig,
+    manifest: Option<&Path>,
+    upload: Option<&str>,
+    proof: Option<(&Path, ProofLayout)>,
+    json: bool,
+) -> Result<()> {
+    // A proof of failed inputs alone would be empty; the failures are
+    // reported below either way.
+    let proof = match proof {
+        Some((path, layout)) if !report.produced().is_empty() => Some((
+            path,
+            lowres::proof::write_proof(path, report, config, &layout)?,
+        )),
+        _ => None,
+    };
+    let finished = finish_batch(report, manifest, upload, json.then_some(config));
+    if let (Some((path, pages)), false) = (proof, json) {
+        println!("Wrote proof {:?} of {} pages.", path, pages);
+    }
+    finished
+}
 
+/// POST the outcome of a run to `webhook`, if there is one, and pass it on.
+/// A failed delivery is only a warning: the outputs are written either way.
+fn notify(webhook: Option<&str>, report: Result<BatchReport>) -> Result<BatchReport> {
+    let Some(url) = webhook else {
+        return report;
+    };
+    let (notification, report) = match report {
+        Ok(report) => (
+            lowres::webhook::Notification::of_report(&report),
+            Ok(report),
+        ),
+        Err(e) => {
+            let error = LowresError::from(e);
+            let notification = lowres::webhook::Notification::of_error(error.clone());
+            (notification, Err(error.into()))
+        }
+    };
+    if let Err(e) = lowres::webhook::post(url, &notification) {
+        eprintln!("warning: {:#}", e);
+    }
+    report
+}
+
+/// Fail if `--upload` names no target this build can upload to.
+#[cfg(feature = "upload")]
+fn check_upload(upload: Option<&str>) -> Result<()> {
+    upload.map_or(Ok(()), |url| lowres::upload::Target::parse(url).map(|_| ()))
+}
+
+#[cfg(not(feature = "upload"))]
+fn check_upload(upload: Option<&str>) -> Result<()> {
+    if upload.is_some() {
+        return Err(LowresError::InvalidConfig(
+            "--upload needs lowres built with the `upload` feature".into(),
+        )
+        .into());
+    }
     Ok(())
 }
 
-fn load_image(path: &PathBuf) -> Result<DynamicImage> {
-    image::open(path).map_err(|e| anyhow::anyhow!("Failed to open {:?}: {}", path, e))
+/// Upload `files` to `upload`, if given, printing where each went when
+/// `print`; fails if any upload failed, after trying them all.
+#[cfg(feature = "upload")]
+fn publish(upload: Option<&str>, files: &[PathBuf], print: bool) -> Result<()> {
+    let Some(url) = upload else {
+        return Ok(());
+    };
+    let target = lowres::upload::Target::parse(url)?;
+    let uploaded = lowres::upload::upload_all(&target, files);
+    let mut failed = 0;
+    for upload in &uploaded {
+        match &upload.error {
+            None if print => println!("Uploaded {:?} to {}.", upload.path, upload.url),
+            None => {}
+            Some(error) => {
+                failed += 1;
+                eprintln!("{:?}: {}", upload.path, error);
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} uploads failed", failed, uploaded.len());
+    }
+    Ok(())
 }
 
-fn pick_target_size(
-    img: &DynamicImage,
-    width: Option<u32>,
-    height: Option<u32>,
-    mode: ResizeMode,
-) -> Result<(u32, u32)> {
-    let (w0, h0) = img.dimensions();
-
-    match (width, height, mode) {
-        (Some(w), Some(h), ResizeMode::Exact) => Ok((w, h)),
-        (Some(w), Some(h), ResizeMode::Auto) => Ok((w, h)),
-
-        (Some(w), None, _) => {
-            let h = ((h0 as f64) * (w as f64) / (w0 as f64)).round().max(1.0) as u32;
-            Ok((w, h))
-        }
-        (None, Some(h), _) => {
-            let w = ((w0 as f64) * (h as f64) / (h0 as f64)).round().max(1.0) as u32;
-            Ok((w, h))
-        }
-        (None, None, _) => Ok((64, 64)),
-    }
-}
-
-fn resize_image(
-    img: &DynamicImage,
-    w: u32,
-    h: u32,
-    filter: FilterType,
-    _mode: ResizeMode,
-) -> Result<DynamicImage> {
-    // Keep as DynamicImage so we can call to_rgba8()
-    Ok(img.resize(w, h, filter))
-}
-
-/// Pixelate by downscaling to a coarse grid, then upscaling back with Nearest.
-/// `block` is the desired block size in source pixels (≈ square size).
-/// Optimized version using direct pixel manipulation with parallel processing.
-fn pixelate(img: &DynamicImage, block: u32, _down_filter: FilterType) -> Result<RgbaImage> {
-    let (w, h) = img.dimensions();
-    let b = block.max(1) as usize;
-
-    // Convert to RGBA once at the start
-    let rgba = img.to_rgba8();
-
-    // Calculate block grid dimensions
-    let blocks_x = (w as usize + b - 1) / b;
-    let blocks_y = (h as usize + b - 1) / b;
-
-    // Pre-compute average color for each block in parallel
-    let block_colors: Vec<Rgba<u8>> = (0..blocks_y * blocks_x)
-        .into_par_iter()
-        .map(|idx| {
-            let block_y = idx / blocks_x;
-            let block_x = idx % blocks_x;
-
-            let x_start = block_x * b;
-            let y_start = block_y * b;
-            let x_end = ((x_start + b).min(w as usize)) as u32;
-            let y_end = ((y_start + b).min(h as usize)) as u32;
-
-            // Average the pixels in this block
-            let mut r_sum = 0u32;
-            let mut g_sum = 0u32;
-            let mut b_sum = 0u32;
-            let mut a_sum = 0u32;
-            let mut count = 0u32;
-
-            for y in y_start as u32..y_end {
-                for x in x_start as u32..x_end {
-                    let pixel = rgba.get_pixel(x, y);
-                    r_sum += pixel[0] as u32;
-                    g_sum += pixel[1] as u32;
-                    b_sum += pixel[2] as u32;
-                    a_sum += pixel[3] as u32;
-                    count += 1;
+/// `check_upload` has already refused `--upload` in this build.
+#[cfg(not(feature = "upload"))]
+fn publish(_: Option<&str>, _: &[PathBuf], _: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Print what a batch did, or with `json` the config it used and its report
+/// as JSON, write its manifest, upload its outputs to `upload`, and fail if
+/// any input failed.
+fn finish_batch(
+    report: &BatchReport,
+    manifest: Option<&Path>,
+    upload: Option<&str>,
+    json: Option<&LowresConfig>,
+) -> Result<()> {
+    if let Some(config) = json {
+        if let Some(manifest) = manifest {
+            lowres::manifest::write_manifest(manifest, &report.produced())?;
+        }
+        print_json(report, config, manifest)?;
+    } else {
+        print_batch(report, manifest)?;
+    }
+    publish(upload, &report.produced(), json.is_none())?;
+
+    let failed = report.failed();
+    if failed > 0 {
+        anyhow::bail!("{} of {} inputs failed", failed, report.items.len());
+    }
+    Ok(())
+}
+
+/// What --json prints on completion, for a single image as for a batch.
+fn print_json(report: &BatchReport, config: &LowresConfig, manifest: Option<&Path>) -> Result<()> {
+    let result = serde_json::json!({
+        "config": config,
+        "items": report.items,
+        "summary": report.summary(),
+        "palette": report.palette,
+        "manifest": manifest,
+    });
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+/// Print what processing `inputs` would produce, or with `json` print it as
+/// JSON, without decoding anything. `output` names a single input's output.
+fn dry_run(
+    inputs: &[PathBuf],
+    out_dir: Option<&Path>,
+    output: Option<&Path>,
+    config: &LowresConfig,
+    json: bool,
+) -> Result<()> {
+    let mut items = Vec::new();
+    let mut failed = 0;
+    let mut megapixels = 0.0;
+    for input in inputs {
+        let plan = match lowres::plan::plan(input, config) {
+            Ok(plan) => plan,
+            Err(e) => {
+                failed += 1;
+                let error = lowres::LowresError::from(e);
+                if !json {
+                    eprintln!("{:?}: {}", input, error);
                 }
+                items.push(serde_json::json!({ "input": input, "error": error }));
+                continue;
             }
+        };
+        let output = output.map_or_else(
+            || lowres::plan::output_path(input, out_dir, config, &plan),
+            Path::to_path_buf,
+        );
+        megapixels += plan.megapixels;
+        if !json {
+            let blocks = plan
+                .block_grid
+                .map_or(String::new(), |(x, y)| format!(" in {}x{} blocks", x, y));
+            let exists = if output.exists() {
+                ", which exists"
+            } else {
+                ""
+            };
+            println!(
+                "{:?}: {}x{} -> {}x{}{} ({:.2} MP) as {:?}{}.",
+                input,
+                plan.source_width,
+                plan.source_height,
+                plan.width,
+                plan.height,
+                blocks,
+                plan.megapixels,
+                output,
+                exists
+            );
+        }
+        items.push(serde_json::json!({
+            "input": input,
+            "output": output,
+            "exists": output.exists(),
+            "plan": plan,
+        }));
+    }
+
+    if json {
+        let result = serde_json::json!({
+            "config": config,
+            "items": items,
+            "summary": { "planned": inputs.len() - failed, "failed": failed, "megapixels": megapixels },
+        });
+        println!("{}", serde_json::to_string_pretty(&result)?);
+    } else if inputs.len() > 1 {
+        println!(
+            "Summary: {} planned, {} failed, {:.2} MP of output.",
+            inputs.len() - failed,
+            failed,
+            megapixels
+        );
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} inputs can't be processed", failed, inputs.len());
+    }
+    Ok(())
+}
+
+fn print_batch(report: &BatchReport, manifest: Option<&Path>) -> Result<()> {
+    if let Some(palette) = &report.palette {
+        println!("Wrote shared palette {:?}.", palette);
+    }
+    for item in &report.items {
+        print_item(item);
+    }
+
+    if report.items.len() > 1 {
+        print_summary(&report.summary());
+    }
 
-            if count > 0 {
-                Rgba([
-                    (r_sum / count) as u8,
-                    (g_sum / count) as u8,
-                    (b_sum / count) as u8,
-                    (a_sum / count) as u8,
-                ])
+    if let Some(manifest) = manifest {
+        let produced = report.produced();
+        lowres::manifest::write_manifest(manifest, &produced)?;
+        println!(
+            "Wrote manifest {:?} of {} outputs.",
+            manifest,
+            produced.len()
+        );
+    }
+    Ok(())
+}
+
+fn print_item(item: &BatchItem) {
+    match (&item.error, item.action) {
+        (Some(e), _) => eprintln!("{:?}: {}", item.input, e),
+        (None, CollisionAction::Skipped) => {
+            println!("Skipped {:?}: {:?} exists.", item.input, item.output)
+        }
+        (None, _) => println!("Wrote {:?} ({:?}).", item.output, item.action),
+    }
+    for note in item.report.iter().flat_map(report_notes) {
+        eprintln!("{:?}: {}", item.output, note);
+    }
+}
+
+/// Warnings for what `--banding-check` and `--legibility-guard` found.
+fn report_notes(report: &ProcessReport) -> Vec<String> {
+    let mut notes = Vec::new();
+    if let Some(found) = report.banding.as_ref().filter(|b| !b.regions.is_empty()) {
+        let regions: Vec<String> = found.regions.iter().map(|r| r.to_string()).collect();
+        notes.push(format!(
+            "Banding in {}{}.",
+            regions.join(" "),
+            if found.dithered {
+                "; dithered to hide it"
             } else {
-                Rgba([0, 0, 0, 255])
+                ""
             }
+        ));
+    }
+    if let Some(guard) = &report.legibility {
+        if guard.enlarged {
+            notes.push(format!(
+                "Enlarged blocks to {}x{} so region text no longer reads.",
+                guard.block_width, guard.block_height
+            ));
+        }
+        for readable in &guard.readable {
+            notes.push(format!(
+                "Text still reads in region {}: {:?}.",
+                readable.region, readable.text
+            ));
+        }
+    }
+    notes
+}
+
+fn integrate_shell(app: Option<&Path>, remove: bool, print: bool) -> Result<()> {
+    let changes = match app {
+        Some(app) if print => {
+            print!(
+                "{}",
+                lowres::shell::reg_file(&lowres::shell::context_menu_values(app))
+            );
+            return Ok(());
+        }
+        Some(app) if !remove => {
+            let changes = lowres::shell::integrate(app)?;
+            println!("Added:");
+            changes
+        }
+        _ => {
+            let changes = lowres::shell::remove()?;
+            println!("Removed:");
+            changes
+        }
+    };
+    for change in changes {
+        println!("  {}", change);
+    }
+    Ok(())
+}
+
+fn integrate_finder(
+    name: &str,
+    target: Option<lowres::services::Target>,
+    out_dir: Option<&Path>,
+    remove: bool,
+) -> Result<()> {
+    match target {
+        Some(target) if !remove => {
+            let bundle = match out_dir {
+                Some(dir) => lowres::services::write_workflow(dir, name, target)?,
+                None => lowres::services::install(name, target)?,
+            };
+            println!("Wrote {:?}.", bundle);
+        }
+        _ => match lowres::services::remove(name)? {
+            Some(bundle) => println!("Removed {:?}.", bundle),
+            None => println!("No Quick Action named {:?} to remove.", name),
+        },
+    }
+    Ok(())
+}
+
+/// Start serving metrics on `addr`, if given, returning them for the jobs to
+/// be recorded in.
+fn serve_metrics(addr: Option<&str>) -> Result<Option<Arc<Metrics>>> {
+    let Some(addr) = addr else {
+        return Ok(None);
+    };
+    let metrics = Arc::new(Metrics::default());
+    let bound = lowres::metrics::serve(addr, metrics.clone())?;
+    eprintln!("Serving metrics on http://{}/metrics.", bound);
+    Ok(Some(metrics))
+}
+
+/// Process images dropped into `dir` until interrupted; with `json`, print
+/// each result as one line of JSON, with `webhook`, POST it there, and with
+/// `metrics`, count it.
+fn watch(
+    dir: &Path,
+    out_dir: &Path,
+    config: &LowresConfig,
+    debounce: u64,
+    webhook: Option<&str>,
+    metrics: Option<&Metrics>,
+    json: bool,
+) -> Result<()> {
+    eprintln!(
+        "Watching {:?}, writing into {:?}. Press Ctrl-C to stop.",
+        dir, out_dir
+    );
+    let quiet = std::time::Duration::from_millis(debounce);
+    lowres::watch::watch(dir, out_dir, config, quiet, |item| {
+        if let Some(metrics) = metrics {
+            metrics.record(item.report.as_ref(), item.error.as_ref());
+        }
+        if webhook.is_some() {
+            // Never an error: the report is passed back as it was given.
+            let _ = notify(webhook, Ok(BatchReport::from(item.clone())));
+        }
+        if json {
+            match serde_json::to_string(&item) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("{:?}: {}", item.input, e),
+            }
+        } else {
+            print_item(&item);
+        }
+    })
+}
+
+fn print_summary(summary: &lowres::batch::BatchSummary) {
+    println!(
+        "Summary: {} processed, {} skipped, {} failed.",
+        summary.processed, summary.skipped, summary.failed
+    );
+    if summary.processed == 0 {
+        return;
+    }
+    let saved = 1.0 - summary.output_bytes as f64 / summary.input_bytes.max(1) as f64;
+    print!(
+        "Size: {} in, {} out ({:+.1}%)",
+        format_bytes(summary.input_bytes),
+        format_bytes(summary.output_bytes),
+        -saved * 100.0
+    );
+    match summary.mean_ratio {
+        Some(ratio) => println!("; outputs average {:.1}% of their input.", ratio * 100.0),
+        None => println!("."),
+    }
+    let formats: Vec<String> = summary
+        .formats
+        .iter()
+        .map(|(format, count)| format!("{} {}", format, count))
+        .collect();
+    println!("Inputs: {}.", formats.join(", "));
+    let slowest: Vec<String> = summary
+        .slowest
+        .iter()
+        .map(|s| {
+            let name = s.input.file_name().unwrap_or_default().to_string_lossy();
+            format!("{} {:.0} ms", name, s.ms)
         })
         .collect();
+    println!("Slowest: {}.", slowest.join(", "));
+}
 
-    // Create output image by filling each block with its average color
-    let mut output: RgbaImage = ImageBuffer::new(w, h);
-
-    output
-        .enumerate_pixels_mut()
-        .par_bridge()
-        .for_each(|(x, y, pixel)| {
-            let block_x = (x as usize) / b;
-            let block_y = (y as usize) / b;
-            let block_idx = block_y * blocks_x + block_x;
-            *pixel = block_colors[block_idx];
-        });
-
-    Ok(output)
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 20 => format!("{:.1} MB", b as f64 / (1 << 20) as f64),
+        b if b >= 1 << 10 => format!("{:.1} KB", b as f64 / (1 << 10) as f64),
+        b => format!("{} B", b),
+    }
 }
 
-fn dpi_to_ppm(dpi: u32) -> u32 {
-    // PNG pHYs uses pixels-per-meter. 1 inch = 0.0254 m.
-    ((dpi as f64) / 0.0254).round() as u32
+fn selftest(json: bool) -> Result<()> {
+    use lowres::selftest::Status;
+
+    let checks = lowres::selftest::run_selftest();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        let width = checks.iter().map(|c| c.name.len()).max().unwrap_or(0);
+        for check in &checks {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Disabled => "off",
+                Status::Failed => "FAILED",
+            };
+            println!(
+                "{:<width$}  {:<6}  {}",
+                check.name,
+                status,
+                check.detail,
+                width = width
+            );
+        }
+    }
+    let failed = checks.iter().filter(|c| c.status == Status::Failed).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
 }
 
-fn write_png_with_dpi(out_path: &PathBuf, rgba: image::RgbaImage, dpi: u32) -> Result<()> {
-    use png::{BitDepth, ColorType, Encoder, PixelDimensions, Unit};
+fn audit(input: &PathBuf, config: &LowresConfig, json: bool) -> Result<()> {
+    let audit = lowres::audit::audit(input, config)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&audit)?);
+        return Ok(());
+    }
+    for r in &audit.regions {
+        println!(
+            "{}: {}x{} blocks keep {:.1} bits each; interpolating them recovers {:.0}% \
+of the detail, strength {}/100{}",
+            r.region,
+            r.columns,
+            r.rows,
+            r.bits_per_block,
+            r.recovered * 100.0,
+            r.strength,
+            if r.strength >= lowres::audit::SAFE_STRENGTH {
+                String::new()
+            } else {
+                format!(", safe from --block {}", r.safe_block)
+            }
+        );
+    }
+    if audit.strength >= lowres::audit::SAFE_STRENGTH {
+        println!(
+            "Safe at {}x{} blocks.",
+            audit.block_width, audit.block_height
+        );
+    } else {
+        println!(
+            "Weak at {}x{} blocks: use --block {} or larger.",
+            audit.block_width, audit.block_height, audit.safe_block
+        );
+    }
+    Ok(())
+}
 
-    let (w, h) = (rgba.width(), rgba.height());
-    let file = File::create(out_path)
-        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", out_path, e))?;
-    let wtr = BufWriter::new(file);
+fn explain(config: &LowresConfig) -> Result<()> {
+    let pipeline = lowres::pipeline::explain(config);
+    println!("{}", serde_json::to_string_pretty(&pipeline)?);
+    Ok(())
+}
 
-    let mut encoder = Encoder::new(wtr, w, h);
-    encoder.set_color(ColorType::Rgba);
-    encoder.set_depth(BitDepth::Eight);
+fn explore(
+    input: &PathBuf,
+    output: &Path,
+    config: &LowresConfig,
+    count: u32,
+    seed: Option<u64>,
+) -> Result<()> {
+    let seed = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64)
+    });
+    let variations = lowres::explore::variations(config, count, seed);
+    let sheet = lowres::explore::render_contact_sheet(input, &variations)?;
+    std::fs::write(output, sheet)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", output, e))?;
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    for (i, variation) in variations.iter().enumerate() {
+        let path = output.with_file_name(format!("{}_{}.json", stem, i + 1));
+        let pipeline = lowres::pipeline::explain(&variation.config);
+        std::fs::write(&path, serde_json::to_string_pretty(&pipeline)?)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+    }
+    println!(
+        "Wrote contact sheet {:?} of {} variations, with their pipelines as {}_<n>.json (seed {}).",
+        output, count, stem, seed
+    );
+    Ok(())
+}
 
-    let ppm = dpi_to_ppm(dpi);
-    encoder.set_pixel_dims(Some(PixelDimensions {
-        xppu: ppm,
-        yppu: ppm,
-        unit: Unit::Meter,
-    }));
+fn save_regions(file: &Path, name: &str, regions: &[Region]) -> Result<()> {
+    let sets = lowres::region_sets::save_region_set(file, name, regions.to_vec())?;
+    if regions.is_empty() {
+        println!("Removed region set {:?} from {:?}.", name, file);
+    } else {
+        let noun = if regions.len() == 1 {
+            "region"
+        } else {
+            "regions"
+        };
+        println!(
+            "Saved {} {} as {:?} in {:?}.",
+            regions.len(),
+            noun,
+            name,
+            file
+        );
+    }
+    for (name, regions) in &sets {
+        let shown: Vec<String> = regions.iter().map(|r| r.to_string()).collect();
+        println!("  {}: {}", name, shown.join(" "));
+    }
+    Ok(())
+}
 
-    let mut writer = encoder
-        .write_header()
-        .map_err(|e| anyhow::anyhow!("PNG header error: {}", e))?;
+fn info(file: &PathBuf) -> Result<()> {
+    let image = lowres::probe(file)?;
+    println!(
+        "{:?}: {}x{} {}, {}-bit{}",
+        file,
+        image.width,
+        image.height,
+        image.format.as_deref().unwrap_or("unknown format"),
+        image.bit_depth,
+        if image.has_alpha { " with alpha" } else { "" }
+    );
+    if let Some(dpi) = image.dpi {
+        println!("{} DPI", dpi);
+    }
+    match (&image.camera, &image.taken) {
+        (Some(camera), Some(taken)) => println!("Taken {} with {}", taken, camera),
+        (None, Some(taken)) => println!("Taken {}", taken),
+        (Some(camera), None) => println!("Camera: {}", camera),
+        (None, None) => {}
+    }
+    let settings = match image.format.as_deref() {
+        Some("png") => lowres::read_embedded_settings(file)?,
+        _ => None,
+    };
+    match settings {
+        Some(config) => {
+            let mut value = serde_json::to_value(&config)?;
+            if let serde_json::Value::Object(map) = &mut value {
+                map.retain(|_, v| !v.is_null());
+            }
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+        None => println!("No embedded lowres settings."),
+    }
+    Ok(())
+}
 
-    writer
-        .write_image_data(&rgba)
-        .map_err(|e| anyhow::anyhow!("PNG write error: {}", e))?;
+fn retag(files: &[PathBuf], out_dir: Option<&Path>, dpi: u32) -> Result<()> {
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let mut failed = 0;
+    for file in files {
+        let output = match out_dir {
+            Some(dir) => dir.join(file.file_name().unwrap_or_default()),
+            None => file.clone(),
+        };
+        if let Err(e) = lowres::retag_dpi(file, &output, dpi) {
+            eprintln!("{:#}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed", failed, files.len());
+    }
+    println!("Retagged {} files at {} DPI.", files.len(), dpi);
+    Ok(())
+}
 
+fn rotate(files: &[PathBuf], out_dir: Option<&Path>) -> Result<()> {
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let mut failed = 0;
+    for file in files {
+        let output = match out_dir {
+            Some(dir) => dir.join(file.file_name().unwrap_or_default()),
+            None => file.clone(),
+        };
+        match lowres::rotate_jpeg(file, &output) {
+            Ok(true) => println!("Rotated {:?}.", output),
+            Ok(false) => println!("{:?} is already upright.", file),
+            Err(e) => {
+                eprintln!("{:?}: {:#}", file, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed", failed, files.len());
+    }
     Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn split_channels(files: &[PathBuf], out_dir: Option<&Path>, space: ChannelSpace) -> Result<()> {
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let mut failed = 0;
+    for file in files {
+        match lowres::split_channels(file, out_dir, space) {
+            Ok(written) => {
+                for path in written {
+                    println!("Wrote {:?}.", path);
+                }
+            }
+            Err(e) => {
+                eprintln!("{:?}: {:#}", file, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} files failed", failed, files.len());
+    }
+    Ok(())
+}
 
-    #[test]
-    fn dpi_conversion_is_reasonable() {
-        assert_eq!(dpi_to_ppm(300), 11811);
-        assert_eq!(dpi_to_ppm(72), 2835);
+fn verify(manifest: &Path) -> Result<()> {
+    let checks = lowres::manifest::verify_manifest(manifest)?;
+    let bad: Vec<_> = checks
+        .iter()
+        .filter(|c| c.status != ManifestStatus::Ok)
+        .collect();
+    for check in &bad {
+        eprintln!("{}: {:?}", check.file, check.status);
+    }
+    if !bad.is_empty() {
+        anyhow::bail!(
+            "{} of {} files failed verification",
+            bad.len(),
+            checks.len()
+        );
     }
+    println!("All {} files match {:?}.", checks.len(), manifest);
+    Ok(())
 }
@@ -0,0 +1,289 @@
+//! Newline-delimited JSON-RPC 2.0 over stdio, for driving lowres as a subprocess.
+//!
+//! Methods:
+//! - `process` `{input, output, config, webhook}` → `ProcessReport`, with `progress`
+//!   notifications `{id, stage}` sent while it runs; with a `webhook` URL, the
+//!   result or error is also POSTed there, as a batch's is by `--webhook`
+//! - `info` `{path}` → `ImageInfo`
+//! - `preview` `{input, config, preview_quality}` → `{data_url, report}` (nothing is
+//!   written to disk; `preview_quality` `Proxy` renders a downscaled copy, the default is `Full`)
+//! - `process_bytes` `{data, config}` → `{data_url, report}`, for images held in
+//!   memory: `data` is a base64 data URL or plain base64, and nothing touches disk
+//! - `thumbnail` `{path, max_edge}` → `{data_url}`, a small JPEG or PNG of the source
+//! - `formats` → `{extensions}`, the input file extensions this build reads
+//! - `capabilities` → `Capabilities`: formats, features, effect values,
+//!   acceleration and limits of this build
+//!
+//! Processing errors carry the error kind as `data.kind` (see `LowresError`).
+
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use crate::lowres::batch::{BatchItem, BatchReport, CollisionAction};
+use crate::lowres::metrics::Metrics;
+use crate::lowres::webhook::Notification;
+use crate::lowres::{self, LowresConfig, LowresError, PreviewQuality, Stage};
+
+type Result<T> = anyhow::Result<T>;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const PROCESSING_ERROR: i64 = -32000;
+
+#[derive(Deserialize)]
+struct Request {
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct ProcessParams {
+    input: PathBuf,
+    output: PathBuf,
+    #[serde(default)]
+    config: Value,
+    webhook: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProcessBytesParams {
+    data: String,
+    #[serde(default)]
+    config: Value,
+}
+
+#[derive(Deserialize)]
+struct InfoParams {
+    path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct PreviewParams {
+    input: PathBuf,
+    #[serde(default)]
+    config: Value,
+    #[serde(default)]
+    preview_quality: PreviewQuality,
+}
+
+#[derive(Deserialize)]
+struct ThumbnailParams {
+    path: PathBuf,
+    #[serde(default = "default_thumbnail_edge")]
+    max_edge: u32,
+}
+
+fn default_thumbnail_edge() -> u32 {
+    1024
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+
+    fn processing(e: anyhow::Error) -> Self {
+        let error = LowresError::from(e);
+        Self {
+            code: PROCESSING_ERROR,
+            message: error.to_string(),
+            data: Some(json!({ "kind": error.kind() })),
+        }
+    }
+}
+
+/// Serve requests from stdin until it is closed, counting the `process` and
+/// `process_bytes` jobs in `metrics`.
+pub fn serve(metrics: Option<&Metrics>) -> Result<()> {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request: Request = match serde_json::from_str::<Value>(&line) {
+            Err(e) => {
+                send_error(Value::Null, RpcError::new(PARSE_ERROR, e))?;
+                continue;
+            }
+            Ok(value) => {
+                let id = value.get("id").cloned().unwrap_or(Value::Null);
+                match serde_json::from_value(value) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        send_error(id, RpcError::new(INVALID_REQUEST, e))?;
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let id = request.id.clone().unwrap_or(Value::Null);
+        match dispatch(&request, metrics) {
+            Ok(result) => send(json!({ "jsonrpc": "2.0", "id": id, "result": result }))?,
+            Err(err) => send_error(id, err)?,
+        }
+    }
+    Ok(())
+}
+
+fn dispatch(request: &Request, metrics: Option<&Metrics>) -> std::result::Result<Value, RpcError> {
+    let id = request.id.clone().unwrap_or(Value::Null);
+    match request.method.as_str() {
+        "process" => {
+            let p: ProcessParams = params(&request.params)?;
+            let config = config(p.config)?;
+            if let Some(url) = &p.webhook {
+                lowres::webhook::check_url(url)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e)))?;
+            }
+            let action = if p.output.exists() {
+                CollisionAction::Overwrote
+            } else {
+                CollisionAction::Created
+            };
+            let mut on_stage = |stage: Stage| {
+                // Progress is best effort; a closed stdout surfaces on the response.
+                let _ = send(json!({
+                    "jsonrpc": "2.0",
+                    "method": "progress",
+                    "params": { "id": id, "stage": stage },
+                }));
+            };
+            let report = lowres::pool::in_pool(&config, || {
+                lowres::process_image_with_progress(
+                    p.input.clone(),
+                    p.output.clone(),
+                    config.clone(),
+                    &mut on_stage,
+                )
+            })
+            .and_then(|r| r)
+            .map_err(LowresError::from);
+            if let Some(metrics) = metrics {
+                metrics.record(report.as_ref().ok(), report.as_ref().err());
+            }
+            if let Some(url) = &p.webhook {
+                let (report, error) = match &report {
+                    Ok(report) => (Some(report.clone()), None),
+                    Err(error) => (None, Some(error.clone())),
+                };
+                let item = BatchItem {
+                    input: p.input,
+                    output: p.output,
+                    action,
+                    report,
+                    error,
+                };
+                let notification = Notification::of_report(&BatchReport::from(item));
+                if let Err(e) = lowres::webhook::post(url, &notification) {
+                    eprintln!("warning: {:#}", e);
+                }
+            }
+            let report = report.map_err(|e| RpcError::processing(e.into()))?;
+            Ok(json!(report))
+        }
+        "process_bytes" => {
+            let p: ProcessBytesParams = params(&request.params)?;
+            let config = config(p.config)?;
+            let data = lowres::decode_data_url(&p.data)
+                .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e)))?;
+            let result = lowres::pool::in_pool(&config, || {
+                lowres::process_image_bytes(&data, config.clone())
+            })
+            .and_then(|r| r)
+            .map_err(LowresError::from);
+            if let Some(metrics) = metrics {
+                let error = result.as_ref().err();
+                metrics.record(result.as_ref().ok().map(|(_, report)| report), error);
+            }
+            let (png, report) = result.map_err(|e| RpcError::processing(e.into()))?;
+            let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+            Ok(json!({
+                "data_url": format!("data:image/png;base64,{}", b64),
+                "report": report,
+            }))
+        }
+        "info" => {
+            let p: InfoParams = params(&request.params)?;
+            let info = lowres::probe(&p.path).map_err(RpcError::processing)?;
+            Ok(json!(info))
+        }
+        "preview" => {
+            let p: PreviewParams = params(&request.params)?;
+            let config = config(p.config)?;
+            let source = lowres::load_source(&p.input).map_err(RpcError::processing)?;
+            let (png, report) = lowres::pool::in_pool(&config, || {
+                lowres::render_source(&source, config.clone(), p.preview_quality, &mut |_| {})
+            })
+            .and_then(|r| r)
+            .map_err(RpcError::processing)?;
+            let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+            Ok(json!({
+                "data_url": format!("data:image/png;base64,{}", b64),
+                "report": report,
+            }))
+        }
+        "thumbnail" => {
+            let p: ThumbnailParams = params(&request.params)?;
+            let (data, mime) =
+                lowres::render_thumbnail(&p.path, p.max_edge).map_err(RpcError::processing)?;
+            let b64 = base64::engine::general_purpose::STANDARD.encode(data);
+            Ok(json!({ "data_url": format!("data:{};base64,{}", mime, b64) }))
+        }
+        "formats" => Ok(json!({ "extensions": lowres::input_extensions() })),
+        "capabilities" => Ok(json!(lowres::capabilities())),
+        other => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("Unknown method {:?}", other),
+        )),
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(value: &Value) -> std::result::Result<T, RpcError> {
+    serde_json::from_value(value.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+/// Configs from clients may be saved presets in an older format.
+fn config(value: Value) -> std::result::Result<LowresConfig, RpcError> {
+    let config = lowres::migrate::upgrade(value)
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e)))?;
+    config
+        .validate()
+        .map_err(|e| RpcError::new(INVALID_PARAMS, format!("{:#}", e)))?;
+    Ok(config)
+}
+
+fn send_error(id: Value, err: RpcError) -> Result<()> {
+    let mut error = json!({ "code": err.code, "message": err.message });
+    if let Some(data) = err.data {
+        error["data"] = data;
+    }
+    send(json!({ "jsonrpc": "2.0", "id": id, "error": error }))
+}
+
+fn send(message: Value) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &message)?;
+    stdout.write_all(b"\n")?;
+    stdout.flush()?;
+    Ok(())
+}
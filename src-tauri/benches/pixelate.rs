@@ -0,0 +1,61 @@
+//! Block averaging: the vectorized row sum against the portable one, and
+//! pixelating an 8K frame end to end: to the small grid, so encoding the
+//! output doesn't swamp the timing, and at full size, which adds filling
+//! every output row from the block colors (guarding against the per-pixel
+//! fill that once made this many times slower) and a fast PNG encode.
+//!
+//! Run with `cargo bench --bench pixelate`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{DynamicImage, Rgba, RgbaImage};
+use lowres_lib::lowres::{self, kernels, LowresConfig, PreviewQuality, Source};
+use std::hint::black_box;
+
+const WIDTH: u32 = 7680;
+const HEIGHT: u32 = 4320;
+
+fn frame() -> RgbaImage {
+    RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        Rgba([(x % 251) as u8, (y % 241) as u8, ((x ^ y) % 256) as u8, 255])
+    })
+}
+
+fn sum_rows(c: &mut Criterion) {
+    let img = frame();
+    let mut group = c.benchmark_group("sum_rgba");
+    for block in [8usize, 64] {
+        let run = &img.as_raw()[..block * 4];
+        group.throughput(Throughput::Bytes(run.len() as u64));
+        group.bench_with_input(BenchmarkId::new("vector", block), run, |b, run| {
+            b.iter(|| kernels::sum_rgba(black_box(run)))
+        });
+        group.bench_with_input(BenchmarkId::new("scalar", block), run, |b, run| {
+            b.iter(|| kernels::sum_rgba_scalar(black_box(run)))
+        });
+    }
+    group.finish();
+}
+
+fn pixelate_8k(c: &mut Criterion) {
+    let source = Source::from(DynamicImage::ImageRgba8(frame()));
+    let mut group = c.benchmark_group("pixelate_8k");
+    group.sample_size(10);
+    for (output, block) in [("Small", 8), ("Small", 32), ("Full", 8), ("Full", 32)] {
+        let config: LowresConfig = serde_json::from_str(&format!(
+            r#"{{"block": {}, "block_output": "{}"}}"#,
+            block, output
+        ))
+        .unwrap();
+        let id = BenchmarkId::new(output.to_lowercase(), block);
+        group.bench_function(id, |b| {
+            b.iter(|| {
+                lowres::render_source(&source, config.clone(), PreviewQuality::Full, &mut |_| {})
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sum_rows, pixelate_8k);
+criterion_main!(benches);
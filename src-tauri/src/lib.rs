@@ -1,15 +1,28 @@
-mod lowres;
-use lowres::LowresConfig;
-use std::path::PathBuf;
+pub mod lowres;
+use lowres::LowresError;
+use std::path::{Path, PathBuf};
 
 use base64::Engine;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::State;
 
-fn file_to_base64(path: &PathBuf) -> Result<String, String> {
-    let mut file = File::open(path).map_err(|e| e.to_string())?;
+/// Sources decoded for live preview, by the handle `load_source` returned.
+#[derive(Default)]
+struct Sources {
+    next_handle: AtomicU64,
+    loaded: Mutex<HashMap<u64, Arc<lowres::Source>>>,
+}
+
+fn file_to_base64(path: &PathBuf) -> Result<String, LowresError> {
+    let read_failed =
+        |e: std::io::Error| LowresError::Io(format!("Failed to read file {:?}: {}", path, e));
+    let mut file = File::open(path).map_err(read_failed)?;
     let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).map_err(|e| e.to_string())?;
+    file.read_to_end(&mut buffer).map_err(read_failed)?;
 
     // Determine mime type based on extension
     let ext = path
@@ -29,35 +42,649 @@ fn file_to_base64(path: &PathBuf) -> Result<String, String> {
     Ok(format!("data:{};base64,{}", mime, b64))
 }
 
+/// Upgrade a config from the frontend (saved presets may predate the current
+/// format) and check it before any image is decoded.
+fn load_config(value: serde_json::Value) -> Result<lowres::LowresConfig, LowresError> {
+    let config = lowres::migrate::upgrade(value)?;
+    config.validate()?;
+    Ok(config)
+}
+
 #[tauri::command]
-async fn get_image_base64(path: String) -> Result<String, String> {
+async fn get_image_base64(path: String) -> Result<String, LowresError> {
     let path_buf = PathBuf::from(path);
     file_to_base64(&path_buf)
 }
 
+/// Longest edge of input previews when the frontend doesn't ask for one.
+const THUMBNAIL_MAX_EDGE: u32 = 1024;
+
+/// A downsized preview of `path` as a data URL. Unlike `get_image_base64`
+/// this stays small for huge sources, which would otherwise stall the webview.
+#[tauri::command]
+async fn get_thumbnail(path: String, max_edge: Option<u32>) -> Result<String, LowresError> {
+    let (data, mime) =
+        lowres::render_thumbnail(&PathBuf::from(path), max_edge.unwrap_or(THUMBNAIL_MAX_EDGE))?;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(data);
+    Ok(format!("data:{};base64,{}", mime, b64))
+}
+
+/// Dimensions, format, bit depth, alpha, DPI and EXIF capture details of
+/// `path`, from its headers alone.
 #[tauri::command]
-async fn process_image(input: String, config: LowresConfig) -> Result<(String, String), String> {
+async fn get_image_metadata(path: String) -> Result<lowres::ImageInfo, LowresError> {
+    lowres::probe(&PathBuf::from(path)).map_err(LowresError::from)
+}
+
+/// Process `input` into the config's output directory and file name, or
+/// `{stem}_lowres.png` next to it. `on_collision` decides what happens to an
+/// existing output (overwritten by default); the action taken is returned,
+/// and a skipped input has no report.
+#[tauri::command]
+async fn process_image(
+    input: String,
+    config: serde_json::Value,
+    on_collision: Option<lowres::OnCollision>,
+) -> Result<Processed, LowresError> {
+    let config = load_config(config)?;
+    let out_dir = config.output_dir.clone();
+    process_into(&PathBuf::from(&input), out_dir, &config, on_collision)
+}
+
+/// The result of `process_image` and `process_image_url`: the output path,
+/// the output as a data URL, the report and the collision action taken.
+type Processed = (
+    String,
+    String,
+    Option<lowres::ProcessReport>,
+    lowres::batch::CollisionAction,
+);
+
+fn process_into(
+    input: &std::path::Path,
+    out_dir: Option<PathBuf>,
+    config: &lowres::LowresConfig,
+    on_collision: Option<lowres::OnCollision>,
+) -> Result<Processed, LowresError> {
+    if let Some(dir) = &out_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", dir, e)))?;
+    }
+
+    let item = lowres::pool::in_pool(config, || {
+        lowres::batch::process_into(
+            input,
+            out_dir.as_deref(),
+            config,
+            on_collision.unwrap_or(lowres::OnCollision::Overwrite),
+        )
+    })?;
+    if let Some(error) = item.error {
+        return Err(error);
+    }
+
+    let b64 = file_to_base64(&item.output)?;
+    Ok((
+        item.output.to_string_lossy().to_string(),
+        b64,
+        item.report,
+        item.action,
+    ))
+}
+
+/// Download the image at `url` and process it as `process_image` would, into
+/// the config's output directory or the user's Downloads folder.
+#[tauri::command]
+async fn process_image_url(
+    app: tauri::AppHandle,
+    url: String,
+    config: serde_json::Value,
+    on_collision: Option<lowres::OnCollision>,
+) -> Result<Processed, LowresError> {
+    use tauri::Manager;
+    static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+
+    let config = load_config(config)?;
+    if !lowres::remote::is_url(&url) {
+        return Err(LowresError::InvalidConfig(format!(
+            "Not an http(s) URL: {}",
+            url
+        )));
+    }
+    let out_dir = match &config.output_dir {
+        Some(dir) => dir.clone(),
+        None => app
+            .path()
+            .download_dir()
+            .map_err(|e| LowresError::Io(format!("No Downloads folder: {}", e)))?,
+    };
+
+    // Each download gets its own directory, removed once processed.
+    let temp = std::env::temp_dir().join(format!(
+        "lowres_download_{}_{}",
+        std::process::id(),
+        DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&temp)
+        .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", temp, e)))?;
+    let processed = lowres::remote::download(&url, &temp)
+        .map_err(LowresError::from)
+        .and_then(|input| process_into(&input, Some(out_dir), &config, on_collision));
+    let _ = std::fs::remove_dir_all(&temp);
+    processed
+}
+
+/// Process an image the frontend already holds, such as a browser drag or a
+/// canvas export, given as a base64 data URL. Nothing is written to disk; the
+/// result comes back as a PNG data URL with its report.
+#[tauri::command]
+async fn process_image_data(
+    data_url: String,
+    config: serde_json::Value,
+) -> Result<(String, lowres::ProcessReport), LowresError> {
+    let config = load_config(config)?;
+    let data = lowres::decode_data_url(&data_url)?;
+    let (png, report) = lowres::pool::in_pool(&config, || {
+        lowres::process_image_bytes(&data, config.clone())
+    })??;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+    Ok((format!("data:image/png;base64,{}", b64), report))
+}
+
+/// Decode `path` once and keep it in memory for `preview`.
+#[tauri::command]
+async fn load_source(path: String, sources: State<'_, Sources>) -> Result<u64, LowresError> {
+    let source = lowres::load_source(&PathBuf::from(path))?;
+    let handle = sources.next_handle.fetch_add(1, Ordering::Relaxed);
+    sources
+        .loaded
+        .lock()
+        .unwrap()
+        .insert(handle, Arc::new(source));
+    Ok(handle)
+}
+
+/// Render a loaded source with `config` without touching disk, for live
+/// preview: only the transform and encode stages run, on a proxy of the
+/// source unless `preview_quality` is `Full`.
+#[tauri::command]
+async fn preview(
+    handle: u64,
+    config: serde_json::Value,
+    preview_quality: Option<lowres::PreviewQuality>,
+    sources: State<'_, Sources>,
+) -> Result<(String, lowres::ProcessReport), LowresError> {
+    let config = load_config(config)?;
+    let source = sources.loaded.lock().unwrap().get(&handle).cloned();
+    let source =
+        source.ok_or_else(|| LowresError::Other(format!("No source loaded as {}", handle)))?;
+    let quality = preview_quality.unwrap_or(lowres::PreviewQuality::Proxy);
+    let (png, report) = lowres::pool::in_pool(&config, || {
+        lowres::render_source(&source, config.clone(), quality, &mut |_| {})
+    })??;
+    let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+    Ok((format!("data:image/png;base64,{}", b64), report))
+}
+
+/// Drop a source loaded with `load_source`.
+#[tauri::command]
+fn release_source(handle: u64, sources: State<'_, Sources>) {
+    sources.loaded.lock().unwrap().remove(&handle);
+}
+
+fn clipboard_error(e: arboard::Error) -> LowresError {
+    LowresError::Io(format!("Clipboard unavailable: {}", e))
+}
+
+/// Process the image on the clipboard, such as a screenshot, without touching
+/// disk, and put the result back on the clipboard unless `copy_result` is
+/// false. Returns the result as a data URL and its report.
+#[tauri::command]
+async fn process_clipboard_image(
+    config: serde_json::Value,
+    copy_result: Option<bool>,
+) -> Result<(String, lowres::ProcessReport), LowresError> {
+    let config = load_config(config)?;
+    let mut clipboard = arboard::Clipboard::new().map_err(clipboard_error)?;
+    let pasted = clipboard.get_image().map_err(|e| match e {
+        arboard::Error::ContentNotAvailable => {
+            LowresError::UnsupportedFormat("The clipboard holds no image".into())
+        }
+        e => clipboard_error(e),
+    })?;
+    let rgba = image::RgbaImage::from_raw(
+        pasted.width as u32,
+        pasted.height as u32,
+        pasted.bytes.into_owned(),
+    )
+    .ok_or_else(|| LowresError::Decode("Clipboard image data is truncated".into()))?;
+
+    let source = lowres::Source::from(image::DynamicImage::ImageRgba8(rgba));
+    let (png, report) = lowres::pool::in_pool(&config, || {
+        lowres::render_source(
+            &source,
+            config.clone(),
+            lowres::PreviewQuality::Full,
+            &mut |_| {},
+        )
+    })??;
+    if copy_result.unwrap_or(true) {
+        let result = image::load_from_memory(&png)
+            .map_err(|e| LowresError::Decode(format!("Failed to read the result: {}", e)))?
+            .to_rgba8();
+        clipboard
+            .set_image(arboard::ImageData {
+                width: result.width() as usize,
+                height: result.height() as usize,
+                bytes: result.into_raw().into(),
+            })
+            .map_err(clipboard_error)?;
+    }
+    let b64 = base64::engine::general_purpose::STANDARD.encode(png);
+    Ok((format!("data:image/png;base64,{}", b64), report))
+}
+
+/// Write an original/resized/pixelated/quantized comparison figure next to the input.
+#[tauri::command]
+async fn export_comparison(
+    input: String,
+    config: serde_json::Value,
+) -> Result<(String, String), LowresError> {
+    let config = load_config(config)?;
     let input_path = PathBuf::from(&input);
     let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
     let parent = input_path
         .parent()
         .unwrap_or_else(|| std::path::Path::new("."));
+    let output_path = parent.join(format!("{}_compare.png", file_stem));
+
+    let png = lowres::render_comparison(&input_path, config).map_err(LowresError::from)?;
+    std::fs::write(&output_path, png)
+        .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", output_path, e)))?;
+
+    let b64 = file_to_base64(&output_path)?;
+    Ok((output_path.to_string_lossy().to_string(), b64))
+}
 
-    let output_filename = format!("{}_lowres.png", file_stem);
-    let output_path = parent.join(output_filename);
+/// Write a preview with composition guides over the region `config` keeps
+/// next to the input, for planning a crop. Defaults to the rule of thirds.
+#[tauri::command]
+async fn export_guides(
+    input: String,
+    config: serde_json::Value,
+    guides: Option<Vec<lowres::Guide>>,
+) -> Result<(String, String), LowresError> {
+    let config = load_config(config)?;
+    let guides = guides.unwrap_or_else(|| vec![lowres::Guide::Thirds]);
+    let input_path = PathBuf::from(&input);
+    let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = input_path
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new("."));
+    let output_path = parent.join(format!("{}_guides.png", file_stem));
 
-    lowres::process_image(input_path, output_path.clone(), config).map_err(|e| e.to_string())?;
+    let png = lowres::render_guides(&input_path, &config, &guides).map_err(LowresError::from)?;
+    std::fs::write(&output_path, png)
+        .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", output_path, e)))?;
 
     let b64 = file_to_base64(&output_path)?;
     Ok((output_path.to_string_lossy().to_string(), b64))
 }
 
+/// Write a heatmap of each block's distinct colors or color variance next
+/// to the input, to find the regions that force a large palette. Returns its
+/// path, the PNG as base64 and the values it shows.
+#[tauri::command]
+async fn export_heatmap(
+    input: String,
+    config: serde_json::Value,
+    metric: Option<lowres::heatmap::HeatmapMetric>,
+) -> Result<(String, String, lowres::heatmap::HeatmapStats), LowresError> {
+    let config = load_config(config)?;
+    let metric = metric.unwrap_or(lowres::heatmap::HeatmapMetric::Colors);
+    let input_path = PathBuf::from(&input);
+    let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = input_path
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new("."));
+    let output_path = parent.join(format!("{}_heatmap.png", file_stem));
+
+    let (png, stats) =
+        lowres::heatmap::render_heatmap(&input_path, &config, metric).map_err(LowresError::from)?;
+    std::fs::write(&output_path, png)
+        .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", output_path, e)))?;
+
+    let b64 = file_to_base64(&output_path)?;
+    Ok((output_path.to_string_lossy().to_string(), b64, stats))
+}
+
+/// Estimate how reversible pixelating the input's regions with `config`
+/// would be, and the block size that makes it safe.
+#[tauri::command]
+async fn audit_redaction(
+    input: String,
+    config: serde_json::Value,
+) -> Result<lowres::audit::Audit, LowresError> {
+    let config = load_config(config)?;
+    lowres::audit::audit(&PathBuf::from(input), &config).map_err(LowresError::from)
+}
+
+/// The app's region set file, shared with `lowres --regions-file`.
+fn region_sets_path(app: &tauri::AppHandle) -> Result<PathBuf, LowresError> {
+    use tauri::Manager;
+    let dir = app
+        .path()
+        .app_config_dir()
+        .map_err(|e| LowresError::Io(format!("No config folder: {}", e)))?;
+    Ok(dir.join("region_sets.json"))
+}
+
+/// The saved region sets by name, and the file they are kept in.
+#[tauri::command]
+async fn list_region_sets(
+    app: tauri::AppHandle,
+) -> Result<(String, lowres::region_sets::RegionSets), LowresError> {
+    let path = region_sets_path(&app)?;
+    let sets = if path.exists() {
+        lowres::region_sets::read_region_sets(&path)?
+    } else {
+        Default::default()
+    };
+    Ok((path.to_string_lossy().to_string(), sets))
+}
+
+/// Save `regions` as the region set `name`, or remove it when empty.
+/// Returns every saved set.
+#[tauri::command]
+async fn save_region_set(
+    app: tauri::AppHandle,
+    name: String,
+    regions: Vec<lowres::Region>,
+) -> Result<lowres::region_sets::RegionSets, LowresError> {
+    let path = region_sets_path(&app)?;
+    lowres::region_sets::save_region_set(&path, &name, regions).map_err(LowresError::from)
+}
+
+/// Split a sprite sheet into `{stem}_sprites/` next to it, processing each
+/// sprite with `config` when one is given.
+#[tauri::command]
+async fn extract_sprites(
+    input: String,
+    config: Option<serde_json::Value>,
+) -> Result<lowres::sprites::SpriteSheet, LowresError> {
+    let config = config.map(load_config).transpose()?;
+    let input_path = PathBuf::from(&input);
+    let file_stem = input_path.file_stem().unwrap_or_default().to_string_lossy();
+    let parent = input_path
+        .parent()
+        .unwrap_or_else(|| std::path::Path::new("."));
+    let out_dir = parent.join(format!("{}_sprites", file_stem));
+
+    lowres::extract_sprites(&input_path, &out_dir, config).map_err(LowresError::from)
+}
+
+#[tauri::command]
+async fn analyze_image(path: String) -> Result<lowres::analyze::Analysis, LowresError> {
+    lowres::analyze(&PathBuf::from(path)).map_err(LowresError::from)
+}
+
+/// Process several files with one config, into `out_dir` or next to each input.
+/// With `manifest`, also write a SHA-256 manifest of the outputs there, and
+/// with `proof`, PDF proof sheets of them laid out 3 by 4.
+/// Returns the report and its summary statistics.
+#[tauri::command]
+async fn process_batch(
+    inputs: Vec<String>,
+    config: serde_json::Value,
+    out_dir: Option<String>,
+    on_collision: Option<lowres::OnCollision>,
+    manifest: Option<String>,
+    proof: Option<String>,
+) -> Result<(lowres::batch::BatchReport, lowres::batch::BatchSummary), LowresError> {
+    let config = load_config(config)?;
+    let inputs: Vec<PathBuf> = inputs.into_iter().map(PathBuf::from).collect();
+    let out_dir = out_dir.map(PathBuf::from);
+    let report = lowres::pool::in_pool(&config, || {
+        lowres::process_batch(
+            &inputs,
+            out_dir.as_deref(),
+            &config,
+            on_collision.unwrap_or(lowres::OnCollision::Overwrite),
+        )
+    })?
+    .map_err(LowresError::from)?;
+    if let Some(manifest) = manifest {
+        lowres::manifest::write_manifest(&PathBuf::from(manifest), &report.produced())
+            .map_err(LowresError::from)?;
+    }
+    if let Some(proof) = proof.filter(|_| !report.produced().is_empty()) {
+        let layout = lowres::proof::ProofLayout::default();
+        lowres::proof::write_proof(&PathBuf::from(proof), &report, &config, &layout)
+            .map_err(LowresError::from)?;
+    }
+    let summary = report.summary();
+    Ok((report, summary))
+}
+
+#[tauri::command]
+async fn verify_manifest(
+    path: String,
+) -> Result<Vec<lowres::manifest::ManifestCheck>, LowresError> {
+    lowres::manifest::verify_manifest(&PathBuf::from(path)).map_err(LowresError::from)
+}
+
+/// The settings a previously exported PNG was made with, to restore the controls.
+#[tauri::command]
+async fn get_embedded_settings(path: String) -> Result<Option<lowres::LowresConfig>, LowresError> {
+    lowres::read_embedded_settings(&PathBuf::from(path)).map_err(LowresError::from)
+}
+
+/// DPI of images whose metadata records none, as when processing.
+const DEFAULT_PRINT_DPI: u32 = 300;
+
+/// An HTML page holding the image at `path` at its physical size: its pixel
+/// size over the DPI in its metadata. Pixels stay crisp when the printer
+/// driver resamples them.
+fn print_page(path: &PathBuf) -> Result<String, LowresError> {
+    let info = lowres::probe(path)?;
+    let dpi = info.dpi.unwrap_or(DEFAULT_PRINT_DPI) as f64;
+    let (width, height) = (info.width as f64 / dpi, info.height as f64 / dpi);
+    let title = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;");
+    Ok(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+@page {{ margin: 0; }}
+html, body {{ margin: 0; }}
+img {{ display: block; width: {width:.4}in; height: {height:.4}in; image-rendering: pixelated; }}
+</style>
+</head>
+<body><img src="{image}" alt=""></body>
+</html>
+"#,
+        image = file_to_base64(path)?,
+    ))
+}
+
+/// Open the print dialog for the processed image at `path`, laid out at the
+/// physical size its DPI metadata gives it rather than fitted to the page.
+/// The page shows in its own window, which the user closes when done.
+#[tauri::command]
+async fn print_result(app: tauri::AppHandle, path: String) -> Result<(), LowresError> {
+    use tauri::webview::PageLoadEvent;
+    static PRINTS: AtomicU64 = AtomicU64::new(0);
+
+    let n = PRINTS.fetch_add(1, Ordering::Relaxed);
+    let path = PathBuf::from(path);
+    let name = format!("lowres_print_{}_{}.html", std::process::id(), n);
+    let page = std::env::temp_dir().join(name);
+    std::fs::write(&page, print_page(&path)?)
+        .map_err(|e| LowresError::Io(format!("Failed to create {:?}: {}", page, e)))?;
+    let url = tauri::Url::from_file_path(&page)
+        .map_err(|_| LowresError::Other(format!("Can't open {:?} in a window", page)))?;
+
+    let label = format!("print-{}", n);
+    tauri::WebviewWindowBuilder::new(&app, label, tauri::WebviewUrl::External(url))
+        .title(format!(
+            "Print {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ))
+        .inner_size(480.0, 640.0)
+        .on_page_load(|window, payload| {
+            if payload.event() == PageLoadEvent::Finished {
+                let _ = window.print();
+            }
+        })
+        .build()
+        .map_err(|e| LowresError::Other(format!("Failed to open the print window: {}", e)))?;
+    Ok(())
+}
+
+/// Extensions the open dialog offers, which vary with the build's features.
+#[tauri::command]
+fn get_input_extensions() -> Vec<&'static str> {
+    lowres::input_extensions()
+}
+
+/// Formats, features, effect values, acceleration and limits of this build,
+/// so the frontend can hide what it doesn't support.
+#[tauri::command]
+fn get_capabilities() -> lowres::capabilities::Capabilities {
+    lowres::capabilities()
+}
+
+/// Images the app was launched with, such as by Explorer's "Send to lowres"
+/// or a Finder Quick Action, not yet taken by the frontend.
+#[derive(Default)]
+struct Intake(Mutex<Vec<PathBuf>>);
+
+/// The image files among a launch's arguments, relative ones resolved
+/// against the directory it was started in.
+fn intake_files(args: &[String], cwd: &Path) -> Vec<PathBuf> {
+    args.iter()
+        // The executable.
+        .skip(1)
+        .filter(|arg| !arg.starts_with('-'))
+        .map(|arg| cwd.join(arg))
+        .filter(|path| is_input_file(path))
+        .collect()
+}
+
+/// Whether `path` is a file the app reads, judged by its extension.
+fn is_input_file(path: &Path) -> bool {
+    let extensions = lowres::input_extensions();
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase().as_str()))
+}
+
+/// Queue the images a launch or Finder was given, tell the frontend to take
+/// them, and bring the window forward.
+fn intake(app: &tauri::AppHandle, files: Vec<PathBuf>) {
+    use tauri::{Emitter, Manager};
+
+    if files.is_empty() {
+        return;
+    }
+    app.state::<Intake>().0.lock().unwrap().extend(files);
+    let _ = app.emit("intake", ());
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+/// The images queued by launches since the last call, oldest first.
+#[tauri::command]
+fn take_intake(intake: State<'_, Intake>) -> Vec<PathBuf> {
+    std::mem::take(&mut *intake.0.lock().unwrap())
+}
+
+#[tauri::command]
+fn get_config_schema() -> schemars::schema::RootSchema {
+    lowres::config_schema()
+}
+
+/// Size limits of the app, which decodes whatever is dropped on it: room for
+/// large camera files and scans, well short of what would exhaust memory.
+const APP_LIMITS: lowres::limits::SizeLimits = lowres::limits::SizeLimits {
+    max_file_bytes: Some(512 * 1024 * 1024),
+    max_input_megapixels: Some(250.0),
+    max_output_megapixels: Some(150.0),
+};
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    tauri::Builder::default()
+    lowres::limits::set(APP_LIMITS);
+    let builder = tauri::Builder::default();
+    // Registered first so that a second launch, such as one per file from
+    // Explorer's context menu, hands its files to this instance before
+    // starting anything else. Mobile apps only ever run once.
+    #[cfg(desktop)]
+    let builder = builder.plugin(tauri_plugin_single_instance::init(|app, args, cwd| {
+        intake(app, intake_files(&args, Path::new(&cwd)));
+    }));
+    builder
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
-        .invoke_handler(tauri::generate_handler![process_image, get_image_base64])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .manage(Sources::default())
+        .manage(Intake::default())
+        .setup(|app| {
+            let args: Vec<String> = std::env::args().collect();
+            let cwd = std::env::current_dir().unwrap_or_default();
+            intake(app.handle(), intake_files(&args, &cwd));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            process_image,
+            process_image_url,
+            process_image_data,
+            get_image_base64,
+            get_thumbnail,
+            get_image_metadata,
+            load_source,
+            preview,
+            release_source,
+            process_clipboard_image,
+            get_config_schema,
+            get_input_extensions,
+            get_capabilities,
+            take_intake,
+            print_result,
+            analyze_image,
+            export_comparison,
+            export_guides,
+            export_heatmap,
+            audit_redaction,
+            list_region_sets,
+            save_region_set,
+            extract_sprites,
+            process_batch,
+            verify_manifest,
+            get_embedded_settings
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|_app, _event| {
+            // Finder, and so a Quick Action's `open -a`, hands files to the
+            // app as an event rather than as arguments, running or not.
+            #[cfg(target_os = "macos")]
+            if let tauri::RunEvent::Opened { urls } = _event {
+                let files = urls
+                    .into_iter()
+                    .filter_map(|url| url.to_file_path().ok())
+                    .filter(|path| is_input_file(path))
+                    .collect();
+                intake(_app, files);
+            }
+        });
 }
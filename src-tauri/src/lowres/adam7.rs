@@ -0,0 +1,108 @@
+//! Adam7 interlacing for PNG output, which the png crate reads but doesn't
+//! write. The image goes out as seven passes of ever finer pixel grids, so a
+//! browser can show a coarse whole image before the file has loaded; each
+//! pass is filtered like a small image of its own and the lot deflated into
+//! one IDAT stream.
+
+use flate2::write::ZlibEncoder;
+use std::io::Write;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Each pass's first column and row, and its steps across and down.
+const PASSES: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The IDAT stream of `data`, `w`×`h` pixels of `bpp` bytes, interlaced:
+/// every row filtered with Sub, or with whichever filter leaves the smallest
+/// bytes when `adaptive`, then deflated at `level`.
+pub fn interlaced_idat(
+    (w, h): (u32, u32),
+    bpp: usize,
+    data: &[u8],
+    adaptive: bool,
+    level: flate2::Compression,
+) -> Result<Vec<u8>> {
+    let (w, h) = (w as usize, h as usize);
+    let mut zlib = ZlibEncoder::new(Vec::new(), level);
+    for (x0, y0, dx, dy) in PASSES {
+        if x0 >= w || y0 >= h {
+            continue;
+        }
+        let row_len = (w - x0).div_ceil(dx) * bpp;
+        let mut prev = vec![0u8; row_len];
+        let mut row = Vec::with_capacity(row_len);
+        let mut filtered = vec![0u8; row_len + 1];
+        for y in (y0..h).step_by(dy) {
+            row.clear();
+            for x in (x0..w).step_by(dx) {
+                let at = (y * w + x) * bpp;
+                row.extend_from_slice(&data[at..at + bpp]);
+            }
+            if adaptive {
+                let mut best = Vec::new();
+                let mut best_cost = u64::MAX;
+                for kind in 0..5 {
+                    filter_row(kind, bpp, &prev, &row, &mut filtered);
+                    // The usual heuristic: the smallest sum of signed magnitudes.
+                    let cost = filtered[1..]
+                        .iter()
+                        .map(|&b| (b as i8).unsigned_abs() as u64)
+                        .sum();
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best.clone_from(&filtered);
+                    }
+                }
+                zlib.write_all(&best)?;
+            } else {
+                filter_row(1, bpp, &prev, &row, &mut filtered);
+                zlib.write_all(&filtered)?;
+            }
+            std::mem::swap(&mut prev, &mut row);
+        }
+    }
+    Ok(zlib.finish()?)
+}
+
+/// Filter `row` with PNG filter `kind` against the row above, `prev`,
+/// writing the filter byte and the filtered bytes to `out`.
+fn filter_row(kind: u8, bpp: usize, prev: &[u8], row: &[u8], out: &mut [u8]) {
+    out[0] = kind;
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up = prev[i];
+        let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match kind {
+            0 => 0,
+            1 => left,
+            2 => up,
+            3 => ((left as u16 + up as u16) / 2) as u8,
+            _ => paeth(left, up, up_left),
+        };
+        out[i + 1] = row[i].wrapping_sub(predicted);
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
@@ -0,0 +1,197 @@
+//! Inspects a source image and suggests settings for it: the backend of the
+//! UI's "Suggested settings" wizard and the CLI's `--auto`.
+
+use image::{Rgba, RgbaImage};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+use super::{decode_image, luma, metadata, palette, BlockStat, LowresConfig, Palette};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Upper bound on how many pixels the color statistics look at; larger inputs are strided.
+const SAMPLES: usize = 1 << 16;
+/// Suggested blocks aim for roughly this many blocks along the longest edge.
+const TARGET_BLOCKS: u32 = 96;
+/// Images with at most this many colors are treated as pixel art already.
+const PIXEL_ART_COLORS: usize = 16;
+/// A built-in palette is suggested when its RMS error is below this.
+const PALETTE_MAX_RMS: f64 = 16.0;
+/// Mean luma step between horizontal neighbors above which pixelation is likely to alias.
+const MOIRE_EDGE_ENERGY: f64 = 24.0;
+/// Luma levels the middle 96% of pixels must span to not count as low contrast.
+const LOW_CONTRAST_SPREAD: u8 = 64;
+
+/// What `analyze` found out about an image.
+#[derive(Serialize, Debug, Clone)]
+pub struct Analysis {
+    pub width: u32,
+    pub height: u32,
+    /// Resolution recorded in the file (EXIF, PNG pHYs or JFIF), if any. The
+    /// output keeps it unless the config sets a DPI.
+    pub source_dpi: Option<u32>,
+    /// Distinct colors among the sampled pixels.
+    pub distinct_colors: usize,
+    /// Settings to start from; fields without a suggestion are left unset.
+    pub recommended: LowresConfig,
+    /// Problems worth showing the user before processing.
+    pub warnings: Vec<String>,
+}
+
+/// Look at the image at `path` and recommend a config for it.
+pub fn analyze(path: &PathBuf) -> Result<Analysis> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+    let source_dpi = metadata::read_source_facts(&mut Cursor::new(&data)).dpi;
+    let rgba = decode_image(&data)?.to_rgba8();
+    let (width, height) = rgba.dimensions();
+
+    let step = (rgba.pixels().len() / SAMPLES).max(1);
+    let samples: Vec<Rgba<u8>> = rgba.pixels().step_by(step).copied().collect();
+    let distinct_colors = samples
+        .iter()
+        .map(|p| [p[0], p[1], p[2]])
+        .collect::<HashSet<_>>()
+        .len();
+
+    let mut recommended = LowresConfig::default();
+    let mut warnings = Vec::new();
+
+    if distinct_colors <= PIXEL_ART_COLORS {
+        warnings.push(format!(
+            "Image already uses only {} colors and may be pixel art; consider upscale instead of pixelating it again",
+            distinct_colors
+        ));
+    } else {
+        recommended.block = Some((width.max(height) / TARGET_BLOCKS).clamp(2, 64));
+        recommended.block_stat = Some(BlockStat::Mean);
+        match closest_palette(&samples) {
+            Some(p) => recommended.palette = Some(p),
+            None => recommended.colors = Some(PIXEL_ART_COLORS as u32),
+        }
+        if edge_energy(&rgba) > MOIRE_EDGE_ENERGY {
+            recommended.linear_light = Some(true);
+            warnings.push(
+                "Fine high-contrast detail may alias into moiré when pixelated; linear-light averaging is recommended"
+                    .into(),
+            );
+        }
+    }
+
+    let spread = luma_spread(&samples);
+    if spread < LOW_CONTRAST_SPREAD {
+        warnings.push(format!(
+            "Low contrast: most pixels span only {} of 255 luma levels, so the result may look flat",
+            spread
+        ));
+    }
+
+    Ok(Analysis {
+        width,
+        height,
+        source_dpi,
+        distinct_colors,
+        recommended,
+        warnings,
+    })
+}
+
+impl LowresConfig {
+    /// Take `suggested` values for whatever this config leaves open. Fields
+    /// that decide the same thing (size vs. block, palette vs. colors) are
+    /// only filled when none of them is set.
+    pub fn with_suggestions(mut self, suggested: LowresConfig) -> Self {
+        let sized = self.width.is_some() || self.height.is_some() || self.block_size().is_some();
+        if !sized {
+            self.block = suggested.block;
+        }
+        if self.palette.is_none() && self.palette_file.is_none() && self.colors.is_none() {
+            self.palette = suggested.palette;
+            self.colors = suggested.colors;
+        }
+        self.block_stat = self.block_stat.or(suggested.block_stat);
+        self.linear_light = self.linear_light.or(suggested.linear_light);
+        self.dpi = self.dpi.or(suggested.dpi);
+        self
+    }
+}
+
+/// The built-in palette that reproduces `samples` best, if any is close enough.
+fn closest_palette(samples: &[Rgba<u8>]) -> Option<Palette> {
+    let rms = |p: Palette| {
+        let sum: f64 = samples
+            .iter()
+            .map(|&px| {
+                let q = palette::nearest(p.colors(), px);
+                (0..3)
+                    .map(|c| (q[c] as f64 - px[c] as f64).powi(2))
+                    .sum::<f64>()
+            })
+            .sum();
+        (sum / (3 * samples.len().max(1)) as f64).sqrt()
+    };
+    Palette::ALL
+        .into_iter()
+        .map(|p| (p, rms(p)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .filter(|&(_, err)| err < PALETTE_MAX_RMS)
+        .map(|(p, _)| p)
+}
+
+/// Mean luma difference between horizontal neighbors, in 0..=255 levels.
+fn edge_energy(img: &RgbaImage) -> f64 {
+    let row_step = (img.height() as usize / 256).max(1);
+    let (mut sum, mut count) = (0u64, 0u64);
+    for y in (0..img.height()).step_by(row_step) {
+        for x in 1..img.width() {
+            let a = luma(img.get_pixel(x - 1, y)) as i64;
+            let b = luma(img.get_pixel(x, y)) as i64;
+            sum += (a - b).unsigned_abs();
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return 0.0;
+    }
+    sum as f64 / count as f64 / 1000.0
+}
+
+/// Luma levels between the 2nd and 98th percentile of `samples`.
+fn luma_spread(samples: &[Rgba<u8>]) -> u8 {
+    let mut levels: Vec<u8> = samples.iter().map(|p| (luma(p) / 1000) as u8).collect();
+    if levels.is_empty() {
+        return 0;
+    }
+    levels.sort_unstable();
+    let at = |q: usize| levels[(levels.len() - 1) * q / 100];
+    at(98) - at(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_statistics() {
+        let checker = RgbaImage::from_fn(8, 8, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        assert_eq!(edge_energy(&checker), 255.0);
+        assert_eq!(
+            luma_spread(&checker.pixels().copied().collect::<Vec<_>>()),
+            255
+        );
+        assert_eq!(luma_spread(&[Rgba([90, 90, 90, 255]); 10]), 0);
+
+        // CGA cyan and brown appear in no other built-in palette.
+        let cga = [Rgba([0x00, 0xaa, 0xaa, 255]), Rgba([0xaa, 0x55, 0x00, 255])];
+        assert_eq!(closest_palette(&cga), Some(Palette::Cga));
+        assert_eq!(closest_palette(&[Rgba([128, 0, 255, 255])]), None);
+    }
+}
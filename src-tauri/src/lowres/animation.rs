@@ -0,0 +1,763 @@
+//! Animated GIFs, APNGs and WebPs: every frame goes through the same
+//! transform, in parallel, and is written back as an animation with the
+//! source's frame delays and loop count. Without this, only the first frame
+//! survives. Consecutive frames that pixelate to the same image are written
+//! once, with their delays added up, so the static stretches of a screen
+//! recording cost nothing; after the first, GIF and APNG frames hold only
+//! the box that changed since the frame before, drawn over it.
+
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::{
+    AnimationDecoder, Delay, DynamicImage, ExtendedColorType, Frame, ImageFormat, RgbaImage,
+};
+use rayon::prelude::*;
+use std::io::Cursor;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::limits::{self, SizeLimits};
+use super::{
+    decode_image, elapsed_ms, encode_png_frames, metadata, plan, png_options, read_input_within,
+    transform, LowresConfig, LowresError, ProcessReport, Region, Stage, Timings,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// The frames of an animation, composited to full size, and how many times
+/// it plays (0 for forever).
+pub struct Animation {
+    pub frames: Vec<Frame>,
+    pub plays: u32,
+}
+
+/// Containers an animation is written in.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum AnimatedFormat {
+    Gif,
+    /// Animated PNG: full alpha, and the DPI and settings chunks of a still
+    /// PNG output.
+    Apng,
+    /// Lossless WebP, animated if the source is.
+    Webp,
+}
+
+impl AnimatedFormat {
+    /// The animated format `output`'s extension names, if any.
+    pub fn of(output: &Path) -> Option<Self> {
+        let ext = output.extension()?.to_string_lossy().to_ascii_lowercase();
+        match ext.as_str() {
+            "gif" => Some(AnimatedFormat::Gif),
+            "apng" => Some(AnimatedFormat::Apng),
+            "webp" => Some(AnimatedFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// The frames of an animated GIF, APNG or WebP source, or `None` for a still
+/// image.
+pub fn decode_animation(data: &[u8]) -> Result<Option<Animation>> {
+    match image::guess_format(data).ok() {
+        Some(ImageFormat::Gif) => {
+            let frames = GifDecoder::new(Cursor::new(data))?
+                .into_frames()
+                .collect_frames()?;
+            Ok(Some(Animation {
+                frames,
+                plays: gif_plays(data),
+            }))
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(data))?;
+            if !decoder.is_apng()? {
+                return Ok(None);
+            }
+            let frames = decoder.apng()?.into_frames().collect_frames()?;
+            Ok(Some(Animation {
+                frames,
+                plays: apng_plays(data),
+            }))
+        }
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(data))?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            let frames = decoder.into_frames().collect_frames()?;
+            Ok(Some(Animation {
+                frames,
+                plays: webp_plays(data),
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The play count of a GIF, from its NETSCAPE2.0 (or ANIMEXTS1.0) loop count:
+/// 0 loops forever, and `n` repeats the animation `n` times after the first
+/// play. Without the extension, a GIF plays once.
+fn gif_plays(data: &[u8]) -> u32 {
+    let found = [&b"NETSCAPE2.0"[..], b"ANIMEXTS1.0"].iter().find_map(|id| {
+        let at = data.windows(id.len()).position(|w| w == *id)? + id.len();
+        // Sub-block: length 3, id 1, loop count (little-endian u16).
+        match data.get(at..at + 4)? {
+            [3, 1, lo, hi] => Some(u16::from_le_bytes([*lo, *hi])),
+            _ => None,
+        }
+    });
+    match found {
+        Some(0) => 0,
+        Some(n) => n as u32 + 1,
+        None => 1,
+    }
+}
+
+/// The play count in an APNG's acTL chunk.
+fn apng_plays(data: &[u8]) -> u32 {
+    data.windows(4)
+        .position(|w| w == b"acTL")
+        .and_then(|at| data.get(at + 8..at + 12))
+        .map_or(0, |n| u32::from_be_bytes([n[0], n[1], n[2], n[3]]))
+}
+
+/// The loop count in an animated WebP's ANIM chunk, which counts plays.
+fn webp_plays(data: &[u8]) -> u32 {
+    // ANIM payload: background color (4 bytes), then the loop count (LE u16).
+    data.windows(4)
+        .position(|w| w == b"ANIM")
+        .and_then(|at| data.get(at + 12..at + 14))
+        .map_or(0, |n| u16::from_le_bytes([n[0], n[1]]) as u32)
+}
+
+fn gif_repeat(plays: u32) -> gif::Repeat {
+    match plays {
+        0 => gif::Repeat::Infinite,
+        n => gif::Repeat::Finite((n - 1).min(u16::MAX as u32) as u16),
+    }
+}
+
+/// The smallest box holding every pixel that differs between `prev` and
+/// `next`, rows of `w` pixels of `bpp` bytes; `None` when they're the same.
+pub fn changed_box(prev: &[u8], next: &[u8], w: u32, bpp: usize) -> Option<Region> {
+    let row_len = w as usize * bpp;
+    let mut found: Option<(usize, usize, usize, usize)> = None;
+    for (y, (a, b)) in prev
+        .chunks_exact(row_len)
+        .zip(next.chunks_exact(row_len))
+        .enumerate()
+    {
+        if a == b {
+            continue;
+        }
+        let differs = |x: &usize| a[x * bpp..(x + 1) * bpp] != b[x * bpp..(x + 1) * bpp];
+        let first = (0..w as usize).find(differs).unwrap_or(0);
+        let last = (0..w as usize).rev().find(differs).unwrap_or(first);
+        found = Some(match found {
+            Some((x0, x1, y0, _)) => (x0.min(first), x1.max(last), y0, y),
+            None => (first, last, y, y),
+        });
+    }
+    found.map(|(x0, x1, y0, y1)| Region {
+        x: x0 as u32,
+        y: y0 as u32,
+        width: (x1 - x0 + 1) as u32,
+        height: (y1 - y0 + 1) as u32,
+    })
+}
+
+/// The bytes of `area` in rows of `w` pixels of `bpp` bytes.
+pub fn crop_rows(data: &[u8], w: u32, bpp: usize, area: Region) -> Vec<u8> {
+    let row_len = w as usize * bpp;
+    let (start, len) = (area.x as usize * bpp, area.width as usize * bpp);
+    data.chunks_exact(row_len)
+        .skip(area.y as usize)
+        .take(area.height as usize)
+        .flat_map(|row| &row[start..start + len])
+        .copied()
+        .collect()
+}
+
+/// Encode `frames` as a GIF that plays `plays` times. In an opaque
+/// animation, each frame after the first is the box that changed, kept over
+/// the frames before it, with the box's unchanged pixels transparent so they
+/// compress to runs when the palette leaves room for it. Frames with
+/// transparency of their own would let what they cover show through, so they
+/// are written whole.
+fn encode_gif(frames: &[Frame], plays: u32) -> Result<Vec<u8>> {
+    let first = frames
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("No frames to encode"))?;
+    let (w, h) = first.buffer().dimensions();
+    let side = |n: u32| {
+        u16::try_from(n).map_err(|_| anyhow::anyhow!("GIF frames are at most 65535 pixels a side"))
+    };
+    let opaque = frames.iter().all(|f| f.buffer().pixels().all(|p| p[3] > 0));
+    let whole = Region {
+        x: 0,
+        y: 0,
+        width: w,
+        height: h,
+    };
+
+    let mut out = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut out, side(w)?, side(h)?, &[])?;
+        encoder.set_repeat(gif_repeat(plays))?;
+        let mut prev: Option<&RgbaImage> = None;
+        for frame in frames {
+            let buffer = frame.buffer();
+            let area = match prev.filter(|_| opaque) {
+                Some(prev) => changed_box(prev.as_raw(), buffer.as_raw(), w, 4).unwrap_or(Region {
+                    width: 1,
+                    height: 1,
+                    ..whole
+                }),
+                None => whole,
+            };
+            let mut pixels = crop_rows(buffer.as_raw(), w, 4, area);
+            if let Some(prev) = prev.filter(|_| opaque) {
+                let before = crop_rows(prev.as_raw(), w, 4, area);
+                // Only an exact palette keeps transparency apart from the
+                // colors; past 256, quantizing could make some of them clear.
+                let mut colors = std::collections::HashSet::new();
+                let exact = pixels
+                    .chunks_exact(4)
+                    .zip(before.chunks_exact(4))
+                    .filter(|(p, b)| p != b)
+                    .all(|(p, _)| {
+                        colors.insert([p[0], p[1], p[2]]);
+                        colors.len() < 256
+                    });
+                if exact {
+                    for (p, b) in pixels.chunks_exact_mut(4).zip(before.chunks_exact(4)) {
+                        if p == b {
+                            p.fill(0);
+                        }
+                    }
+                }
+            }
+            let mut gif_frame =
+                gif::Frame::from_rgba_speed(side(area.width)?, side(area.height)?, &mut pixels, 10);
+            gif_frame.left = area.x as u16;
+            gif_frame.top = area.y as u16;
+            let ms = Duration::from(frame.delay()).as_millis();
+            gif_frame.delay = (ms / 10).min(u16::MAX as u128) as u16;
+            gif_frame.dispose = if opaque {
+                gif::DisposalMethod::Keep
+            } else {
+                gif::DisposalMethod::Background
+            };
+            encoder.write_frame(&gif_frame)?;
+            prev = Some(buffer);
+        }
+    }
+    Ok(out)
+}
+
+/// A frame delay as APNG stores it, in seconds as a fraction: milliseconds,
+/// or hundredths past the 65 seconds those reach.
+fn apng_delay(frame: &Frame) -> (u16, u16) {
+    let ms = Duration::from(frame.delay()).as_millis();
+    match u16::try_from(ms) {
+        Ok(ms) => (ms, 1000),
+        Err(_) => ((ms / 10).min(u16::MAX as u128) as u16, 100),
+    }
+}
+
+/// `frames` with each one that differs from the last one kept by at most
+/// `tolerance` in every channel folded into it, its delay added on.
+fn merge_repeated_frames(frames: Vec<Frame>, tolerance: u8) -> Vec<Frame> {
+    let mut kept: Vec<(RgbaImage, Duration)> = Vec::with_capacity(frames.len());
+    for frame in frames {
+        let delay = Duration::from(frame.delay());
+        let buffer = frame.into_buffer();
+        match kept.last_mut() {
+            Some((last, total))
+                if last.dimensions() == buffer.dimensions()
+                    && last
+                        .as_raw()
+                        .iter()
+                        .zip(buffer.as_raw())
+                        .all(|(a, b)| a.abs_diff(*b) <= tolerance) =>
+            {
+                *total += delay;
+            }
+            _ => kept.push((buffer, delay)),
+        }
+    }
+    kept.into_iter()
+        .map(|(buffer, delay)| {
+            Frame::from_parts(buffer, 0, 0, Delay::from_saturating_duration(delay))
+        })
+        .collect()
+}
+
+/// Append a RIFF chunk, padded to an even length.
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Encode `frames` as a lossless WebP: animated with `plays`, or the first
+/// frame as a still image without.
+fn encode_webp(frames: &[Frame], plays: Option<u32>) -> Result<Vec<u8>> {
+    let encode = |frame: &Frame| -> Result<Vec<u8>> {
+        let buffer = frame.buffer();
+        let mut out = Vec::new();
+        WebPEncoder::new_lossless(&mut out).encode(
+            buffer.as_raw(),
+            buffer.width(),
+            buffer.height(),
+            ExtendedColorType::Rgba8,
+        )?;
+        Ok(out)
+    };
+    let (Some(plays), Some(first)) = (plays, frames.first()) else {
+        return match frames.first() {
+            Some(frame) => encode(frame),
+            None => Err(anyhow::anyhow!("No frames to encode")),
+        };
+    };
+
+    let (w, h) = first.buffer().dimensions();
+    let u24 = |n: u32| -> [u8; 3] {
+        let [a, b, c, _] = n.to_le_bytes();
+        [a, b, c]
+    };
+    let mut chunks = Vec::new();
+    let mut vp8x = vec![0x10 | 0x02, 0, 0, 0]; // alpha, animation
+    vp8x.extend_from_slice(&u24(w - 1));
+    vp8x.extend_from_slice(&u24(h - 1));
+    write_chunk(&mut chunks, b"VP8X", &vp8x);
+    let mut anim = vec![0; 4]; // transparent background
+    anim.extend_from_slice(&(plays.min(u16::MAX as u32) as u16).to_le_bytes());
+    write_chunk(&mut chunks, b"ANIM", &anim);
+    for frame in frames {
+        // A still WebP from the encoder: RIFF header, then its VP8L chunk.
+        let still = encode(frame)?;
+        let ms = Duration::from(frame.delay()).as_millis().min(0xFF_FFFF) as u32;
+        let mut anmf = Vec::new();
+        anmf.extend_from_slice(&[0; 6]); // x and y offset
+        anmf.extend_from_slice(&u24(w - 1));
+        anmf.extend_from_slice(&u24(h - 1));
+        anmf.extend_from_slice(&u24(ms));
+        anmf.push(0x02); // replace rather than blend, no disposal
+        anmf.extend_from_slice(&still[12..]);
+        write_chunk(&mut chunks, b"ANMF", &anmf);
+    }
+    let mut out = Vec::with_capacity(12 + chunks.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(4 + chunks.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend_from_slice(&chunks);
+    Ok(out)
+}
+
+/// Run the pipeline on every frame of `input` and encode it as a `format`
+/// animation. A still source gives a one-frame GIF or APNG, or a still WebP.
+/// GIF and WebP have no DPI, so their report's is `None`.
+pub fn render_animation(
+    input: &Path,
+    format: AnimatedFormat,
+    config: LowresConfig,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<(Vec<u8>, ProcessReport)> {
+    render_animation_within(input, format, config, &limits::current(), on_stage)
+}
+
+/// `render_animation`, refusing an input or output over `limits`.
+fn render_animation_within(
+    input: &Path,
+    format: AnimatedFormat,
+    config: LowresConfig,
+    limits: &SizeLimits,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<(Vec<u8>, ProcessReport)> {
+    let invalid = |message: &str| -> Result<(Vec<u8>, ProcessReport)> {
+        Err(LowresError::InvalidConfig(message.into()).into())
+    };
+    if config.max_bytes.is_some() || config.email_safe == Some(true) {
+        return invalid("max_bytes and email_safe apply to still PNG output only");
+    }
+    if config.matte == Some(true) || config.sidecar == Some(true) || config.sizes.is_some() {
+        return invalid("matte, sidecar and sizes apply to still PNG output only");
+    }
+    let config = config.resolve_presets();
+
+    on_stage(Stage::Decode);
+    let started = Instant::now();
+    let data = read_input_within(input, limits)?;
+    let source_dpi = metadata::read_source_facts(&mut Cursor::new(&data)).dpi;
+    // Every frame is decoded at the canvas size, so check it before any is.
+    if let Ok((w, h)) = image::ImageReader::new(Cursor::new(&data))
+        .with_guessed_format()?
+        .into_dimensions()
+    {
+        limits.check_input(w, h)?;
+    }
+    let (animation, animated) = match decode_animation(&data)? {
+        Some(animation) => (animation, true),
+        None => {
+            let still = Animation {
+                frames: vec![Frame::new(decode_image(&data)?.to_rgba8())],
+                plays: 1,
+            };
+            (still, false)
+        }
+    };
+    let mut timings = Timings {
+        decode_ms: elapsed_ms(started),
+        ..Default::default()
+    };
+    let (orig_w, orig_h) = animation
+        .frames
+        .first()
+        .map(|f| f.buffer().dimensions())
+        .unwrap_or_default();
+    if limits.max_output_megapixels.is_some() {
+        let plan = plan::plan_for((orig_w, orig_h), source_dpi, &config)?;
+        limits.check_output(plan.width, plan.height)?;
+    }
+    let config = config.resolve_percentages((orig_w, orig_h));
+
+    on_stage(Stage::Transform);
+    let started = Instant::now();
+    let quantize = config.quantize()?;
+    let reference = config.color_reference()?;
+    // The config's DPI, else the source's, else 300.
+    let dpi = config.dpi.or(source_dpi).unwrap_or(300);
+    let frames = animation
+        .frames
+        .into_par_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let img = DynamicImage::ImageRgba8(frame.into_buffer());
+            let img = match &config.crop {
+                Some(crop) => crop.crop(&img)?,
+                None => img,
+            };
+            let mut frame_timings = Timings::default();
+            let rgba = transform(
+                &img,
+                &config,
+                quantize.as_ref(),
+                reference.as_ref(),
+                dpi,
+                &mut frame_timings,
+            )?;
+            Ok((
+                Frame::from_parts(rgba, 0, 0, delay),
+                frame_timings.quantize_ms,
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    timings.quantize_ms = frames.iter().map(|(_, ms)| ms).sum();
+    timings.transform_ms = elapsed_ms(started) - timings.quantize_ms;
+    let frames = merge_repeated_frames(
+        frames.into_iter().map(|(f, _)| f).collect(),
+        config.frame_tolerance.unwrap_or(0),
+    );
+    let (width, height) = frames
+        .first()
+        .map(|f| f.buffer().dimensions())
+        .unwrap_or_default();
+
+    on_stage(Stage::Encode);
+    let started = Instant::now();
+    let (encoded, dpi) = match format {
+        AnimatedFormat::Gif => (encode_gif(&frames, animation.plays)?, None),
+        AnimatedFormat::Apng => {
+            let opts = png_options(&config, dpi, None)?;
+            let data: Vec<_> = frames
+                .iter()
+                .map(|f| (f.buffer().as_raw().as_slice(), apng_delay(f)))
+                .collect();
+            let encoded = encode_png_frames(
+                (width, height),
+                png::ColorType::Rgba,
+                &data,
+                Some(animation.plays),
+                &opts,
+            )?;
+            (encoded, opts.dpi)
+        }
+        AnimatedFormat::Webp => (
+            encode_webp(&frames, animated.then_some(animation.plays))?,
+            None,
+        ),
+    };
+    timings.encode_ms = elapsed_ms(started);
+
+    let report = ProcessReport {
+        original_width: orig_w,
+        original_height: orig_h,
+        width,
+        height,
+        bytes: encoded.len() as u64,
+        dpi,
+        timings,
+        matte: None,
+        sidecar: None,
+        banding: None,
+        legibility: None,
+    };
+    Ok((encoded, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::gif::{GifEncoder, Repeat};
+    use image::Rgba;
+
+    #[test]
+    fn pixelates_every_frame_keeping_delays_and_loops() {
+        let frame = |color: [u8; 4], ms| {
+            let img = RgbaImage::from_fn(16, 16, |x, _| {
+                if x < 8 {
+                    Rgba(color)
+                } else {
+                    Rgba([255, 255, 255, 255])
+                }
+            });
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(ms, 1))
+        };
+        let mut gif = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut gif);
+            encoder.set_repeat(Repeat::Finite(3)).unwrap();
+            encoder
+                .encode_frames([frame([255, 0, 0, 255], 100), frame([0, 0, 255, 255], 250)])
+                .unwrap();
+        }
+        let input = std::env::temp_dir().join("lowres_animation_test.gif");
+        std::fs::write(&input, &gif).unwrap();
+        let config = LowresConfig {
+            width: Some(4),
+            height: Some(4),
+            ..Default::default()
+        };
+        let outputs: Vec<_> = [
+            AnimatedFormat::Gif,
+            AnimatedFormat::Apng,
+            AnimatedFormat::Webp,
+        ]
+        .into_iter()
+        .map(|format| render_animation(&input, format, config.clone(), &mut |_| {}))
+        .collect();
+        std::fs::remove_file(&input).unwrap();
+
+        for rendered in outputs {
+            let (out, report) = rendered.unwrap();
+            assert_eq!((report.width, report.height), (4, 4));
+            let animation = decode_animation(&out).unwrap().unwrap();
+            assert_eq!(animation.plays, 4);
+            let delays: Vec<_> = animation
+                .frames
+                .iter()
+                .map(|f| Duration::from(f.delay()).as_millis())
+                .collect();
+            assert_eq!(delays, [100, 250]);
+            assert_eq!(animation.frames[1].buffer().dimensions(), (4, 4));
+            assert_eq!(animation.frames[1].buffer().get_pixel(0, 0)[2], 255);
+        }
+    }
+
+    #[test]
+    fn source_dpi_is_the_default() {
+        let dir = std::env::temp_dir().join("lowres_animation_dpi_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (untagged, input) = (dir.join("untagged.png"), dir.join("72dpi.png"));
+        RgbaImage::from_pixel(8, 8, Rgba([10, 20, 30, 255]))
+            .save(&untagged)
+            .unwrap();
+        super::super::retag_dpi(&untagged, &input, 72).unwrap();
+        let render = |dpi: Option<u32>| {
+            let config = LowresConfig {
+                dpi,
+                print_width: Some("1in".parse().unwrap()),
+                ..Default::default()
+            };
+            let (_, report) =
+                render_animation(&input, AnimatedFormat::Apng, config, &mut |_| {}).unwrap();
+            (report.width, report.dpi)
+        };
+        let results = (render(None), render(Some(150)));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(results, ((72, Some(72)), (150, Some(150))));
+    }
+
+    #[test]
+    fn merges_repeated_frames_into_longer_delays() {
+        let frame = |value: u8, ms| {
+            let img = RgbaImage::from_pixel(4, 4, Rgba([value, value, value, 255]));
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(ms, 1))
+        };
+        let frames = || {
+            vec![
+                frame(10, 100),
+                frame(10, 100),
+                frame(12, 50),
+                frame(200, 40),
+            ]
+        };
+        let delays = |frames: Vec<Frame>| -> Vec<u128> {
+            frames
+                .iter()
+                .map(|f| Duration::from(f.delay()).as_millis())
+                .collect()
+        };
+        assert_eq!(delays(merge_repeated_frames(frames(), 0)), [200, 50, 40]);
+        assert_eq!(delays(merge_repeated_frames(frames(), 2)), [250, 40]);
+
+        let long = frame(0, 90_000);
+        assert_eq!(apng_delay(&long), (9000, 100));
+    }
+
+    #[test]
+    fn writes_only_the_changed_box_after_the_first_frame() {
+        let base = RgbaImage::from_fn(32, 24, |x, y| {
+            Rgba([(x / 8 * 60) as u8, (y / 8 * 80) as u8, 90, 255])
+        });
+        let mut moved = base.clone();
+        for (x, y) in (10..14).flat_map(|x| (5..9).map(move |y| (x, y))) {
+            moved.put_pixel(x, y, Rgba([255, 255, 0, 255]));
+        }
+        let mut cleared = moved.clone();
+        cleared.put_pixel(30, 20, Rgba([0, 0, 0, 0]));
+        let buffers = [base, moved, cleared];
+        let frames: Vec<Frame> = buffers
+            .iter()
+            .map(|b| Frame::from_parts(b.clone(), 0, 0, Delay::from_numer_denom_ms(100, 1)))
+            .collect();
+
+        let raw: Vec<_> = frames
+            .iter()
+            .map(|f| (f.buffer().as_raw().as_slice(), apng_delay(f)))
+            .collect();
+        let opts = png_options(&LowresConfig::default(), 72, None).unwrap();
+        let apng = encode_png_frames((32, 24), png::ColorType::Rgba, &raw, Some(0), &opts).unwrap();
+        let mut reader = png::Decoder::new(Cursor::new(&apng)).read_info().unwrap();
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let sizes: Vec<_> = (0..3)
+            .map(|_| {
+                let info = reader.next_frame(&mut buf).unwrap();
+                (info.width, info.height)
+            })
+            .collect();
+        assert_eq!(sizes, [(32, 24), (4, 4), (1, 1)]);
+
+        // GIF transparency is all or nothing, so only a frame with a fully
+        // clear pixel makes it write every frame whole: a delta's clear
+        // pixels show the frame before through.
+        let gifs = [
+            encode_gif(&frames[..2], 0).unwrap(),
+            encode_gif(&frames, 0).unwrap(),
+        ];
+        let gif_sizes = |gif: &[u8]| {
+            let mut decoder = gif::DecodeOptions::new()
+                .read_info(Cursor::new(gif))
+                .unwrap();
+            let mut sizes = Vec::new();
+            while let Some(frame) = decoder.read_next_frame().unwrap() {
+                sizes.push((frame.width, frame.height));
+            }
+            sizes
+        };
+        assert_eq!(gif_sizes(&gifs[0]), [(32, 24), (4, 4)]);
+        assert_eq!(gif_sizes(&gifs[1]), [(32, 24); 3]);
+        for (out, count) in [(apng, 3), (gifs[0].clone(), 2), (gifs[1].clone(), 3)] {
+            let decoded = decode_animation(&out).unwrap().unwrap();
+            assert_eq!(decoded.frames.len(), count);
+            for (frame, expected) in decoded.frames.iter().zip(&buffers) {
+                assert_eq!(frame.buffer(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn strips_source_metadata_from_every_animated_format() {
+        use super::super::tests::{carries_source_metadata, png_chunks, tagged_png};
+
+        let input = std::env::temp_dir().join("lowres_animation_strip_test.png");
+        std::fs::write(&input, tagged_png(2)).unwrap();
+        let config = LowresConfig {
+            width: Some(4),
+            strip_metadata: Some(true),
+            ..Default::default()
+        };
+        let outputs: Vec<_> = [
+            AnimatedFormat::Gif,
+            AnimatedFormat::Apng,
+            AnimatedFormat::Webp,
+        ]
+        .into_iter()
+        .map(|format| render_animation(&input, format, config.clone(), &mut |_| {}))
+        .collect();
+        std::fs::remove_file(&input).unwrap();
+
+        for rendered in outputs {
+            let (out, _) = rendered.unwrap();
+            assert!(!carries_source_metadata(&out));
+            if out.starts_with(b"\x89PNG") {
+                assert_eq!(
+                    png_chunks(&out),
+                    ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_animations_over_the_limits() {
+        let frames = [0u8, 255].map(|v| {
+            let img = RgbaImage::from_pixel(16, 16, Rgba([v, v, v, 255]));
+            Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1))
+        });
+        let input = std::env::temp_dir().join("lowres_animation_limits_test.gif");
+        std::fs::write(&input, encode_gif(&frames, 0).unwrap()).unwrap();
+        let config = LowresConfig {
+            width: Some(4),
+            height: Some(4),
+            ..Default::default()
+        };
+        let render = |limits: SizeLimits| {
+            render_animation_within(
+                &input,
+                AnimatedFormat::Gif,
+                config.clone(),
+                &limits,
+                &mut |_| {},
+            )
+        };
+        let over = [
+            SizeLimits {
+                max_file_bytes: Some(10),
+                ..Default::default()
+            },
+            SizeLimits {
+                max_input_megapixels: Some(0.0001),
+                ..Default::default()
+            },
+            SizeLimits {
+                max_output_megapixels: Some(0.00001),
+                ..Default::default()
+            },
+        ];
+        let results: Vec<_> = over.into_iter().map(render).collect();
+        assert!(render(SizeLimits::default()).is_ok());
+        std::fs::remove_file(&input).unwrap();
+
+        for result in results {
+            let error = LowresError::from(result.unwrap_err());
+            assert!(matches!(error, LowresError::TooLarge(_)), "{:?}", error);
+        }
+    }
+}
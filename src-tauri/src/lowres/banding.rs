@@ -0,0 +1,354 @@
+//! Banding controls for smooth gradients such as skies: posterize brightness
+//! into hard steps for a retro look, or smooth and dither gradients so that
+//! reducing them to few colors doesn't leave broken-looking contours. Outputs
+//! can also be checked for the staircase contours that banding leaves.
+
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use super::color::{from_ycbcr, to_ycbcr};
+use super::Region;
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Banding {
+    /// Snap brightness to a few hard steps, keeping each pixel's color.
+    Posterize,
+    /// Smooth shallow gradients and dither them ahead of color reduction.
+    Deband,
+}
+
+impl Display for Banding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Banding::Posterize => "posterize",
+            Banding::Deband => "deband",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Banding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "posterize" => Ok(Banding::Posterize),
+            "deband" => Ok(Banding::Deband),
+            other => Err(anyhow::anyhow!("Unknown banding {:?}", other)),
+        }
+    }
+}
+
+/// What checking an output for banding does when it finds some.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum BandingCheck {
+    /// Report where it shows.
+    Report,
+    /// Report it, and render again with ordered dithering when colors are
+    /// being reduced without any.
+    Dither,
+}
+
+impl Display for BandingCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BandingCheck::Report => "report",
+            BandingCheck::Dither => "dither",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for BandingCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "report" => Ok(BandingCheck::Report),
+            "dither" => Ok(BandingCheck::Dither),
+            other => Err(anyhow::anyhow!("Unknown banding check {:?}", other)),
+        }
+    }
+}
+
+/// What a banding check found.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BandingReport {
+    /// Where the first render showed banding, in output pixels.
+    pub regions: Vec<Region>,
+    /// Whether the output was rendered again with dithering to hide it.
+    pub dithered: bool,
+}
+
+/// Default number of brightness steps for `Banding::Posterize`.
+pub const DEFAULT_LEVELS: u32 = 8;
+
+/// Snap the luma of every pixel to one of `levels` evenly spaced steps.
+pub fn posterize_luma(img: &mut RgbaImage, levels: u32) {
+    let step = 255.0 / (levels.max(2) - 1) as f32;
+    img.par_chunks_exact_mut(4).for_each(|px| {
+        let p = Rgba([px[0], px[1], px[2], px[3]]);
+        let [y, cb, cr] = to_ycbcr(&p);
+        let out = from_ycbcr([(y / step).round() * step, cb, cr], p[3]);
+        px.copy_from_slice(&out.0);
+    });
+}
+
+/// Neighbors differing from the center by more than this on any channel are
+/// treated as an edge and left out of the smoothing.
+const EDGE_THRESHOLD: i32 = 8;
+const RADIUS: i64 = 2;
+
+/// 8×8 Bayer matrix, thresholds 0..64.
+const BAYER: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+/// Average each pixel with the similar pixels around it, which melts the
+/// one-level steps of an 8-bit gradient without softening edges, then add an
+/// ordered dither of `spread` levels (peak to peak) so a later color
+/// reduction mixes neighboring colors instead of drawing contours.
+pub fn deband(img: &mut RgbaImage, spread: f32) {
+    let src = img.clone();
+    let (w, h) = src.dimensions();
+    img.par_chunks_exact_mut((w * 4) as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let y = y as u32;
+            for x in 0..w {
+                let center = src.get_pixel(x, y);
+                let mut sum = [0u32; 3];
+                let mut n = 0;
+                for dy in -RADIUS..=RADIUS {
+                    for dx in -RADIUS..=RADIUS {
+                        let nx = (x as i64 + dx).clamp(0, w as i64 - 1) as u32;
+                        let ny = (y as i64 + dy).clamp(0, h as i64 - 1) as u32;
+                        let p = src.get_pixel(nx, ny);
+                        let similar = (0..3)
+                            .all(|c| (p[c] as i32 - center[c] as i32).abs() <= EDGE_THRESHOLD);
+                        if similar {
+                            for c in 0..3 {
+                                sum[c] += p[c] as u32;
+                            }
+                            n += 1;
+                        }
+                    }
+                }
+                let threshold = BAYER[(y % 8) as usize][(x % 8) as usize] as f32 / 64.0;
+                let offset = (threshold - 0.5) * spread;
+                let i = (x * 4) as usize;
+                for c in 0..3 {
+                    let v = sum[c] as f32 / n as f32 + offset;
+                    row[i + c] = v.round().clamp(0.0, 255.0) as u8;
+                }
+                row[i + 3] = center[3];
+            }
+        });
+}
+
+/// Luma steps between flat runs within this range read as the contours of a
+/// reduced gradient: one-level steps are an 8-bit gradient's own and don't
+/// show, and larger ones are edges in the picture.
+const CONTOUR_STEPS: std::ops::RangeInclusive<i32> = 2..=40;
+/// Contours in a row, all rising or all falling, that make a staircase.
+const STAIRCASE_CONTOURS: usize = 2;
+/// The narrowest flat run that counts as a band, in pixelation blocks, so
+/// neighboring blocks of one color aren't taken for banding.
+const BAND_BLOCKS: u32 = 3;
+const MIN_BAND: u32 = 6;
+/// Tiles along the longest edge that banding is reported in.
+const TILES: u32 = 8;
+/// Share of a tile's pixels on staircases that marks it as banded.
+const BANDED_SHARE: f32 = 0.4;
+
+/// Where `img` shows banding: tiles mostly covered by staircases of wide
+/// flat runs a few luma levels apart, across or down, with the banded tiles
+/// joined. `block` is the side of a pixelation block in
+/// output pixels, 1 when not pixelating.
+pub fn find_banding(img: &RgbaImage, block: u32) -> Vec<Region> {
+    let (w, h) = img.dimensions();
+    if w == 0 || h == 0 {
+        return Vec::new();
+    }
+    let band = (block.max(1) * BAND_BLOCKS).max(MIN_BAND) as usize;
+    let luma: Vec<i32> = img
+        .pixels()
+        .map(|p| to_ycbcr(p)[0].round() as i32)
+        .collect();
+    let (wu, hu) = (w as usize, h as usize);
+    let across: Vec<Vec<bool>> = luma
+        .par_chunks_exact(wu)
+        .map(|row| staircases(row, band))
+        .collect();
+    let down: Vec<Vec<bool>> = (0..wu)
+        .into_par_iter()
+        .map(|x| {
+            let column: Vec<i32> = (0..hu).map(|y| luma[y * wu + x]).collect();
+            staircases(&column, band)
+        })
+        .collect();
+
+    let tile = w.max(h).div_ceil(TILES).max(1);
+    let mut regions: Vec<Region> = Vec::new();
+    for ty in (0..h).step_by(tile as usize) {
+        let th = tile.min(h - ty);
+        let mut joined: Option<Region> = None;
+        for tx in (0..w).step_by(tile as usize) {
+            let tw = tile.min(w - tx);
+            let banded = (ty..ty + th)
+                .flat_map(|y| (tx..tx + tw).map(move |x| (x as usize, y as usize)))
+                .filter(|&(x, y)| across[y][x] || down[x][y])
+                .count();
+            if banded as f32 >= (tw * th) as f32 * BANDED_SHARE {
+                match &mut joined {
+                    Some(region) => region.width += tw,
+                    None => {
+                        joined = Some(Region {
+                            x: tx,
+                            y: ty,
+                            width: tw,
+                            height: th,
+                        })
+                    }
+                }
+            } else if let Some(region) = joined.take() {
+                join_below(&mut regions, region);
+            }
+        }
+        if let Some(region) = joined {
+            join_below(&mut regions, region);
+        }
+    }
+    regions
+}
+
+/// Add `region`, or grow the region just above it that has the same span.
+fn join_below(regions: &mut Vec<Region>, region: Region) {
+    match regions
+        .iter_mut()
+        .find(|r| r.x == region.x && r.width == region.width && r.y + r.height == region.y)
+    {
+        Some(above) => above.height += region.height,
+        None => regions.push(region),
+    }
+}
+
+/// Which values of `line` lie on a staircase of runs at least `band` long.
+fn staircases(line: &[i32], band: usize) -> Vec<bool> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for i in 1..=line.len() {
+        if i == line.len() || line[i] != line[start] {
+            runs.push(start..i);
+            start = i;
+        }
+    }
+    let mut marked = vec![false; line.len()];
+    let mut i = 0;
+    while i < runs.len() {
+        // Climb from run `i` while each step is a contour the same way.
+        let (mut j, mut direction) = (i, 0);
+        while j + 1 < runs.len() && runs[j].len() >= band && runs[j + 1].len() >= band {
+            let step = line[runs[j + 1].start] - line[runs[j].start];
+            if !CONTOUR_STEPS.contains(&step.abs()) || direction == -step.signum() {
+                break;
+            }
+            direction = step.signum();
+            j += 1;
+        }
+        if j - i >= STAIRCASE_CONTOURS {
+            marked[runs[i].start..runs[j].end].fill(true);
+        }
+        i = if j > i { j } else { i + 1 };
+    }
+    marked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A horizontal gray ramp from `from` to `to`.
+    fn ramp(from: u8, to: u8) -> RgbaImage {
+        RgbaImage::from_fn(64, 4, |x, _| {
+            let v = from as u32 + (to - from) as u32 * x / 63;
+            Rgba([v as u8, v as u8, v as u8, 255])
+        })
+    }
+
+    #[test]
+    fn posterizes_into_the_requested_steps() {
+        let mut img = ramp(0, 255);
+        posterize_luma(&mut img, 4);
+        let mut levels: Vec<u8> = (0..64).map(|x| img.get_pixel(x, 0)[0]).collect();
+        levels.dedup();
+        assert_eq!(levels, vec![0, 85, 170, 255]);
+    }
+
+    #[test]
+    fn debands_gradients_but_keeps_edges() {
+        // A shallow ramp: one-level steps spread over many pixels.
+        let mut img = ramp(100, 108);
+        deband(&mut img, 0.0);
+        let row: Vec<u8> = (0..64).map(|x| img.get_pixel(x, 1)[0]).collect();
+        assert!(row.windows(2).all(|w| w[1] >= w[0]));
+        assert!(row[0] >= 100 && row[63] <= 108);
+
+        let mut edge = RgbaImage::from_fn(8, 8, |x, _| {
+            let v = if x < 4 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        });
+        let before = edge.clone();
+        deband(&mut edge, 0.0);
+        assert_eq!(edge, before);
+    }
+
+    #[test]
+    fn finds_banding_in_reduced_gradients_only() {
+        // A wide ramp reduced to steps 10 levels apart, 16 pixels wide.
+        let bands = RgbaImage::from_fn(128, 32, |x, _| {
+            let v = 100 + (x / 16 * 10) as u8;
+            Rgba([v, v, v, 255])
+        });
+        assert_eq!(
+            find_banding(&bands, 1),
+            vec![Region {
+                x: 0,
+                y: 0,
+                width: 128,
+                height: 32
+            }]
+        );
+        // Too narrow to be more than the blocks of a pixelation.
+        assert!(find_banding(&bands, 8).is_empty());
+
+        // One hard edge, and a smooth 8-bit gradient, are not banding.
+        let edge = RgbaImage::from_fn(128, 32, |x, _| {
+            let v = if x < 64 { 20 } else { 220 };
+            Rgba([v, v, v, 255])
+        });
+        assert!(find_banding(&edge, 1).is_empty());
+        let smooth = RgbaImage::from_fn(128, 32, |x, _| {
+            let v = 100 + (x / 16) as u8;
+            Rgba([v, v, v, 255])
+        });
+        assert!(find_banding(&smooth, 1).is_empty());
+        assert_eq!(
+            "Dither".parse::<BandingCheck>().unwrap(),
+            BandingCheck::Dither
+        );
+    }
+}
@@ -0,0 +1,702 @@
+//! Processing many inputs in one run, with an explicit policy for outputs
+//! that already exist, and naming outputs from a template.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::{
+    load_source, palette, render_png, render_source, sidecar, write_output, LowresConfig,
+    LowresError, OutputSpec, PreviewQuality, ProcessReport,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// What to do when an output file already exists.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum OnCollision {
+    /// Replace the existing file.
+    Overwrite,
+    /// Leave the existing file alone and don't process the input.
+    Skip,
+    /// Write to the first free `<name>_1.png`, `<name>_2.png`, ….
+    #[serde(alias = "RenameWithSuffix")]
+    RenameIncrement,
+    /// Don't process the input and report it as failed.
+    Fail,
+}
+
+impl Display for OnCollision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            OnCollision::Overwrite => "overwrite",
+            OnCollision::Skip => "skip",
+            OnCollision::RenameIncrement => "rename-increment",
+            OnCollision::Fail => "fail",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for OnCollision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "overwrite" => Ok(OnCollision::Overwrite),
+            "skip" => Ok(OnCollision::Skip),
+            "rename-increment" | "rename-with-suffix" | "rename" => {
+                Ok(OnCollision::RenameIncrement)
+            }
+            "fail" => Ok(OnCollision::Fail),
+            other => Err(anyhow::anyhow!("Unknown collision policy {:?}", other)),
+        }
+    }
+}
+
+/// What was done about an input's output path.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize)]
+pub enum CollisionAction {
+    /// No file was in the way.
+    Created,
+    Overwrote,
+    Skipped,
+    /// Written under an incremented name instead; see `BatchItem::output`.
+    Renamed,
+    /// The file was in the way and the policy was `Fail`.
+    Refused,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct BatchItem {
+    pub input: PathBuf,
+    /// Where the output was written, or would have been.
+    pub output: PathBuf,
+    pub action: CollisionAction,
+    pub report: Option<ProcessReport>,
+    pub error: Option<LowresError>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BatchReport {
+    pub items: Vec<BatchItem>,
+    /// The palette file written for a `shared_palette` batch.
+    pub palette: Option<PathBuf>,
+}
+
+/// The report of a run with one output, such as a single image or icon.
+impl From<BatchItem> for BatchReport {
+    fn from(item: BatchItem) -> Self {
+        BatchReport {
+            items: vec![item],
+            palette: None,
+        }
+    }
+}
+
+impl BatchReport {
+    pub fn failed(&self) -> usize {
+        self.items.iter().filter(|i| i.error.is_some()).count()
+    }
+
+    /// Outputs written by this batch, leaving out skipped and failed inputs.
+    pub fn produced(&self) -> Vec<PathBuf> {
+        self.items
+            .iter()
+            .filter(|i| i.report.is_some())
+            .map(|i| i.output.clone())
+            .collect()
+    }
+
+    /// Totals over the batch, to see what a change of format or settings saved.
+    pub fn summary(&self) -> BatchSummary {
+        let done: Vec<(&BatchItem, &ProcessReport)> = self
+            .items
+            .iter()
+            .filter_map(|i| Some((i, i.report.as_ref()?)))
+            .collect();
+
+        // With `sizes` one input has several outputs; count it once and
+        // compare it with all of them together.
+        let mut per_input: BTreeMap<&Path, u64> = BTreeMap::new();
+        for (item, report) in &done {
+            *per_input.entry(&item.input).or_default() += report.bytes;
+        }
+        let mut input_bytes = 0;
+        let mut ratios = Vec::new();
+        let mut formats = BTreeMap::new();
+        for (input, output_bytes) in &per_input {
+            let format = input
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase())
+                .unwrap_or_default();
+            *formats.entry(format).or_default() += 1;
+            // An input deleted since it was processed only counts toward formats.
+            let Ok(meta) = std::fs::metadata(input) else {
+                continue;
+            };
+            input_bytes += meta.len();
+            if meta.len() > 0 {
+                ratios.push(*output_bytes as f64 / meta.len() as f64);
+            }
+        }
+
+        let mut slowest: Vec<SlowFile> = done
+            .iter()
+            .map(|(item, report)| {
+                let t = &report.timings;
+                SlowFile {
+                    input: item.input.clone(),
+                    ms: t.decode_ms + t.transform_ms + t.quantize_ms + t.encode_ms,
+                }
+            })
+            .collect();
+        slowest.sort_by(|a, b| b.ms.total_cmp(&a.ms));
+        slowest.truncate(SLOWEST_FILES);
+
+        BatchSummary {
+            processed: done.len(),
+            skipped: self
+                .items
+                .iter()
+                .filter(|i| i.error.is_none() && i.action == CollisionAction::Skipped)
+                .count(),
+            failed: self.failed(),
+            input_bytes,
+            output_bytes: done.iter().map(|(_, r)| r.bytes).sum(),
+            mean_ratio: (!ratios.is_empty())
+                .then(|| ratios.iter().sum::<f64>() / ratios.len() as f64),
+            formats,
+            slowest,
+        }
+    }
+}
+
+/// How many of the slowest files a `BatchSummary` lists.
+const SLOWEST_FILES: usize = 5;
+
+/// Aggregate statistics of a batch run.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BatchSummary {
+    /// Outputs written.
+    pub processed: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Size of the processed inputs together.
+    pub input_bytes: u64,
+    /// Size of the outputs together.
+    pub output_bytes: u64,
+    /// Average of each input's output size divided by its own size; below 1
+    /// means smaller. `None` if nothing was processed.
+    pub mean_ratio: Option<f64>,
+    /// Processed inputs by lowercase file extension.
+    pub formats: BTreeMap<String, usize>,
+    /// The slowest outputs to render, slowest first.
+    pub slowest: Vec<SlowFile>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SlowFile {
+    pub input: PathBuf,
+    /// Decode, transform, quantize and encode time together.
+    pub ms: f64,
+}
+
+/// File name of the palette a `shared_palette` batch writes beside its outputs.
+pub const SHARED_PALETTE_FILE: &str = "shared_palette.hex";
+
+/// Output file name when the config has no `output_template`.
+pub const DEFAULT_TEMPLATE: &str = "{stem}_lowres.{ext}";
+/// The tokens an output template may use.
+const TEMPLATE_TOKENS: [&str; 5] = ["stem", "ext", "block", "width", "height"];
+
+/// Check that `template` only uses known tokens and names a file, not a path.
+pub fn check_template(template: &str) -> Result<()> {
+    let invalid =
+        |message: String| -> Result<()> { Err(LowresError::InvalidConfig(message).into()) };
+    if template.trim().is_empty() {
+        return invalid("output_template is empty".into());
+    }
+    if template.contains(std::path::is_separator) {
+        return invalid(format!(
+            "output_template {:?} must be a file name; set output_dir for the directory",
+            template
+        ));
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(len) = rest[start..].find('}') else {
+            return invalid(format!("Unclosed {{ in output_template {:?}", template));
+        };
+        let token = &rest[start + 1..start + len];
+        if !TEMPLATE_TOKENS.contains(&token) {
+            return invalid(format!(
+                "Unknown token {{{}}} in output_template; use {}",
+                token,
+                TEMPLATE_TOKENS.map(|t| format!("{{{}}}", t)).join(", ")
+            ));
+        }
+        rest = &rest[start + len + 1..];
+    }
+    Ok(())
+}
+
+/// Fill in `template` for `input`. `size` is the output's width and height;
+/// without it, `None` if the template needs them.
+fn fill_template(
+    template: &str,
+    input: &Path,
+    config: &LowresConfig,
+    size: Option<(u32, u32)>,
+) -> Option<String> {
+    let needs_size = template.contains("{width}") || template.contains("{height}");
+    if needs_size && size.is_none() {
+        return None;
+    }
+    let (width, height) = size.unwrap_or_default();
+    let block = config
+        .block_size()
+        .filter(|_| !config.no_pixelate.unwrap_or(false))
+        .map_or("none".to_string(), |b| b.to_string());
+    Some(
+        template
+            .replace(
+                "{stem}",
+                &input.file_stem().unwrap_or_default().to_string_lossy(),
+            )
+            .replace("{ext}", "png")
+            .replace("{block}", &block)
+            .replace("{width}", &width.to_string())
+            .replace("{height}", &height.to_string()),
+    )
+}
+
+/// `out_dir`, or the directory of `input`.
+fn output_dir<'a>(input: &'a Path, out_dir: Option<&'a Path>) -> &'a Path {
+    out_dir
+        .or_else(|| input.parent())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Process `inputs` one after another (each image is already processed in
+/// parallel), applying `on_collision` to outputs that exist. Outputs are
+/// named by the config's `output_template` and go into `out_dir`, else the
+/// config's `output_dir`, else next to each input. Per-file failures are
+/// recorded in the report rather than stopping the batch.
+///
+/// With `shared_palette`, a first pass samples every input to build one
+/// palette, which is written to `SHARED_PALETTE_FILE` (replacing any there)
+/// and used as the palette file of every output.
+pub fn process_batch(
+    inputs: &[PathBuf],
+    out_dir: Option<&Path>,
+    config: &LowresConfig,
+    on_collision: OnCollision,
+) -> Result<BatchReport> {
+    let out_dir = out_dir.or(config.output_dir.as_deref());
+    if let Some(dir) = out_dir {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let mut report = BatchReport::default();
+    let shared;
+    let config = match (config.shared_palette, config.colors) {
+        (Some(true), Some(n)) => {
+            let colors = palette::shared_palette(inputs, n as usize);
+            let dir = out_dir
+                .or_else(|| inputs.first().and_then(|i| i.parent()))
+                .unwrap_or(Path::new("."));
+            let path = dir.join(SHARED_PALETTE_FILE);
+            palette::write_hex_list(&path, &colors)?;
+            report.palette = Some(path.clone());
+            shared = LowresConfig {
+                palette_file: Some(path),
+                colors: None,
+                shared_palette: None,
+                ..config.clone()
+            };
+            &shared
+        }
+        _ => config,
+    };
+    for input in inputs {
+        report
+            .items
+            .push(process_into(input, out_dir, config, on_collision));
+    }
+    Ok(report)
+}
+
+/// Process one `input` as `process_batch` would.
+pub fn process_into(
+    input: &Path,
+    out_dir: Option<&Path>,
+    config: &LowresConfig,
+    on_collision: OnCollision,
+) -> BatchItem {
+    if let Some(wanted) = planned_output(input, out_dir, config, None) {
+        return process_item(input.to_path_buf(), wanted, config.clone(), on_collision);
+    }
+    let dir = output_dir(input, out_dir);
+    let template = config
+        .output_template
+        .as_deref()
+        .unwrap_or(DEFAULT_TEMPLATE);
+
+    // The name needs the output size, so render before deciding where it goes.
+    let item = BatchItem {
+        input: input.to_path_buf(),
+        output: dir.to_path_buf(),
+        action: CollisionAction::Created,
+        report: None,
+        error: None,
+    };
+    let (encoded, report) = match render_png(&item.input, config.clone(), &mut |_| {}) {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            return BatchItem {
+                error: Some(e.into()),
+                ..item
+            }
+        }
+    };
+    let name = fill_template(template, input, config, Some((report.width, report.height)));
+    let wanted = dir.join(name.unwrap_or_default());
+    write_item(item, &wanted, on_collision, || Ok((encoded, report)))
+}
+
+/// Where `process_into` writes the output of `input` before applying a
+/// collision policy, or `None` if its name needs the output size, which is
+/// only known once rendered, and `size` doesn't give it.
+pub fn planned_output(
+    input: &Path,
+    out_dir: Option<&Path>,
+    config: &LowresConfig,
+    size: Option<(u32, u32)>,
+) -> Option<PathBuf> {
+    let template = config
+        .output_template
+        .as_deref()
+        .unwrap_or(DEFAULT_TEMPLATE);
+    fill_template(template, input, config, size).map(|name| output_dir(input, out_dir).join(name))
+}
+
+/// Render `input` once per entry of `config.sizes`, decoding it only once.
+/// Each size is written next to `output` with `_<size>` added to its stem:
+/// `icon.png` gives `icon_16.png`, `icon_32.png`, ….
+pub fn process_sizes(
+    input: &Path,
+    output: &Path,
+    config: &LowresConfig,
+    on_collision: OnCollision,
+) -> Result<BatchReport> {
+    let sizes = config.sizes.clone().unwrap_or_default();
+    let source = load_source(&input.to_path_buf())?;
+    let items = sizes
+        .into_iter()
+        .map(|size| {
+            let config = LowresConfig {
+                width: Some(size.width),
+                height: Some(size.height),
+                sizes: None,
+                ..config.clone()
+            };
+            let item = BatchItem {
+                input: input.to_path_buf(),
+                output: output.to_path_buf(),
+                action: CollisionAction::Created,
+                report: None,
+                error: None,
+            };
+            write_item(item, &sized_output(output, size), on_collision, || {
+                let (encoded, mut report) =
+                    render_source(&source, config, PreviewQuality::Full, &mut |_| {})?;
+                sidecar::attach_source(&mut report, input)?;
+                Ok((encoded, report))
+            })
+        })
+        .collect();
+    Ok(BatchReport {
+        items,
+        ..Default::default()
+    })
+}
+
+/// `output` with `_<size>` added to its stem.
+fn sized_output(output: &Path, size: OutputSpec) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    match output.extension() {
+        Some(ext) => output.with_file_name(format!("{}_{}.{}", stem, size, ext.to_string_lossy())),
+        None => output.with_file_name(format!("{}_{}", stem, size)),
+    }
+}
+
+/// Claim `wanted` for `item` under `on_collision`, then render and write it
+/// unless the policy says not to.
+pub fn write_item(
+    mut item: BatchItem,
+    wanted: &Path,
+    on_collision: OnCollision,
+    render: impl FnOnce() -> Result<(Vec<u8>, ProcessReport)>,
+) -> BatchItem {
+    (item.output, item.action) = claim_output(wanted, on_collision);
+    match item.action {
+        CollisionAction::Skipped => {}
+        CollisionAction::Refused => {
+            item.error = Some(LowresError::Io(format!(
+                "Output {:?} already exists",
+                item.output
+            )));
+        }
+        _ => match render() {
+            Ok((encoded, report)) => match write_output(&item.output, &encoded, &report) {
+                Ok(()) => item.report = Some(report),
+                Err(e) => item.error = Some(e.into()),
+            },
+            Err(e) => item.error = Some(e.into()),
+        },
+    }
+    item
+}
+
+/// Process each input into its wanted output with its own config, applying
+/// `on_collision`.
+pub fn process_pairs(
+    pairs: impl Iterator<Item = (PathBuf, PathBuf, LowresConfig)>,
+    on_collision: OnCollision,
+) -> BatchReport {
+    BatchReport {
+        items: pairs
+            .map(|(input, wanted, config)| process_item(input, wanted, config, on_collision))
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn process_item(
+    input: PathBuf,
+    wanted: PathBuf,
+    config: LowresConfig,
+    on_collision: OnCollision,
+) -> BatchItem {
+    let item = BatchItem {
+        input,
+        output: wanted.clone(),
+        action: CollisionAction::Created,
+        report: None,
+        error: None,
+    };
+    let input = item.input.clone();
+    write_item(item, &wanted, on_collision, || {
+        render_png(&input, config, &mut |_| {})
+    })
+}
+
+/// Where to write an output wanted at `wanted` under `policy`, and what that
+/// does to an existing file. Nothing is written yet.
+pub fn claim_output(wanted: &Path, policy: OnCollision) -> (PathBuf, CollisionAction) {
+    resolve_collision(wanted, policy, |p| p.exists())
+}
+
+/// Decide where to write given that `exists` reports which paths are taken.
+fn resolve_collision(
+    wanted: &Path,
+    policy: OnCollision,
+    exists: impl Fn(&Path) -> bool,
+) -> (PathBuf, CollisionAction) {
+    if !exists(wanted) {
+        return (wanted.to_path_buf(), CollisionAction::Created);
+    }
+    match policy {
+        OnCollision::Overwrite => (wanted.to_path_buf(), CollisionAction::Overwrote),
+        OnCollision::Skip => (wanted.to_path_buf(), CollisionAction::Skipped),
+        OnCollision::Fail => (wanted.to_path_buf(), CollisionAction::Refused),
+        OnCollision::RenameIncrement => {
+            let stem = wanted.file_stem().unwrap_or_default().to_string_lossy();
+            let ext = wanted.extension().unwrap_or_default().to_string_lossy();
+            let renamed = (1u32..)
+                .map(|n| wanted.with_file_name(format!("{}_{}.{}", stem, n, ext)))
+                .find(|p| !exists(p))
+                .unwrap_or_else(|| wanted.to_path_buf());
+            (renamed, CollisionAction::Renamed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collision_policies_and_templates() {
+        let input = Path::new("in/photo.jpg");
+        let default_name = fill_template(DEFAULT_TEMPLATE, input, &LowresConfig::default(), None);
+        let wanted = output_dir(input, Some(Path::new("out"))).join(default_name.unwrap());
+        assert_eq!(wanted, Path::new("out/photo_lowres.png"));
+        let config = LowresConfig {
+            block: Some(8),
+            ..Default::default()
+        };
+        let named = |size| {
+            fill_template(
+                "{stem}_{width}x{height}_{block}.{ext}",
+                Path::new("in/photo.jpg"),
+                &config,
+                size,
+            )
+        };
+        assert_eq!(named(None), None);
+        assert_eq!(named(Some((64, 48))).as_deref(), Some("photo_64x48_8.png"));
+        assert!(check_template("{stem}_{block}.{ext}").is_ok());
+        assert!(check_template("{stem}_{size}.png").is_err());
+        assert!(check_template("sub/{stem}.png").is_err());
+        assert_eq!(
+            "rename-with-suffix".parse::<OnCollision>().unwrap(),
+            OnCollision::RenameIncrement
+        );
+
+        let taken = [wanted.clone(), PathBuf::from("out/photo_lowres_1.png")];
+        let exists = |p: &Path| taken.iter().any(|t| t == p);
+        let resolve = |policy| resolve_collision(&wanted, policy, exists);
+
+        assert_eq!(
+            resolve(OnCollision::Overwrite),
+            (wanted.clone(), CollisionAction::Overwrote)
+        );
+        assert_eq!(resolve(OnCollision::Skip).1, CollisionAction::Skipped);
+        assert_eq!(resolve(OnCollision::Fail).1, CollisionAction::Refused);
+        assert_eq!(
+            resolve(OnCollision::RenameIncrement),
+            (
+                PathBuf::from("out/photo_lowres_2.png"),
+                CollisionAction::Renamed
+            )
+        );
+        assert_eq!(
+            resolve_collision(Path::new("new.png"), OnCollision::Fail, exists).1,
+            CollisionAction::Created
+        );
+    }
+
+    #[test]
+    fn shares_one_palette_across_a_batch() {
+        let dir = std::env::temp_dir().join("lowres_shared_palette_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let inputs = [
+            ([220, 30, 30, 255], "red.png"),
+            ([30, 30, 220, 255], "blue.png"),
+        ]
+        .map(|(color, name)| {
+            let path = dir.join(name);
+            image::RgbaImage::from_fn(8, 8, |x, _| {
+                image::Rgba(if x < 4 { color } else { [250, 250, 250, 255] })
+            })
+            .save(&path)
+            .unwrap();
+            path
+        });
+        let config = LowresConfig {
+            no_resize: Some(true),
+            colors: Some(3),
+            shared_palette: Some(true),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        let report = process_batch(
+            &inputs,
+            Some(&dir.join("out")),
+            &config,
+            OnCollision::Overwrite,
+        )
+        .unwrap();
+        let palette = palette::load_palette_file(report.palette.as_ref().unwrap()).unwrap();
+        let outputs: Vec<_> = report
+            .produced()
+            .iter()
+            .map(|p| image::open(p).unwrap().to_rgb8())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(palette.len(), 3);
+        assert_eq!(outputs.len(), 2);
+        for output in outputs {
+            assert!(output.pixels().all(|p| palette.contains(&p.0)));
+        }
+        let without_colors = LowresConfig {
+            colors: None,
+            ..config
+        };
+        assert!(without_colors.validate().is_err());
+    }
+
+    #[test]
+    fn exports_every_size_from_one_decode() {
+        let dir = std::env::temp_dir().join("lowres_sizes_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("logo.png");
+        image::RgbaImage::from_pixel(40, 30, image::Rgba([200, 40, 40, 255]))
+            .save(&input)
+            .unwrap();
+        let config = LowresConfig {
+            sizes: Some(vec!["16".parse().unwrap(), "64x32".parse().unwrap()]),
+            mode: Some(super::super::ResizeMode::Exact),
+            ..Default::default()
+        };
+        config.validate().unwrap();
+        let report = process_sizes(
+            &input,
+            &dir.join("icon.png"),
+            &config,
+            OnCollision::Overwrite,
+        )
+        .unwrap();
+        let written: Vec<_> = report
+            .items
+            .iter()
+            .map(|i| {
+                let r = i.report.as_ref().unwrap();
+                (i.output.file_name().unwrap().to_owned(), r.width, r.height)
+            })
+            .collect();
+        // Both sizes are weighed against the one input they came from.
+        let summary = report.summary();
+        let input_bytes = std::fs::metadata(&input).unwrap().len();
+        let output_bytes: u64 = report
+            .items
+            .iter()
+            .map(|i| i.report.as_ref().unwrap().bytes)
+            .sum();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            (summary.processed, summary.skipped, summary.failed),
+            (2, 0, 0)
+        );
+        assert_eq!(
+            (summary.input_bytes, summary.output_bytes),
+            (input_bytes, output_bytes)
+        );
+        assert_eq!(
+            summary.mean_ratio,
+            Some(output_bytes as f64 / input_bytes as f64)
+        );
+        assert_eq!(summary.formats, BTreeMap::from([("png".to_string(), 1)]));
+        assert_eq!(summary.slowest.len(), 2);
+        assert!(summary.slowest[0].ms >= summary.slowest[1].ms);
+        assert_eq!(
+            written,
+            [
+                ("icon_16.png".into(), 16, 16),
+                ("icon_64x32.png".into(), 64, 32)
+            ]
+        );
+
+        let with_width = LowresConfig {
+            width: Some(8),
+            ..config
+        };
+        assert!(with_width.validate().is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! What the running backend can do, so frontends can hide options this build
+//! lacks instead of letting them fail at run time.
+
+use serde::Serialize;
+
+use super::limits::{self, SizeLimits};
+use super::{
+    codecs, gpu_available, input_extensions, remote, upscale, ColorMatch, Dither, Guide, Palette,
+    Style, Upscaler, MAX_DIMENSION, PROXY_EDGE,
+};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Capabilities {
+    /// Version of the lowres build.
+    pub lowres: String,
+    /// Extensions of the files read as input.
+    pub input_extensions: Vec<&'static str>,
+    /// Extensions of the outputs written; any other output is a PNG.
+    pub output_extensions: Vec<&'static str>,
+    pub features: Features,
+    pub effects: Effects,
+    pub acceleration: Acceleration,
+    pub limits: Limits,
+}
+
+/// The optional Cargo features this build has, and the optional codecs found.
+#[derive(Serialize, Debug, Clone)]
+pub struct Features {
+    /// libheif is linked in.
+    pub heif: bool,
+    pub raw: bool,
+    /// `auto_mask`.
+    pub segmentation: bool,
+    /// `legibility_guard`.
+    pub ocr: bool,
+    /// `backend: Gpu` has an adapter to run on; needs the `gpu` feature.
+    pub gpu: bool,
+    /// JPEGs for small outputs are decoded at a reduced scale.
+    pub dct_scaling: bool,
+    /// `--upload` to S3 or over HTTP PUT.
+    pub upload: bool,
+    /// The image crate decodes AVIF itself.
+    pub avif: bool,
+    /// An ffmpeg runs on this machine, reading video, and AVIF and HEIC
+    /// where nothing built in does.
+    pub ffmpeg: bool,
+}
+
+/// The values the config's effect fields accept, as they are serialized.
+#[derive(Serialize, Debug, Clone)]
+pub struct Effects {
+    pub palettes: Vec<Palette>,
+    pub dithers: Vec<Dither>,
+    pub styles: Vec<Style>,
+    pub upscalers: Vec<Upscaler>,
+    pub match_methods: Vec<ColorMatch>,
+    pub guides: Vec<Guide>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Acceleration {
+    /// The vector instructions used for block averaging, and on aarch64
+    /// palette matching, if any.
+    pub simd: Option<&'static str>,
+    /// Threads images are processed on.
+    pub threads: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Limits {
+    /// Largest output width or height, and block edge.
+    pub max_dimension: u32,
+    pub max_upscale: u32,
+    /// Longest edge of the proxy used for previews.
+    pub proxy_edge: u32,
+    pub max_download_bytes: u64,
+    pub download_timeout_secs: u64,
+    /// The input and output size limits in force.
+    pub size: SizeLimits,
+}
+
+pub fn capabilities() -> Capabilities {
+    Capabilities {
+        lowres: env!("CARGO_PKG_VERSION").to_string(),
+        input_extensions: input_extensions(),
+        output_extensions: vec!["png", "gif", "apng", "webp", "ico", "icns"],
+        features: Features {
+            heif: cfg!(feature = "heif"),
+            raw: cfg!(feature = "raw"),
+            segmentation: cfg!(feature = "segmentation"),
+            ocr: cfg!(feature = "ocr"),
+            gpu: gpu_available(),
+            dct_scaling: cfg!(feature = "dct-scaling"),
+            upload: cfg!(feature = "upload"),
+            avif: codecs::avif_built_in(),
+            ffmpeg: codecs::ffmpeg().is_some(),
+        },
+        effects: Effects {
+            palettes: Palette::ALL.to_vec(),
+            dithers: vec![Dither::Ordered, Dither::FloydSteinberg, Dither::Atkinson],
+            styles: vec![Style::Mac, Style::Cga, Style::Riso, Style::Newspaper],
+            upscalers: vec![Upscaler::Nearest, Upscaler::Scale2x],
+            match_methods: vec![ColorMatch::Histogram, ColorMatch::Reinhard],
+            guides: vec![Guide::Thirds, Guide::GoldenRatio, Guide::CenterCross],
+        },
+        acceleration: Acceleration {
+            simd: if cfg!(target_arch = "aarch64") {
+                Some("neon")
+            } else if cfg!(target_arch = "x86_64") {
+                Some("sse2")
+            } else {
+                None
+            },
+            threads: rayon::current_num_threads(),
+        },
+        limits: Limits {
+            max_dimension: MAX_DIMENSION,
+            max_upscale: upscale::MAX_FACTOR,
+            proxy_edge: PROXY_EDGE,
+            max_download_bytes: remote::MAX_DOWNLOAD_BYTES,
+            download_timeout_secs: remote::DOWNLOAD_TIMEOUT.as_secs(),
+            size: limits::current(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn lists_the_outputs_that_get_their_own_encoder() {
+        let caps = capabilities();
+        for ext in &caps.output_extensions[1..] {
+            let path = Path::new("out").with_extension(ext);
+            assert!(
+                super::super::animation::AnimatedFormat::of(&path).is_some()
+                    || super::super::icons::IconFormat::of(&path).is_some(),
+                "{}",
+                ext
+            );
+        }
+        assert_eq!(caps.features.segmentation, cfg!(feature = "segmentation"));
+        assert_eq!(caps.effects.palettes.len(), Palette::ALL.len());
+    }
+}
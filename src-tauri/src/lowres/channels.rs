@@ -0,0 +1,199 @@
+//! Splitting an image into one grayscale image per channel, and merging such
+//! images back into one, for texture and VFX workflows that treat channels
+//! separately (packed roughness/metalness maps, luma-only grading).
+
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use image::{GrayImage, Rgba, RgbaImage};
+
+use super::{color, encode_png, encode_png_data, load_image, LowresError, PngOptions};
+
+type Result<T> = anyhow::Result<T>;
+
+/// The channels an image is split into.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum ChannelSpace {
+    /// Red, green, blue and alpha.
+    Rgb,
+    /// Full-range BT.601 luma and chroma, and alpha.
+    Ycbcr,
+}
+
+impl ChannelSpace {
+    /// Channel names in order, as used in file names; alpha is always last.
+    pub fn channels(self) -> [&'static str; 4] {
+        match self {
+            ChannelSpace::Rgb => ["r", "g", "b", "a"],
+            ChannelSpace::Ycbcr => ["y", "cb", "cr", "a"],
+        }
+    }
+}
+
+impl Display for ChannelSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ChannelSpace::Rgb => "rgb",
+            ChannelSpace::Ycbcr => "ycbcr",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ChannelSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rgb" | "rgba" => Ok(ChannelSpace::Rgb),
+            "ycbcr" | "ycc" => Ok(ChannelSpace::Ycbcr),
+            other => Err(anyhow::anyhow!("Unknown channel space {:?}", other)),
+        }
+    }
+}
+
+fn png_options(drop_alpha: bool) -> PngOptions {
+    PngOptions {
+        drop_alpha,
+        dpi: None,
+        srgb: !drop_alpha,
+        metadata: None,
+        settings: None,
+        compression: png::Compression::Fast,
+        adaptive_filter: false,
+        interlace: false,
+    }
+}
+
+/// Write each channel of `input` as a grayscale PNG, `<stem>_<channel>.png`,
+/// into `out_dir` or next to `input`. Alpha is written only if the source
+/// has it. Returns the files written, in channel order.
+pub fn split_channels(
+    input: &Path,
+    out_dir: Option<&Path>,
+    space: ChannelSpace,
+) -> Result<Vec<PathBuf>> {
+    let img = load_image(&input.to_path_buf())?;
+    let has_alpha = img.color().has_alpha();
+    let rgba = img.to_rgba8();
+    let planes: Vec<Vec<u8>> = (0..4)
+        .map(|c| {
+            rgba.pixels()
+                .map(|p| match (space, c) {
+                    (_, 3) => p[3],
+                    (ChannelSpace::Rgb, c) => p[c],
+                    (ChannelSpace::Ycbcr, c) => {
+                        color::to_ycbcr(p)[c].round().clamp(0.0, 255.0) as u8
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let dir = out_dir
+        .or(input.parent())
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    let count = if has_alpha { 4 } else { 3 };
+    let mut written = Vec::new();
+    for (plane, name) in planes.iter().zip(space.channels()).take(count) {
+        let path = dir.join(format!("{}_{}.png", stem, name));
+        let encoded = encode_png_data(
+            rgba.dimensions(),
+            png::ColorType::Grayscale,
+            plane,
+            &png_options(false),
+        )?;
+        std::fs::write(&path, encoded)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// Build an image from grayscale channel images in `space`'s order: three
+/// without alpha, or four with it. All must be the same size; color inputs
+/// are read by their luma.
+pub fn merge_channels(inputs: &[PathBuf], space: ChannelSpace, output: &Path) -> Result<()> {
+    if !(3..=4).contains(&inputs.len()) {
+        return Err(LowresError::InvalidConfig(format!(
+            "Merging {} channels needs 3 or 4 images ({}), got {}",
+            space,
+            space.channels().join(", "),
+            inputs.len()
+        ))
+        .into());
+    }
+    let planes = inputs
+        .iter()
+        .map(|path| Ok(load_image(path)?.to_luma8()))
+        .collect::<Result<Vec<GrayImage>>>()?;
+    let (w, h) = planes[0].dimensions();
+    if let Some((path, plane)) = inputs
+        .iter()
+        .zip(&planes)
+        .find(|(_, p)| p.dimensions() != (w, h))
+    {
+        return Err(LowresError::InvalidConfig(format!(
+            "Channel images must be the same size: {:?} is {}x{}, {:?} is {}x{}",
+            inputs[0],
+            w,
+            h,
+            path,
+            plane.width(),
+            plane.height()
+        ))
+        .into());
+    }
+
+    let rgba = RgbaImage::from_fn(w, h, |x, y| {
+        let v = |c: usize| planes.get(c).map_or(255, |p| p.get_pixel(x, y)[0]);
+        match space {
+            ChannelSpace::Rgb => Rgba([v(0), v(1), v(2), v(3)]),
+            ChannelSpace::Ycbcr => color::from_ycbcr([v(0) as f32, v(1) as f32, v(2) as f32], v(3)),
+        }
+    });
+    let encoded = encode_png(&rgba, &png_options(planes.len() == 3))?;
+    std::fs::write(output, encoded)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", output, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_channels_merge_back() {
+        let dir = std::env::temp_dir().join("lowres_channels_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("tex.png");
+        let src = RgbaImage::from_fn(4, 3, |x, y| {
+            Rgba([x as u8 * 60, y as u8 * 80, 200, 100 + x as u8])
+        });
+        src.save(&input).unwrap();
+
+        let rgb = split_channels(&input, None, ChannelSpace::Rgb).unwrap();
+        let names: Vec<_> = rgb.iter().map(|p| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["tex_r.png", "tex_g.png", "tex_b.png", "tex_a.png"]);
+        merge_channels(&rgb, ChannelSpace::Rgb, &dir.join("rgb.png")).unwrap();
+        let merged = image::open(dir.join("rgb.png")).unwrap().to_rgba8();
+        assert_eq!(merged, src);
+
+        let ycc = split_channels(&input, None, ChannelSpace::Ycbcr).unwrap();
+        merge_channels(&ycc[..3], ChannelSpace::Ycbcr, &dir.join("ycc.png")).unwrap();
+        let merged = image::open(dir.join("ycc.png")).unwrap();
+        assert!(!merged.color().has_alpha());
+        for (a, b) in merged.to_rgba8().pixels().zip(src.pixels()) {
+            assert!(
+                (0..3).all(|c| a[c].abs_diff(b[c]) <= 1),
+                "{:?} vs {:?}",
+                a,
+                b
+            );
+        }
+        assert!(merge_channels(&ycc[..2], ChannelSpace::Ycbcr, &dir.join("x.png")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+//! Inputs whose decoders may be missing from a given machine or build: AVIF,
+//! which the image crate only decodes when built with its native decoder, and
+//! video, read through an `ffmpeg` found at run time. HEIC falls back on that
+//! ffmpeg too when lowres is built without libheif. These inputs are
+//! recognized by their contents, so a missing codec fails only them, with an
+//! error naming what would add it, while every other format keeps working.
+
+use anyhow::Context;
+use image::error::UnsupportedErrorKind;
+use image::{DynamicImage, ImageError, ImageFormat};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Environment variable naming the ffmpeg to run, for one not on the PATH.
+pub const FFMPEG_ENV: &str = "LOWRES_FFMPEG";
+
+/// Video file extensions read through ffmpeg, for file dialogs.
+pub const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "mov", "m4v", "webm", "mkv"];
+
+/// Containers decoded by an optional codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Avif,
+    /// MP4 or QuickTime video.
+    Mp4,
+    /// Matroska or WebM video.
+    Matroska,
+}
+
+impl Container {
+    /// The container `data` starts like, if any.
+    pub fn of(data: &[u8]) -> Option<Self> {
+        const MP4_BRANDS: [&[u8]; 8] = [
+            b"isom", b"iso2", b"mp41", b"mp42", b"qt  ", b"M4V ", b"avc1", b"dash",
+        ];
+        if data.get(4..8) == Some(b"ftyp") {
+            let brand = data.get(8..12)?;
+            if brand == b"avif" || brand == b"avis" {
+                return Some(Container::Avif);
+            }
+            return MP4_BRANDS.contains(&brand).then_some(Container::Mp4);
+        }
+        // Both start with an EBML header.
+        data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3])
+            .then_some(Container::Matroska)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Avif => "avif",
+            Container::Mp4 => "mp4",
+            Container::Matroska => "mkv",
+        }
+    }
+}
+
+/// Whether the image crate in this build decodes AVIF itself. Its
+/// `reading_enabled` also says yes for the encoder alone, so ask the decoder:
+/// without one, even empty data is refused as an unsupported format.
+pub fn avif_built_in() -> bool {
+    let refused = image::load_from_memory_with_format(&[], ImageFormat::Avif);
+    !matches!(refused, Err(ImageError::Unsupported(e))
+        if matches!(e.kind(), UnsupportedErrorKind::Format(_)))
+}
+
+/// The ffmpeg that runs on this machine, if any. Looked for once per process.
+pub fn ffmpeg() -> Option<&'static PathBuf> {
+    static FFMPEG: OnceLock<Option<PathBuf>> = OnceLock::new();
+    FFMPEG
+        .get_or_init(|| {
+            let program = std::env::var_os(FFMPEG_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| "ffmpeg".into());
+            Command::new(&program)
+                .arg("-version")
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+                .then_some(program)
+        })
+        .as_ref()
+}
+
+/// Decode an AVIF image with the image crate, or else with ffmpeg.
+pub fn decode_avif(data: &[u8]) -> Result<DynamicImage> {
+    if avif_built_in() {
+        return image::load_from_memory_with_format(data, ImageFormat::Avif)
+            .context("Failed to decode image");
+    }
+    decode_with_ffmpeg(
+        data,
+        "AVIF images",
+        "lowres built with the image crate's `avif-native` feature, or ffmpeg",
+    )
+}
+
+/// The first frame of a video, upright, decoded with ffmpeg.
+pub fn decode_video_frame(data: &[u8]) -> Result<DynamicImage> {
+    decode_with_ffmpeg(data, "Video files", "ffmpeg")
+}
+
+/// Decode an HEIF image with ffmpeg, for builds without libheif.
+pub fn decode_heif(data: &[u8]) -> Result<DynamicImage> {
+    decode_with_ffmpeg(
+        data,
+        "HEIC/HEIF images",
+        "lowres built with the `heif` feature, or ffmpeg",
+    )
+}
+
+/// Have ffmpeg write the first frame of `data` as a PNG. `what` names the
+/// input and `needs` what reads it, for the error when there's no ffmpeg.
+fn decode_with_ffmpeg(data: &[u8], what: &str, needs: &str) -> Result<DynamicImage> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+
+    let Some(ffmpeg) = ffmpeg() else {
+        return Err(LowresError::UnsupportedFormat(format!(
+            "{} need {} (on the PATH or named by {})",
+            what, needs, FFMPEG_ENV
+        ))
+        .into());
+    };
+    // MP4s often keep their index at the end, which ffmpeg can only reach in
+    // a file it can seek, not a pipe.
+    let input = std::env::temp_dir().join(format!(
+        "lowres_ffmpeg_{}_{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::write(&input, data)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", input, e))?;
+    let output = Command::new(ffmpeg)
+        .args(["-v", "error", "-i"])
+        .arg(&input)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+        .stdin(Stdio::null())
+        .output();
+    let _ = std::fs::remove_file(&input);
+    let output = output.map_err(|e| LowresError::Io(format!("Failed to run ffmpeg: {}", e)))?;
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(LowresError::Decode(format!(
+            "ffmpeg couldn't decode the input: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    image::load_from_memory_with_format(&output.stdout, ImageFormat::Png)
+        .context("Failed to read the frame ffmpeg decoded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_containers_with_optional_codecs() {
+        assert_eq!(
+            Container::of(b"\0\0\0\x1cftypavif\0\0\0\0"),
+            Some(Container::Avif)
+        );
+        assert_eq!(
+            Container::of(b"\0\0\0\x20ftypisom\0\0\x02\0"),
+            Some(Container::Mp4)
+        );
+        assert_eq!(
+            Container::of(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81"),
+            Some(Container::Matroska)
+        );
+        assert_eq!(Container::of(b"\0\0\0\x18ftypheic\0\0\0\0"), None);
+        assert_eq!(Container::of(b"\x89PNG\r\n\x1a\n"), None);
+        // The image crate's default features only encode AVIF.
+        assert!(!avif_built_in());
+
+        // Without ffmpeg, a video is refused as a format, not as damaged data.
+        if ffmpeg().is_none() {
+            let err =
+                LowresError::from(decode_video_frame(b"\0\0\0\x20ftypisom\0\0\x02\0").unwrap_err());
+            assert!(
+                matches!(err, LowresError::UnsupportedFormat(_)),
+                "{:?}",
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! sRGB ↔ linear-light conversions, so averaging and resampling mix light
+//! rather than gamma-encoded values (which darkens and desaturates), and the
+//! YCbCr split used to pixelate brightness and color separately. Also bakes
+//! an ICC profile into plain sRGB pixels for outputs that can't carry it.
+
+use image::{DynamicImage, Rgba, Rgba32FImage, RgbaImage};
+use std::sync::OnceLock;
+
+type Result<T> = anyhow::Result<T>;
+
+/// `img`'s pixels, described by the ICC profile `icc`, converted to sRGB;
+/// `None` when the profile can't be read or transformed from.
+pub fn icc_to_srgb(img: &DynamicImage, icc: &[u8]) -> Option<DynamicImage> {
+    use moxcms::{ColorProfile, Layout, TransformOptions};
+
+    let profile = ColorProfile::new_from_slice(icc).ok()?;
+    let transform = profile
+        .create_transform_8bit(
+            Layout::Rgba,
+            &ColorProfile::new_srgb(),
+            Layout::Rgba,
+            TransformOptions::default(),
+        )
+        .ok()?;
+    let rgba = img.to_rgba8();
+    let mut out = RgbaImage::new(rgba.width(), rgba.height());
+    transform.transform(rgba.as_raw(), &mut out).ok()?;
+    Some(DynamicImage::ImageRgba8(out))
+}
+
+/// Decode an 8-bit sRGB channel value to linear light in 0.0..=1.0.
+pub fn srgb_to_linear(v: u8) -> f32 {
+    static LUT: OnceLock<[f32; 256]> = OnceLock::new();
+    LUT.get_or_init(|| {
+        std::array::from_fn(|i| {
+            let c = i as f32 / 255.0;
+            if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+    })[v as usize]
+}
+
+/// Encode linear light in 0.0..=1.0 back to an 8-bit sRGB channel value.
+pub fn linear_to_srgb(v: f32) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let c = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round() as u8
+}
+
+/// Linearize the color channels of `img`; alpha is already linear and only rescaled.
+pub fn to_linear(img: &DynamicImage) -> Rgba32FImage {
+    let rgba = img.to_rgba8();
+    Rgba32FImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        Rgba([
+            srgb_to_linear(p[0]),
+            srgb_to_linear(p[1]),
+            srgb_to_linear(p[2]),
+            p[3] as f32 / 255.0,
+        ])
+    })
+}
+
+/// Inverse of `to_linear`.
+pub fn from_linear(img: &Rgba32FImage) -> RgbaImage {
+    RgbaImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        Rgba([
+            linear_to_srgb(p[0]),
+            linear_to_srgb(p[1]),
+            linear_to_srgb(p[2]),
+            (p[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// Full-range BT.601 (JPEG) YCbCr of an sRGB pixel.
+pub fn to_ycbcr(p: &Rgba<u8>) -> [f32; 3] {
+    let [r, g, b] = [p[0] as f32, p[1] as f32, p[2] as f32];
+    [
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    ]
+}
+
+/// Inverse of `to_ycbcr`, clamped to 8 bits.
+pub fn from_ycbcr([y, cb, cr]: [f32; 3], alpha: u8) -> Rgba<u8> {
+    let channel = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    Rgba([
+        channel(y + 1.402 * (cr - 128.0)),
+        channel(y - 0.344_136 * (cb - 128.0) - 0.714_136 * (cr - 128.0)),
+        channel(y + 1.772 * (cb - 128.0)),
+        alpha,
+    ])
+}
+
+/// Combine the luma (and alpha) of `luma` with the chroma of `chroma`, which
+/// must be the same size.
+pub fn merge_ycbcr(luma: &RgbaImage, chroma: &RgbaImage) -> RgbaImage {
+    RgbaImage::from_fn(luma.width(), luma.height(), |x, y| {
+        let l = luma.get_pixel(x, y);
+        let [y_, _, _] = to_ycbcr(l);
+        let [_, cb, cr] = to_ycbcr(chroma.get_pixel(x, y));
+        from_ycbcr([y_, cb, cr], l[3])
+    })
+}
+
+/// Parse `#rrggbb`, `#rrggbbaa` or `transparent`.
+pub fn parse_color(s: &str) -> Result<Rgba<u8>> {
+    if s.eq_ignore_ascii_case("transparent") {
+        return Ok(Rgba([0, 0, 0, 0]));
+    }
+    let hex = s.trim_start_matches('#');
+    if !matches!(hex.len(), 6 | 8) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!(
+            "Bad color {:?}, expected #rrggbb, #rrggbbaa or transparent",
+            s
+        );
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+    let alpha = if hex.len() == 8 { channel(6)? } else { 255 };
+    Ok(Rgba([channel(0)?, channel(2)?, channel(4)?, alpha]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_channel_value() {
+        for v in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(v)), v);
+        }
+        // Mixing black and white in linear light gives ~188, not 128.
+        let mid = (srgb_to_linear(0) + srgb_to_linear(255)) / 2.0;
+        assert_eq!(linear_to_srgb(mid), 188);
+    }
+
+    #[test]
+    fn merges_luma_and_chroma() {
+        let gray = RgbaImage::from_pixel(1, 1, Rgba([100, 100, 100, 255]));
+        let red = RgbaImage::from_pixel(1, 1, Rgba([200, 40, 40, 128]));
+        // Same image on both sides is a round trip.
+        assert_eq!(merge_ycbcr(&red, &red), red);
+        // Gray brightness with red's hue: reddish, but no brighter than gray.
+        let p = *merge_ycbcr(&gray, &red).get_pixel(0, 0);
+        assert!(p[0] > p[1] && p[1] == p[2] && p[3] == 255);
+        let [y, _, _] = to_ycbcr(&p);
+        assert!((y - 100.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff8000").unwrap(), Rgba([255, 128, 0, 255]));
+        assert_eq!(parse_color("ff800080").unwrap(), Rgba([255, 128, 0, 128]));
+        assert_eq!(parse_color("Transparent").unwrap(), Rgba([0, 0, 0, 0]));
+        assert!(parse_color("#fff").is_err());
+    }
+}
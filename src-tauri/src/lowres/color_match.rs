@@ -0,0 +1,190 @@
+//! Color matching: moving an image's colors toward those of a reference image,
+//! so a batch of assets made from different sources shares one look.
+//! Fully transparent pixels count toward neither image's distribution.
+
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::color::{from_ycbcr, to_ycbcr};
+use super::{load_image, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ColorMatch {
+    /// Remap each RGB channel so its histogram matches the reference's.
+    Histogram,
+    /// Shift and scale luma and chroma to the reference's mean and spread
+    /// (Reinhard transfer); gentler, keeping the image's own contrast shape.
+    Reinhard,
+}
+
+impl Display for ColorMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColorMatch::Histogram => "histogram",
+            ColorMatch::Reinhard => "reinhard",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ColorMatch {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "histogram" => Ok(ColorMatch::Histogram),
+            "reinhard" => Ok(ColorMatch::Reinhard),
+            other => Err(anyhow::anyhow!("Unknown color match method {:?}", other)),
+        }
+    }
+}
+
+/// A reference image, reduced to what its method needs.
+pub enum Reference {
+    /// Cumulative distribution of each RGB channel.
+    Histogram(Box<[[f64; 256]; 3]>),
+    /// Mean and standard deviation of Y, Cb and Cr.
+    Reinhard([(f32, f32); 3]),
+}
+
+impl Reference {
+    pub fn load(path: &Path, method: ColorMatch) -> Result<Self> {
+        let img = load_image(&path.to_path_buf())?.to_rgba8();
+        if !img.pixels().any(|p| p[3] > 0) {
+            return Err(LowresError::InvalidConfig(format!(
+                "Color reference {:?} has no visible pixels",
+                path
+            ))
+            .into());
+        }
+        Ok(Self::of(&img, method))
+    }
+
+    fn of(img: &RgbaImage, method: ColorMatch) -> Self {
+        match method {
+            ColorMatch::Histogram => Reference::Histogram(Box::new(cdfs(img))),
+            ColorMatch::Reinhard => Reference::Reinhard(ycbcr_stats(img)),
+        }
+    }
+
+    /// `img` with its colors matched to the reference's. Alpha is kept.
+    pub fn apply(&self, img: &RgbaImage) -> RgbaImage {
+        let mut out = img.clone();
+        match self {
+            Reference::Histogram(target) => {
+                let source = cdfs(img);
+                let luts: Vec<[u8; 256]> = (0..3)
+                    .map(|c| {
+                        std::array::from_fn(|v| {
+                            // The first reference level at least as common as this one.
+                            target[c].partition_point(|&p| p < source[c][v]).min(255) as u8
+                        })
+                    })
+                    .collect();
+                out.par_chunks_exact_mut(4).for_each(|px| {
+                    for c in 0..3 {
+                        px[c] = luts[c][px[c] as usize];
+                    }
+                });
+            }
+            Reference::Reinhard(target) => {
+                let source = ycbcr_stats(img);
+                out.par_chunks_exact_mut(4).for_each(|px| {
+                    let ycc = to_ycbcr(&Rgba([px[0], px[1], px[2], px[3]]));
+                    let matched: [f32; 3] = std::array::from_fn(|c| {
+                        let ((mean, sd), (target_mean, target_sd)) = (source[c], target[c]);
+                        let gain = if sd > 1e-3 { target_sd / sd } else { 1.0 };
+                        (ycc[c] - mean) * gain + target_mean
+                    });
+                    px.copy_from_slice(&from_ycbcr(matched, px[3]).0);
+                });
+            }
+        }
+        out
+    }
+}
+
+/// Cumulative distribution of each RGB channel over visible pixels; uniform
+/// when there are none.
+fn cdfs(img: &RgbaImage) -> [[f64; 256]; 3] {
+    let mut counts = [[0u64; 256]; 3];
+    for p in img.pixels().filter(|p| p[3] > 0) {
+        for c in 0..3 {
+            counts[c][p[c] as usize] += 1;
+        }
+    }
+    counts.map(|hist| {
+        let total: u64 = hist.iter().sum();
+        let mut sum = 0;
+        std::array::from_fn(|v| {
+            sum += hist[v];
+            if total == 0 {
+                (v + 1) as f64 / 256.0
+            } else {
+                sum as f64 / total as f64
+            }
+        })
+    })
+}
+
+/// Mean and standard deviation of Y, Cb and Cr over visible pixels.
+fn ycbcr_stats(img: &RgbaImage) -> [(f32, f32); 3] {
+    let mut sum = [0f64; 3];
+    let mut sum_sq = [0f64; 3];
+    let mut n = 0u64;
+    for p in img.pixels().filter(|p| p[3] > 0) {
+        for (c, v) in to_ycbcr(p).into_iter().enumerate() {
+            sum[c] += v as f64;
+            sum_sq[c] += (v as f64) * (v as f64);
+        }
+        n += 1;
+    }
+    std::array::from_fn(|c| {
+        if n == 0 {
+            return (0.0, 0.0);
+        }
+        let mean = sum[c] / n as f64;
+        let var = (sum_sq[c] / n as f64 - mean * mean).max(0.0);
+        (mean as f32, var.sqrt() as f32)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_colors_to_the_reference() {
+        // A dark ramp matched to a bright one ends up bright, keeping its order.
+        let ramp = |lo: u8| RgbaImage::from_fn(64, 1, |x, _| Rgba([lo + x as u8, lo, lo, 255]));
+        let (dark, bright) = (ramp(10), ramp(150));
+
+        for method in [ColorMatch::Histogram, ColorMatch::Reinhard] {
+            let matched = Reference::of(&bright, method).apply(&dark);
+            for (a, b) in matched.pixels().zip(bright.pixels()) {
+                assert!(
+                    (0..3).all(|c| a[c].abs_diff(b[c]) <= 2),
+                    "{}: {:?} vs {:?}",
+                    method,
+                    a,
+                    b
+                );
+            }
+        }
+
+        let hidden = std::env::temp_dir().join("lowres_color_match_test.png");
+        RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 0]))
+            .save(&hidden)
+            .unwrap();
+        let loaded = Reference::load(&hidden, ColorMatch::Reinhard);
+        std::fs::remove_file(&hidden).unwrap();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Reduced-scale JPEG decoding: the decoder's scaled inverse DCT turns each
+//! 8×8 block into 4×4, 2×2 or a single pixel, so a photo meant for a small
+//! output is decoded at 1/2, 1/4 or 1/8 of its size, in a fraction of the
+//! time and memory of a full decode and a resize.
+
+use image::{DynamicImage, GrayImage, RgbImage};
+use jpeg_decoder::{CodingProcess, Decoder, PixelFormat};
+use std::io::Cursor;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Decode the JPEG `data` at 1/`denominator` (2, 4 or 8) of its size, as
+/// stored, before any EXIF orientation. `None` for the JPEGs this decoder
+/// leaves to the image crate: lossless and CMYK ones.
+pub fn decode_jpeg_scaled(data: &[u8], denominator: u32) -> Result<Option<DynamicImage>> {
+    let mut decoder = Decoder::new(Cursor::new(data));
+    decoder.read_info()?;
+    let Some(info) = decoder.info() else {
+        return Ok(None);
+    };
+    let gray = match info.pixel_format {
+        PixelFormat::L8 => true,
+        PixelFormat::RGB24 => false,
+        // The image crate converts CMYK, and 16-bit samples are lossless only.
+        _ => return Ok(None),
+    };
+    if matches!(info.coding_process, CodingProcess::Lossless) {
+        return Ok(None);
+    }
+    // The decoder picks the smallest scale giving at least the size asked
+    // for, which for these sizes is exactly 1/`denominator`.
+    let side = |n: u16| (n as u32).div_ceil(denominator) as u16;
+    let (width, height) = decoder.scale(side(info.width), side(info.height))?;
+    let pixels = decoder.decode()?;
+    let (width, height) = (width as u32, height as u32);
+    let img = if gray {
+        GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8)
+    } else {
+        RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8)
+    };
+    Ok(img)
+}
@@ -0,0 +1,106 @@
+//! The error type handed to the desktop frontend, the CLI and RPC clients.
+//! Internally the core uses `anyhow`; failures worth telling apart are raised
+//! as a `LowresError` and found again in the chain at the boundary.
+
+use serde::Serialize;
+
+/// Serialized as `{"kind": "...", "message": "..."}`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, Serialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum LowresError {
+    /// A file couldn't be read or written.
+    #[error("{0}")]
+    Io(String),
+    /// The input isn't in an image format lowres reads.
+    #[error("{0}")]
+    UnsupportedFormat(String),
+    /// The input is in a known format but its data is damaged.
+    #[error("{0}")]
+    Decode(String),
+    /// The config is malformed or asks for something that can't be done.
+    #[error("{0}")]
+    InvalidConfig(String),
+    /// An input or output is over a size limit.
+    #[error("{0}")]
+    TooLarge(String),
+    /// Anything else.
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<anyhow::Error> for LowresError {
+    fn from(e: anyhow::Error) -> Self {
+        let message = format!("{:#}", e);
+        for cause in e.chain() {
+            if let Some(err) = cause.downcast_ref::<LowresError>() {
+                // Keep any context added on the way up.
+                return err.with_message(message);
+            }
+            if cause.downcast_ref::<std::io::Error>().is_some() {
+                return LowresError::Io(message);
+            }
+            if let Some(err) = cause.downcast_ref::<image::ImageError>() {
+                return match err {
+                    image::ImageError::Unsupported(_) => LowresError::UnsupportedFormat(message),
+                    image::ImageError::IoError(_) => LowresError::Io(message),
+                    image::ImageError::Limits(_) => LowresError::TooLarge(message),
+                    _ => LowresError::Decode(message),
+                };
+            }
+        }
+        LowresError::Other(message)
+    }
+}
+
+impl LowresError {
+    /// The variant name, as serialized in `kind`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LowresError::Io(_) => "Io",
+            LowresError::UnsupportedFormat(_) => "UnsupportedFormat",
+            LowresError::Decode(_) => "Decode",
+            LowresError::InvalidConfig(_) => "InvalidConfig",
+            LowresError::TooLarge(_) => "TooLarge",
+            LowresError::Other(_) => "Other",
+        }
+    }
+
+    fn with_message(&self, message: String) -> Self {
+        match self {
+            LowresError::Io(_) => LowresError::Io(message),
+            LowresError::UnsupportedFormat(_) => LowresError::UnsupportedFormat(message),
+            LowresError::Decode(_) => LowresError::Decode(message),
+            LowresError::InvalidConfig(_) => LowresError::InvalidConfig(message),
+            LowresError::TooLarge(_) => LowresError::TooLarge(message),
+            LowresError::Other(_) => LowresError::Other(message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn classifies_errors_through_context() {
+        let e = anyhow::Error::new(LowresError::InvalidConfig("bad block".into()))
+            .context("processing a.png");
+        assert_eq!(
+            LowresError::from(e),
+            LowresError::InvalidConfig("processing a.png: bad block".into())
+        );
+
+        let io = std::fs::read("/definitely/not/here").context("reading input");
+        assert!(matches!(
+            LowresError::from(io.unwrap_err()),
+            LowresError::Io(_)
+        ));
+
+        let json = serde_json::to_value(LowresError::Decode("truncated".into())).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({ "kind": "Decode", "message": "truncated" })
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Exploring settings: rendering one source under many randomly chosen
+//! combinations of block size, colors, dithering and grain, laid out on a
+//! captioned contact sheet. Each variation has its own seed and full config,
+//! so a favorite can be reproduced exactly.
+
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+use super::figure::{compose, Panel};
+use super::{
+    decode_image, encode_png, load_source, render_source, Banding, Dither, LowresConfig, Palette,
+    PixelateChannels, PngOptions, PreviewQuality,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Panels per row of the contact sheet.
+const COLUMNS: usize = 4;
+const BLOCKS: [u32; 8] = [2, 3, 4, 6, 8, 12, 16, 24];
+const COLOR_COUNTS: [u32; 5] = [2, 4, 8, 16, 32];
+const DITHERS: [Dither; 3] = [Dither::Ordered, Dither::FloydSteinberg, Dither::Atkinson];
+
+/// One explored combination.
+#[derive(Debug, Clone)]
+pub struct Variation {
+    /// Seed that `vary` turns into this variation's settings.
+    pub seed: u64,
+    pub config: LowresConfig,
+}
+
+/// SplitMix64: small, fast, and the same on every platform.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+        items[(self.next() % items.len() as u64) as usize]
+    }
+
+    /// True with probability `1 / n`.
+    fn one_in(&mut self, n: u64) -> bool {
+        self.next().is_multiple_of(n)
+    }
+}
+
+/// `base` with a random block size, colors, dithering and grain from `seed`,
+/// for whichever of those it (or its style) leaves unset.
+pub fn vary(base: &LowresConfig, seed: u64) -> LowresConfig {
+    let mut rng = Rng(seed);
+    let mut config = base.clone().resolve_presets();
+    if config.block_size().is_none() && config.no_pixelate != Some(true) {
+        config.block = Some(rng.pick(&BLOCKS));
+    }
+    let own_colors =
+        config.palette.is_some() || config.palette_file.is_some() || config.colors.is_some();
+    // Channel-only pixelation and posterizing take no palette.
+    let takes_colors = config
+        .pixelate_channels
+        .is_none_or(|c| c == PixelateChannels::All)
+        && config.banding != Some(Banding::Posterize);
+    if !own_colors && takes_colors {
+        if rng.one_in(2) {
+            config.palette = Some(rng.pick(&Palette::ALL));
+        } else {
+            config.colors = Some(rng.pick(&COLOR_COUNTS));
+        }
+    }
+    let quantizes =
+        config.palette.is_some() || config.palette_file.is_some() || config.colors.is_some();
+    if quantizes && config.dither.is_none() && !rng.one_in(2) {
+        config.dither = Some(rng.pick(&DITHERS));
+    }
+    if config.grain.is_none() && rng.one_in(3) {
+        config.grain = Some((5 + rng.next() % 26) as f32 / 100.0);
+    }
+    config
+}
+
+/// `count` variations of `base`, each seeded from `seed` and its position.
+pub fn variations(base: &LowresConfig, count: u32, seed: u64) -> Vec<Variation> {
+    let mut seeds = Rng(seed);
+    (0..count)
+        .map(|_| {
+            let seed = seeds.next();
+            Variation {
+                seed,
+                config: vary(base, seed),
+            }
+        })
+        .collect()
+}
+
+/// Render `variations` of `input` at preview resolution onto a contact sheet,
+/// returned as a PNG. Each panel is captioned with its number and seed.
+pub fn render_contact_sheet(input: &PathBuf, variations: &[Variation]) -> Result<Vec<u8>> {
+    let source = load_source(input)?;
+    let panels = variations
+        .par_iter()
+        .enumerate()
+        .map(|(i, variation)| {
+            let (png, _) = render_source(
+                &source,
+                variation.config.clone(),
+                PreviewQuality::Proxy,
+                &mut |_| {},
+            )?;
+            Ok(Panel {
+                image: decode_image(&png)?.to_rgba8(),
+                title: format!("#{} seed {}", i + 1, variation.seed),
+                params: describe(&variation.config),
+            })
+        })
+        .collect::<Result<Vec<Panel>>>()?;
+    // Every panel has the shape of the cropped, resized output.
+    let (w, h) = panels.first().map_or((1, 1), |p| p.image.dimensions());
+    let sheet: RgbaImage = compose(&panels, w, h, COLUMNS);
+    encode_png(
+        &sheet,
+        &PngOptions {
+            drop_alpha: false,
+            dpi: None,
+            srgb: true,
+            metadata: None,
+            settings: None,
+            compression: png::Compression::Fast,
+            adaptive_filter: false,
+            interlace: false,
+        },
+    )
+}
+
+/// The explored settings of `config`, as a caption.
+fn describe(config: &LowresConfig) -> String {
+    let mut parts = Vec::new();
+    if let Some(block) = config.block_size() {
+        parts.push(format!("block={}", block));
+    }
+    match (&config.palette_file, config.palette, config.colors) {
+        (Some(_), _, _) => parts.push("palette file".into()),
+        (None, Some(palette), _) => parts.push(format!("palette={}", palette)),
+        (None, None, Some(n)) => parts.push(format!("colors={}", n)),
+        (None, None, None) => {}
+    }
+    if let Some(dither) = config.dither {
+        parts.push(format!("dither={}", dither));
+    }
+    if let Some(grain) = config.grain {
+        parts.push(format!("grain={}", grain));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variations_are_reproducible_and_keep_explicit_settings() {
+        let base = LowresConfig {
+            block: Some(5),
+            ..Default::default()
+        };
+        let first = variations(&base, 6, 42);
+        let again = variations(&base, 6, 42);
+        for (a, b) in first.iter().zip(&again) {
+            assert_eq!(a.seed, b.seed);
+            assert_eq!(describe(&a.config), describe(&b.config));
+            assert_eq!(describe(&vary(&base, a.seed)), describe(&a.config));
+            assert_eq!(a.config.block, Some(5));
+            a.config.validate().unwrap();
+        }
+        let seeds: Vec<u64> = first.iter().map(|v| v.seed).collect();
+        assert!(seeds
+            .iter()
+            .enumerate()
+            .all(|(i, s)| !seeds[..i].contains(s)));
+        assert_ne!(
+            first
+                .iter()
+                .map(|v| describe(&v.config))
+                .collect::<Vec<_>>(),
+            variations(&base, 6, 43)
+                .iter()
+                .map(|v| describe(&v.config))
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! Side-by-side comparison figures (original, resized, pixelated, quantized)
+//! with each panel captioned by the parameters that produced it, for
+//! tutorials and design reviews.
+
+use image::{imageops::FilterType, GenericImageView, Rgba, RgbaImage};
+use std::path::PathBuf;
+
+use super::{
+    encode_png, font, load_image, pad_to, pick_target_size, pixelate, resize_image, BlockOutput,
+    BlockSize, BlockStat, LowresConfig, PixelateOptions, PngOptions, Quantize, Resample,
+    ResizeMode, Timings,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Longest edge of each panel; every panel is shown at the same size.
+const PANEL_MAX: u32 = 480;
+/// Space around and between panels.
+const GAP: u32 = 16;
+const TEXT_SCALE: u32 = 2;
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const INK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+/// Block size for the pixelated panels when the config has none.
+const DEFAULT_BLOCK: u32 = 8;
+/// Color count for the quantized panel when the config has no palette or colors.
+const DEFAULT_COLORS: u32 = 16;
+
+pub struct Panel {
+    pub image: RgbaImage,
+    pub title: String,
+    pub params: String,
+}
+
+/// Render the comparison figure for `input` as a PNG. Settings missing from
+/// `config` fall back to defaults so every panel shows something.
+pub fn render_comparison(input: &PathBuf, config: LowresConfig) -> Result<Vec<u8>> {
+    let img = load_image(input)?;
+    let config = config
+        .resolve_presets()
+        .resolve_percentages(img.dimensions());
+    let img = match &config.crop {
+        Some(crop) => crop.crop(&img)?,
+        None => img,
+    };
+    let (w, h) = img.dimensions();
+
+    let mode = config.mode.unwrap_or(ResizeMode::Auto);
+    let filter = config.filter.unwrap_or(Resample::Nearest);
+    let scale = match (config.width, config.height, config.scale) {
+        (None, None, None) => Some(0.25),
+        (_, _, scale) => scale,
+    };
+    let default = config.default_size.unwrap_or_default();
+    let (tw, th) = pick_target_size(
+        img.dimensions(),
+        config.width,
+        config.height,
+        scale,
+        mode,
+        default,
+    )?;
+    let mut resized = resize_image(&img, tw, th, filter.into(), mode)?.to_rgba8();
+    if mode == ResizeMode::Pad {
+        resized = pad_to(&resized, tw, th, Rgba([0, 0, 0, 0]));
+    }
+
+    let block = config.block_size().unwrap_or(BlockSize {
+        width: DEFAULT_BLOCK,
+        height: DEFAULT_BLOCK,
+    });
+    let stat = config.block_stat.unwrap_or(BlockStat::Mean);
+    let linear_light = config.linear_light.unwrap_or(false);
+    let mut opts = PixelateOptions {
+        block,
+        stat,
+        linear_light,
+        quantize: None,
+        dither: None,
+        grain: None,
+        output: BlockOutput::Full,
+        low_memory: config.low_memory.unwrap_or(false),
+        gpu: false,
+    };
+    let mut timings = Timings::default();
+    let pixelated = pixelate(&img, &opts, &mut timings)?;
+
+    let quantize = config
+        .quantize()?
+        .unwrap_or(Quantize::Adaptive(DEFAULT_COLORS as usize));
+    opts.quantize = Some(&quantize);
+    opts.dither = config.dither;
+    let quantized = pixelate(&img, &opts, &mut timings)?;
+
+    let colors = match (&config.palette_file, config.palette, config.colors) {
+        (Some(path), _, _) => format!(
+            "palette file {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        (None, Some(p), _) => format!("palette={}", p),
+        (None, None, n) => format!("colors={}", n.unwrap_or(DEFAULT_COLORS)),
+    };
+    let pixel_params = format!(
+        "block={} stat={}{}",
+        block,
+        stat,
+        if linear_light { " linear" } else { "" }
+    );
+
+    let panels = [
+        Panel {
+            image: img.to_rgba8(),
+            title: "Original".into(),
+            params: format!("{}x{}", w, h),
+        },
+        Panel {
+            image: resized,
+            title: "Resized".into(),
+            params: format!("{}x{} mode={} filter={}", tw, th, mode, filter),
+        },
+        Panel {
+            image: pixelated,
+            title: "Pixelated".into(),
+            params: pixel_params.clone(),
+        },
+        Panel {
+            image: quantized,
+            title: "Quantized".into(),
+            params: match config.dither {
+                Some(dither) => format!("{} {} dither={}", pixel_params, colors, dither),
+                None => format!("{} {}", pixel_params, colors),
+            },
+        },
+    ];
+
+    let figure = compose(&panels, w, h, panels.len());
+    encode_png(
+        &figure,
+        &PngOptions {
+            drop_alpha: false,
+            dpi: Some(config.dpi.unwrap_or(300)),
+            srgb: config.srgb.unwrap_or(true),
+            metadata: None,
+            settings: None,
+            compression: png::Compression::Fast,
+            adaptive_filter: false,
+            interlace: false,
+        },
+    )
+}
+
+/// Lay `panels` out in rows of `columns`, each scaled to the source's shape,
+/// with captions below.
+pub fn compose(panels: &[Panel], w: u32, h: u32, columns: usize) -> RgbaImage {
+    let scale = PANEL_MAX as f64 / w.max(h) as f64;
+    let pw = ((w as f64 * scale).round() as u32).max(1);
+    let ph = ((h as f64 * scale).round() as u32).max(1);
+
+    let captions: Vec<Vec<String>> = panels
+        .iter()
+        .map(|p| {
+            let mut lines = vec![p.title.clone()];
+            lines.extend(wrap(&p.params, pw));
+            lines
+        })
+        .collect();
+    let line_height = font::LINE_HEIGHT * TEXT_SCALE;
+    let caption_lines = captions.iter().map(Vec::len).max().unwrap_or(0) as u32;
+
+    let columns = columns.clamp(1, panels.len().max(1));
+    let rows = panels.len().div_ceil(columns) as u32;
+    let cell_h = GAP + ph + GAP / 2 + caption_lines * line_height;
+    let mut canvas = RgbaImage::from_pixel(
+        columns as u32 * pw + (columns as u32 + 1) * GAP,
+        rows * cell_h + GAP,
+        BACKGROUND,
+    );
+    for (i, (panel, lines)) in panels.iter().zip(&captions).enumerate() {
+        let x = GAP + (i % columns) as u32 * (pw + GAP);
+        let top = (i / columns) as u32 * cell_h + GAP;
+        // Nearest keeps pixel edges crisp when small outputs are blown up.
+        let shown = image::imageops::resize(&panel.image, pw, ph, FilterType::Nearest);
+        image::imageops::overlay(&mut canvas, &shown, x as i64, top as i64);
+        for (k, line) in lines.iter().enumerate() {
+            let y = top + ph + GAP / 2 + k as u32 * line_height;
+            font::draw_text(&mut canvas, x, y, line, TEXT_SCALE, INK);
+        }
+    }
+    canvas
+}
+
+/// Break `text` into lines no wider than `max_width` pixels, at spaces where possible.
+fn wrap(text: &str, max_width: u32) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line)
+                if font::text_width(&format!("{} {}", line, word), TEXT_SCALE) <= max_width =>
+            {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_captions_at_spaces() {
+        let width = font::text_width("block=8 stat=mean", TEXT_SCALE);
+        assert_eq!(
+            wrap("block=8 stat=mean colors=16", width),
+            vec!["block=8 stat=mean", "colors=16"]
+        );
+        // A word longer than the line gets a line of its own.
+        assert_eq!(wrap("abcdef", 1), vec!["abcdef"]);
+    }
+}
@@ -0,0 +1,251 @@
+//! Pixelation on the GPU: block means and the nearest-neighbor expansion of
+//! the block grid back to full size, as wgpu compute shaders. Built with the
+//! `gpu` feature. Anything the GPU can't do (no adapter, buffers over its
+//! limits, a failed dispatch) returns `None` and the caller stays on the
+//! CPU, so results never depend on the hardware.
+
+use image::{Rgba, RgbaImage};
+use std::sync::OnceLock;
+use wgpu::util::DeviceExt;
+
+use super::BlockSize;
+
+/// Invocations per workgroup along each axis, as in the shader.
+const WORKGROUP: u32 = 8;
+
+const SHADER: &str = r#"
+struct Params {
+    width: u32,
+    height: u32,
+    block_w: u32,
+    block_h: u32,
+    blocks_x: u32,
+    blocks_y: u32,
+    // Uniforms are laid out in 16-byte rows.
+    pad0: u32,
+    pad1: u32,
+}
+
+@group(0) @binding(0) var<uniform> p: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn unpack(v: u32) -> vec4<u32> {
+    return vec4<u32>(v & 0xffu, (v >> 8u) & 0xffu, (v >> 16u) & 0xffu, v >> 24u);
+}
+
+fn pack(c: vec4<u32>) -> u32 {
+    return c.x | (c.y << 8u) | (c.z << 16u) | (c.w << 24u);
+}
+
+// One invocation per block: the truncated mean, as the CPU computes it.
+@compute @workgroup_size(8, 8)
+fn block_means(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= p.blocks_x || id.y >= p.blocks_y) {
+        return;
+    }
+    let x0 = id.x * p.block_w;
+    let y0 = id.y * p.block_h;
+    let x1 = min(x0 + p.block_w, p.width);
+    let y1 = min(y0 + p.block_h, p.height);
+    var sum = vec4<u32>(0u);
+    for (var y = y0; y < y1; y++) {
+        for (var x = x0; x < x1; x++) {
+            sum += unpack(src[y * p.width + x]);
+        }
+    }
+    dst[id.y * p.blocks_x + id.x] = pack(sum / ((x1 - x0) * (y1 - y0)));
+}
+
+// One invocation per output pixel: the color of its block.
+@compute @workgroup_size(8, 8)
+fn expand(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= p.width || id.y >= p.height) {
+        return;
+    }
+    dst[id.y * p.width + id.x] = src[(id.y / p.block_h) * p.blocks_x + id.x / p.block_w];
+}
+"#;
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    block_means: wgpu::ComputePipeline,
+    expand: wgpu::ComputePipeline,
+}
+
+/// The adapter's device and pipelines, set up on first use; `None` when
+/// there is no adapter.
+fn gpu() -> Option<&'static Gpu> {
+    static GPU: OnceLock<Option<Gpu>> = OnceLock::new();
+    GPU.get_or_init(|| pollster::block_on(connect())).as_ref()
+}
+
+async fn connect() -> Option<Gpu> {
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await?;
+    let (device, queue) = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("lowres"),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            },
+            None,
+        )
+        .await
+        .ok()?;
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("pixelate"),
+        source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+    });
+    let pipeline = |entry_point: &str| {
+        device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(entry_point),
+            layout: None,
+            module: &module,
+            entry_point,
+            compilation_options: Default::default(),
+            cache: None,
+        })
+    };
+    let (block_means, expand) = (pipeline("block_means"), pipeline("expand"));
+    Some(Gpu {
+        device,
+        queue,
+        block_means,
+        expand,
+    })
+}
+
+/// Whether a GPU adapter is available to pixelate on.
+pub fn gpu_available() -> bool {
+    gpu().is_some()
+}
+
+/// The truncated mean color of each `block` of `rgba`, row by row.
+pub fn gpu_block_means(rgba: &RgbaImage, block: BlockSize) -> Option<Vec<Rgba<u8>>> {
+    // The shader sums in u32.
+    if block.width as u64 * block.height as u64 * 255 > u32::MAX as u64 {
+        return None;
+    }
+    let (w, h) = rgba.dimensions();
+    let blocks = (w.div_ceil(block.width), h.div_ceil(block.height));
+    let out = run(
+        |gpu| &gpu.block_means,
+        [w, h, block.width, block.height, blocks.0, blocks.1, 0, 0],
+        rgba.as_raw(),
+        blocks,
+    )?;
+    Some(
+        out.chunks_exact(4)
+            .map(|c| Rgba([c[0], c[1], c[2], c[3]]))
+            .collect(),
+    )
+}
+
+/// The `w`×`h` RGBA pixels of a grid of `colors` `blocks_x` wide, each
+/// block filling `block` pixels.
+pub fn gpu_expand(
+    colors: &[Rgba<u8>],
+    blocks_x: u32,
+    block: BlockSize,
+    (w, h): (u32, u32),
+) -> Option<Vec<u8>> {
+    let blocks_y = colors.len() as u32 / blocks_x.max(1);
+    let data: Vec<u8> = colors.iter().flat_map(|c| c.0).collect();
+    run(
+        |gpu| &gpu.expand,
+        [w, h, block.width, block.height, blocks_x, blocks_y, 0, 0],
+        &data,
+        (w, h),
+    )
+}
+
+/// Run `pipeline` over `src` with `params`, one invocation per cell of
+/// `grid`, and read back a packed RGBA value per cell.
+fn run(
+    pipeline: impl Fn(&Gpu) -> &wgpu::ComputePipeline,
+    params: [u32; 8],
+    src: &[u8],
+    grid: (u32, u32),
+) -> Option<Vec<u8>> {
+    let gpu = gpu()?;
+    let limits = gpu.device.limits();
+    let out_size = grid.0 as u64 * grid.1 as u64 * 4;
+    let largest = (src.len() as u64).max(out_size);
+    let groups = (grid.0.div_ceil(WORKGROUP), grid.1.div_ceil(WORKGROUP));
+    if largest > limits.max_storage_buffer_binding_size as u64
+        || largest > limits.max_buffer_size
+        || groups.0.max(groups.1) > limits.max_compute_workgroups_per_dimension
+    {
+        return None;
+    }
+
+    let device = &gpu.device;
+    let params: Vec<u8> = params.iter().flat_map(|v| v.to_le_bytes()).collect();
+    let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("params"),
+        contents: &params,
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let input = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("src"),
+        contents: src,
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+    let output = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("dst"),
+        size: out_size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback"),
+        size: out_size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let pipeline = pipeline(gpu);
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &pipeline.get_bind_group_layout(0),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: input.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: output.as_entire_binding(),
+            },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(&Default::default());
+    {
+        let mut pass = encoder.begin_compute_pass(&Default::default());
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(groups.0, groups.1, 1);
+    }
+    encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, out_size);
+    gpu.queue.submit(Some(encoder.finish()));
+
+    let slice = readback.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    device.poll(wgpu::Maintain::Wait);
+    receiver.recv().ok()?.ok()?;
+    let data = slice.get_mapped_range().to_vec();
+    readback.unmap();
+    Some(data)
+}
@@ -0,0 +1,50 @@
+//! Film grain: fixed monochrome noise for print looks, added before colors
+//! are reduced so a palette turns it into speckle. The noise depends only on
+//! pixel position, so frames of a sequence and reruns get identical grain.
+
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+
+/// Brightness noise at full `amount` (1.0), in 8-bit levels either way.
+const MAX_SPREAD: f32 = 96.0;
+
+/// Pseudo-random value in [-1, 1) for a pixel position.
+fn noise(x: u32, y: u32) -> f32 {
+    let mut h = (x as u64) << 32 | y as u64;
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    h ^= h >> 33;
+    h = h.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    h ^= h >> 33;
+    (h >> 40) as f32 / (1u64 << 23) as f32 - 1.0
+}
+
+/// Add grain of strength `amount` (0 to 1) to one RGBA pixel at (x, y),
+/// unless it is fully transparent.
+fn grain_pixel(px: &mut [u8], x: usize, y: usize, amount: f32) {
+    if px[3] == 0 {
+        return;
+    }
+    let delta = noise(x as u32, y as u32) * amount.clamp(0.0, 1.0) * MAX_SPREAD;
+    for c in &mut px[..3] {
+        *c = (*c as f32 + delta).round().clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Add grain of strength `amount` to `img`.
+pub fn add_grain(img: &mut RgbaImage, amount: f32) {
+    let width = img.width() as usize;
+    img.par_chunks_exact_mut(4)
+        .enumerate()
+        .for_each(|(i, px)| grain_pixel(px, i % width, i / width, amount));
+}
+
+/// Add grain of strength `amount` to a `width`-wide grid of block colors, one
+/// grain per block.
+pub fn add_grain_to_blocks(blocks: &mut [Rgba<u8>], width: usize, amount: f32) {
+    let width = width.max(1);
+    blocks
+        .par_iter_mut()
+        .enumerate()
+        .for_each(|(i, px)| grain_pixel(&mut px.0, i % width, i / width, amount));
+}
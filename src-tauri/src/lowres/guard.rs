@@ -0,0 +1,78 @@
+//! Read-only source guarantee: a check that nothing is about to be written
+//! into a directory that holds a source file. Sources themselves are only
+//! ever opened for reading; this catches outputs that would land beside them.
+
+use std::path::{Path, PathBuf};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Fail if writing into `dest_dir` would touch the directory of any of
+/// `sources` or anything beneath it. `dest_dir` doesn't have to exist yet.
+pub fn ensure_outside_sources(sources: &[PathBuf], dest_dir: &Path) -> Result<()> {
+    let dest = resolve(dest_dir)?;
+    for source in sources {
+        let source = source
+            .canonicalize()
+            .map_err(|e| anyhow::anyhow!("Failed to resolve {:?}: {}", source, e))?;
+        let source_dir = source.parent().unwrap_or(&source);
+        if dest.starts_with(source_dir) {
+            anyhow::bail!(
+                "Refusing to write into {:?}: it is within the source directory {:?}",
+                dest_dir,
+                source_dir
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Canonicalize `path`, resolving the longest existing ancestor and appending
+/// the parts that don't exist yet.
+fn resolve(path: &Path) -> Result<PathBuf> {
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        path
+    };
+    let mut existing = path;
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => {
+                return Ok(missing.into_iter().rev().fold(resolved, |p, c| p.join(c)));
+            }
+            Err(_) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name.to_owned());
+                    existing = if parent.as_os_str().is_empty() {
+                        Path::new(".")
+                    } else {
+                        parent
+                    };
+                }
+                _ => anyhow::bail!("Failed to resolve {:?}", path),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_destinations_inside_source_directories() {
+        let root = std::env::temp_dir().join(format!("lowres_guard_{}", std::process::id()));
+        let sources_dir = root.join("archive");
+        std::fs::create_dir_all(&sources_dir).unwrap();
+        let source = sources_dir.join("scan.png");
+        std::fs::write(&source, b"").unwrap();
+        let sources = [source];
+
+        assert!(ensure_outside_sources(&sources, &sources_dir).is_err());
+        assert!(ensure_outside_sources(&sources, &sources_dir.join("new/out")).is_err());
+        assert!(ensure_outside_sources(&sources, &root.join("derivatives")).is_ok());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
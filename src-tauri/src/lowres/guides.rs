@@ -0,0 +1,233 @@
+//! Composition guides for crop planning: rule-of-thirds, golden-ratio and
+//! center lines drawn over the part of the source an output keeps, with the
+//! rest dimmed.
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::{
+    encode_png, load_image, pick_target_size, LowresConfig, PngOptions, Region, ResizeMode,
+    PROXY_EDGE,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Brightness kept outside the crop.
+const DIM: f32 = 0.35;
+const BORDER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const THIRDS: Rgba<u8> = Rgba([255, 255, 255, 200]);
+const GOLDEN: Rgba<u8> = Rgba([255, 196, 0, 220]);
+const CENTER: Rgba<u8> = Rgba([0, 220, 255, 220]);
+/// 1/φ: golden-ratio lines sit this far in from either edge.
+const PHI_INV: f64 = 0.618_033_988_749_895;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Guide {
+    /// Lines at a third and two thirds of each side.
+    Thirds,
+    /// Lines dividing each side by the golden ratio, from both ends.
+    GoldenRatio,
+    /// A cross marking the center.
+    CenterCross,
+}
+
+impl Display for Guide {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Guide::Thirds => "thirds",
+            Guide::GoldenRatio => "golden",
+            Guide::CenterCross => "center",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Guide {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "thirds" => Ok(Guide::Thirds),
+            "golden" | "golden-ratio" | "phi" => Ok(Guide::GoldenRatio),
+            "center" | "centre" | "cross" => Ok(Guide::CenterCross),
+            other => Err(anyhow::anyhow!("Unknown guide {:?}", other)),
+        }
+    }
+}
+
+/// The region of `img` an output made with `config` shows: its `crop`, then
+/// the center-crop of `ResizeMode::Cover` on the resize path.
+pub fn target_crop(img: &DynamicImage, config: &LowresConfig) -> Result<Region> {
+    let (w, h) = img.dimensions();
+    let mut region = config.crop.and_then(|c| c.clip(w, h)).unwrap_or(Region {
+        x: 0,
+        y: 0,
+        width: w,
+        height: h,
+    });
+    let resizes = config.block_size().is_none() || config.no_pixelate == Some(true);
+    if config.mode == Some(ResizeMode::Cover) && resizes && config.no_resize != Some(true) {
+        let cropped = region.crop(img)?;
+        let dpi = config.dpi.unwrap_or(300);
+        let (width, height) = config.target_dimensions(dpi)?;
+        let (tw, th) = pick_target_size(
+            cropped.dimensions(),
+            width,
+            height,
+            config.scale,
+            ResizeMode::Cover,
+            config.default_size.unwrap_or_default(),
+        )?;
+        // The largest part of the region with the output's aspect, centered.
+        let (rw, rh) = (region.width as f64, region.height as f64);
+        let fit = (rw / tw as f64).min(rh / th as f64);
+        let cw = ((tw as f64 * fit).round() as u32).clamp(1, region.width);
+        let ch = ((th as f64 * fit).round() as u32).clamp(1, region.height);
+        region = Region {
+            x: region.x + (region.width - cw) / 2,
+            y: region.y + (region.height - ch) / 2,
+            width: cw,
+            height: ch,
+        };
+    }
+    Ok(region)
+}
+
+/// A preview of `input`, at most `PROXY_EDGE` pixels on its longest edge,
+/// with `guides` drawn over the region `config` keeps and the rest dimmed,
+/// as a PNG.
+pub fn render_guides(input: &PathBuf, config: &LowresConfig, guides: &[Guide]) -> Result<Vec<u8>> {
+    let img = load_image(input)?;
+    let config = config.clone().resolve_percentages(img.dimensions());
+    let crop = target_crop(&img, &config)?;
+    let (w, h) = img.dimensions();
+    let mut preview = if w.max(h) > PROXY_EDGE {
+        img.thumbnail(PROXY_EDGE, PROXY_EDGE).to_rgba8()
+    } else {
+        img.to_rgba8()
+    };
+    let scale = preview.width() as f64 / w as f64;
+    let at = |v: u32| (v as f64 * scale).round() as i64;
+    let (left, top) = (at(crop.x), at(crop.y));
+    let (right, bottom) = (at(crop.x + crop.width), at(crop.y + crop.height));
+
+    for (x, y, px) in preview.enumerate_pixels_mut() {
+        let (x, y) = (x as i64, y as i64);
+        if x < left || x >= right || y < top || y >= bottom {
+            for c in &mut px.0[..3] {
+                *c = (*c as f32 * DIM) as u8;
+            }
+        }
+    }
+
+    let thickness = (preview.width().max(preview.height()) / 600).max(1) as i64;
+    let (cw, ch) = ((right - left) as f64, (bottom - top) as f64);
+    let vertical = |img: &mut RgbaImage, f: f64, color| {
+        let x = left + (cw * f).round() as i64;
+        fill(img, x - thickness / 2, top, thickness, bottom - top, color);
+    };
+    let horizontal = |img: &mut RgbaImage, f: f64, color| {
+        let y = top + (ch * f).round() as i64;
+        fill(img, left, y - thickness / 2, right - left, thickness, color);
+    };
+    for guide in guides {
+        match guide {
+            Guide::Thirds => {
+                for f in [1.0 / 3.0, 2.0 / 3.0] {
+                    vertical(&mut preview, f, THIRDS);
+                    horizontal(&mut preview, f, THIRDS);
+                }
+            }
+            Guide::GoldenRatio => {
+                for f in [1.0 - PHI_INV, PHI_INV] {
+                    vertical(&mut preview, f, GOLDEN);
+                    horizontal(&mut preview, f, GOLDEN);
+                }
+            }
+            Guide::CenterCross => {
+                let arm = (cw.min(ch) * 0.05).round() as i64;
+                let (cx, cy) = (left + (cw / 2.0) as i64, top + (ch / 2.0) as i64);
+                let t = thickness;
+                fill(&mut preview, cx - arm, cy - t / 2, 2 * arm, t, CENTER);
+                fill(&mut preview, cx - t / 2, cy - arm, t, 2 * arm, CENTER);
+            }
+        }
+    }
+    // The crop's outline, just inside it.
+    let t = thickness;
+    fill(&mut preview, left, top, right - left, t, BORDER);
+    fill(&mut preview, left, bottom - t, right - left, t, BORDER);
+    fill(&mut preview, left, top, t, bottom - top, BORDER);
+    fill(&mut preview, right - t, top, t, bottom - top, BORDER);
+
+    encode_png(
+        &preview,
+        &PngOptions {
+            drop_alpha: false,
+            dpi: None,
+            srgb: true,
+            metadata: None,
+            settings: None,
+            compression: png::Compression::Fast,
+            adaptive_filter: false,
+            interlace: false,
+        },
+    )
+}
+
+/// Blend `color` over a `w`×`h` rectangle of `img`, clipped to it.
+fn fill(img: &mut RgbaImage, x: i64, y: i64, w: i64, h: i64, color: Rgba<u8>) {
+    let alpha = color[3] as f32 / 255.0;
+    let (iw, ih) = (img.width() as i64, img.height() as i64);
+    for py in y.max(0)..(y + h).min(ih) {
+        for px in x.max(0)..(x + w).min(iw) {
+            let p = img.get_pixel_mut(px as u32, py as u32);
+            for c in 0..3 {
+                p[c] = (p[c] as f32 * (1.0 - alpha) + color[c] as f32 * alpha).round() as u8;
+            }
+            p[3] = p[3].max(color[3]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_crop_follows_crop_and_cover() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(400, 200));
+        let whole = target_crop(&img, &LowresConfig::default()).unwrap();
+        assert_eq!((whole.width, whole.height), (400, 200));
+
+        let cover = LowresConfig {
+            width: Some(100),
+            height: Some(100),
+            mode: Some(ResizeMode::Cover),
+            ..Default::default()
+        };
+        let square = target_crop(&img, &cover).unwrap();
+        assert_eq!(square.to_string(), "100,0,200x200");
+
+        let cropped = LowresConfig {
+            crop: Some("40,20,200x100".parse().unwrap()),
+            width: Some(50),
+            height: Some(100),
+            ..cover.clone()
+        };
+        assert_eq!(
+            target_crop(&img, &cropped).unwrap().to_string(),
+            "115,20,50x100"
+        );
+
+        // Pixelation ignores the resize mode.
+        let pixelated = LowresConfig {
+            block: Some(8),
+            ..cover
+        };
+        assert_eq!(target_crop(&img, &pixelated).unwrap(), whole);
+    }
+}
@@ -0,0 +1,279 @@
+//! Color heatmaps: how many distinct colors, or how much color variance,
+//! each pixelation block of a source holds, drawn as a heatmap over a dimmed
+//! preview. Busy blocks are the ones that force a large palette, and where
+//! smaller blocks would keep detail that larger ones average away.
+
+use image::{DynamicImage, GenericImageView, Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use super::font::{draw_text, LINE_HEIGHT};
+use super::{encode_png, load_image, BlockSize, LowresConfig, PngOptions, PROXY_EDGE};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Blocks along the longest edge when the config sets no block size.
+const DEFAULT_BLOCKS: u32 = 64;
+/// Brightness kept of the source under the heat.
+const DIM: f32 = 0.4;
+/// Opacity of the heat over the source.
+const HEAT_ALPHA: f32 = 0.7;
+/// The color ramp, from the calmest block to the busiest.
+const RAMP: [[f32; 3]; 5] = [
+    [20.0, 20.0, 90.0],
+    [120.0, 30.0, 140.0],
+    [220.0, 50.0, 50.0],
+    [250.0, 150.0, 30.0],
+    [255.0, 240.0, 120.0],
+];
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HeatmapMetric {
+    /// Distinct colors in each block.
+    Colors,
+    /// Variance of the colors in each block, averaged over R, G and B.
+    Variance,
+}
+
+impl Display for HeatmapMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            HeatmapMetric::Colors => "colors",
+            HeatmapMetric::Variance => "variance",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for HeatmapMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "colors" | "colours" => Ok(HeatmapMetric::Colors),
+            "variance" => Ok(HeatmapMetric::Variance),
+            other => Err(anyhow::anyhow!("Unknown heatmap metric {:?}", other)),
+        }
+    }
+}
+
+/// The block values a heatmap shows.
+#[derive(Serialize, Debug, Clone)]
+pub struct HeatmapStats {
+    pub metric: HeatmapMetric,
+    pub block_width: u32,
+    pub block_height: u32,
+    /// Blocks across and down.
+    pub columns: u32,
+    pub rows: u32,
+    pub min: f64,
+    pub median: f64,
+    pub max: f64,
+}
+
+/// The metric of each `block` of `img`, row by row.
+fn measure(img: &RgbaImage, block: BlockSize, metric: HeatmapMetric) -> (u32, u32, Vec<f64>) {
+    let columns = img.width().div_ceil(block.width);
+    let rows = img.height().div_ceil(block.height);
+    let values = (0..rows)
+        .into_par_iter()
+        .flat_map_iter(|row| {
+            (0..columns).map(move |column| {
+                let x0 = column * block.width;
+                let y0 = row * block.height;
+                let pixels = (y0..(y0 + block.height).min(img.height())).flat_map(|y| {
+                    (x0..(x0 + block.width).min(img.width())).map(move |x| img.get_pixel(x, y))
+                });
+                match metric {
+                    HeatmapMetric::Colors => pixels
+                        .map(|p| [p[0], p[1], p[2]])
+                        .collect::<HashSet<_>>()
+                        .len() as f64,
+                    HeatmapMetric::Variance => variance(pixels),
+                }
+            })
+        })
+        .collect();
+    (columns, rows, values)
+}
+
+fn variance<'a>(pixels: impl Iterator<Item = &'a Rgba<u8>>) -> f64 {
+    let (mut n, mut sum, mut sum_sq) = (0.0, [0.0; 3], [0.0; 3]);
+    for p in pixels {
+        n += 1.0;
+        for c in 0..3 {
+            let v = p[c] as f64;
+            sum[c] += v;
+            sum_sq[c] += v * v;
+        }
+    }
+    (0..3)
+        .map(|c| sum_sq[c] / n - (sum[c] / n).powi(2))
+        .sum::<f64>()
+        .max(0.0)
+        / 3.0
+}
+
+/// Where `t` in 0..=1 falls on the ramp.
+fn heat(t: f64) -> [f32; 3] {
+    let at = t.clamp(0.0, 1.0) as f32 * (RAMP.len() - 1) as f32;
+    let i = (at as usize).min(RAMP.len() - 2);
+    let f = at - i as f32;
+    [0, 1, 2].map(|c| RAMP[i][c] + (RAMP[i + 1][c] - RAMP[i][c]) * f)
+}
+
+/// A preview of `input`, at most `PROXY_EDGE` pixels on its longest edge,
+/// with each block of `config`'s size (after its `crop`) colored by
+/// `metric`, as a PNG, and the values shown.
+pub fn render_heatmap(
+    input: &PathBuf,
+    config: &LowresConfig,
+    metric: HeatmapMetric,
+) -> Result<(Vec<u8>, HeatmapStats)> {
+    let mut img = load_image(input)?;
+    let config = &config.clone().resolve_percentages(img.dimensions());
+    if let Some(crop) = &config.crop {
+        crop.check_fits(img.width(), img.height())?;
+        img = img.crop_imm(crop.x, crop.y, crop.width, crop.height);
+    }
+    let (w, h) = img.dimensions();
+    let block = config.block_size().unwrap_or_else(|| {
+        let side = (w.max(h) / DEFAULT_BLOCKS).max(2);
+        BlockSize {
+            width: side,
+            height: side,
+        }
+    });
+    let (columns, rows, values) = measure(&img.to_rgba8(), block, metric);
+
+    let mut sorted = values.clone();
+    sorted.sort_by(f64::total_cmp);
+    let stats = HeatmapStats {
+        metric,
+        block_width: block.width,
+        block_height: block.height,
+        columns,
+        rows,
+        min: sorted[0],
+        median: sorted[sorted.len() / 2],
+        max: sorted[sorted.len() - 1],
+    };
+    // Variance grows with the square of the spread the eye sees.
+    let shade = |v: f64| match metric {
+        HeatmapMetric::Colors if stats.max > 1.0 => (v - 1.0) / (stats.max - 1.0),
+        HeatmapMetric::Variance if stats.max > 0.0 => (v / stats.max).sqrt(),
+        _ => 0.0,
+    };
+
+    let mut preview = preview_of(&img);
+    let scale = w as f64 / preview.width() as f64;
+    for (x, y, px) in preview.enumerate_pixels_mut() {
+        let column = ((x as f64 * scale) as u32 / block.width).min(columns - 1);
+        let row = ((y as f64 * scale) as u32 / block.height).min(rows - 1);
+        let heat = heat(shade(values[(row * columns + column) as usize]));
+        let gray = (0.299 * px[0] as f32 + 0.587 * px[1] as f32 + 0.114 * px[2] as f32) * DIM;
+        for c in 0..3 {
+            px[c] = (gray * (1.0 - HEAT_ALPHA) + heat[c] * HEAT_ALPHA).round() as u8;
+        }
+        px[3] = 255;
+    }
+    draw_legend(&mut preview, &stats);
+
+    let png = encode_png(
+        &preview,
+        &PngOptions {
+            drop_alpha: false,
+            dpi: None,
+            srgb: true,
+            metadata: None,
+            settings: None,
+            compression: png::Compression::Fast,
+            adaptive_filter: false,
+            interlace: false,
+        },
+    )?;
+    Ok((png, stats))
+}
+
+fn preview_of(img: &DynamicImage) -> RgbaImage {
+    if img.width().max(img.height()) > PROXY_EDGE {
+        img.thumbnail(PROXY_EDGE, PROXY_EDGE).to_rgba8()
+    } else {
+        img.to_rgba8()
+    }
+}
+
+/// The ramp and the range it spans, along the bottom edge.
+fn draw_legend(img: &mut RgbaImage, stats: &HeatmapStats) {
+    let scale = (img.width() / 400).max(1);
+    let band = (LINE_HEIGHT + 4) * scale;
+    if img.height() <= band * 2 {
+        return;
+    }
+    let top = img.height() - band;
+    let bar = img.width() / 4;
+    for y in top..img.height() {
+        for x in 0..img.width() {
+            let color = if x < bar {
+                let [r, g, b] = heat(x as f64 / bar.max(2) as f64);
+                Rgba([r as u8, g as u8, b as u8, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            };
+            img.put_pixel(x, y, color);
+        }
+    }
+    let (low, high) = match stats.metric {
+        HeatmapMetric::Colors => (format!("{}", stats.min), format!("{}", stats.max)),
+        HeatmapMetric::Variance => (format!("{:.0}", stats.min), format!("{:.0}", stats.max)),
+    };
+    let caption = format!(
+        "{} to {} {} per {}x{} block, median {:.0}",
+        low, high, stats.metric, stats.block_width, stats.block_height, stats.median
+    );
+    let white = Rgba([255, 255, 255, 255]);
+    draw_text(
+        img,
+        bar + 2 * scale,
+        top + 2 * scale,
+        &caption,
+        scale,
+        white,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_colors_and_variance_per_block() {
+        // A flat left half and a two-color checkered right half.
+        let img = RgbaImage::from_fn(8, 4, |x, y| match (x < 4, (x + y) % 2) {
+            (true, _) => Rgba([100, 100, 100, 255]),
+            (false, 0) => Rgba([0, 0, 0, 255]),
+            (false, _) => Rgba([255, 255, 255, 255]),
+        });
+        let block = BlockSize {
+            width: 4,
+            height: 4,
+        };
+        assert_eq!(
+            measure(&img, block, HeatmapMetric::Colors),
+            (2, 1, vec![1.0, 2.0])
+        );
+        let (_, _, variance) = measure(&img, block, HeatmapMetric::Variance);
+        assert_eq!(variance[0], 0.0);
+        assert!((variance[1] - 127.5 * 127.5).abs() < 1e-6);
+        assert_eq!(heat(0.0), RAMP[0]);
+        assert_eq!(heat(1.0), RAMP[4]);
+        assert_eq!(
+            "colours".parse::<HeatmapMetric>().unwrap(),
+            HeatmapMetric::Colors
+        );
+    }
+}
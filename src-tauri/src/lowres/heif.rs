@@ -0,0 +1,53 @@
+//! HEIC/HEIF input, such as iPhone photos, decoded with libheif. Built with
+//! the `heif` feature, which needs libheif installed.
+
+use image::{DynamicImage, RgbaImage};
+use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+fn decode_error(e: libheif_rs::HeifError) -> anyhow::Error {
+    LowresError::Decode(format!("Failed to decode HEIF image: {}", e)).into()
+}
+
+/// Decode the primary image of an HEIF file as RGBA. libheif applies the
+/// container's rotation and mirroring, so the result is upright.
+pub fn decode_heif(data: &[u8]) -> Result<DynamicImage> {
+    let context = HeifContext::read_from_bytes(data).map_err(decode_error)?;
+    let handle = context.primary_image_handle().map_err(decode_error)?;
+    let image = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(decode_error)?;
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| LowresError::Decode("HEIF image has no RGBA plane".into()))?;
+
+    // Rows may be padded past their 4 bytes per pixel.
+    let row = plane.width as usize * 4;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|line| &line[..row])
+        .copied()
+        .collect();
+    let rgba = RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .ok_or_else(|| LowresError::Decode("HEIF image data is truncated".into()))?;
+    Ok(DynamicImage::ImageRgba8(rgba))
+}
+
+/// Width, height, bits per channel and alpha of an HEIF file's primary image,
+/// without decoding it.
+pub fn probe_heif(data: &[u8]) -> Result<(u32, u32, u16, bool)> {
+    let context = HeifContext::read_from_bytes(data).map_err(decode_error)?;
+    let handle = context.primary_image_handle().map_err(decode_error)?;
+    Ok((
+        handle.width(),
+        handle.height(),
+        handle.luma_bits_per_pixel() as u16,
+        handle.has_alpha_channel(),
+    ))
+}
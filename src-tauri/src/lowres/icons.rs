@@ -0,0 +1,253 @@
+//! Application icon containers: Windows `.ico` and macOS `.icns`, packing the
+//! PNGs of a multi-size export into one file. Both formats store PNG images
+//! as is, so every size keeps the encoder's output exactly.
+
+use std::path::Path;
+
+use super::batch::{write_item, BatchItem, CollisionAction, OnCollision};
+use super::{
+    load_source, render_source, LowresConfig, LowresError, OutputSpec, PreviewQuality,
+    ProcessReport,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum IconFormat {
+    Ico,
+    Icns,
+}
+
+/// ICNS element types holding PNG data, by pixel size. Sizes with a Retina
+/// (@2x) type are written under both.
+const ICNS_TYPES: [(u32, &[&[u8; 4]]); 7] = [
+    (16, &[b"icp4"]),
+    (32, &[b"icp5", b"ic11"]),
+    (64, &[b"icp6", b"ic12"]),
+    (128, &[b"ic07"]),
+    (256, &[b"ic08", b"ic13"]),
+    (512, &[b"ic09", b"ic14"]),
+    (1024, &[b"ic10"]),
+];
+
+impl IconFormat {
+    /// The icon format `output`'s extension names, if any.
+    pub fn of(output: &Path) -> Option<Self> {
+        let ext = output.extension()?.to_string_lossy().to_ascii_lowercase();
+        match ext.as_str() {
+            "ico" => Some(IconFormat::Ico),
+            "icns" => Some(IconFormat::Icns),
+            _ => None,
+        }
+    }
+
+    /// The sizes an icon gets when the config lists none.
+    pub fn default_sizes(self) -> Vec<OutputSpec> {
+        let sizes: &[u32] = match self {
+            IconFormat::Ico => &[16, 24, 32, 48, 64, 128, 256],
+            IconFormat::Icns => &[16, 32, 64, 128, 256, 512, 1024],
+        };
+        sizes
+            .iter()
+            .map(|&n| OutputSpec {
+                width: n,
+                height: n,
+            })
+            .collect()
+    }
+
+    /// Reject sizes the format can't hold.
+    pub fn check(self, sizes: &[OutputSpec]) -> Result<()> {
+        let bad = sizes.iter().find(|s| match self {
+            IconFormat::Ico => s.width > 256 || s.height > 256,
+            IconFormat::Icns => {
+                s.width != s.height || !ICNS_TYPES.iter().any(|(n, _)| *n == s.width)
+            }
+        });
+        match (bad, self) {
+            (None, _) => Ok(()),
+            (Some(size), IconFormat::Ico) => Err(LowresError::InvalidConfig(format!(
+                "ICO images are at most 256x256, got {}",
+                size
+            ))
+            .into()),
+            (Some(size), IconFormat::Icns) => Err(LowresError::InvalidConfig(format!(
+                "ICNS sizes are 16, 32, 64, 128, 256, 512 and 1024, got {}",
+                size
+            ))
+            .into()),
+        }
+    }
+
+    /// Pack encoded PNGs, one per size, into an icon file.
+    fn encode(self, images: &[(OutputSpec, Vec<u8>)]) -> Vec<u8> {
+        match self {
+            IconFormat::Ico => encode_ico(images),
+            IconFormat::Icns => encode_icns(images),
+        }
+    }
+}
+
+fn encode_ico(images: &[(OutputSpec, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // 1: icon, 2: cursor
+    out.extend_from_slice(&(images.len() as u16).to_le_bytes());
+    let mut offset = 6 + 16 * images.len() as u32;
+    for (size, png) in images {
+        // A dimension of 256 is stored as 0.
+        out.push(size.width as u8);
+        out.push(size.height as u8);
+        out.extend_from_slice(&[0, 0]); // no palette, reserved
+        out.extend_from_slice(&1u16.to_le_bytes()); // color planes
+        out.extend_from_slice(&32u16.to_le_bytes()); // bits per pixel
+        out.extend_from_slice(&(png.len() as u32).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        offset += png.len() as u32;
+    }
+    for (_, png) in images {
+        out.extend_from_slice(png);
+    }
+    out
+}
+
+fn encode_icns(images: &[(OutputSpec, Vec<u8>)]) -> Vec<u8> {
+    let mut elements = Vec::new();
+    for (size, png) in images {
+        let types = ICNS_TYPES
+            .iter()
+            .find(|(n, _)| *n == size.width)
+            .map_or(&[][..], |(_, types)| types);
+        for ostype in types {
+            elements.extend_from_slice(*ostype);
+            elements.extend_from_slice(&(8 + png.len() as u32).to_be_bytes());
+            elements.extend_from_slice(png);
+        }
+    }
+    let mut out = Vec::with_capacity(8 + elements.len());
+    out.extend_from_slice(b"icns");
+    out.extend_from_slice(&(8 + elements.len() as u32).to_be_bytes());
+    out.extend_from_slice(&elements);
+    out
+}
+
+/// Render `input` at every size in `config.sizes`, decoding it once, and
+/// write them together as one `format` icon at `output`. The report is that
+/// of the largest size, with the icon file's size in bytes.
+pub fn process_icon(
+    input: &Path,
+    output: &Path,
+    format: IconFormat,
+    config: &LowresConfig,
+    on_collision: OnCollision,
+) -> BatchItem {
+    let item = BatchItem {
+        input: input.to_path_buf(),
+        output: output.to_path_buf(),
+        action: CollisionAction::Created,
+        report: None,
+        error: None,
+    };
+    write_item(item, output, on_collision, || {
+        let sizes = config.sizes.clone().unwrap_or_default();
+        format.check(&sizes)?;
+        let source = load_source(&input.to_path_buf())?;
+        let mut images = Vec::new();
+        let mut largest: Option<ProcessReport> = None;
+        for size in sizes {
+            let config = LowresConfig {
+                width: Some(size.width),
+                height: Some(size.height),
+                sizes: None,
+                // Icons keep their alpha.
+                matte: None,
+                ..config.clone()
+            };
+            let (png, report) = render_source(&source, config, PreviewQuality::Full, &mut |_| {})?;
+            images.push((size, png));
+            if largest.as_ref().is_none_or(|r| report.width > r.width) {
+                largest = Some(report);
+            }
+        }
+        let encoded = format.encode(&images);
+        let mut report = largest.ok_or_else(|| anyhow::anyhow!("No icon sizes given"))?;
+        report.bytes = encoded.len() as u64;
+        report.matte = None;
+        Ok((encoded, report))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_pngs_into_ico_and_icns() {
+        let size = |n| OutputSpec {
+            width: n,
+            height: n,
+        };
+        let images = [(size(16), vec![1u8; 10]), (size(256), vec![2u8; 20])];
+
+        let ico = encode_ico(&images);
+        assert_eq!(&ico[..6], &[0, 0, 1, 0, 2, 0]);
+        // Second entry: 256 stored as 0, 20 bytes at offset 6 + 32 + 10.
+        assert_eq!(&ico[22..24], &[0, 0]);
+        assert_eq!(u32::from_le_bytes(ico[30..34].try_into().unwrap()), 20);
+        assert_eq!(u32::from_le_bytes(ico[34..38].try_into().unwrap()), 48);
+        assert_eq!(ico.len(), 48 + 20);
+
+        let icns = encode_icns(&images);
+        assert_eq!(&icns[..4], b"icns");
+        assert_eq!(
+            u32::from_be_bytes(icns[4..8].try_into().unwrap()) as usize,
+            icns.len()
+        );
+        // 16 as icp4, 256 as ic08 and ic13.
+        assert_eq!(&icns[8..12], b"icp4");
+        assert_eq!(icns.len(), 8 + (8 + 10) + 2 * (8 + 20));
+
+        assert!(IconFormat::Ico.check(&[size(512)]).is_err());
+        assert!(IconFormat::Icns.check(&[size(48)]).is_err());
+        assert!(IconFormat::Icns
+            .check(&IconFormat::Icns.default_sizes())
+            .is_ok());
+        assert_eq!(
+            IconFormat::of(Path::new("app.ICNS")),
+            Some(IconFormat::Icns)
+        );
+    }
+
+    #[test]
+    fn strips_source_metadata_from_every_icon_size() {
+        use super::super::tests::{carries_source_metadata, png_chunks, tagged_png};
+
+        let dir = std::env::temp_dir().join("lowres_icon_strip_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("source.png");
+        std::fs::write(&input, tagged_png(1)).unwrap();
+        for format in [IconFormat::Ico, IconFormat::Icns] {
+            let output = dir.join("icon");
+            let config = LowresConfig {
+                sizes: Some(format.default_sizes()),
+                strip_metadata: Some(true),
+                ..Default::default()
+            };
+            let item = process_icon(&input, &output, format, &config, OnCollision::Overwrite);
+            assert!(item.error.is_none(), "{:?}", item.error);
+            let icon = std::fs::read(&output).unwrap();
+            assert!(!carries_source_metadata(&icon));
+            let pngs: Vec<_> = icon
+                .windows(8)
+                .enumerate()
+                .filter(|(_, w)| w == b"\x89PNG\r\n\x1a\n")
+                .map(|(at, _)| png_chunks(&icon[at..]))
+                .collect();
+            assert!(!pngs.is_empty());
+            for chunks in pngs {
+                assert_eq!(chunks, ["IHDR", "IDAT", "IEND"]);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1007 @@
+//! Lossless application of a JPEG's EXIF orientation. Like `jpegtran`, the
+//! rotation is done on the quantized DCT coefficients, so the image is not
+//! decoded and re-compressed and no generation loss is introduced. The
+//! orientation tag is reset to 1 afterwards.
+//!
+//! Only sequential Huffman JPEGs (baseline and extended) are handled. Mirroring
+//! can only move whole MCUs, so a partial MCU column or row on a mirrored edge
+//! is trimmed away, as `jpegtran -trim` does.
+
+use std::path::Path;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Natural (row-major) index of the n-th coefficient in zigzag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+type Block = [i16; 64];
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    /// Quantization table index.
+    tq: usize,
+    /// Blocks across and down, padded to whole MCUs.
+    bw: usize,
+    bh: usize,
+    /// Coefficients in natural order.
+    blocks: Vec<Block>,
+}
+
+struct Jpeg {
+    /// SOF marker (baseline or extended sequential).
+    sof: u8,
+    precision: u8,
+    width: usize,
+    height: usize,
+    components: Vec<Component>,
+    /// Precision flag and natural-order values of each defined table.
+    qtables: [Option<(u8, [u16; 64])>; 4],
+    /// APPn and COM segments (marker, body) in file order.
+    segments: Vec<(u8, Vec<u8>)>,
+}
+
+impl Jpeg {
+    fn max_sampling(&self) -> (usize, usize) {
+        let h = self.components.iter().map(|c| c.h).max().unwrap_or(1);
+        let v = self.components.iter().map(|c| c.v).max().unwrap_or(1);
+        (h, v)
+    }
+
+    /// Blocks a component covers within the image, without MCU padding.
+    fn component_blocks(&self, c: &Component) -> (usize, usize) {
+        let (hmax, vmax) = self.max_sampling();
+        let w = (self.width * c.h).div_ceil(hmax);
+        let h = (self.height * c.v).div_ceil(vmax);
+        (w.div_ceil(8), h.div_ceil(8))
+    }
+}
+
+/// Which way the coefficients move. Mirroring is applied to the source axes,
+/// then the result is transposed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Transform {
+    transpose: bool,
+    mirror_x: bool,
+    mirror_y: bool,
+}
+
+impl Transform {
+    /// The transform that displays an image with EXIF `orientation` upright.
+    fn for_orientation(orientation: u32) -> Option<Transform> {
+        let (transpose, mirror_x, mirror_y) = match orientation {
+            2 => (false, true, false),
+            3 => (false, true, true),
+            4 => (false, false, true),
+            5 => (true, false, false),
+            6 => (true, false, true),
+            7 => (true, true, true),
+            8 => (true, true, false),
+            _ => return None,
+        };
+        Some(Transform {
+            transpose,
+            mirror_x,
+            mirror_y,
+        })
+    }
+}
+
+/// Apply the EXIF orientation of the JPEG at `input` losslessly and write the
+/// result to `output`, which may be `input`. Returns `false` if the image was
+/// already upright, in which case it is copied unchanged.
+pub fn rotate_jpeg(input: &Path, output: &Path) -> Result<bool> {
+    let data = std::fs::read(input)
+        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", input, e))?;
+    let (rotated, data) = match rotate_jpeg_data(&data)? {
+        Some(rotated) => (true, rotated),
+        None => (false, data),
+    };
+    if !rotated && input == output {
+        return Ok(false);
+    }
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let temp = output.with_file_name(format!(".{}.rotate", name));
+    std::fs::write(&temp, data)
+        .and_then(|_| std::fs::rename(&temp, output))
+        .map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            anyhow::anyhow!("Failed to create {:?}: {}", output, e)
+        })?;
+    Ok(rotated)
+}
+
+/// The upright JPEG, or `None` if there is no orientation to apply.
+fn rotate_jpeg_data(data: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut jpeg = parse(data)?;
+    let orientation = jpeg
+        .segments
+        .iter_mut()
+        .filter(|(marker, _)| *marker == 0xe1)
+        .find_map(|(_, body)| reset_orientation(body));
+    let Some(transform) = orientation.and_then(Transform::for_orientation) else {
+        return Ok(None);
+    };
+    let jpeg = apply(jpeg, transform)?;
+    Ok(Some(encode(&jpeg)))
+}
+
+/// Set the Orientation tag of an APP1 Exif segment to 1 in place, returning
+/// the value it had.
+fn reset_orientation(app1: &mut [u8]) -> Option<u32> {
+    if !app1.starts_with(b"Exif\0\0") {
+        return None;
+    }
+    let base = 6;
+    let le = match app1.get(base..base + 2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |d: &[u8], at: usize| -> Option<usize> {
+        let b = [*d.get(base + at)?, *d.get(base + at + 1)?];
+        Some(if le {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        } as usize)
+    };
+    let u32_at = |d: &[u8], at: usize| -> Option<usize> {
+        let b: [u8; 4] = d.get(base + at..base + at + 4)?.try_into().ok()?;
+        Some(if le {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        } as usize)
+    };
+
+    let ifd0 = u32_at(app1, 4)?;
+    for i in 0..u16_at(app1, ifd0)? {
+        let entry = ifd0 + 2 + 12 * i;
+        if u16_at(app1, entry)? == 0x0112 {
+            let value = u16_at(app1, entry + 8)? as u32;
+            let one = if le {
+                1u16.to_le_bytes()
+            } else {
+                1u16.to_be_bytes()
+            };
+            app1.get_mut(base + entry + 8..base + entry + 10)?
+                .copy_from_slice(&one);
+            return Some(value);
+        }
+    }
+    None
+}
+
+// --- Decoding to coefficients ---
+
+struct Huffman {
+    maxcode: [i32; 17],
+    valptr: [i32; 17],
+    mincode: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(bits: &[u8], values: &[u8]) -> Huffman {
+        let mut table = Huffman {
+            maxcode: [-1; 17],
+            valptr: [0; 17],
+            mincode: [0; 17],
+            values: values.to_vec(),
+        };
+        let (mut code, mut k) = (0i32, 0i32);
+        for len in 1..=16 {
+            let n = bits[len - 1] as i32;
+            if n > 0 {
+                table.valptr[len] = k;
+                table.mincode[len] = code;
+                code += n;
+                k += n;
+                table.maxcode[len] = code - 1;
+            }
+            code <<= 1;
+        }
+        table
+    }
+
+    fn decode(&self, r: &mut BitReader) -> Result<u8> {
+        let mut code = 0i32;
+        for len in 1..=16 {
+            code = (code << 1) | r.bit()? as i32;
+            if code <= self.maxcode[len] {
+                let index = self.valptr[len] + code - self.mincode[len];
+                return self
+                    .values
+                    .get(index as usize)
+                    .copied()
+                    .ok_or_else(|| anyhow::anyhow!("Corrupt JPEG data"));
+            }
+        }
+        anyhow::bail!("Corrupt JPEG data")
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bits: u32,
+    count: u32,
+}
+
+impl BitReader<'_> {
+    fn bit(&mut self) -> Result<u32> {
+        if self.count == 0 {
+            self.bits = self.next_byte()?;
+            self.count = 8;
+        }
+        self.count -= 1;
+        Ok((self.bits >> self.count) & 1)
+    }
+
+    fn next_byte(&mut self) -> Result<u32> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG data"))?;
+        if byte != 0xff {
+            self.pos += 1;
+            return Ok(byte as u32);
+        }
+        match self.data.get(self.pos + 1) {
+            Some(0) => {
+                self.pos += 2;
+                Ok(0xff)
+            }
+            // A marker ends the data; decoders pad with zeros.
+            _ => Ok(0),
+        }
+    }
+
+    fn receive(&mut self, n: u8) -> Result<i32> {
+        let mut v = 0;
+        for _ in 0..n {
+            v = (v << 1) | self.bit()? as i32;
+        }
+        Ok(v)
+    }
+
+    /// Drop the leftover bits and step over an RSTn marker.
+    fn restart(&mut self) -> Result<()> {
+        self.count = 0;
+        match self.data.get(self.pos..self.pos + 2) {
+            Some([0xff, 0xd0..=0xd7]) => {
+                self.pos += 2;
+                Ok(())
+            }
+            _ => anyhow::bail!("Missing JPEG restart marker"),
+        }
+    }
+}
+
+/// Sign-extend an `n`-bit magnitude category value.
+fn extend(v: i32, n: u8) -> i32 {
+    if n == 0 || v >= 1 << (n - 1) {
+        v
+    } else {
+        v - (1 << n) + 1
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Result<usize> {
+    match data.get(at..at + 2) {
+        Some(b) => Ok(u16::from_be_bytes([b[0], b[1]]) as usize),
+        None => anyhow::bail!("Truncated JPEG"),
+    }
+}
+
+fn parse(data: &[u8]) -> Result<Jpeg> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        anyhow::bail!("Not a JPEG file");
+    }
+    let mut jpeg: Option<Jpeg> = None;
+    let mut qtables: [Option<(u8, [u16; 64])>; 4] = [None; 4];
+    let mut huffman: [[Option<Huffman>; 4]; 2] = Default::default();
+    let mut segments = Vec::new();
+    let mut restart_interval = 0;
+    let mut scanned = false;
+
+    let mut pos = 2;
+    loop {
+        if data.get(pos) != Some(&0xff) {
+            anyhow::bail!("Malformed JPEG marker");
+        }
+        let marker = *data
+            .get(pos + 1)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG"))?;
+        pos += 2;
+        match marker {
+            0xff => {
+                pos -= 1;
+                continue;
+            }
+            0xd9 => break,
+            0x01 | 0xd0..=0xd7 => continue,
+            _ => {}
+        }
+        let len = be16(data, pos)?;
+        let body = data
+            .get(pos + 2..pos + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG"))?;
+        pos += len;
+
+        match marker {
+            0xc0 | 0xc1 => jpeg = Some(parse_frame(marker, body)?),
+            0xc2 | 0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                anyhow::bail!("Only sequential Huffman JPEGs can be rotated losslessly")
+            }
+            0xc4 => parse_huffman(body, &mut huffman)?,
+            0xdb => parse_quantization(body, &mut qtables)?,
+            0xdd => restart_interval = be16(body, 0)?,
+            0xda => {
+                let frame = jpeg
+                    .as_mut()
+                    .ok_or_else(|| anyhow::anyhow!("JPEG scan before frame header"))?;
+                pos = decode_scan(data, pos, body, frame, &huffman, restart_interval)?;
+                scanned = true;
+            }
+            0xe0..=0xef | 0xfe => segments.push((marker, body.to_vec())),
+            _ => {}
+        }
+    }
+
+    let mut jpeg = jpeg.ok_or_else(|| anyhow::anyhow!("JPEG has no frame header"))?;
+    if !scanned {
+        anyhow::bail!("JPEG has no image data");
+    }
+    for c in &jpeg.components {
+        if qtables[c.tq].is_none() {
+            anyhow::bail!("JPEG uses an undefined quantization table");
+        }
+    }
+    jpeg.qtables = qtables;
+    jpeg.segments = segments;
+    Ok(jpeg)
+}
+
+fn parse_frame(sof: u8, body: &[u8]) -> Result<Jpeg> {
+    let count = *body
+        .get(5)
+        .ok_or_else(|| anyhow::anyhow!("Truncated JPEG frame header"))? as usize;
+    if body.len() < 6 + 3 * count || count == 0 {
+        anyhow::bail!("Truncated JPEG frame header");
+    }
+    let mut jpeg = Jpeg {
+        sof,
+        precision: body[0],
+        height: be16(body, 1)?,
+        width: be16(body, 3)?,
+        components: Vec::with_capacity(count),
+        qtables: [None; 4],
+        segments: Vec::new(),
+    };
+    if jpeg.width == 0 || jpeg.height == 0 {
+        anyhow::bail!("JPEGs with a DNL height are not supported");
+    }
+    for i in 0..count {
+        let c = &body[6 + 3 * i..9 + 3 * i];
+        let (h, v) = ((c[1] >> 4) as usize, (c[1] & 15) as usize);
+        if !(1..=4).contains(&h) || !(1..=4).contains(&v) || c[2] > 3 {
+            anyhow::bail!("Invalid JPEG component");
+        }
+        jpeg.components.push(Component {
+            id: c[0],
+            h,
+            v,
+            tq: c[2] as usize,
+            bw: 0,
+            bh: 0,
+            blocks: Vec::new(),
+        });
+    }
+    let (hmax, vmax) = jpeg.max_sampling();
+    let mcus_x = jpeg.width.div_ceil(8 * hmax);
+    let mcus_y = jpeg.height.div_ceil(8 * vmax);
+    for c in &mut jpeg.components {
+        c.bw = mcus_x * c.h;
+        c.bh = mcus_y * c.v;
+        c.blocks = vec![[0; 64]; c.bw * c.bh];
+    }
+    Ok(jpeg)
+}
+
+fn parse_huffman(mut body: &[u8], tables: &mut [[Option<Huffman>; 4]; 2]) -> Result<()> {
+    while !body.is_empty() {
+        let (class, id) = ((body[0] >> 4) as usize, (body[0] & 15) as usize);
+        let bits = body
+            .get(1..17)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG Huffman table"))?;
+        let n: usize = bits.iter().map(|&b| b as usize).sum();
+        let values = body
+            .get(17..17 + n)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG Huffman table"))?;
+        if class > 1 || id > 3 {
+            anyhow::bail!("Invalid JPEG Huffman table");
+        }
+        tables[class][id] = Some(Huffman::new(bits, values));
+        body = &body[17 + n..];
+    }
+    Ok(())
+}
+
+fn parse_quantization(mut body: &[u8], tables: &mut [Option<(u8, [u16; 64])>; 4]) -> Result<()> {
+    while !body.is_empty() {
+        let (precision, id) = (body[0] >> 4, (body[0] & 15) as usize);
+        let size = if precision == 0 { 64 } else { 128 };
+        let values = body
+            .get(1..1 + size)
+            .ok_or_else(|| anyhow::anyhow!("Truncated JPEG quantization table"))?;
+        if id > 3 {
+            anyhow::bail!("Invalid JPEG quantization table");
+        }
+        let mut table = [0u16; 64];
+        for (k, &natural) in ZIGZAG.iter().enumerate() {
+            table[natural] = if precision == 0 {
+                values[k] as u16
+            } else {
+                u16::from_be_bytes([values[2 * k], values[2 * k + 1]])
+            };
+        }
+        tables[id] = Some((precision, table));
+        body = &body[1 + size..];
+    }
+    Ok(())
+}
+
+/// Decode one scan starting at `pos`; returns the position of the next marker.
+fn decode_scan(
+    data: &[u8],
+    pos: usize,
+    header: &[u8],
+    jpeg: &mut Jpeg,
+    huffman: &[[Option<Huffman>; 4]; 2],
+    restart_interval: usize,
+) -> Result<usize> {
+    let count = *header
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("Truncated JPEG scan header"))? as usize;
+    let spectral = header
+        .get(1 + 2 * count..4 + 2 * count)
+        .ok_or_else(|| anyhow::anyhow!("Truncated JPEG scan header"))?;
+    if spectral != [0, 63, 0] {
+        anyhow::bail!("Only sequential Huffman JPEGs can be rotated losslessly");
+    }
+
+    let mut scan = Vec::with_capacity(count);
+    for i in 0..count {
+        let (id, tables) = (header[1 + 2 * i], header[2 + 2 * i]);
+        let index = jpeg
+            .components
+            .iter()
+            .position(|c| c.id == id)
+            .ok_or_else(|| anyhow::anyhow!("JPEG scan names an unknown component"))?;
+        let dc = huffman[0][(tables >> 4) as usize & 3].as_ref();
+        let ac = huffman[1][(tables & 15) as usize & 3].as_ref();
+        match (dc, ac) {
+            (Some(dc), Some(ac)) => scan.push((index, dc, ac)),
+            _ => anyhow::bail!("JPEG scan uses an undefined Huffman table"),
+        }
+    }
+
+    // A single-component scan visits that component's own blocks, one per MCU.
+    let (mcus_x, mcus_y) = if count == 1 {
+        jpeg.component_blocks(&jpeg.components[scan[0].0])
+    } else {
+        let (hmax, vmax) = jpeg.max_sampling();
+        (
+            jpeg.width.div_ceil(8 * hmax),
+            jpeg.height.div_ceil(8 * vmax),
+        )
+    };
+
+    let mut r = BitReader {
+        data,
+        pos,
+        bits: 0,
+        count: 0,
+    };
+    let mut predictions = vec![0i32; count];
+    for m in 0..mcus_x * mcus_y {
+        if restart_interval > 0 && m > 0 && m % restart_interval == 0 {
+            r.restart()?;
+            predictions.fill(0);
+        }
+        let (mx, my) = (m % mcus_x, m / mcus_x);
+        for (&(index, dc, ac), prediction) in scan.iter().zip(&mut predictions) {
+            let c = &mut jpeg.components[index];
+            let (hs, vs) = if count == 1 { (1, 1) } else { (c.h, c.v) };
+            for v in 0..vs {
+                for h in 0..hs {
+                    let (bx, by) = (mx * hs + h, my * vs + v);
+                    let block = &mut c.blocks[by * c.bw + bx];
+                    decode_block(&mut r, block, dc, ac, prediction)?;
+                }
+            }
+        }
+    }
+
+    // Skip to the marker that follows the entropy-coded data.
+    let mut pos = r.pos;
+    while pos + 1 < data.len()
+        && !(data[pos] == 0xff && data[pos + 1] != 0 && !(0xd0..=0xd7).contains(&data[pos + 1]))
+    {
+        pos += 1;
+    }
+    Ok(pos)
+}
+
+fn decode_block(
+    r: &mut BitReader,
+    block: &mut Block,
+    dc: &Huffman,
+    ac: &Huffman,
+    prediction: &mut i32,
+) -> Result<()> {
+    let size = dc.decode(r)?;
+    *prediction += extend(r.receive(size)?, size);
+    block[0] = *prediction as i16;
+
+    let mut k = 1;
+    while k < 64 {
+        let rs = ac.decode(r)?;
+        let (run, size) = ((rs >> 4) as usize, rs & 15);
+        if size == 0 {
+            if run != 15 {
+                break;
+            }
+            k += 16;
+            continue;
+        }
+        k += run;
+        if k > 63 {
+            anyhow::bail!("Corrupt JPEG data");
+        }
+        block[ZIGZAG[k]] = extend(r.receive(size)?, size) as i16;
+        k += 1;
+    }
+    Ok(())
+}
+
+// --- Transforming ---
+
+fn apply(jpeg: Jpeg, t: Transform) -> Result<Jpeg> {
+    let (hmax, vmax) = jpeg.max_sampling();
+    // Mirrored axes lose their partial MCU, which would otherwise lead.
+    let width = if t.mirror_x {
+        jpeg.width / (8 * hmax) * (8 * hmax)
+    } else {
+        jpeg.width
+    };
+    let height = if t.mirror_y {
+        jpeg.height / (8 * vmax) * (8 * vmax)
+    } else {
+        jpeg.height
+    };
+    if width == 0 || height == 0 {
+        anyhow::bail!("Image is smaller than one JPEG block group and can't be rotated losslessly");
+    }
+
+    let components = jpeg
+        .components
+        .iter()
+        .map(|c| {
+            let sbw = if t.mirror_x {
+                width / (8 * hmax) * c.h
+            } else {
+                c.bw
+            };
+            let sbh = if t.mirror_y {
+                height / (8 * vmax) * c.v
+            } else {
+                c.bh
+            };
+            let (bw, bh) = if t.transpose { (sbh, sbw) } else { (sbw, sbh) };
+            let blocks = (0..bh)
+                .flat_map(|oy| (0..bw).map(move |ox| (ox, oy)))
+                .map(|(ox, oy)| {
+                    let (a, b) = if t.transpose { (oy, ox) } else { (ox, oy) };
+                    let sx = if t.mirror_x { sbw - 1 - a } else { a };
+                    let sy = if t.mirror_y { sbh - 1 - b } else { b };
+                    transform_block(&c.blocks[sy * c.bw + sx], t)
+                })
+                .collect();
+            let (h, v) = if t.transpose { (c.v, c.h) } else { (c.h, c.v) };
+            Component {
+                id: c.id,
+                h,
+                v,
+                tq: c.tq,
+                bw,
+                bh,
+                blocks,
+            }
+        })
+        .collect();
+
+    let qtables = jpeg.qtables.map(|table| {
+        table.map(|(precision, q)| {
+            let mut out = q;
+            if t.transpose {
+                for (i, value) in q.iter().enumerate() {
+                    out[(i % 8) * 8 + i / 8] = *value;
+                }
+            }
+            (precision, out)
+        })
+    });
+
+    let (width, height) = if t.transpose {
+        (height, width)
+    } else {
+        (width, height)
+    };
+    Ok(Jpeg {
+        width,
+        height,
+        components,
+        qtables,
+        ..jpeg
+    })
+}
+
+/// Mirroring a block negates its odd frequencies along that axis;
+/// transposing it transposes the coefficients.
+fn transform_block(block: &Block, t: Transform) -> Block {
+    let mut out = [0i16; 64];
+    for (i, &c) in block.iter().enumerate() {
+        let (row, col) = (i / 8, i % 8);
+        let negate = (t.mirror_x && col % 2 == 1) != (t.mirror_y && row % 2 == 1);
+        let c = if negate { -c } else { c };
+        let dst = if t.transpose { col * 8 + row } else { i };
+        out[dst] = c;
+    }
+    out
+}
+
+// --- Encoding ---
+
+/// Canonical Huffman code from JPEG `bits` counts and `values`.
+struct Code {
+    bits: [u8; 16],
+    values: Vec<u8>,
+    /// (code, length) by symbol.
+    codes: Vec<(u16, u8)>,
+}
+
+impl Code {
+    /// Optimal code for the symbol frequencies, limited to 16 bits (JPEG Annex K.2).
+    fn optimal(freq: &[u64; 256]) -> Code {
+        let mut freq: Vec<u64> = freq.iter().copied().chain([1]).collect();
+        let mut size = [0usize; 257];
+        let mut others = [usize::MAX; 257];
+        loop {
+            // The two least frequent live symbols, preferring higher indices on ties.
+            let mut c1 = None;
+            for i in 0..257 {
+                if freq[i] > 0 && c1.is_none_or(|c: usize| freq[i] <= freq[c]) {
+                    c1 = Some(i);
+                }
+            }
+            let mut c2 = None;
+            for i in 0..257 {
+                if freq[i] > 0 && Some(i) != c1 && c2.is_none_or(|c: usize| freq[i] <= freq[c]) {
+                    c2 = Some(i);
+                }
+            }
+            let (Some(mut c1), Some(mut c2)) = (c1, c2) else {
+                break;
+            };
+            freq[c1] += freq[c2];
+            freq[c2] = 0;
+            size[c1] += 1;
+            while others[c1] != usize::MAX {
+                c1 = others[c1];
+                size[c1] += 1;
+            }
+            others[c1] = c2;
+            size[c2] += 1;
+            while others[c2] != usize::MAX {
+                c2 = others[c2];
+                size[c2] += 1;
+            }
+        }
+
+        let mut count = [0usize; 33];
+        for &s in size.iter().filter(|&&s| s > 0) {
+            count[s.min(32)] += 1;
+        }
+        for i in (17..=32).rev() {
+            while count[i] > 0 {
+                let mut j = i - 2;
+                while count[j] == 0 {
+                    j -= 1;
+                }
+                count[i] -= 2;
+                count[i - 1] += 1;
+                count[j + 1] += 2;
+                count[j] -= 1;
+            }
+        }
+        // Drop the reserved all-ones code of the dummy symbol.
+        let mut longest = 16;
+        while count[longest] == 0 {
+            longest -= 1;
+        }
+        count[longest] -= 1;
+
+        let mut values = Vec::new();
+        for len in 1..=32 {
+            values.extend((0..256).filter(|&s| size[s] == len).map(|s| s as u8));
+        }
+        let mut bits = [0u8; 16];
+        for (len, b) in bits.iter_mut().enumerate() {
+            *b = count[len + 1] as u8;
+        }
+
+        let mut codes = vec![(0u16, 0u8); 256];
+        let mut code = 0u16;
+        let mut values_iter = values.iter();
+        for (len, &n) in bits.iter().enumerate() {
+            for _ in 0..n {
+                if let Some(&symbol) = values_iter.next() {
+                    codes[symbol as usize] = (code, len as u8 + 1);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Code {
+            bits,
+            values,
+            codes,
+        }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u8,
+    count: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u16, len: u8) {
+        for i in (0..len).rev() {
+            self.acc = (self.acc << 1) | ((bits >> i) & 1) as u8;
+            self.count += 1;
+            if self.count == 8 {
+                self.out.push(self.acc);
+                if self.acc == 0xff {
+                    self.out.push(0);
+                }
+                self.acc = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Pad the last byte with one bits.
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            let pad = 8 - self.count;
+            self.put((1 << pad) - 1, pad);
+        }
+        self.out
+    }
+}
+
+/// Category and extra bits of a coefficient value.
+fn magnitude(v: i32) -> (u8, u16) {
+    let size = 32 - v.unsigned_abs().leading_zeros();
+    let bits = if v < 0 { v - 1 } else { v } as u32 & ((1u32 << size) - 1);
+    (size as u8, bits as u16)
+}
+
+/// Feed the Huffman symbols of `block` to `emit` as (is_ac, symbol, extra bits, extra length).
+fn block_symbols(block: &Block, prediction: &mut i32, emit: &mut impl FnMut(bool, u8, u16, u8)) {
+    let dc = block[0] as i32;
+    let (size, bits) = magnitude(dc - *prediction);
+    *prediction = dc;
+    emit(false, size, bits, size);
+
+    let mut run = 0;
+    for &natural in &ZIGZAG[1..] {
+        let c = block[natural] as i32;
+        if c == 0 {
+            run += 1;
+            continue;
+        }
+        while run > 15 {
+            emit(true, 0xf0, 0, 0);
+            run -= 16;
+        }
+        let (size, bits) = magnitude(c);
+        emit(true, (run << 4) | size, bits, size);
+        run = 0;
+    }
+    if run > 0 {
+        emit(true, 0, 0, 0);
+    }
+}
+
+/// Visit every block in single-scan order as (component index, block).
+fn for_each_block(jpeg: &Jpeg, mut f: impl FnMut(usize, &Block)) {
+    if let [c] = jpeg.components.as_slice() {
+        let (bw, bh) = jpeg.component_blocks(c);
+        for by in 0..bh {
+            for bx in 0..bw {
+                f(0, &c.blocks[by * c.bw + bx]);
+            }
+        }
+        return;
+    }
+    let (hmax, vmax) = jpeg.max_sampling();
+    let mcus_x = jpeg.width.div_ceil(8 * hmax);
+    let mcus_y = jpeg.height.div_ceil(8 * vmax);
+    for my in 0..mcus_y {
+        for mx in 0..mcus_x {
+            for (i, c) in jpeg.components.iter().enumerate() {
+                for v in 0..c.v {
+                    for h in 0..c.h {
+                        f(i, &c.blocks[(my * c.v + v) * c.bw + mx * c.h + h]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, body: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(body);
+}
+
+/// Write `jpeg` as a single sequential scan with optimized Huffman tables:
+/// table 0 for the first component, table 1 for the others.
+fn encode(jpeg: &Jpeg) -> Vec<u8> {
+    let table = |i: usize| usize::from(i > 0);
+    let tables = if jpeg.components.len() > 1 { 2 } else { 1 };
+
+    let mut freq = [[[0u64; 256]; 2]; 2];
+    let mut predictions = vec![0i32; jpeg.components.len()];
+    for_each_block(jpeg, |i, block| {
+        block_symbols(block, &mut predictions[i], &mut |ac, symbol, _, _| {
+            freq[table(i)][ac as usize][symbol as usize] += 1;
+        });
+    });
+    let codes: Vec<[Code; 2]> = (0..tables)
+        .map(|t| [Code::optimal(&freq[t][0]), Code::optimal(&freq[t][1])])
+        .collect();
+
+    let mut out = vec![0xff, 0xd8];
+    for (marker, body) in &jpeg.segments {
+        segment(&mut out, *marker, body);
+    }
+    for (id, (precision, q)) in jpeg
+        .qtables
+        .iter()
+        .enumerate()
+        .filter_map(|(id, t)| t.map(|t| (id, t)))
+    {
+        let mut body = vec![(precision << 4) | id as u8];
+        for &natural in &ZIGZAG {
+            match precision {
+                0 => body.push(q[natural] as u8),
+                _ => body.extend_from_slice(&q[natural].to_be_bytes()),
+            }
+        }
+        segment(&mut out, 0xdb, &body);
+    }
+
+    let mut sof = vec![jpeg.precision];
+    sof.extend_from_slice(&(jpeg.height as u16).to_be_bytes());
+    sof.extend_from_slice(&(jpeg.width as u16).to_be_bytes());
+    sof.push(jpeg.components.len() as u8);
+    for c in &jpeg.components {
+        sof.extend_from_slice(&[c.id, ((c.h << 4) | c.v) as u8, c.tq as u8]);
+    }
+    segment(&mut out, jpeg.sof, &sof);
+
+    let mut dht = Vec::new();
+    for (t, pair) in codes.iter().enumerate() {
+        for (class, code) in pair.iter().enumerate() {
+            dht.push(((class << 4) | t) as u8);
+            dht.extend_from_slice(&code.bits);
+            dht.extend_from_slice(&code.values);
+        }
+    }
+    segment(&mut out, 0xc4, &dht);
+
+    let mut sos = vec![jpeg.components.len() as u8];
+    for (i, c) in jpeg.components.iter().enumerate() {
+        sos.extend_from_slice(&[c.id, ((table(i) << 4) | table(i)) as u8]);
+    }
+    sos.extend_from_slice(&[0, 63, 0]);
+    segment(&mut out, 0xda, &sos);
+
+    let mut w = BitWriter {
+        out,
+        acc: 0,
+        count: 0,
+    };
+    predictions.fill(0);
+    for_each_block(jpeg, |i, block| {
+        let pair = &codes[table(i)];
+        block_symbols(block, &mut predictions[i], &mut |ac, symbol, bits, len| {
+            let (code, code_len) = pair[ac as usize].codes[symbol as usize];
+            w.put(code, code_len);
+            w.put(bits, len);
+        });
+    });
+    let mut out = w.finish();
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::jpeg::JpegEncoder, GenericImageView, Rgb, RgbImage};
+
+    /// A 32×16 JPEG with an APP1 segment setting `orientation`.
+    fn sample(orientation: u16) -> Vec<u8> {
+        let img = RgbImage::from_fn(32, 16, |x, y| Rgb([x as u8 * 8, y as u8 * 16, 128]));
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 90)
+            .encode_image(&img)
+            .unwrap();
+
+        let mut app1 = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        app1.extend_from_slice(&orientation.to_be_bytes());
+        app1.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let mut out = vec![0xff, 0xd8];
+        segment(&mut out, 0xe1, &app1);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    #[test]
+    fn rotates_like_decoding_with_the_orientation_applied() {
+        let original = sample(6);
+        let rotated = rotate_jpeg_data(&original).unwrap().unwrap();
+
+        let expected = super::super::decode_image(&original).unwrap();
+        let actual = image::load_from_memory(&rotated).unwrap();
+        assert_eq!(actual.dimensions(), (16, 32));
+        assert_eq!(expected.dimensions(), (16, 32));
+        let worst = expected
+            .to_rgb8()
+            .pixels()
+            .zip(actual.to_rgb8().pixels())
+            .flat_map(|(a, b)| (0..3).map(move |i| a[i].abs_diff(b[i])))
+            .max()
+            .unwrap();
+        assert!(worst <= 4, "pixels differ by up to {}", worst);
+
+        // The tag now says upright, so there is nothing left to do.
+        assert!(rotate_jpeg_data(&rotated).unwrap().is_none());
+    }
+
+    #[test]
+    fn transforms_are_lossless_on_the_coefficients() {
+        let original = parse(&sample(1)).unwrap();
+        let half = Transform::for_orientation(3).unwrap();
+        let turned = parse(&encode(&apply(parse(&sample(1)).unwrap(), half).unwrap())).unwrap();
+        let back = apply(turned, half).unwrap();
+        for (a, b) in original.components.iter().zip(&back.components) {
+            assert_eq!(a.blocks, b.blocks);
+        }
+        assert_eq!(original.qtables, back.qtables);
+    }
+}
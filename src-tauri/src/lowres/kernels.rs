@@ -0,0 +1,254 @@
+//! The per-pixel inner loops of pixelation and palette snapping, with NEON
+//! versions on aarch64 (Raspberry Pi and other ARM boards, where lowres is
+//! often run to generate e-ink/LED content) and an SSE2 block sum on x86_64,
+//! where it is always available. Other targets use the portable code, which
+//! the compiler vectorizes as it can. `benches/pixelate.rs` compares them.
+
+use image::Rgba;
+
+/// Per-channel sums of a run of RGBA pixels (`bytes.len()` a multiple of 4).
+/// Each channel of a run of up to 2^24 pixels fits in a `u32`.
+pub fn sum_rgba(bytes: &[u8]) -> [u32; 4] {
+    #[cfg(target_arch = "aarch64")]
+    {
+        neon::sum_rgba(bytes)
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        sse2::sum_rgba(bytes)
+    }
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+    {
+        sum_rgba_scalar(bytes)
+    }
+}
+
+/// `sum_rgba` without vector instructions.
+pub fn sum_rgba_scalar(bytes: &[u8]) -> [u32; 4] {
+    let mut sum = [0u32; 4];
+    for px in bytes.chunks_exact(4) {
+        for (s, &v) in sum.iter_mut().zip(px) {
+            *s += v as u32;
+        }
+    }
+    sum
+}
+
+/// A palette laid out channel by channel for nearest-color search, padded to
+/// a multiple of 8 entries by repeating the last one.
+pub struct NearestColor {
+    r: Vec<u8>,
+    g: Vec<u8>,
+    b: Vec<u8>,
+    len: usize,
+}
+
+impl NearestColor {
+    pub fn new(palette: &[[u8; 3]]) -> Self {
+        let len = palette.len();
+        let padded = len.div_ceil(8) * 8;
+        let channel = |c: usize| -> Vec<u8> {
+            (0..padded)
+                .map(|i| {
+                    palette
+                        .get(i.min(len.saturating_sub(1)))
+                        .map_or(0, |p| p[c])
+                })
+                .collect()
+        };
+        NearestColor {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
+            len,
+        }
+    }
+
+    /// Closest entry by squared RGB distance, the first one on ties. Alpha is
+    /// kept as is, and an empty palette leaves the color unchanged.
+    pub fn nearest(&self, color: Rgba<u8>) -> Rgba<u8> {
+        if self.len == 0 {
+            return color;
+        }
+        #[cfg(target_arch = "aarch64")]
+        let i = neon::nearest_index(&self.r, &self.g, &self.b, color);
+        #[cfg(not(target_arch = "aarch64"))]
+        let i = self.nearest_index_scalar(color);
+        Rgba([self.r[i], self.g[i], self.b[i], color[3]])
+    }
+
+    #[cfg_attr(target_arch = "aarch64", allow(dead_code))]
+    fn nearest_index_scalar(&self, color: Rgba<u8>) -> usize {
+        (0..self.len)
+            .min_by_key(|&i| {
+                let dr = self.r[i] as i32 - color[0] as i32;
+                let dg = self.g[i] as i32 - color[1] as i32;
+                let db = self.b[i] as i32 - color[2] as i32;
+                dr * dr + dg * dg + db * db
+            })
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use image::Rgba;
+    use std::arch::aarch64::*;
+
+    use super::sum_rgba_scalar;
+
+    pub fn sum_rgba(bytes: &[u8]) -> [u32; 4] {
+        // 16 pixels per step; a u16 lane takes 2 bytes per step, so flush
+        // into the u32 accumulators every 128 steps before it can overflow.
+        let chunks = bytes.chunks_exact(64);
+        let tail = sum_rgba_scalar(chunks.remainder());
+        let mut acc = [0u32; 4];
+        // SAFETY: NEON is part of the aarch64 baseline, and every load reads
+        // a full 64-byte chunk.
+        unsafe {
+            let mut total = [vdupq_n_u32(0); 4];
+            let mut partial = [vdupq_n_u16(0); 4];
+            for (step, chunk) in chunks.enumerate() {
+                let px = vld4q_u8(chunk.as_ptr());
+                partial[0] = vpadalq_u8(partial[0], px.0);
+                partial[1] = vpadalq_u8(partial[1], px.1);
+                partial[2] = vpadalq_u8(partial[2], px.2);
+                partial[3] = vpadalq_u8(partial[3], px.3);
+                if step % 128 == 127 {
+                    for c in 0..4 {
+                        total[c] = vpadalq_u16(total[c], partial[c]);
+                        partial[c] = vdupq_n_u16(0);
+                    }
+                }
+            }
+            for c in 0..4 {
+                total[c] = vpadalq_u16(total[c], partial[c]);
+                acc[c] = vaddvq_u32(total[c]) + tail[c];
+            }
+        }
+        acc
+    }
+
+    /// `r`, `g` and `b` have the same length, a multiple of 8.
+    pub fn nearest_index(r: &[u8], g: &[u8], b: &[u8], color: Rgba<u8>) -> usize {
+        let mut best = (u32::MAX, 0);
+        let mut dist = [0u32; 8];
+        // SAFETY: NEON is part of the aarch64 baseline, and each load reads
+        // 8 entries at an offset below the (padded) length.
+        unsafe {
+            let (cr, cg, cb) = (
+                vdup_n_u8(color[0]),
+                vdup_n_u8(color[1]),
+                vdup_n_u8(color[2]),
+            );
+            for start in (0..r.len()).step_by(8) {
+                let dr = vabd_u8(vld1_u8(r.as_ptr().add(start)), cr);
+                let dg = vabd_u8(vld1_u8(g.as_ptr().add(start)), cg);
+                let db = vabd_u8(vld1_u8(b.as_ptr().add(start)), cb);
+                // Squares fit in u16; their sum needs u32.
+                let (sr, sg, sb) = (vmull_u8(dr, dr), vmull_u8(dg, dg), vmull_u8(db, db));
+                let lo = vaddq_u32(
+                    vaddl_u16(vget_low_u16(sr), vget_low_u16(sg)),
+                    vmovl_u16(vget_low_u16(sb)),
+                );
+                let hi = vaddq_u32(
+                    vaddl_u16(vget_high_u16(sr), vget_high_u16(sg)),
+                    vmovl_u16(vget_high_u16(sb)),
+                );
+                vst1q_u32(dist.as_mut_ptr(), lo);
+                vst1q_u32(dist.as_mut_ptr().add(4), hi);
+                for (i, &d) in dist.iter().enumerate() {
+                    if d < best.0 {
+                        best = (d, start + i);
+                    }
+                }
+            }
+        }
+        best.1
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    use super::sum_rgba_scalar;
+
+    pub fn sum_rgba(bytes: &[u8]) -> [u32; 4] {
+        // 4 pixels per step, widened to u16 and folded onto the lanes of two
+        // pixels. A u16 lane takes 2 bytes per step, so flush into the u32
+        // accumulator every 128 steps before it can overflow.
+        let chunks = bytes.chunks_exact(16);
+        let tail = sum_rgba_scalar(chunks.remainder());
+        let mut lanes = [0u32; 8];
+        // SAFETY: SSE2 is part of the x86_64 baseline, and every unaligned
+        // load reads a full 16-byte chunk.
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let widen = |partial| {
+                (
+                    _mm_unpacklo_epi16(partial, zero),
+                    _mm_unpackhi_epi16(partial, zero),
+                )
+            };
+            let (mut lo, mut hi) = (zero, zero);
+            let mut partial = zero;
+            for (step, chunk) in chunks.enumerate() {
+                let px = _mm_loadu_si128(chunk.as_ptr() as *const __m128i);
+                let pairs = _mm_add_epi16(_mm_unpacklo_epi8(px, zero), _mm_unpackhi_epi8(px, zero));
+                partial = _mm_add_epi16(partial, pairs);
+                if step % 128 == 127 {
+                    let (l, h) = widen(partial);
+                    lo = _mm_add_epi32(lo, l);
+                    hi = _mm_add_epi32(hi, h);
+                    partial = zero;
+                }
+            }
+            let (l, h) = widen(partial);
+            _mm_storeu_si128(lanes.as_mut_ptr() as *mut __m128i, _mm_add_epi32(lo, l));
+            _mm_storeu_si128(
+                lanes.as_mut_ptr().add(4) as *mut __m128i,
+                _mm_add_epi32(hi, h),
+            );
+        }
+        [0, 1, 2, 3].map(|c| lanes[c] + lanes[c + 4] + tail[c])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_channels_of_any_length() {
+        for n in [0, 1, 15, 16, 17, 16 * 200 + 3] {
+            let bytes: Vec<u8> = (0..n * 4).map(|i| (i * 7 % 256) as u8).collect();
+            assert_eq!(sum_rgba(&bytes), sum_rgba_scalar(&bytes), "{} pixels", n);
+        }
+        assert_eq!(sum_rgba(&[255; 4 * 70_000]), [255 * 70_000; 4]);
+    }
+
+    #[test]
+    fn finds_the_first_nearest_entry() {
+        let palette = [[0, 0, 0], [255, 255, 255], [250, 250, 250], [250, 250, 250]];
+        let lookup = NearestColor::new(&palette);
+        assert_eq!(
+            lookup.nearest(Rgba([240, 240, 240, 9])),
+            Rgba([250, 250, 250, 9])
+        );
+        assert_eq!(lookup.nearest(Rgba([10, 0, 0, 255])), Rgba([0, 0, 0, 255]));
+        let many: Vec<[u8; 3]> = (0..37u8).map(|j| [j * 7, j * 5, 255 - j * 3]).collect();
+        let lookup = NearestColor::new(&many);
+        for i in 0..=255u8 {
+            let color = Rgba([i, i.wrapping_mul(3), i.wrapping_mul(7), 255]);
+            assert_eq!(
+                lookup.nearest(color),
+                super::super::palette::nearest(&many, color)
+            );
+        }
+        assert_eq!(
+            NearestColor::new(&[]).nearest(Rgba([1, 2, 3, 4])),
+            Rgba([1, 2, 3, 4])
+        );
+    }
+}
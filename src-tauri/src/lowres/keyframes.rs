@@ -0,0 +1,189 @@
+//! Per-frame configs for sequences: settings keyed to frame numbers and
+//! interpolated in between, e.g. block 32 at frame 0 easing to block 2 at
+//! frame 120 for a progressive de-pixelation shot.
+//!
+//! ```json
+//! { "keyframes": [
+//!     { "frame": 0, "block": 32, "easing": "EaseInOut" },
+//!     { "frame": 120, "block": 2 }
+//! ] }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use super::{migrate, LowresConfig, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// How values move from one keyframe to the next.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum Easing {
+    #[default]
+    Linear,
+    /// Start slowly, then speed up.
+    EaseIn,
+    /// Start quickly, then settle.
+    EaseOut,
+    EaseInOut,
+    /// Keep this keyframe's values until the next one.
+    Hold,
+}
+
+impl Easing {
+    /// Progress along a segment, for `t` from 0 to 1.
+    fn apply(self, t: f64) -> f64 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+            Easing::Hold => 0.0,
+        }
+    }
+}
+
+/// Config values at one frame.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Keyframe {
+    pub frame: u32,
+    /// Easing of the segment from this keyframe to the next.
+    #[serde(default)]
+    pub easing: Easing,
+    /// `LowresConfig` fields. Numbers are interpolated; anything else holds
+    /// until the next keyframe.
+    #[serde(flatten)]
+    pub values: Map<String, Value>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Keyframes {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl Keyframes {
+    /// Read and check a keyframes file.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+        let keyframes: Keyframes = serde_json::from_str(&text).map_err(|e| {
+            LowresError::InvalidConfig(format!("Invalid keyframes file {:?}: {}", path, e))
+        })?;
+        keyframes.check()?;
+        Ok(keyframes)
+    }
+
+    fn check(&self) -> Result<()> {
+        let invalid =
+            |message: String| -> Result<()> { Err(LowresError::InvalidConfig(message).into()) };
+        if self.keyframes.is_empty() {
+            return invalid("Keyframes file has no keyframes".into());
+        }
+        if self.keyframes.windows(2).any(|w| w[0].frame >= w[1].frame) {
+            return invalid("Keyframes must be in increasing frame order".into());
+        }
+        let known = match serde_json::to_value(LowresConfig::default())? {
+            Value::Object(fields) => fields,
+            _ => Map::new(),
+        };
+        for key in self.keyframes.iter().flat_map(|k| k.values.keys()) {
+            if !known.contains_key(key) || key == "version" {
+                return invalid(format!("Unknown keyframed setting {:?}", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// `base` with the keyframed values at `frame` applied over it.
+    pub fn config_at(&self, base: &LowresConfig, frame: u32) -> Result<LowresConfig> {
+        migrate::overlay(base, &self.values_at(frame))
+    }
+
+    /// Every keyframed setting's value at `frame`. Before the first keyframe
+    /// and after the last, a setting keeps the nearest value it has.
+    fn values_at(&self, frame: u32) -> Map<String, Value> {
+        let mut values = Map::new();
+        for (i, key) in self.keyframes.iter().enumerate() {
+            for (name, value) in &key.values {
+                let past = key.frame <= frame;
+                if !past && values.contains_key(name) {
+                    continue;
+                }
+                if !past {
+                    // Not reached yet and never set before: hold the first value.
+                    values.insert(name.clone(), value.clone());
+                    continue;
+                }
+                // The next keyframe that sets this value, if `frame` lies before it.
+                let next = self.keyframes[i + 1..]
+                    .iter()
+                    .find(|k| k.values.contains_key(name))
+                    .filter(|k| k.frame > frame);
+                let value = match next {
+                    Some(next) => {
+                        let t = (frame - key.frame) as f64 / (next.frame - key.frame) as f64;
+                        interpolate(value, &next.values[name], key.easing.apply(t))
+                    }
+                    None => value.clone(),
+                };
+                values.insert(name.clone(), value);
+            }
+        }
+        values
+    }
+}
+
+/// `from` moved `t` of the way to `to`. Integers stay integers; values that
+/// aren't both numbers don't move.
+fn interpolate(from: &Value, to: &Value, t: f64) -> Value {
+    let (Some(a), Some(b)) = (from.as_f64(), to.as_f64()) else {
+        return from.clone();
+    };
+    let v = a + (b - a) * t;
+    if from.is_f64() || to.is_f64() {
+        serde_json::Number::from_f64(v).map_or(from.clone(), Value::Number)
+    } else {
+        Value::from(v.round() as i64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let keyframes: Keyframes = serde_json::from_value(json!({ "keyframes": [
+            { "frame": 10, "block": 32, "easing": "EaseIn", "palette": "GameBoy" },
+            { "frame": 20, "block": 2, "easing": "Hold", "scale": 0.5 },
+            { "frame": 30, "block": 8, "palette": "Nes" },
+        ] }))
+        .unwrap();
+        keyframes.check().unwrap();
+        let base = LowresConfig {
+            dpi: Some(72),
+            ..Default::default()
+        };
+        let at = |frame| keyframes.config_at(&base, frame).unwrap();
+
+        assert_eq!(at(0).block, Some(32));
+        assert_eq!(at(10).block, Some(32));
+        // Ease-in: a quarter of the way at the segment's midpoint.
+        assert_eq!(at(15).block, Some(25));
+        assert_eq!(at(20).block, Some(2));
+        assert_eq!(at(25).block, Some(2));
+        assert_eq!(at(40).block, Some(8));
+        assert_eq!(at(29).palette, Some(super::super::Palette::GameBoy));
+        assert_eq!(at(30).palette, Some(super::super::Palette::Nes));
+        assert_eq!(at(0).scale, Some(0.5));
+        assert_eq!(at(25).dpi, Some(72));
+
+        let typo: Keyframes = serde_json::from_value(json!({ "keyframes": [
+            { "frame": 0, "blok": 4 },
+        ] }))
+        .unwrap();
+        assert!(typo.check().is_err());
+    }
+}
@@ -0,0 +1,224 @@
+//! A legibility guard for redaction: after pixelating `regions`, run text
+//! recognition over each and warn, or enlarge the blocks, while any still
+//! reads. Pixelation that still reads is a privacy failure, not a style.
+
+use image::RgbaImage;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use super::{BlockSize, LowresConfig, Region};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Fewer letters and digits than this read in a region is noise, not text.
+const MIN_CHARS: usize = 3;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum LegibilityGuard {
+    /// Report the regions whose text still reads.
+    Warn,
+    /// Double the block size until no region's text reads, or the blocks
+    /// are as large as the largest region.
+    Enlarge,
+}
+
+impl Display for LegibilityGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            LegibilityGuard::Warn => "warn",
+            LegibilityGuard::Enlarge => "enlarge",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for LegibilityGuard {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "warn" => Ok(LegibilityGuard::Warn),
+            "enlarge" => Ok(LegibilityGuard::Enlarge),
+            other => Err(anyhow::anyhow!("Unknown legibility guard {:?}", other)),
+        }
+    }
+}
+
+/// What the guard found, and the block size the output was made with.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct LegibilityReport {
+    pub block_width: u32,
+    pub block_height: u32,
+    /// Whether the blocks were enlarged from the config's.
+    pub enlarged: bool,
+    /// Regions whose text still reads in the output.
+    pub readable: Vec<ReadableRegion>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ReadableRegion {
+    /// In source pixels, as in the config.
+    pub region: Region,
+    pub text: String,
+}
+
+/// Check the `regions` of `config` in `out`, rendered from a `source`-sized
+/// image, reading text with `read`. With `LegibilityGuard::Enlarge`,
+/// `out` is rendered again with `render` at doubled block sizes until
+/// nothing reads.
+pub fn guard(
+    guard: LegibilityGuard,
+    config: &LowresConfig,
+    source: (u32, u32),
+    out: &mut RgbaImage,
+    read: impl Fn(&RgbaImage) -> Result<Option<String>>,
+    mut render: impl FnMut(&LowresConfig) -> Result<RgbaImage>,
+) -> Result<LegibilityReport> {
+    let regions: Vec<Region> = config
+        .regions
+        .iter()
+        .flatten()
+        .filter_map(|r| r.clip(source.0, source.1))
+        .collect();
+    let mut block = config.block_size().unwrap_or(BlockSize {
+        width: 1,
+        height: 1,
+    });
+    let largest = regions
+        .iter()
+        .fold((0, 0), |(w, h), r| (w.max(r.width), h.max(r.height)));
+    let mut enlarged = false;
+    loop {
+        let readable = readable_regions(out, &regions, source, &read)?;
+        let grown = block.width >= largest.0 && block.height >= largest.1;
+        if readable.is_empty() || guard == LegibilityGuard::Warn || grown {
+            return Ok(LegibilityReport {
+                block_width: block.width,
+                block_height: block.height,
+                enlarged,
+                readable,
+            });
+        }
+        block = BlockSize {
+            width: block.width * 2,
+            height: block.height * 2,
+        };
+        *out = render(&LowresConfig {
+            block: None,
+            block_width: Some(block.width),
+            block_height: Some(block.height),
+            ..config.clone()
+        })?;
+        enlarged = true;
+    }
+}
+
+/// The `regions` of `out` whose text `read` still reads. `out` may have
+/// been scaled from the `source`'s size.
+fn readable_regions(
+    out: &RgbaImage,
+    regions: &[Region],
+    source: (u32, u32),
+    read: &impl Fn(&RgbaImage) -> Result<Option<String>>,
+) -> Result<Vec<ReadableRegion>> {
+    let scale = out.width() as f64 / source.0 as f64;
+    let mut readable = Vec::new();
+    for region in regions {
+        let side = |n: u32| (n as f64 * scale).round() as u32;
+        let Some(scaled) = (Region {
+            x: side(region.x),
+            y: side(region.y),
+            width: side(region.width).max(1),
+            height: side(region.height).max(1),
+        })
+        .clip(out.width(), out.height()) else {
+            continue;
+        };
+        let crop = image::imageops::crop_imm(out, scaled.x, scaled.y, scaled.width, scaled.height)
+            .to_image();
+        let text =
+            read(&crop)?.filter(|t| t.chars().filter(|c| c.is_alphanumeric()).count() >= MIN_CHARS);
+        if let Some(text) = text {
+            readable.push(ReadableRegion {
+                region: *region,
+                text,
+            });
+        }
+    }
+    Ok(readable)
+}
+
+/// The text recognition `guard` runs by default.
+#[cfg(feature = "ocr")]
+pub use super::ocr::read_text;
+
+#[cfg(not(feature = "ocr"))]
+pub fn read_text(_: &RgbaImage) -> Result<Option<String>> {
+    Err(super::LowresError::InvalidConfig(
+        "legibility_guard needs lowres built with the `ocr` feature".into(),
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn enlarges_blocks_until_text_stops_reading() {
+        // A stand-in recognizer that reads any crop with more than 4 colors.
+        let read = |img: &RgbaImage| -> Result<Option<String>> {
+            let mut colors: Vec<_> = img.pixels().map(|p| p.0).collect();
+            colors.sort();
+            colors.dedup();
+            Ok((colors.len() > 4).then(|| "SECRET".to_string()))
+        };
+        let source =
+            RgbaImage::from_fn(64, 32, |x, y| Rgba([(x * 4) as u8, (y * 8) as u8, 0, 255]));
+        let config: LowresConfig = serde_json::from_str(
+            r#"{"block": 2, "regions": [{"x": 0, "y": 0, "width": 32, "height": 16}]}"#,
+        )
+        .unwrap();
+        // Blocks of side b leave (32/b)*(16/b) colors in the region.
+        let render = |c: &LowresConfig| -> Result<RgbaImage> {
+            let b = c.block_size().unwrap();
+            Ok(RgbaImage::from_fn(64, 32, |x, y| {
+                if x < 32 && y < 16 {
+                    Rgba([(x / b.width) as u8, (y / b.height) as u8, 0, 255])
+                } else {
+                    *source.get_pixel(x, y)
+                }
+            }))
+        };
+        let mut out = render(&config).unwrap();
+
+        let warned = guard(
+            LegibilityGuard::Warn,
+            &config,
+            (64, 32),
+            &mut out.clone(),
+            read,
+            render,
+        )
+        .unwrap();
+        assert_eq!(warned.readable.len(), 1);
+        assert!(!warned.enlarged);
+
+        let report = guard(
+            LegibilityGuard::Enlarge,
+            &config,
+            (64, 32),
+            &mut out,
+            read,
+            render,
+        )
+        .unwrap();
+        assert!(report.readable.is_empty());
+        assert!(report.enlarged);
+        assert_eq!((report.block_width, report.block_height), (16, 16));
+        assert_eq!(out.get_pixel(31, 15), &Rgba([1, 0, 0, 255]));
+    }
+}
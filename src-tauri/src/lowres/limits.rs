@@ -0,0 +1,132 @@
+//! Guardrails against decompression bombs and runaway sizes: a file of a few
+//! kilobytes can declare a 100,000×100,000 image that takes tens of
+//! gigabytes to decode. The limits are set once per process, and checked
+//! against file sizes and image headers before anything is decoded, and
+//! against the planned output size before anything is rendered.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::RwLock;
+
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Size limits; `None` leaves a size unchecked.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize, JsonSchema)]
+pub struct SizeLimits {
+    /// Largest input file read, in bytes.
+    pub max_file_bytes: Option<u64>,
+    /// Largest input decoded, in megapixels, as its header declares it.
+    pub max_input_megapixels: Option<f64>,
+    /// Largest output rendered, in megapixels.
+    pub max_output_megapixels: Option<f64>,
+}
+
+static LIMITS: RwLock<SizeLimits> = RwLock::new(SizeLimits {
+    max_file_bytes: None,
+    max_input_megapixels: None,
+    max_output_megapixels: None,
+});
+
+/// Apply `limits` to everything processed from now on.
+pub fn set(limits: SizeLimits) {
+    *LIMITS.write().unwrap() = limits;
+}
+
+/// The limits in force.
+pub fn current() -> SizeLimits {
+    *LIMITS.read().unwrap()
+}
+
+fn megapixels(width: u32, height: u32) -> f64 {
+    width as f64 * height as f64 / 1e6
+}
+
+fn too_large(message: String) -> anyhow::Error {
+    LowresError::TooLarge(message).into()
+}
+
+impl SizeLimits {
+    /// Fail if an input file of `bytes` is over the limit.
+    pub fn check_file_bytes(&self, bytes: u64) -> Result<()> {
+        match self.max_file_bytes {
+            Some(max) if bytes > max => Err(too_large(format!(
+                "The input is {:.1} MB, over the {:.1} MB file size limit",
+                bytes as f64 / 1e6,
+                max as f64 / 1e6
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if an input declaring `width`×`height` is over the limit.
+    pub fn check_input(&self, width: u32, height: u32) -> Result<()> {
+        let mp = megapixels(width, height);
+        match self.max_input_megapixels {
+            Some(max) if mp > max => Err(too_large(format!(
+                "The input is {}x{} ({:.1} MP), over the {} MP input limit",
+                width, height, mp, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail if an output of `width`×`height` is over the limit.
+    pub fn check_output(&self, width: u32, height: u32) -> Result<()> {
+        let mp = megapixels(width, height);
+        match self.max_output_megapixels {
+            Some(max) if mp > max => Err(too_large(format!(
+                "The output would be {}x{} ({:.1} MP), over the {} MP output limit",
+                width, height, mp, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Fail if the file at `path` is over the size limit in force, without
+/// reading it.
+pub fn check_file(path: &Path) -> Result<()> {
+    current().check_file(path)
+}
+
+impl SizeLimits {
+    /// Fail if the file at `path` is over the size limit, without reading it.
+    pub fn check_file(&self, path: &Path) -> Result<()> {
+        if self.max_file_bytes.is_none() {
+            return Ok(());
+        }
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+        self.check_file_bytes(metadata.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_sizes_over_the_limits() {
+        let limits = SizeLimits {
+            max_file_bytes: Some(1_000),
+            max_input_megapixels: Some(1.0),
+            max_output_megapixels: Some(0.5),
+        };
+        assert!(limits.check_file_bytes(1_000).is_ok());
+        assert!(limits.check_file_bytes(1_001).is_err());
+        assert!(limits.check_input(1_000, 1_000).is_ok());
+        assert!(limits.check_output(1_000, 500).is_ok());
+        assert!(limits.check_output(1_000, 501).is_err());
+        let bomb = LowresError::from(limits.check_input(100_000, 100_000).unwrap_err());
+        assert_eq!(
+            bomb,
+            LowresError::TooLarge(
+                "The input is 100000x100000 (10000.0 MP), over the 1 MP input limit".into()
+            )
+        );
+        assert!(SizeLimits::default().check_input(100_000, 100_000).is_ok());
+    }
+}
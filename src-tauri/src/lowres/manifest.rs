@@ -0,0 +1,123 @@
+//! SHA-256 manifests of produced outputs, so a derivative set can be checked
+//! after it has been copied or archived. The format is the one `sha256sum`
+//! writes (`<hex>  <path>` per line), so `sha256sum -c` can check it too.
+
+use anyhow::Context;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize)]
+pub enum ManifestStatus {
+    Ok,
+    /// The file exists but its contents changed.
+    Mismatch,
+    Missing,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ManifestCheck {
+    /// The path as listed in the manifest.
+    pub file: String,
+    pub status: ManifestStatus,
+}
+
+/// Hash `files` into a manifest at `path`. Files under the manifest's
+/// directory are listed relative to it so the set can be moved as a whole.
+pub fn write_manifest(path: &Path, files: &[PathBuf]) -> Result<()> {
+    let base = manifest_dir(path).canonicalize().ok();
+    let mut out = String::new();
+    for file in files {
+        let digest = sha256_file(file)?;
+        let resolved = file.canonicalize().unwrap_or_else(|_| file.clone());
+        let listed = base
+            .as_deref()
+            .and_then(|b| resolved.strip_prefix(b).ok())
+            .unwrap_or(&resolved);
+        out.push_str(&format!("{}  {}\n", digest, listed.to_string_lossy()));
+    }
+    std::fs::write(path, out).with_context(|| format!("Failed to create {:?}", path))
+}
+
+/// Re-hash every file listed in the manifest at `path`.
+pub fn verify_manifest(path: &Path) -> Result<Vec<ManifestCheck>> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+    let base = manifest_dir(path);
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (digest, file) = parse_line(line)
+                .ok_or_else(|| anyhow::anyhow!("Malformed manifest line {:?}", line))?;
+            let status = match sha256_file(&base.join(file)) {
+                Ok(actual) if actual.eq_ignore_ascii_case(digest) => ManifestStatus::Ok,
+                Ok(_) => ManifestStatus::Mismatch,
+                Err(_) => ManifestStatus::Missing,
+            };
+            Ok(ManifestCheck {
+                file: file.to_string(),
+                status,
+            })
+        })
+        .collect()
+}
+
+fn manifest_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Split `<hex>  <path>`; a `*` before the path (binary mode) is accepted.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (digest, rest) = line.split_once(' ')?;
+    let file = rest.strip_prefix([' ', '*'])?;
+    let valid = digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit());
+    (valid && !file.is_empty()).then_some((digest, file))
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(Sha256::digest(&data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_what_it_wrote() {
+        let dir = std::env::temp_dir().join(format!("lowres_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a_lowres.png"), dir.join("b_lowres.png"));
+        std::fs::write(&a, b"abc").unwrap();
+        std::fs::write(&b, b"def").unwrap();
+        let manifest = dir.join("SHA256SUMS");
+        write_manifest(&manifest, &[a.clone(), b.clone()]).unwrap();
+
+        let text = std::fs::read_to_string(&manifest).unwrap();
+        assert!(text.starts_with(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  a_lowres.png\n"
+        ));
+
+        std::fs::write(&a, b"abd").unwrap();
+        std::fs::remove_file(&b).unwrap();
+        let statuses: Vec<_> = verify_manifest(&manifest)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![ManifestStatus::Mismatch, ManifestStatus::Missing]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
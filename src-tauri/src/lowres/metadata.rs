@@ -0,0 +1,657 @@
+//! Carrying descriptive metadata (EXIF, XMP, copyright) from the source into
+//! the output PNG when `keep_metadata` is on, keywords and captions for asset
+//! managers with `keep_keywords` and `add_keywords`, the `lowres:settings` chunk
+//! recording the config an output was made with, and the header facts (DPI,
+//! capture date, camera) shown for a source.
+
+use exif::{experimental::Writer, Exif, Field, In, Reader, Tag, Value};
+use image::ImageDecoder;
+use std::io::{BufRead, Cursor, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use super::{migrate, LowresConfig};
+
+type Result<T> = anyhow::Result<T>;
+
+/// XMP packets in JPEG live in an APP1 segment starting with this.
+const JPEG_XMP_ID: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Photoshop resources, IPTC among them, live in an APP13 segment starting with this.
+const JPEG_PHOTOSHOP_ID: &[u8] = b"Photoshop 3.0\0";
+/// PNG iTXt keyword for XMP packets.
+const PNG_XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+/// PNG iTXt keyword for the embedded `LowresConfig` JSON.
+pub const SETTINGS_KEYWORD: &str = "lowres:settings";
+
+/// Metadata read from a source file, ready to be written into a PNG.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    /// TIFF-structured EXIF for an eXIf chunk, with orientation removed
+    /// because it is already baked into the pixels.
+    pub exif: Option<Vec<u8>>,
+    pub xmp: Option<String>,
+    /// tEXt chunks such as `Copyright` and `Author`.
+    pub text: Vec<(String, String)>,
+    /// The source's color profile, written as iCCP. Unlike the rest it is
+    /// carried without `keep_metadata`, since the pixels mean nothing without it.
+    pub icc_profile: Option<Vec<u8>>,
+    /// The source's resolution, the output DPI unless the config sets one.
+    /// Also used without `keep_metadata`.
+    pub dpi: Option<u32>,
+    /// Keywords from the XMP `dc:subject` or IPTC, for `keep_keywords`.
+    pub keywords: Vec<String>,
+    /// The caption from the XMP `dc:description` or IPTC, for `keep_keywords`.
+    pub caption: Option<String>,
+}
+
+/// Collect what can be carried over from an encoded JPEG or PNG. Anything
+/// unreadable is skipped; metadata never makes processing fail.
+pub fn read_metadata(data: &[u8]) -> Metadata {
+    let mut meta = Metadata::default();
+
+    let exif = Reader::new()
+        .read_from_container(&mut Cursor::new(data))
+        .ok();
+    meta.dpi = source_dpi(&mut Cursor::new(data), exif.as_ref());
+    if let Some(exif) = exif {
+        for (tag, keyword) in [(Tag::Copyright, "Copyright"), (Tag::Artist, "Author")] {
+            if let Some(text) = exif.get_field(tag, In::PRIMARY).and_then(ascii) {
+                meta.text.push((keyword.to_string(), text));
+            }
+        }
+        meta.exif = rewrite_exif(exif.fields(), exif.little_endian());
+    }
+
+    if let Ok(reader) = png::Decoder::new(Cursor::new(data)).read_info() {
+        let info = reader.info();
+        meta.xmp = info
+            .utf8_text
+            .iter()
+            .find(|t| t.keyword == PNG_XMP_KEYWORD)
+            .and_then(|t| t.get_text().ok());
+        for t in &info.uncompressed_latin1_text {
+            let copied = ["Copyright", "Author", "Title", "Description"];
+            if copied.contains(&t.keyword.as_str())
+                && !meta.text.iter().any(|(k, _)| *k == t.keyword)
+            {
+                meta.text.push((t.keyword.clone(), t.text.clone()));
+            }
+        }
+    } else {
+        meta.xmp = jpeg_xmp(data);
+    }
+
+    if let Some(xmp) = &meta.xmp {
+        meta.keywords = xmp_items(xmp, "dc:subject");
+        meta.caption = xmp_items(xmp, "dc:description").into_iter().next();
+    }
+    // Files tagged by older tools may only have IPTC, or keywords in both.
+    let (keywords, caption) = jpeg_iptc(data);
+    for keyword in keywords {
+        if !meta.keywords.contains(&keyword) {
+            meta.keywords.push(keyword);
+        }
+    }
+    meta.caption = meta.caption.or(caption);
+
+    meta
+}
+
+/// The embedded ICC profile of an encoded image, if its format has one.
+pub fn read_icc_profile(data: &[u8]) -> Option<Vec<u8>> {
+    image::ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?
+        .icc_profile()
+        .ok()
+        .flatten()
+}
+
+/// What a source's headers and EXIF say about it, beyond its pixel format.
+#[derive(Debug, Clone, Default)]
+pub struct SourceFacts {
+    /// Embedded resolution, from EXIF, a PNG pHYs chunk or the JFIF density,
+    /// in that order.
+    pub dpi: Option<u32>,
+    /// EXIF capture time as `YYYY-MM-DDTHH:MM:SS`, without a time zone.
+    pub taken: Option<String>,
+    /// EXIF make and model.
+    pub camera: Option<String>,
+}
+
+/// Read [`SourceFacts`] from the start of an encoded image. Only headers and
+/// the EXIF block are read, never the pixel data.
+pub fn read_source_facts<R: BufRead + Seek>(r: &mut R) -> SourceFacts {
+    let exif = r
+        .seek(SeekFrom::Start(0))
+        .ok()
+        .and_then(|_| Reader::new().read_from_container(r).ok());
+    let dpi = source_dpi(r, exif.as_ref());
+    let Some(exif) = exif else {
+        return SourceFacts {
+            dpi,
+            ..SourceFacts::default()
+        };
+    };
+
+    let taken = [Tag::DateTimeOriginal, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Ascii(parts) => exif::DateTime::from_ascii(parts.first()?).ok(),
+            _ => None,
+        })
+        .map(|t| {
+            format!(
+                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                t.year, t.month, t.day, t.hour, t.minute, t.second
+            )
+        });
+    let make = exif.get_field(Tag::Make, In::PRIMARY).and_then(ascii);
+    let model = exif.get_field(Tag::Model, In::PRIMARY).and_then(ascii);
+    let camera = match (make, model) {
+        // Models often repeat the make ("Canon" / "Canon EOS R5").
+        (Some(make), Some(model)) if model.starts_with(make.trim()) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make.trim(), model)),
+        (make, model) => make.or(model),
+    };
+
+    SourceFacts { dpi, taken, camera }
+}
+
+fn source_dpi<R: BufRead + Seek>(r: &mut R, exif: Option<&Exif>) -> Option<u32> {
+    exif.and_then(exif_dpi).or_else(|| {
+        r.seek(SeekFrom::Start(0)).ok()?;
+        container_dpi(r)
+    })
+}
+
+/// Resolution from a PNG pHYs chunk or a JPEG JFIF header. Aspect-ratio-only
+/// values (no unit) don't count.
+fn container_dpi<R: BufRead + Seek>(r: &mut R) -> Option<u32> {
+    let mut head = [0u8; 18];
+    r.read_exact(&mut head).ok()?;
+    if head.starts_with(&[0xff, 0xd8, 0xff, 0xe0]) && &head[6..11] == b"JFIF\0" {
+        let density = u16::from_be_bytes([head[14], head[15]]) as f64;
+        return match head[13] {
+            1 => Some(density),
+            2 => Some(density * 2.54),
+            _ => None,
+        }
+        .filter(|&dpi| dpi >= 1.0)
+        .map(|dpi| dpi.round() as u32);
+    }
+    r.seek(SeekFrom::Start(0)).ok()?;
+    let reader = png::Decoder::new(r).read_info().ok()?;
+    let dims = reader.info().pixel_dims?;
+    (dims.unit == png::Unit::Meter && dims.xppu > 0)
+        .then(|| ((dims.xppu as f64 * 0.0254).round() as u32).max(1))
+}
+
+fn exif_dpi(exif: &Exif) -> Option<u32> {
+    let resolution = match &exif.get_field(Tag::XResolution, In::PRIMARY)?.value {
+        Value::Rational(r) if r.first()?.denom != 0 => r[0].to_f64(),
+        _ => return None,
+    };
+    let per_inch = match exif
+        .get_field(Tag::ResolutionUnit, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+    {
+        // 2 (inches) is the default.
+        None | Some(2) => resolution,
+        Some(3) => resolution * 2.54,
+        _ => return None,
+    };
+    (per_inch >= 1.0).then(|| per_inch.round() as u32)
+}
+
+/// Re-encode the primary-image EXIF fields without orientation. Maker notes
+/// are dropped as well: they hold offsets that rewriting would invalidate.
+fn rewrite_exif<'a>(
+    fields: impl Iterator<Item = &'a Field>,
+    little_endian: bool,
+) -> Option<Vec<u8>> {
+    let kept: Vec<&Field> = fields
+        .filter(|f| {
+            f.ifd_num == In::PRIMARY && f.tag != Tag::Orientation && f.tag != Tag::MakerNote
+        })
+        .collect();
+    if kept.is_empty() {
+        return None;
+    }
+    let mut writer = Writer::new();
+    for field in kept {
+        writer.push_field(field);
+    }
+    let mut out = Cursor::new(Vec::new());
+    writer.write(&mut out, little_endian).ok()?;
+    Some(out.into_inner())
+}
+
+fn ascii(field: &Field) -> Option<String> {
+    match &field.value {
+        Value::Ascii(parts) => {
+            let text = parts
+                .iter()
+                .map(|p| {
+                    String::from_utf8_lossy(p)
+                        .trim_end_matches('\0')
+                        .to_string()
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            (!text.trim().is_empty()).then_some(text)
+        }
+        _ => None,
+    }
+}
+
+/// The XMP packet in a JPEG's APP1 segments, if any.
+fn jpeg_xmp(data: &[u8]) -> Option<String> {
+    jpeg_segments(data, 0xe1)
+        .find_map(|body| body.strip_prefix(JPEG_XMP_ID))
+        .and_then(|xmp| String::from_utf8(xmp.to_vec()).ok())
+}
+
+/// Keywords (2:25) and the caption (2:120) from the IPTC resource in a JPEG's
+/// APP13 segment. Text is UTF-8, or Latin-1 where it isn't valid UTF-8.
+fn jpeg_iptc(data: &[u8]) -> (Vec<String>, Option<String>) {
+    let mut keywords = Vec::new();
+    let mut caption = None;
+    let Some(iptc) = jpeg_segments(data, 0xed)
+        .filter_map(|body| body.strip_prefix(JPEG_PHOTOSHOP_ID))
+        .find_map(photoshop_iptc)
+    else {
+        return (keywords, caption);
+    };
+
+    let mut pos = 0;
+    while let Some(&[0x1c, record, dataset, hi, lo]) = iptc.get(pos..pos + 5) {
+        // A set bit 15 means an extended length, which text datasets never need.
+        if hi & 0x80 != 0 {
+            break;
+        }
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        let Some(value) = iptc.get(pos + 5..pos + 5 + len) else {
+            break;
+        };
+        let text = match String::from_utf8(value.to_vec()) {
+            Ok(text) => text,
+            Err(_) => value.iter().map(|&b| b as char).collect(),
+        };
+        match (record, dataset) {
+            (2, 25) if !text.is_empty() && !keywords.contains(&text) => keywords.push(text),
+            (2, 120) if !text.is_empty() => caption = Some(text),
+            _ => {}
+        }
+        pos += 5 + len;
+    }
+    (keywords, caption)
+}
+
+/// Walk the JPEG marker segments before the image data, yielding the bodies
+/// of those with `marker`.
+fn jpeg_segments(data: &[u8], marker: u8) -> impl Iterator<Item = &[u8]> {
+    let mut pos = if data.starts_with(&[0xff, 0xd8]) {
+        2
+    } else {
+        data.len()
+    };
+    std::iter::from_fn(move || {
+        while pos + 4 <= data.len() && data[pos] == 0xff {
+            let found = data[pos + 1];
+            if found == 0xda || found == 0xd9 {
+                return None;
+            }
+            let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let body = data.get(pos + 4..pos + 2 + len)?;
+            pos += 2 + len;
+            if found == marker {
+                return Some(body);
+            }
+        }
+        None
+    })
+}
+
+/// The IPTC-NAA record (resource 0x0404) among Photoshop image resources.
+fn photoshop_iptc(mut resources: &[u8]) -> Option<&[u8]> {
+    while let Some(rest) = resources.strip_prefix(b"8BIM") {
+        let id = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]);
+        // A Pascal string name, padded to an even length with its length byte.
+        let name_len = *rest.get(2)? as usize;
+        let size_at = 2 + (name_len + 2) / 2 * 2;
+        let size = u32::from_be_bytes(rest.get(size_at..size_at + 4)?.try_into().ok()?) as usize;
+        let body = rest.get(size_at + 4..size_at + 4 + size)?;
+        if id == 0x0404 {
+            return Some(body);
+        }
+        resources = rest.get(size_at + 4 + size + size % 2..)?;
+    }
+    None
+}
+
+/// The `rdf:li` values of an XMP property, such as the keywords in
+/// `dc:subject`'s bag or the captions in `dc:description`'s alternatives.
+fn xmp_items(xmp: &str, property: &str) -> Vec<String> {
+    let open = format!("<{}>", property);
+    let close = format!("</{}>", property);
+    let Some(body) = xmp
+        .find(&open)
+        .map(|start| &xmp[start + open.len()..])
+        .and_then(|rest| rest.find(&close).map(|end| &rest[..end]))
+    else {
+        return Vec::new();
+    };
+    body.split("<rdf:li")
+        .skip(1)
+        .filter_map(|item| {
+            let text = &item[item.find('>')? + 1..item.find("</rdf:li>")?];
+            Some(xml_unescape(text.trim()))
+        })
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// An XMP packet with nothing in it yet, for outputs whose source had none.
+const EMPTY_XMP: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+</rdf:RDF>\n\
+</x:xmpmeta>\n\
+<?xpacket end=\"w\"?>";
+
+impl Metadata {
+    /// Write `keywords` and `caption` into the XMP packet, the source's if it
+    /// is being carried over or a new one. Keywords already in the packet are
+    /// not repeated, and a caption it already has is kept.
+    pub fn tag_keywords(&mut self, keywords: &[String], caption: Option<&str>) {
+        // A packet without an RDF body is not one we can add to safely.
+        let mut xmp = self
+            .xmp
+            .take()
+            .filter(|xmp| xmp.contains("</rdf:RDF>"))
+            .unwrap_or_else(|| EMPTY_XMP.to_string());
+        let present = xmp_items(&xmp, "dc:subject");
+        let mut added: Vec<&String> = Vec::new();
+        for keyword in keywords {
+            if !present.contains(keyword) && !added.contains(&keyword) {
+                added.push(keyword);
+            }
+        }
+        let items: String = added
+            .iter()
+            .map(|k| format!("<rdf:li>{}</rdf:li>", xml_escape(k)))
+            .collect();
+
+        let mut properties = String::new();
+        if !items.is_empty() {
+            match xmp
+                .find("<dc:subject>")
+                .and_then(|start| Some(start + xmp[start..].find("</rdf:Bag>")?))
+            {
+                Some(end) => xmp.insert_str(end, &items),
+                None => properties.push_str(&format!(
+                    "<dc:subject><rdf:Bag>{}</rdf:Bag></dc:subject>",
+                    items
+                )),
+            }
+        }
+        if let Some(caption) = caption.filter(|_| !xmp.contains("<dc:description>")) {
+            properties.push_str(&format!(
+                "<dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+                xml_escape(caption)
+            ));
+        }
+        if !properties.is_empty() {
+            let description = format!(
+                "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</rdf:Description>\n",
+                properties
+            );
+            if let Some(end) = xmp.find("</rdf:RDF>") {
+                xmp.insert_str(end, &description);
+            }
+        }
+        self.xmp = Some(xmp);
+    }
+
+    /// Register the text chunks with `encoder`; call before writing the header.
+    pub fn add_text_chunks<W: std::io::Write>(
+        &self,
+        encoder: &mut png::Encoder<W>,
+    ) -> std::result::Result<(), png::EncodingError> {
+        for (keyword, text) in &self.text {
+            encoder.add_text_chunk(keyword.clone(), text.clone())?;
+        }
+        if let Some(xmp) = &self.xmp {
+            encoder.add_itxt_chunk(PNG_XMP_KEYWORD.to_string(), xmp.clone())?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON for the settings chunk: the versioned config without unset fields.
+/// No local paths are recorded: where outputs go is left out, as it says
+/// nothing about the pixels, and the palette and color reference files are
+/// named without their folders.
+pub fn settings_json(config: &LowresConfig) -> Result<String> {
+    let file_name = |path: &Option<PathBuf>| -> Option<PathBuf> {
+        path.as_ref().and_then(|p| p.file_name()).map(PathBuf::from)
+    };
+    let config = LowresConfig {
+        palette_file: file_name(&config.palette_file),
+        match_colors: file_name(&config.match_colors),
+        output_dir: None,
+        output_template: None,
+        ..config.clone()
+    };
+    let mut value = serde_json::to_value(config)?;
+    if let serde_json::Value::Object(map) = &mut value {
+        map.retain(|_, v| !v.is_null());
+        map.insert("version".into(), migrate::CONFIG_VERSION.into());
+    }
+    Ok(serde_json::to_string(&value)?)
+}
+
+/// The settings an output PNG was made with, if it has a `lowres:settings`
+/// chunk. Settings from older builds are upgraded to the current config.
+pub fn read_embedded_settings(path: &PathBuf) -> Result<Option<LowresConfig>> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+    let reader = png::Decoder::new(Cursor::new(data))
+        .read_info()
+        .map_err(|e| anyhow::anyhow!("Not a readable PNG {:?}: {}", path, e))?;
+    let Some(chunk) = reader
+        .info()
+        .utf8_text
+        .iter()
+        .find(|t| t.keyword == SETTINGS_KEYWORD)
+    else {
+        return Ok(None);
+    };
+    let text = chunk
+        .get_text()
+        .map_err(|e| anyhow::anyhow!("Unreadable settings chunk: {}", e))?;
+    let value = serde_json::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Invalid settings chunk: {}", e))?;
+    migrate::upgrade(value).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_record_no_local_paths() {
+        let config = LowresConfig {
+            block: Some(4),
+            palette_file: Some(PathBuf::from("/home/ana/palettes/brand.gpl")),
+            match_colors: Some(PathBuf::from("../clients/acme/key_art.png")),
+            output_dir: Some(PathBuf::from("/home/ana/out")),
+            output_template: Some("{stem}_web.{ext}".into()),
+            ..Default::default()
+        };
+        let json = settings_json(&config).unwrap();
+        for leak in ["/home/ana", "clients", "_web"] {
+            assert!(!json.contains(leak), "{} in {}", leak, json);
+        }
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["block"], 4);
+        assert_eq!(value["palette_file"], "brand.gpl");
+        assert_eq!(value["match_colors"], "key_art.png");
+    }
+
+    #[test]
+    fn drops_orientation_and_finds_jpeg_xmp() {
+        let fields = [
+            Field {
+                tag: Tag::Orientation,
+                ifd_num: In::PRIMARY,
+                value: Value::Short(vec![6]),
+            },
+            Field {
+                tag: Tag::Copyright,
+                ifd_num: In::PRIMARY,
+                value: Value::Ascii(vec![b"(c) Someone".to_vec()]),
+            },
+        ];
+        let exif = rewrite_exif(fields.iter(), false).unwrap();
+        let parsed = Reader::new().read_raw(exif).unwrap();
+        assert!(parsed.get_field(Tag::Orientation, In::PRIMARY).is_none());
+        assert_eq!(
+            parsed
+                .get_field(Tag::Copyright, In::PRIMARY)
+                .and_then(ascii),
+            Some("(c) Someone".to_string())
+        );
+
+        let packet = b"<x:xmpmeta/>";
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend_from_slice(&((2 + JPEG_XMP_ID.len() + packet.len()) as u16).to_be_bytes());
+        jpeg.extend_from_slice(JPEG_XMP_ID);
+        jpeg.extend_from_slice(packet);
+        jpeg.extend_from_slice(&[0xff, 0xda]);
+        assert_eq!(jpeg_xmp(&jpeg).as_deref(), Some("<x:xmpmeta/>"));
+    }
+
+    #[test]
+    fn carries_and_adds_keywords() {
+        // IPTC keywords and caption in a Photoshop APP13 segment, one of them
+        // also in the XMP.
+        let mut iptc = Vec::new();
+        for (dataset, text) in [(25u8, "harbor"), (25, "dusk"), (120, "Boats at dusk")] {
+            iptc.extend_from_slice(&[0x1c, 2, dataset]);
+            iptc.extend_from_slice(&(text.len() as u16).to_be_bytes());
+            iptc.extend_from_slice(text.as_bytes());
+        }
+        let mut app13 = JPEG_PHOTOSHOP_ID.to_vec();
+        app13.extend_from_slice(b"8BIM\x04\x04\0\0");
+        app13.extend_from_slice(&(iptc.len() as u32).to_be_bytes());
+        app13.extend_from_slice(&iptc);
+        let mut xmp = EMPTY_XMP.replace(
+            "</rdf:RDF>",
+            "<rdf:Description><dc:subject><rdf:Bag><rdf:li>harbor</rdf:li>\
+             <rdf:li>R&amp;D</rdf:li></rdf:Bag></dc:subject></rdf:Description></rdf:RDF>",
+        );
+        let mut jpeg = vec![0xff, 0xd8];
+        for (marker, body) in [
+            (0xe1, [JPEG_XMP_ID, xmp.as_bytes()].concat()),
+            (0xed, app13),
+        ] {
+            jpeg.extend_from_slice(&[0xff, marker]);
+            jpeg.extend_from_slice(&((body.len() + 2) as u16).to_be_bytes());
+            jpeg.extend_from_slice(&body);
+        }
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+
+        let meta = read_metadata(&jpeg);
+        assert_eq!(meta.keywords, ["harbor", "R&D", "dusk"]);
+        assert_eq!(meta.caption.as_deref(), Some("Boats at dusk"));
+
+        // Added to the source's packet, keeping its bag and not repeating keywords.
+        let mut carried = meta.clone();
+        let proof = "lowres-proof".to_string();
+        carried.tag_keywords(&[meta.keywords.clone(), vec![proof.clone()]].concat(), None);
+        xmp = carried.xmp.unwrap();
+        assert_eq!(
+            xmp_items(&xmp, "dc:subject"),
+            ["harbor", "R&D", "dusk", "lowres-proof"]
+        );
+        assert!(xmp_items(&xmp, "dc:description").is_empty());
+
+        // A new packet when the source's isn't carried.
+        let mut fresh = Metadata::default();
+        fresh.tag_keywords(&[proof], meta.caption.as_deref());
+        xmp = fresh.xmp.unwrap();
+        assert_eq!(xmp_items(&xmp, "dc:subject"), ["lowres-proof"]);
+        assert_eq!(xmp_items(&xmp, "dc:description"), ["Boats at dusk"]);
+    }
+
+    #[test]
+    fn reads_dpi_date_and_camera() {
+        let mut jfif = vec![0xff, 0xd8, 0xff, 0xe0, 0, 16];
+        jfif.extend_from_slice(b"JFIF\0");
+        // Version 1.1, dots per cm, 118x118.
+        jfif.extend_from_slice(&[1, 1, 2, 0, 118, 0, 118, 0, 0]);
+        let facts = read_source_facts(&mut Cursor::new(&jfif));
+        assert_eq!(facts.dpi, Some(300));
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 1, 1);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_pixel_dims(Some(png::PixelDimensions {
+                xppu: 2835,
+                yppu: 2835,
+                unit: png::Unit::Meter,
+            }));
+            encoder
+                .write_header()
+                .unwrap()
+                .write_image_data(&[0])
+                .unwrap();
+        }
+        assert_eq!(read_source_facts(&mut Cursor::new(&png)).dpi, Some(72));
+
+        let ascii_field = |tag, text: &[u8]| Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.to_vec()]),
+        };
+        let fields = [
+            ascii_field(Tag::Make, b"Canon"),
+            ascii_field(Tag::Model, b"Canon EOS R5"),
+            ascii_field(Tag::DateTimeOriginal, b"2023:05:01 09:30:00"),
+            Field {
+                tag: Tag::XResolution,
+                ifd_num: In::PRIMARY,
+                value: Value::Rational(vec![(240, 1).into()]),
+            },
+        ];
+        let tiff = rewrite_exif(fields.iter(), false).unwrap();
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        let facts = read_source_facts(&mut Cursor::new(&jpeg));
+        assert_eq!(facts.dpi, Some(240));
+        assert_eq!(facts.taken.as_deref(), Some("2023-05-01T09:30:00"));
+        assert_eq!(facts.camera.as_deref(), Some("Canon EOS R5"));
+    }
+}
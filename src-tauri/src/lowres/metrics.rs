@@ -0,0 +1,226 @@
+//! Prometheus metrics for the long-running modes, watch and `--rpc`: jobs
+//! processed, how long each stage took and errors by kind, served as text on
+//! `/metrics` so lowres can be monitored like any other worker.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use super::{LowresError, ProcessReport};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Upper bounds of the stage latency histogram buckets, in seconds.
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const STAGES: [&str; 4] = ["decode", "transform", "quantize", "encode"];
+
+/// Counts kept since the process started.
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Jobs by outcome: processed, skipped or failed.
+    jobs: BTreeMap<&'static str, u64>,
+    /// One per stage of `STAGES`.
+    stages: [Histogram; 4],
+    /// Failed jobs by `LowresError::kind`.
+    errors: BTreeMap<&'static str, u64>,
+}
+
+#[derive(Default, Clone)]
+struct Histogram {
+    /// Observations up to each bound of `BUCKETS`, not cumulative.
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(i) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+impl Metrics {
+    /// Count a job that produced `report`, failed with `error`, or with
+    /// neither was skipped.
+    pub fn record(&self, report: Option<&ProcessReport>, error: Option<&LowresError>) {
+        let mut state = self.state.lock().unwrap();
+        let outcome = match (report, error) {
+            (_, Some(error)) => {
+                *state.errors.entry(error.kind()).or_default() += 1;
+                "failed"
+            }
+            (Some(report), None) => {
+                let t = &report.timings;
+                let ms = [t.decode_ms, t.transform_ms, t.quantize_ms, t.encode_ms];
+                for (histogram, ms) in state.stages.iter_mut().zip(ms) {
+                    histogram.observe(ms / 1000.0);
+                }
+                "processed"
+            }
+            (None, None) => "skipped",
+        };
+        *state.jobs.entry(outcome).or_default() += 1;
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        let _ = writeln!(out, "# HELP lowres_jobs_total Jobs by outcome.");
+        let _ = writeln!(out, "# TYPE lowres_jobs_total counter");
+        for outcome in ["processed", "skipped", "failed"] {
+            let count = state.jobs.get(outcome).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "lowres_jobs_total{{outcome=\"{}\"}} {}",
+                outcome, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP lowres_stage_duration_seconds Time processed jobs spent in each stage."
+        );
+        let _ = writeln!(out, "# TYPE lowres_stage_duration_seconds histogram");
+        for (stage, histogram) in STAGES.iter().zip(&state.stages) {
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "lowres_stage_duration_seconds_bucket{{stage=\"{}\",le=\"{}\"}} {}",
+                    stage, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "lowres_stage_duration_seconds_bucket{{stage=\"{}\",le=\"+Inf\"}} {}",
+                stage, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "lowres_stage_duration_seconds_sum{{stage=\"{}\"}} {}",
+                stage, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "lowres_stage_duration_seconds_count{{stage=\"{}\"}} {}",
+                stage, histogram.count
+            );
+        }
+        let _ = writeln!(out, "# HELP lowres_errors_total Failed jobs by error kind.");
+        let _ = writeln!(out, "# TYPE lowres_errors_total counter");
+        for (kind, count) in &state.errors {
+            let _ = writeln!(out, "lowres_errors_total{{kind=\"{}\"}} {}", kind, count);
+        }
+        out
+    }
+}
+
+/// Serve `metrics` on `addr`, such as `127.0.0.1:9464`, at `/metrics` from a
+/// background thread for as long as the process runs. Returns the address
+/// bound, which has the port chosen when `addr` asks for port 0.
+pub fn serve(addr: &str, metrics: Arc<Metrics>) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)
+        .map_err(|e| LowresError::Io(format!("Failed to serve metrics on {}: {}", addr, e)))?;
+    let bound = listener.local_addr()?;
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A scraper that hangs up early only loses its own response.
+            let _ = respond(stream, &metrics);
+        }
+    });
+    Ok(bound)
+}
+
+/// Answer one request: the metrics for `GET /metrics`, 404 for anything else.
+fn respond(stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Read past the headers, which don't matter here.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path.split('?').next() {
+        Some("/metrics") if request_line.starts_with("GET ") => ("200 OK", metrics.render()),
+        _ => (
+            "404 Not Found",
+            "Not found; metrics are at /metrics\n".to_string(),
+        ),
+    };
+    write!(
+        reader.get_mut(),
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_jobs_stages_and_errors() {
+        let mut png = Vec::new();
+        image::RgbaImage::new(2, 2)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let (_, mut report) = super::super::process_image_bytes(&png, Default::default()).unwrap();
+        report.timings.decode_ms = 20.0;
+        report.timings.encode_ms = 3000.0;
+        let metrics = Arc::new(Metrics::default());
+        metrics.record(Some(&report), None);
+        metrics.record(Some(&report), None);
+        metrics.record(None, None);
+        metrics.record(None, Some(&LowresError::Decode("truncated".into())));
+
+        let addr = serve("127.0.0.1:0", metrics.clone()).unwrap();
+        let text = ureq::get(&format!("http://{}/metrics", addr))
+            .call()
+            .unwrap()
+            .into_string()
+            .unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        for expected in [
+            "lowres_jobs_total{outcome=\"processed\"} 2",
+            "lowres_jobs_total{outcome=\"skipped\"} 1",
+            "lowres_jobs_total{outcome=\"failed\"} 1",
+            "lowres_stage_duration_seconds_bucket{stage=\"decode\",le=\"0.01\"} 0",
+            "lowres_stage_duration_seconds_bucket{stage=\"decode\",le=\"0.025\"} 2",
+            "lowres_stage_duration_seconds_bucket{stage=\"encode\",le=\"2.5\"} 0",
+            "lowres_stage_duration_seconds_bucket{stage=\"encode\",le=\"+Inf\"} 2",
+            "lowres_stage_duration_seconds_sum{stage=\"encode\"} 6",
+            "lowres_stage_duration_seconds_count{stage=\"quantize\"} 2",
+            "lowres_errors_total{kind=\"Decode\"} 1",
+        ] {
+            assert!(
+                lines.contains(&expected),
+                "{} missing from\n{}",
+                expected,
+                text
+            );
+        }
+
+        let missing = ureq::get(&format!("http://{}/", addr)).call();
+        assert!(matches!(missing, Err(ureq::Error::Status(404, _))));
+    }
+}
@@ -0,0 +1,90 @@
+//! Upgrades saved configs (GUI presets, CI config files) to the current `LowresConfig`.
+//!
+//! Every config carries a `version`. When a field is renamed or its meaning
+//! changes, bump `CONFIG_VERSION` and append a step to `MIGRATIONS` that
+//! rewrites the previous version's JSON into the new shape.
+
+use serde_json::{Map, Value};
+
+use super::{LowresConfig, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Version written into configs by this build.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` config to version `n + 1`.
+/// Configs saved before versioning existed have no `version` and count as 0.
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[
+    // 0 → 1: the unversioned format is field-for-field identical to version 1.
+    |_| {},
+];
+
+/// Deserialize a config of any known version, upgrading it to the current one.
+pub fn upgrade(value: Value) -> Result<LowresConfig> {
+    let invalid = |message: String| LowresError::InvalidConfig(message).into();
+    let mut map = match value {
+        Value::Object(map) => map,
+        Value::Null => Map::new(),
+        other => {
+            return Err(invalid(format!(
+                "Config must be a JSON object, got {}",
+                other
+            )))
+        }
+    };
+
+    let version = match map.get("version") {
+        None | Some(Value::Null) => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| invalid(format!("Config version must be a number, got {}", v)))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(invalid(format!(
+            "Config version {} was written by a newer lowres (this build reads up to {})",
+            version, CONFIG_VERSION
+        )));
+    }
+
+    for step in &MIGRATIONS[version as usize..] {
+        step(&mut map);
+    }
+    map.insert("version".into(), CONFIG_VERSION.into());
+
+    serde_json::from_value(Value::Object(map))
+        .map_err(|e| invalid(format!("Invalid config: {}", e)))
+}
+
+/// `base` with `values`, fields of a current config, set over it, checked
+/// like any other config.
+pub fn overlay(base: &LowresConfig, values: &Map<String, Value>) -> Result<LowresConfig> {
+    let mut map = match serde_json::to_value(base)? {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    for (key, value) in values {
+        map.insert(key.clone(), value.clone());
+    }
+    let config = upgrade(Value::Object(map))?;
+    config.validate()?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn upgrades_unversioned_and_rejects_future_configs() {
+        assert_eq!(MIGRATIONS.len(), CONFIG_VERSION as usize);
+
+        let config = upgrade(json!({ "block": 8, "dpi": 72 })).unwrap();
+        assert_eq!(config.version, Some(CONFIG_VERSION));
+        assert_eq!(config.block, Some(8));
+
+        assert!(upgrade(json!({ "version": CONFIG_VERSION + 1 })).is_err());
+    }
+}
@@ -0,0 +1,3904 @@
+use anyhow::Context;
+use exif::{In, Reader, Tag};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageDecoder, Rgba, RgbaImage};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::io::{Cursor, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+mod adam7;
+pub mod analyze;
+mod animation;
+pub mod audit;
+mod banding;
+pub mod batch;
+pub mod capabilities;
+mod channels;
+mod codecs;
+mod color;
+mod color_match;
+#[cfg(feature = "dct-scaling")]
+mod dct_scale;
+mod error;
+pub mod explore;
+mod figure;
+mod font;
+#[cfg(feature = "gpu")]
+mod gpu;
+mod grain;
+mod guard;
+mod guides;
+pub mod heatmap;
+#[cfg(feature = "heif")]
+mod heif;
+pub mod icons;
+mod jpeg_rotate;
+pub mod kernels;
+pub mod keyframes;
+mod legibility;
+pub mod limits;
+pub mod manifest;
+mod metadata;
+pub mod metrics;
+pub mod migrate;
+#[cfg(feature = "ocr")]
+mod ocr;
+mod palette;
+mod pdf;
+pub mod pipeline;
+pub mod plan;
+pub mod pool;
+pub mod proof;
+#[cfg(feature = "raw")]
+mod raw;
+pub mod region_sets;
+pub mod remote;
+mod retag;
+#[cfg(feature = "segmentation")]
+mod segment;
+pub mod selftest;
+pub mod sequence;
+pub mod services;
+pub mod shell;
+pub mod shots;
+mod sidecar;
+pub mod sprites;
+mod styles;
+mod tiled;
+#[cfg(feature = "upload")]
+pub mod upload;
+mod upscale;
+pub mod watch;
+pub mod webhook;
+
+pub use analyze::analyze;
+pub use banding::{Banding, BandingCheck};
+pub use batch::{process_batch, OnCollision};
+pub use capabilities::capabilities;
+pub use channels::{merge_channels, split_channels, ChannelSpace};
+pub use color_match::ColorMatch;
+pub use error::LowresError;
+pub use figure::render_comparison;
+pub use guard::ensure_outside_sources;
+pub use guides::{render_guides, Guide};
+pub use jpeg_rotate::rotate_jpeg;
+pub use legibility::LegibilityGuard;
+pub use metadata::read_embedded_settings;
+pub use palette::{Dither, Palette};
+pub use retag::retag_dpi;
+pub use sidecar::sidecar_path;
+pub use sprites::extract_sprites;
+pub use styles::Style;
+pub use upscale::Upscaler;
+
+use codecs::Container;
+use metadata::Metadata;
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Resample {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl From<Resample> for FilterType {
+    fn from(r: Resample) -> Self {
+        match r {
+            Resample::Nearest => FilterType::Nearest,
+            Resample::Triangle => FilterType::Triangle,
+            Resample::CatmullRom => FilterType::CatmullRom,
+            Resample::Gaussian => FilterType::Gaussian,
+            Resample::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+impl Display for Resample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Resample::Nearest => "nearest",
+            Resample::Triangle => "triangle",
+            Resample::CatmullRom => "catmullrom",
+            Resample::Gaussian => "gaussian",
+            Resample::Lanczos3 => "lanczos3",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Resample {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Resample::Nearest),
+            "triangle" => Ok(Resample::Triangle),
+            "catmullrom" | "catmull-rom" => Ok(Resample::CatmullRom),
+            "gaussian" => Ok(Resample::Gaussian),
+            "lanczos3" => Ok(Resample::Lanczos3),
+            other => Err(anyhow::anyhow!("Unknown filter {:?}", other)),
+        }
+    }
+}
+
+/// Pixelation block size; `8` for squares or `8x2` for rectangles.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub struct BlockSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Display for BlockSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width == self.height {
+            write!(f, "{}", self.width)
+        } else {
+            write!(f, "{}x{}", self.width, self.height)
+        }
+    }
+}
+
+impl FromStr for BlockSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|e| anyhow::anyhow!("Bad block size {:?}: {}", s, e))
+        };
+        match s.split_once(['x', 'X']) {
+            Some((w, h)) => Ok(BlockSize {
+                width: parse(w)?,
+                height: parse(h)?,
+            }),
+            None => {
+                let b = parse(s)?;
+                Ok(BlockSize {
+                    width: b,
+                    height: b,
+                })
+            }
+        }
+    }
+}
+
+/// A rectangle in source pixels; `X,Y,WxH` on the command line.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{},{}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Region {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || anyhow::anyhow!("Bad region {:?}, expected X,Y,WxH", s);
+        let mut parts = s.splitn(3, ',');
+        let (x, y, size) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(x), Some(y), Some(size)) => (x, y, size),
+            _ => return Err(bad()),
+        };
+        let (w, h) = size.split_once(['x', 'X']).ok_or_else(bad)?;
+        let parse = |v: &str| v.trim().parse::<u32>().map_err(|_| bad());
+        Ok(Region {
+            x: parse(x)?,
+            y: parse(y)?,
+            width: parse(w)?,
+            height: parse(h)?,
+        })
+    }
+}
+
+impl Region {
+    /// Cut this region out of `img`, which must contain it.
+    fn crop(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        let (w, h) = img.dimensions();
+        self.check_fits(w, h)?;
+        Ok(img.crop_imm(self.x, self.y, self.width, self.height))
+    }
+
+    /// Fail unless this region is non-empty and inside a `w`×`h` image.
+    fn check_fits(&self, w: u32, h: u32) -> Result<()> {
+        let fits = self.x.checked_add(self.width).is_some_and(|r| r <= w)
+            && self.y.checked_add(self.height).is_some_and(|b| b <= h);
+        if self.width == 0 || self.height == 0 || !fits {
+            anyhow::bail!("Region {} does not fit in the {}x{} image", self, w, h);
+        }
+        Ok(())
+    }
+
+    /// The part of this region inside a `w`×`h` image, if any.
+    fn clip(&self, w: u32, h: u32) -> Option<Region> {
+        let right = self.x.saturating_add(self.width).min(w);
+        let bottom = self.y.saturating_add(self.height).min(h);
+        (self.x < right && self.y < bottom).then(|| Region {
+            x: self.x,
+            y: self.y,
+            width: right - self.x,
+            height: bottom - self.y,
+        })
+    }
+}
+
+/// A rectangle in percent of an image's width and height, so one spec fits
+/// inputs of any size; `X%,Y%,W%,H%` on the command line.
+#[derive(Clone, Debug, Copy, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct PercentRegion {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Display for PercentRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}%,{}%,{}%,{}%",
+            self.x, self.y, self.width, self.height
+        )
+    }
+}
+
+impl FromStr for PercentRegion {
+    type Err = anyhow::Error;
+
+    /// `X%,Y%,W%,H%`, or `X%,Y%,W%xH%` like a `Region`.
+    fn from_str(s: &str) -> Result<Self> {
+        let bad = || anyhow::anyhow!("Bad region {:?}, expected X%,Y%,W%,H%", s);
+        let parts: Vec<&str> = s.split([',', 'x', 'X']).collect();
+        let [x, y, w, h] = parts[..] else {
+            return Err(bad());
+        };
+        let parse = |v: &str| {
+            let v = v.trim();
+            v.strip_suffix('%')
+                .unwrap_or(v)
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| bad())
+        };
+        Ok(PercentRegion {
+            x: parse(x)?,
+            y: parse(y)?,
+            width: parse(w)?,
+            height: parse(h)?,
+        })
+    }
+}
+
+impl PercentRegion {
+    /// This region in a `w`×`h` image, at least a pixel on each side.
+    pub fn to_pixels(self, w: u32, h: u32) -> Region {
+        let at = |p: f64, side: u32| ((p / 100.0 * side as f64).round() as u32).min(side);
+        let x = at(self.x, w).min(w.saturating_sub(1));
+        let y = at(self.y, h).min(h.saturating_sub(1));
+        Region {
+            x,
+            y,
+            width: at(self.x + self.width, w).saturating_sub(x).max(1),
+            height: at(self.y + self.height, h).saturating_sub(y).max(1),
+        }
+    }
+
+    /// Fail unless this region is non-empty and inside 0–100%.
+    fn check(&self) -> Result<()> {
+        let within = |start: f64, len: f64| {
+            start.is_finite()
+                && len.is_finite()
+                && start >= 0.0
+                && len > 0.0
+                && start + len <= 100.0
+        };
+        if !within(self.x, self.width) || !within(self.y, self.height) {
+            return Err(LowresError::InvalidConfig(format!(
+                "Region {} does not lie within 0% to 100%",
+                self
+            ))
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// A region as given on the command line: `X,Y,WxH` in pixels, or with
+/// `%` on its numbers in percent.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum RegionSpec {
+    Pixels(Region),
+    Percent(PercentRegion),
+}
+
+impl RegionSpec {
+    pub fn pixels(self) -> Option<Region> {
+        match self {
+            RegionSpec::Pixels(r) => Some(r),
+            RegionSpec::Percent(_) => None,
+        }
+    }
+
+    pub fn percent(self) -> Option<PercentRegion> {
+        match self {
+            RegionSpec::Pixels(_) => None,
+            RegionSpec::Percent(r) => Some(r),
+        }
+    }
+}
+
+impl FromStr for RegionSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.contains('%') {
+            s.parse().map(RegionSpec::Percent)
+        } else {
+            s.parse().map(RegionSpec::Pixels)
+        }
+    }
+}
+
+/// A physical length for print sizing: `4in`, `10cm` or `90mm`.
+#[derive(Clone, Debug, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Length {
+    pub value: f64,
+    pub unit: LengthUnit,
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub enum LengthUnit {
+    Inch,
+    Centimeter,
+    Millimeter,
+}
+
+impl Length {
+    /// Pixels this length spans at `dpi`, at least 1.
+    pub fn to_pixels(self, dpi: u32) -> u32 {
+        let inches = match self.unit {
+            LengthUnit::Inch => self.value,
+            LengthUnit::Centimeter => self.value / 2.54,
+            LengthUnit::Millimeter => self.value / 25.4,
+        };
+        (inches * dpi as f64).round().max(1.0) as u32
+    }
+}
+
+impl Display for Length {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self.unit {
+            LengthUnit::Inch => "in",
+            LengthUnit::Centimeter => "cm",
+            LengthUnit::Millimeter => "mm",
+        };
+        write!(f, "{}{}", self.value, unit)
+    }
+}
+
+impl FromStr for Length {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let t = s.trim().to_ascii_lowercase();
+        let split = t
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(t.len());
+        let (number, unit) = t.split_at(split);
+        let unit = match unit.trim() {
+            "in" | "inch" | "inches" | "\"" => LengthUnit::Inch,
+            "cm" => LengthUnit::Centimeter,
+            "mm" => LengthUnit::Millimeter,
+            _ => anyhow::bail!("Bad length {:?}, expected a number with in, cm or mm", s),
+        };
+        let value: f64 = number
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Bad length {:?}: {}", s, e))?;
+        if value <= 0.0 {
+            anyhow::bail!("Length must be positive, got {:?}", s);
+        }
+        Ok(Length { value, unit })
+    }
+}
+
+impl TryFrom<String> for Length {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Length> for String {
+    fn from(length: Length) -> String {
+        length.to_string()
+    }
+}
+
+/// Output size of the resize path when a config gives no width, height, print
+/// size or scale: `WxH`, `source` (keep the source size) or `error`.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DefaultSize {
+    Fixed {
+        width: u32,
+        height: u32,
+    },
+    Source,
+    /// Refuse to guess: such configs fail validation.
+    Error,
+}
+
+impl Default for DefaultSize {
+    /// 64×64, what lowres has always fallen back to.
+    fn default() -> Self {
+        DefaultSize::Fixed {
+            width: 64,
+            height: 64,
+        }
+    }
+}
+
+impl Display for DefaultSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefaultSize::Fixed { width, height } => write!(f, "{}x{}", width, height),
+            DefaultSize::Source => write!(f, "source"),
+            DefaultSize::Error => write!(f, "error"),
+        }
+    }
+}
+
+impl FromStr for DefaultSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "source" => Ok(DefaultSize::Source),
+            "error" => Ok(DefaultSize::Error),
+            t => {
+                let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|&v| v > 0);
+                t.split_once('x')
+                    .and_then(|(w, h)| Some((parse(w)?, parse(h)?)))
+                    .map(|(width, height)| DefaultSize::Fixed { width, height })
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Bad default size {:?}, expected WxH, source or error", s)
+                    })
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for DefaultSize {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<DefaultSize> for String {
+    fn from(size: DefaultSize) -> String {
+        size.to_string()
+    }
+}
+
+/// One output of a multi-size export: `N` for N×N pixels, or `WxH`.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct OutputSpec {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Display for OutputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width == self.height {
+            write!(f, "{}", self.width)
+        } else {
+            write!(f, "{}x{}", self.width, self.height)
+        }
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = |v: &str| v.trim().parse::<u32>().ok().filter(|&v| v > 0);
+        let t = s.trim().to_ascii_lowercase();
+        let size = match t.split_once('x') {
+            Some((w, h)) => parse(w).zip(parse(h)),
+            None => parse(&t).map(|n| (n, n)),
+        };
+        size.map(|(width, height)| OutputSpec { width, height })
+            .ok_or_else(|| anyhow::anyhow!("Bad output size {:?}, expected N or WxH", s))
+    }
+}
+
+impl TryFrom<String> for OutputSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<OutputSpec> for String {
+    fn from(size: OutputSpec) -> String {
+        size.to_string()
+    }
+}
+
+/// Parse a scale factor given as a percentage (`25%`) or a plain factor (`0.25`).
+pub fn parse_scale(s: &str) -> Result<f32> {
+    let (number, divisor) = match s.trim().strip_suffix('%') {
+        Some(percent) => (percent, 100.0),
+        None => (s.trim(), 1.0),
+    };
+    let value = number
+        .trim()
+        .parse::<f32>()
+        .map_err(|e| anyhow::anyhow!("Bad scale {:?}: {}", s, e))?
+        / divisor;
+    if !(value.is_finite() && value > 0.0) {
+        anyhow::bail!("Scale must be positive, got {:?}", s);
+    }
+    Ok(value)
+}
+
+/// How the pixels of a block are reduced to a single color.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum BlockStat {
+    /// Average of all pixels.
+    Mean,
+    /// Per-channel median.
+    Median,
+    /// Most frequent color; keeps line art crisp.
+    Dominant,
+    /// Pixel with the lowest luma.
+    Darkest,
+    /// Pixel with the highest luma.
+    Lightest,
+}
+
+impl Display for BlockStat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BlockStat::Mean => "mean",
+            BlockStat::Median => "median",
+            BlockStat::Dominant => "dominant",
+            BlockStat::Darkest => "darkest",
+            BlockStat::Lightest => "lightest",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for BlockStat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "mean" | "average" => Ok(BlockStat::Mean),
+            "median" => Ok(BlockStat::Median),
+            "dominant" | "mode" => Ok(BlockStat::Dominant),
+            "darkest" | "min" => Ok(BlockStat::Darkest),
+            "lightest" | "max" => Ok(BlockStat::Lightest),
+            other => Err(anyhow::anyhow!("Unknown block statistic {:?}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum BlockOutput {
+    /// Keep the source WxH, drawing each block as a square of its color.
+    Full,
+    /// Write the block grid itself: one output pixel per block.
+    Small,
+}
+
+impl Display for BlockOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            BlockOutput::Full => "full",
+            BlockOutput::Small => "small",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for BlockOutput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" => Ok(BlockOutput::Full),
+            "small" => Ok(BlockOutput::Small),
+            other => Err(anyhow::anyhow!("Unknown block output {:?}", other)),
+        }
+    }
+}
+
+/// How hard the PNG encoder compresses: faster encodes or smaller files.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum PngCompression {
+    Fast,
+    Default,
+    /// Smallest files, for web delivery, at a few times the encode time.
+    Best,
+}
+
+impl Display for PngCompression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PngCompression::Fast => "fast",
+            PngCompression::Default => "default",
+            PngCompression::Best => "best",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for PngCompression {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(PngCompression::Fast),
+            "default" => Ok(PngCompression::Default),
+            "best" => Ok(PngCompression::Best),
+            other => Err(anyhow::anyhow!("Unknown PNG compression {:?}", other)),
+        }
+    }
+}
+
+impl From<PngCompression> for png::Compression {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/// Where pixelation runs.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Backend {
+    /// Rayon threads on the CPU.
+    Cpu,
+    /// Compute shaders, for mean block colors and the expansion back to full
+    /// size, with the CPU for everything else. Needs a build with the `gpu`
+    /// feature; without it, or without an adapter, pixelation runs on the CPU.
+    Gpu,
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Backend::Cpu => "cpu",
+            Backend::Gpu => "gpu",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(Backend::Cpu),
+            "gpu" => Ok(Backend::Gpu),
+            other => Err(anyhow::anyhow!("Unknown backend {:?}", other)),
+        }
+    }
+}
+
+/// Which YCbCr channels pixelation averages per block; the rest keep each
+/// source pixel's own values.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum PixelateChannels {
+    /// Pixelate brightness and color alike.
+    All,
+    /// Average brightness per block, keeping each pixel's own color.
+    Luma,
+    /// Average color per block, keeping each pixel's own brightness.
+    Chroma,
+}
+
+impl Display for PixelateChannels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PixelateChannels::All => "all",
+            PixelateChannels::Luma => "luma",
+            PixelateChannels::Chroma => "chroma",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for PixelateChannels {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(PixelateChannels::All),
+            "luma" => Ok(PixelateChannels::Luma),
+            "chroma" => Ok(PixelateChannels::Chroma),
+            other => Err(anyhow::anyhow!("Unknown pixelate channels {:?}", other)),
+        }
+    }
+}
+
+/// Which part of an automatically segmented image to pixelate.
+/// Needs a build with the `segmentation` feature.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum AutoMask {
+    Subject,
+    Background,
+}
+
+impl Display for AutoMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AutoMask::Subject => "subject",
+            AutoMask::Background => "background",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for AutoMask {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "subject" | "foreground" => Ok(AutoMask::Subject),
+            "background" => Ok(AutoMask::Background),
+            other => Err(anyhow::anyhow!("Unknown auto mask {:?}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum ResizeMode {
+    /// If one of width/height is missing, preserve aspect. If both provided, use them.
+    Auto,
+    /// Force exact width×height (may distort); both required.
+    Exact,
+    /// Fit inside width×height, preserving aspect; the result may be smaller on one axis.
+    Contain,
+    /// Fill width×height, preserving aspect, and center-crop the overflow.
+    Cover,
+    /// Fit inside width×height and center on an exact-size canvas of `background`.
+    Pad,
+}
+
+impl Display for ResizeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ResizeMode::Auto => "auto",
+            ResizeMode::Exact => "exact",
+            ResizeMode::Contain => "contain",
+            ResizeMode::Cover => "cover",
+            ResizeMode::Pad => "pad",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for ResizeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(ResizeMode::Auto),
+            "exact" => Ok(ResizeMode::Exact),
+            "contain" => Ok(ResizeMode::Contain),
+            "cover" => Ok(ResizeMode::Cover),
+            "pad" => Ok(ResizeMode::Pad),
+            other => Err(anyhow::anyhow!("Unknown resize mode {:?}", other)),
+        }
+    }
+}
+
+/// Longest edge allowed by the email-safe preset.
+pub const EMAIL_SAFE_MAX_EDGE: u32 = 1600;
+/// Encoded size cap of the email-safe preset (500 KB).
+pub const EMAIL_SAFE_MAX_BYTES: u64 = 500 * 1024;
+/// Largest output width or height, and block edge, a config may ask for.
+pub const MAX_DIMENSION: u32 = 32_768;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, JsonSchema)]
+pub struct LowresConfig {
+    /// Config format version; see `migrate`. Missing means a pre-versioning config.
+    pub version: Option<u32>,
+    /// Crop the (orientation-corrected) source to this region before anything else.
+    pub crop: Option<Region>,
+    /// `crop` in percent of the source's size; `crop` takes precedence.
+    pub percent_crop: Option<PercentRegion>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Resize by this factor of the source size (0.25 = 25%) when width and height are unset.
+    pub scale: Option<f32>,
+    pub mode: Option<ResizeMode>,
+    /// Size used when none of the above is set (`64x64`, `source` or `error`);
+    /// defaults to 64x64.
+    #[schemars(with = "Option<String>")]
+    pub default_size: Option<DefaultSize>,
+    /// Canvas color for `ResizeMode::Pad`: `#rrggbb`, `#rrggbbaa` or `transparent` (the default).
+    pub background: Option<String>,
+    pub filter: Option<Resample>,
+    /// Keep the source size: no resize, `upscale` or `max_edge`, and `max_bytes`
+    /// fails instead of shrinking. With `no_pixelate`, only color, tag and
+    /// metadata changes are made.
+    pub no_resize: Option<bool>,
+    /// Ignore the block settings.
+    pub no_pixelate: Option<bool>,
+    pub block: Option<u32>,
+    /// Block width for rectangular blocks; falls back to `block`.
+    pub block_width: Option<u32>,
+    /// Block height for rectangular blocks; falls back to `block`.
+    pub block_height: Option<u32>,
+    /// How each block's color is chosen; defaults to `Mean`.
+    pub block_stat: Option<BlockStat>,
+    /// Average and resample in linear light rather than on sRGB values.
+    pub linear_light: Option<bool>,
+    /// What the pixelation path writes; defaults to `Full`.
+    pub block_output: Option<BlockOutput>,
+    /// Pixelate only brightness or only color; defaults to `All`. Needs
+    /// `block_output` full, and can't be combined with a palette.
+    pub pixelate_channels: Option<PixelateChannels>,
+    /// Posterize brightness into hard steps, or deband gradients before colors
+    /// are reduced. Deband applies to resized output only.
+    pub banding: Option<Banding>,
+    /// Brightness steps for `Banding::Posterize`; defaults to 8.
+    pub banding_levels: Option<u32>,
+    /// Check the output for banding in gradients and report where it shows,
+    /// or also dither it away.
+    pub banding_check: Option<BandingCheck>,
+    /// Pixelate only inside these regions, leaving the rest of the image untouched
+    /// (for redacting faces or plates). Needs a block size.
+    pub regions: Option<Vec<Region>>,
+    /// More `regions`, in percent of the size after any crop, so one list
+    /// fits a batch of inputs of different sizes.
+    pub percent_regions: Option<Vec<PercentRegion>>,
+    /// Run text recognition over the pixelated `regions` and warn, or
+    /// enlarge the blocks, while any text in them still reads. Needs a build
+    /// with the `ocr` feature.
+    pub legibility_guard: Option<LegibilityGuard>,
+    /// Pixelate only the detected subject or background. Needs a block size.
+    pub auto_mask: Option<AutoMask>,
+    pub pixel_down_filter: Option<Resample>,
+    /// Output DPI; defaults to the source's recorded DPI, or 300 without one.
+    pub dpi: Option<u32>,
+    /// Physical print width (`4in`, `10cm`, `90mm`); sets `width` from `dpi` when it is unset.
+    #[schemars(with = "Option<String>")]
+    pub print_width: Option<Length>,
+    /// Physical print height; sets `height` from `dpi` when it is unset.
+    #[schemars(with = "Option<String>")]
+    pub print_height: Option<Length>,
+    /// Enlarge the result by this integer factor (after resize/pixelation).
+    pub upscale: Option<u32>,
+    /// Algorithm for `upscale`; defaults to `Nearest`.
+    pub upscaler: Option<Upscaler>,
+    /// Scale the result down so its longest edge fits within this many pixels.
+    pub max_edge: Option<u32>,
+    /// Keep shrinking the result until the encoded file fits in this many bytes.
+    pub max_bytes: Option<u64>,
+    /// PNG compression level; defaults to `Fast`, or `Best` with `max_bytes`.
+    pub png_compression: Option<PngCompression>,
+    /// Pick the PNG filter row by row, whichever leaves the least to
+    /// compress, rather than always Sub. Smaller files, slower encodes.
+    pub png_adaptive_filter: Option<bool>,
+    /// Write still PNGs Adam7-interlaced, so browsers show the whole image
+    /// coarsely while it loads. Files come out somewhat larger.
+    pub png_interlace: Option<bool>,
+    /// Tag the output as sRGB (the default). Ignored when the source has an ICC
+    /// profile, which is passed through instead.
+    pub srgb: Option<bool>,
+    /// Copy EXIF (minus orientation, which is baked in), XMP and copyright/author
+    /// text from the source into the output.
+    pub keep_metadata: Option<bool>,
+    /// Privacy mode: write only the critical PNG chunks, so no EXIF, GPS, ICC,
+    /// sRGB, DPI or text survives. Overrides `keep_metadata`, `srgb` and `dpi` tagging.
+    pub strip_metadata: Option<bool>,
+    /// Carry the source's keywords and caption (XMP or IPTC) into the output's
+    /// XMP, without the rest of what `keep_metadata` copies. Implied by it.
+    pub keep_keywords: Option<bool>,
+    /// Keywords added to the output's XMP, such as `lowres-proof`, so asset
+    /// managers can tell derivatives apart.
+    pub add_keywords: Option<Vec<String>>,
+    /// Snap output colors to a built-in palette.
+    pub palette: Option<Palette>,
+    /// Snap output colors to a palette file (.gpl, .act or a hex list). Wins over `palette`.
+    pub palette_file: Option<PathBuf>,
+    /// Reduce the output to at most this many colors (median cut). Ignored if `palette` is set.
+    pub colors: Option<u32>,
+    /// In a batch, build one palette of `colors` colors from all inputs and
+    /// quantize every output against it, so an asset set shares one color
+    /// scheme. The palette is saved as `shared_palette.hex` beside the outputs.
+    pub shared_palette: Option<bool>,
+    /// Match colors to this reference image's, so assets from different
+    /// sources share a look. Applied to the source before pixelation, so a
+    /// palette still wins.
+    pub match_colors: Option<PathBuf>,
+    /// How colors are matched to `match_colors`; defaults to `Histogram`.
+    pub match_method: Option<ColorMatch>,
+    /// Dither between palette colors instead of snapping each pixel (or
+    /// block) to the nearest. Needs `palette`, `palette_file` or `colors`.
+    pub dither: Option<Dither>,
+    /// Monochrome film grain from 0 to 1, added before colors are reduced.
+    pub grain: Option<f32>,
+    /// A named look that fills in the palette, dithering, block size and
+    /// grain it needs; explicit values of those fields win.
+    pub style: Option<Style>,
+    /// Preset for attaching proofs to emails: ≤ 1600px, ≤ 500 KB, sRGB, no metadata.
+    /// A source ICC profile is baked into sRGB pixels and the output tagged
+    /// sRGB, since mail clients ignore profiles; everything else is stripped.
+    /// Explicit `max_edge`/`max_bytes` values take precedence.
+    pub email_safe: Option<bool>,
+    /// Trade speed for a smaller footprint on large images: jobs run on one
+    /// worker unless `threads` is set, and pixelation reads the source a
+    /// strip at a time instead of copying it whole. The output is still built
+    /// whole, so the decoded source and one full-size RGBA output are held at
+    /// once; `tiled` streams the output instead.
+    pub low_memory: Option<bool>,
+    /// Pixelate a strip of block rows at a time and stream the output's rows
+    /// to the encoder, for images too large to hold twice. PNG sources are
+    /// read a strip at a time too. Palettes, dithering and grain apply;
+    /// cropping, regions, resizing and the other passes don't.
+    pub tiled: Option<bool>,
+    /// Where pixelation runs; defaults to `Cpu`.
+    pub backend: Option<Backend>,
+    /// Worker threads to process on; defaults to one per core.
+    pub threads: Option<usize>,
+    /// Process on fewer workers, half the cores unless `threads` is set, at
+    /// background priority, for batches left running while the machine is
+    /// in use.
+    pub low_priority: Option<bool>,
+    /// Directory outputs are written to when no output path is given;
+    /// defaults to next to each input.
+    pub output_dir: Option<PathBuf>,
+    /// Output file name when no output path is given, from the tokens
+    /// `{stem}`, `{ext}`, `{block}`, `{width}` and `{height}` (of the output);
+    /// defaults to `{stem}_lowres.{ext}`.
+    pub output_template: Option<String>,
+    /// Export one output per size from a single decode (favicon and thumbnail
+    /// sets), each named after the output with `_<size>` added to its stem.
+    /// Replaces `width`, `height` and `scale`.
+    #[schemars(with = "Option<Vec<String>>")]
+    pub sizes: Option<Vec<OutputSpec>>,
+    /// Write the result's alpha channel as a grayscale `<stem>_matte.png` next
+    /// to the output, and the output itself as RGB, for compositing tools
+    /// that take separate fill and matte passes.
+    pub matte: Option<bool>,
+    /// Write `<stem>.json` next to the output, recording its source and the
+    /// source's hash, the resolved config, timings, size and color statistics.
+    /// Can't be combined with `strip_metadata` or `email_safe`.
+    pub sidecar: Option<bool>,
+    /// In animated output, fold a frame into the one before it, adding up
+    /// their delays, when no channel of any pixel differs by more than this.
+    /// Defaults to 0, folding only identical frames.
+    pub frame_tolerance: Option<u8>,
+}
+
+/// JSON Schema for `LowresConfig`, the single source of truth for frontends
+/// validating or generating forms for it.
+pub fn config_schema() -> schemars::schema::RootSchema {
+    schemars::schema_for!(LowresConfig)
+}
+
+impl LowresConfig {
+    /// Fill in the constraints implied by presets, leaving explicit values alone.
+    fn resolve_presets(mut self) -> Self {
+        if let Some(style) = self.style {
+            self = style.fill(self);
+        }
+        if self.email_safe.unwrap_or(false) {
+            self.max_edge.get_or_insert(EMAIL_SAFE_MAX_EDGE);
+            self.max_bytes.get_or_insert(EMAIL_SAFE_MAX_BYTES);
+            self.srgb = Some(true);
+            self.strip_metadata = Some(true);
+        }
+        self
+    }
+
+    /// Turn `percent_crop` and `percent_regions` into pixels of a
+    /// `size` source, as `crop` and more `regions`.
+    fn resolve_percentages(mut self, size: (u32, u32)) -> Self {
+        if let Some(crop) = self.percent_crop.take() {
+            self.crop.get_or_insert(crop.to_pixels(size.0, size.1));
+        }
+        if let Some(regions) = self.percent_regions.take() {
+            let (w, h) = self.crop.map_or(size, |c| (c.width, c.height));
+            self.regions
+                .get_or_insert_with(Vec::new)
+                .extend(regions.iter().map(|r| r.to_pixels(w, h)));
+        }
+        self
+    }
+
+    /// Effective pixelation block, if pixelation is enabled. A lone
+    /// `block_width` or `block_height` gives square blocks.
+    pub fn block_size(&self) -> Option<BlockSize> {
+        let width = self.block_width.or(self.block);
+        let height = self.block_height.or(self.block);
+        match (width, height) {
+            (Some(width), Some(height)) => Some(BlockSize { width, height }),
+            (Some(b), None) | (None, Some(b)) => Some(BlockSize {
+                width: b,
+                height: b,
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// The output width and height asked for, in pixels: `width` and
+    /// `height`, else the print sizes at `dpi`. Fails if either is 0 or over
+    /// `MAX_DIMENSION`.
+    pub fn target_dimensions(&self, dpi: u32) -> Result<(Option<u32>, Option<u32>)> {
+        let width = self.width.or(self.print_width.map(|l| l.to_pixels(dpi)));
+        let height = self.height.or(self.print_height.map(|l| l.to_pixels(dpi)));
+        for (name, value) in [("width", width), ("height", height)] {
+            let message = match value {
+                Some(0) => format!("{} must be at least 1", name),
+                Some(v) if v > MAX_DIMENSION => {
+                    format!("{} of {}px is over the {}px limit", name, v, MAX_DIMENSION)
+                }
+                _ => continue,
+            };
+            return Err(LowresError::InvalidConfig(message).into());
+        }
+        Ok((width, height))
+    }
+
+    /// Reject configs that are inconsistent or out of range, before any
+    /// decoding. Checks only what can be known without the image.
+    pub fn validate(&self) -> Result<()> {
+        if self.style.is_some() {
+            // Check the settings the style fills in too.
+            let resolved = LowresConfig {
+                style: None,
+                ..self.clone().resolve_presets()
+            };
+            return resolved.validate();
+        }
+        let invalid =
+            |message: String| -> Result<()> { Err(LowresError::InvalidConfig(message).into()) };
+        let dpi = self.dpi.unwrap_or(300);
+        if dpi == 0 {
+            return invalid("dpi must be at least 1".into());
+        }
+        if self.threads == Some(0) {
+            return invalid("threads must be at least 1".into());
+        }
+
+        // Checked again at the source's DPI once it is known.
+        let (width, height) = self.target_dimensions(dpi)?;
+        if let Some(s) = self.scale {
+            if !(s.is_finite() && s > 0.0) {
+                return invalid(format!("scale must be positive, got {}", s));
+            }
+        }
+        let sized = width.is_some() || height.is_some() || self.scale.is_some();
+        let resizes = self
+            .block_size()
+            .filter(|_| !self.no_pixelate.unwrap_or(false))
+            .is_none()
+            && !self.no_resize.unwrap_or(false);
+        if resizes && !sized && self.default_size == Some(DefaultSize::Error) {
+            return invalid(
+                "No output size given: set width, height, print_width, print_height or scale"
+                    .into(),
+            );
+        }
+        let mode = self.mode.unwrap_or(ResizeMode::Auto);
+        let needs_both = matches!(
+            mode,
+            ResizeMode::Exact | ResizeMode::Cover | ResizeMode::Pad
+        );
+        if needs_both && width.is_some() != height.is_some() && !self.no_resize.unwrap_or(false) {
+            return invalid(format!(
+                "mode {} needs both width and height; use mode auto to keep the aspect ratio",
+                mode
+            ));
+        }
+        if let Some(sizes) = &self.sizes {
+            if sizes.is_empty() {
+                return invalid("sizes is empty".into());
+            }
+            if sized || !resizes {
+                return invalid(
+                    "sizes sets each output's size; it can't be combined with width, height, \
+                     print sizes, scale, no_resize or a block size"
+                        .into(),
+                );
+            }
+            for (i, size) in sizes.iter().enumerate() {
+                if size.width.max(size.height) > MAX_DIMENSION {
+                    return invalid(format!(
+                        "size {} is over the {}px limit",
+                        size, MAX_DIMENSION
+                    ));
+                }
+                if sizes[..i].contains(size) {
+                    return invalid(format!("size {} is listed twice", size));
+                }
+            }
+        }
+
+        for (name, value) in [
+            ("block", self.block),
+            ("block_width", self.block_width),
+            ("block_height", self.block_height),
+        ] {
+            match value {
+                Some(0) => return invalid(format!("{} must be at least 1", name)),
+                Some(v) if v > MAX_DIMENSION => {
+                    return invalid(format!(
+                        "{} of {}px is over the {}px limit",
+                        name, v, MAX_DIMENSION
+                    ))
+                }
+                _ => {}
+            }
+        }
+        let pixelates = self.block_size().is_some() && !self.no_pixelate.unwrap_or(false);
+        let masked =
+            self.regions.is_some() || self.percent_regions.is_some() || self.auto_mask.is_some();
+        if masked && !pixelates {
+            return invalid("regions and auto_mask need a block size".into());
+        }
+        if masked && self.block_output == Some(BlockOutput::Small) {
+            return invalid(
+                "regions and auto_mask keep the full image size; use block_output full".into(),
+            );
+        }
+
+        if self
+            .pixelate_channels
+            .is_some_and(|c| c != PixelateChannels::All)
+        {
+            if self.block_output == Some(BlockOutput::Small) {
+                return invalid("pixelate_channels needs block_output full".into());
+            }
+            if self.palette.is_some() || self.palette_file.is_some() || self.colors.is_some() {
+                return invalid(
+                    "pixelate_channels can't be combined with palette, palette_file or colors"
+                        .into(),
+                );
+            }
+        }
+
+        if let Some(levels) = self.banding_levels {
+            if !(2..=256).contains(&levels) {
+                return invalid(format!(
+                    "banding_levels must be between 2 and 256, got {}",
+                    levels
+                ));
+            }
+        }
+        let quantizes =
+            self.palette.is_some() || self.palette_file.is_some() || self.colors.is_some();
+        match self.banding {
+            Some(Banding::Deband) if pixelates => {
+                return invalid("deband has no effect on pixelated blocks, which are flat".into())
+            }
+            Some(Banding::Posterize) if pixelates && quantizes => return invalid(
+                "posterize can't be combined with palette, palette_file or colors when pixelating"
+                    .into(),
+            ),
+            _ => {}
+        }
+
+        if let Some(factor) = self.upscale {
+            if factor == 0 || factor > upscale::MAX_FACTOR {
+                return invalid(format!(
+                    "upscale must be between 1 and {}, got {}",
+                    upscale::MAX_FACTOR,
+                    factor
+                ));
+            }
+            if self.upscaler == Some(Upscaler::Scale2x) && factor > 4 {
+                return invalid(format!(
+                    "scale2x supports upscale 2, 3 and 4, got {}",
+                    factor
+                ));
+            }
+        }
+        if self.max_edge == Some(0) {
+            return invalid("max_edge must be at least 1".into());
+        }
+        if self.max_bytes == Some(0) {
+            return invalid("max_bytes must be at least 1".into());
+        }
+        if self.colors == Some(0) {
+            return invalid("colors must be at least 1".into());
+        }
+        if self.dither.is_some() && !quantizes {
+            return invalid("dither needs palette, palette_file or colors".into());
+        }
+        if let Some(grain) = self.grain {
+            if !(0.0..=1.0).contains(&grain) {
+                return invalid(format!("grain must be between 0 and 1, got {}", grain));
+            }
+        }
+        if self.legibility_guard.is_some()
+            && self.regions.is_none()
+            && self.percent_regions.is_none()
+        {
+            return invalid("legibility_guard checks pixelated regions; set regions".into());
+        }
+        if self.tiled == Some(true) {
+            tiled::check_tiled(&self.clone().resolve_presets())?;
+        }
+        if let Some(keywords) = &self.add_keywords {
+            if keywords.iter().any(|k| k.trim().is_empty()) {
+                return invalid("add_keywords can't contain empty keywords".into());
+            }
+        }
+        if self.shared_palette == Some(true)
+            && (self.colors.is_none() || self.palette.is_some() || self.palette_file.is_some())
+        {
+            return invalid("shared_palette needs colors, and no palette or palette_file".into());
+        }
+        if let Some(crop) = &self.crop {
+            if crop.width == 0 || crop.height == 0 {
+                return invalid(format!("crop {} is empty", crop));
+            }
+        }
+        for region in self
+            .percent_crop
+            .iter()
+            .chain(self.percent_regions.iter().flatten())
+        {
+            region.check()?;
+        }
+        if let Some(template) = &self.output_template {
+            batch::check_template(template)?;
+        }
+        // email_safe strips metadata too.
+        let strips = self.strip_metadata == Some(true) || self.email_safe == Some(true);
+        if self.sidecar == Some(true) && strips {
+            return invalid(
+                "sidecar records the source's path and hash, which strip_metadata leaves out"
+                    .into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// This config for a copy of the source scaled by `scale`: sizes given in
+    /// source pixels shrink with it, so the result looks like the full render.
+    /// Output sizes stay as they are, and `max_bytes` is dropped.
+    fn scaled_for_proxy(self, scale: f64) -> Self {
+        let mut config = self.scaled_sizes(scale);
+        config.max_bytes = None;
+        config
+    }
+
+    /// The config for rendering a copy of the source scaled by `scale`: its
+    /// sizes in source pixels scaled to match.
+    fn scaled_sizes(mut self, scale: f64) -> Self {
+        if scale == 1.0 {
+            return self;
+        }
+        let px = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+        let region = |r: Region| Region {
+            x: (r.x as f64 * scale).round() as u32,
+            y: (r.y as f64 * scale).round() as u32,
+            width: px(r.width),
+            height: px(r.height),
+        };
+        if let Some(size) = self.block_size() {
+            self.block = None;
+            self.block_width = Some(px(size.width));
+            self.block_height = Some(px(size.height));
+        }
+        self.crop = self.crop.map(region);
+        self.regions = self
+            .regions
+            .map(|regions| regions.into_iter().map(region).collect());
+        self.scale = self.scale.map(|s| (s as f64 / scale) as f32);
+        self
+    }
+
+    fn quantize(&self) -> Result<Option<Quantize>> {
+        if let Some(path) = &self.palette_file {
+            return Ok(Some(Quantize::Fixed(palette::load_palette_file(path)?)));
+        }
+        Ok(match (self.palette, self.colors) {
+            (Some(p), _) => Some(Quantize::Fixed(p.colors().to_vec())),
+            (None, Some(n)) => Some(Quantize::Adaptive(n.max(1) as usize)),
+            (None, None) => None,
+        })
+    }
+
+    /// The `match_colors` reference, loaded and summarized for matching.
+    fn color_reference(&self) -> Result<Option<color_match::Reference>> {
+        self.match_colors
+            .as_deref()
+            .map(|path| {
+                let method = self.match_method.unwrap_or(ColorMatch::Histogram);
+                color_match::Reference::load(path, method)
+                    .with_context(|| format!("Color reference {:?}", path))
+            })
+            .transpose()
+    }
+}
+
+/// How the output colors are restricted.
+enum Quantize {
+    Fixed(Vec<[u8; 3]>),
+    /// Median-cut palette of this many colors, built from the image itself.
+    Adaptive(usize),
+}
+
+impl Quantize {
+    /// Rough spacing between palette colors along one channel, treating the
+    /// palette as a grid over the RGB cube.
+    fn channel_step(&self) -> f32 {
+        let n = match self {
+            Quantize::Fixed(colors) => colors.len(),
+            Quantize::Adaptive(n) => *n,
+        };
+        255.0 / (n.max(2) as f32).cbrt()
+    }
+
+    fn palette_for<'a>(&self, pixels: impl ExactSizeIterator<Item = &'a Rgba<u8>>) -> Vec<[u8; 3]> {
+        match self {
+            Quantize::Fixed(colors) => colors.clone(),
+            Quantize::Adaptive(n) => palette::median_cut(pixels, *n),
+        }
+    }
+}
+
+/// What `process_image` produced.
+#[derive(Serialize, Debug, Clone)]
+pub struct ProcessReport {
+    pub original_width: u32,
+    pub original_height: u32,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    /// DPI tagged in the output; `None` when metadata was stripped.
+    pub dpi: Option<u32>,
+    pub timings: Timings,
+    /// With `matte`, the output's alpha channel as an encoded grayscale PNG,
+    /// which `write_output` writes next to the output.
+    #[serde(skip)]
+    pub matte: Option<Vec<u8>>,
+    /// With `sidecar`, the provenance `write_output` writes next to the output.
+    #[serde(skip)]
+    pub sidecar: Option<sidecar::Sidecar>,
+    /// With `banding_check`, what it found.
+    pub banding: Option<banding::BandingReport>,
+    /// With `legibility_guard`, what it found.
+    pub legibility: Option<legibility::LegibilityReport>,
+}
+
+/// Wall-clock time spent in each stage, in milliseconds.
+/// `transform_ms` excludes the time reported under `quantize_ms`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Timings {
+    pub decode_ms: f64,
+    pub transform_ms: f64,
+    pub quantize_ms: f64,
+    pub encode_ms: f64,
+}
+
+fn elapsed_ms(since: Instant) -> f64 {
+    since.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Pipeline stages, reported in order as processing advances.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize)]
+pub enum Stage {
+    Decode,
+    Transform,
+    Encode,
+    Write,
+}
+
+pub fn process_image(
+    input: PathBuf,
+    output: PathBuf,
+    config: LowresConfig,
+) -> Result<ProcessReport> {
+    process_image_with_progress(input, output, config, &mut |_| {})
+}
+
+/// Like `process_image`, calling `on_stage` as each stage starts. A `.gif`
+/// or `.apng` output is an animation of every frame of the source.
+pub fn process_image_with_progress(
+    input: PathBuf,
+    output: PathBuf,
+    config: LowresConfig,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<ProcessReport> {
+    let (encoded, report) = match animation::AnimatedFormat::of(&output) {
+        Some(format) => animation::render_animation(&input, format, config, on_stage)?,
+        None if config.tiled == Some(true) => {
+            return tiled::process_tiled(&input, &output, &config, on_stage)
+        }
+        None => render_png(&input, config, on_stage)?,
+    };
+
+    on_stage(Stage::Write);
+    write_output(&output, &encoded, &report)?;
+
+    Ok(report)
+}
+
+/// Write a rendered PNG to `output`, its matte, if it has one, to
+/// `matte_path(output)`, and its sidecar, if it has one, to `sidecar_path(output)`.
+pub fn write_output(output: &Path, encoded: &[u8], report: &ProcessReport) -> Result<()> {
+    std::fs::write(output, encoded).with_context(|| format!("Failed to create {:?}", output))?;
+    if let Some(matte) = &report.matte {
+        let path = matte_path(output);
+        std::fs::write(&path, matte).with_context(|| format!("Failed to create {:?}", path))?;
+    }
+    if let Some(sidecar) = &report.sidecar {
+        let path = sidecar_path(output);
+        std::fs::write(&path, sidecar::sidecar_json(sidecar, report)?)
+            .with_context(|| format!("Failed to create {:?}", path))?;
+    }
+    Ok(())
+}
+
+/// Where the matte of `output` is written: `<stem>_matte.png` beside it.
+pub fn matte_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(format!("{}_matte.png", stem))
+}
+
+/// Run the pipeline on `input` and return the encoded PNG without touching disk.
+pub fn render_png(
+    input: &PathBuf,
+    config: LowresConfig,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<(Vec<u8>, ProcessReport)> {
+    if config.tiled == Some(true) {
+        let mut png = Vec::new();
+        let report = tiled::render_tiled(&read_input(input)?, &config, &mut png, on_stage)?;
+        return Ok((png, report));
+    }
+    on_stage(Stage::Decode);
+    let started = Instant::now();
+    let source = load_source_for(input, &config)?;
+    let decode_ms = elapsed_ms(started);
+
+    let (encoded, mut report) = render_source(&source, config, PreviewQuality::Full, on_stage)?;
+    report.timings.decode_ms = decode_ms;
+    sidecar::attach_source(&mut report, input)?;
+    Ok((encoded, report))
+}
+
+/// A decoded source image and its metadata, kept so that several configs can
+/// be rendered from it without reading and decoding the file again.
+pub struct Source {
+    /// Orientation-corrected, uncropped.
+    img: DynamicImage,
+    /// The file's upright size. `img` is smaller when it was decoded at a
+    /// reduced JPEG scale, for a config whose output doesn't need the rest.
+    size: (u32, u32),
+    /// Everything `keep_metadata` would copy, plus the ICC profile.
+    metadata: Metadata,
+    /// Downscaled copy for `PreviewQuality::Proxy` and its scale, made on first use.
+    proxy: OnceLock<(DynamicImage, f64)>,
+}
+
+/// Longest edge of the proxy image used for fast previews.
+pub const PROXY_EDGE: u32 = 1500;
+
+/// Resolution a loaded source is rendered at.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Default, Deserialize, Serialize, JsonSchema)]
+pub enum PreviewQuality {
+    /// A copy at most `PROXY_EDGE` pixels on its longest edge, with the
+    /// config's source-pixel sizes scaled to match. Fast enough for sliders.
+    Proxy,
+    /// The source itself, as exported.
+    #[default]
+    Full,
+}
+
+/// A source made from pixels that never were a file, such as a clipboard
+/// image. It has no metadata to carry over.
+impl From<DynamicImage> for Source {
+    fn from(img: DynamicImage) -> Self {
+        Source {
+            size: img.dimensions(),
+            img,
+            metadata: Metadata::default(),
+            proxy: OnceLock::new(),
+        }
+    }
+}
+
+impl Source {
+    fn proxy(&self) -> &(DynamicImage, f64) {
+        self.proxy.get_or_init(|| {
+            let (w, h) = self.img.dimensions();
+            let scale = |img: &DynamicImage| img.width() as f64 / self.size.0 as f64;
+            if w.max(h) <= PROXY_EDGE {
+                return (self.img.clone(), scale(&self.img));
+            }
+            let proxy = self.img.thumbnail(PROXY_EDGE, PROXY_EDGE);
+            let scale = scale(&proxy);
+            (proxy, scale)
+        })
+    }
+}
+
+/// Read and decode `input` for `render_source`.
+pub fn load_source(input: &PathBuf) -> Result<Source> {
+    decode_source(&read_input(input)?)
+}
+
+/// Decode an encoded image held in memory for `render_source`.
+pub fn decode_source(data: &[u8]) -> Result<Source> {
+    let img = decode_image(data)?;
+    Ok(source_of(data, img.dimensions(), img))
+}
+
+fn source_of(data: &[u8], size: (u32, u32), img: DynamicImage) -> Source {
+    Source {
+        img,
+        size,
+        metadata: source_metadata(data),
+        proxy: OnceLock::new(),
+    }
+}
+
+/// Everything `keep_metadata` would copy from the encoded image `data`, plus
+/// its ICC profile.
+fn source_metadata(data: &[u8]) -> Metadata {
+    let mut metadata = metadata::read_metadata(data);
+    metadata.icc_profile = metadata::read_icc_profile(data);
+    metadata
+}
+
+/// Read and decode `input` for rendering with `config` at full quality. A
+/// JPEG is decoded at the smallest DCT scale whose output is the same as a
+/// full decode's, which for a small output from a large photo saves most
+/// of the time and memory.
+pub fn load_source_for(input: &PathBuf, config: &LowresConfig) -> Result<Source> {
+    let data = read_input(input)?;
+    let dpi = metadata::read_source_facts(&mut Cursor::new(&data)).dpi;
+    let config = config.clone().resolve_presets();
+    let (img, size) = decode_scaled(&data, |size, denominator| {
+        decodes_reduced(&config, size, dpi, denominator)
+    })?;
+    Ok(source_of(&data, size, img))
+}
+
+/// Whether rendering `config` from a source of `size` decoded at 1/`denominator`
+/// gives an output of the same size, from at least as many pixels. Crops and
+/// regions are left at full scale, as rounding would shift them.
+fn decodes_reduced(
+    config: &LowresConfig,
+    size: (u32, u32),
+    dpi: Option<u32>,
+    denominator: u32,
+) -> bool {
+    if config.crop.is_some()
+        || config.percent_crop.is_some()
+        || config.regions.is_some()
+        || config.percent_regions.is_some()
+        || config.auto_mask.is_some()
+    {
+        return false;
+    }
+    let blocks_divide = config
+        .block_size()
+        .is_none_or(|b| b.width % denominator == 0 && b.height % denominator == 0);
+    let reduced = (size.0.div_ceil(denominator), size.1.div_ceil(denominator));
+    let scaled = config
+        .clone()
+        .scaled_sizes(reduced.0 as f64 / size.0 as f64);
+    match (
+        plan::plan_for(size, dpi, config),
+        plan::plan_for(reduced, dpi, &scaled),
+    ) {
+        (Ok(full), Ok(plan)) => {
+            blocks_divide
+                && (plan.width, plan.height) == (full.width, full.height)
+                && plan.block_grid == full.block_grid
+                && reduced.0 >= full.width
+                && reduced.1 >= full.height
+        }
+        _ => false,
+    }
+}
+
+/// Run the pipeline on an encoded image held in memory, such as a browser
+/// drag or a canvas export, and return the encoded PNG without touching disk.
+/// A matte or sidecar the config asks for is left in the report.
+pub fn process_image_bytes(data: &[u8], config: LowresConfig) -> Result<(Vec<u8>, ProcessReport)> {
+    let started = Instant::now();
+    let source = decode_source(data)?;
+    let decode_ms = elapsed_ms(started);
+
+    let (encoded, mut report) = render_source(&source, config, PreviewQuality::Full, &mut |_| {})?;
+    report.timings.decode_ms = decode_ms;
+    Ok((encoded, report))
+}
+
+/// The bytes of a `data:` URL with base64 content, or of plain base64.
+pub fn decode_data_url(data_url: &str) -> Result<Vec<u8>> {
+    use base64::Engine;
+
+    let b64 = match data_url.strip_prefix("data:") {
+        Some(rest) => match rest.split_once(',') {
+            Some((header, b64)) if header.ends_with(";base64") => b64,
+            _ => {
+                return Err(LowresError::InvalidConfig(
+                    "Only base64 data URLs are supported".into(),
+                )
+                .into())
+            }
+        },
+        None => data_url,
+    };
+    base64::engine::general_purpose::STANDARD
+        .decode(b64.trim())
+        .map_err(|e| LowresError::Decode(format!("Invalid base64 image data: {}", e)).into())
+}
+
+/// Run the pipeline on an already decoded `source`; the report's decode time is zero.
+pub fn render_source(
+    source: &Source,
+    config: LowresConfig,
+    quality: PreviewQuality,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<(Vec<u8>, ProcessReport)> {
+    let (orig_w, orig_h) = source.size;
+    if quality == PreviewQuality::Full && limits::current().max_output_megapixels.is_some() {
+        let plan = plan::plan_for((orig_w, orig_h), source.metadata.dpi, &config)?;
+        limits::current().check_output(plan.width, plan.height)?;
+    }
+    // Before proxy scaling, so sizes a style fills in are scaled too.
+    let config = config
+        .resolve_presets()
+        .resolve_percentages((orig_w, orig_h));
+    let (source_img, config) = match quality {
+        // A source decoded at a reduced scale was decoded for this config.
+        PreviewQuality::Full => {
+            let scale = source.img.width() as f64 / orig_w as f64;
+            (&source.img, config.scaled_sizes(scale))
+        }
+        PreviewQuality::Proxy => {
+            let (proxy, scale) = source.proxy();
+            let mut config = config.scaled_for_proxy(*scale);
+            // Rounding may push a scaled crop just past the proxy's edge.
+            config.crop = config
+                .crop
+                .and_then(|c| c.clip(proxy.width(), proxy.height()));
+            (proxy, config)
+        }
+    };
+    let quantize = config.quantize()?;
+
+    let img = match &config.crop {
+        Some(crop) => Cow::Owned(crop.crop(source_img)?),
+        None => Cow::Borrowed(source_img),
+    };
+    let metadata = output_metadata(&source.metadata, &config);
+    let (encoded, mut report) = render_decoded(
+        &img,
+        &config,
+        quantize.as_ref(),
+        Some(metadata),
+        Timings::default(),
+        on_stage,
+    )?;
+    report.original_width = orig_w;
+    report.original_height = orig_h;
+    Ok((encoded, report))
+}
+
+/// The metadata an output made with `config` carries from its `source`'s:
+/// the ICC profile and DPI, and what `keep_metadata`, `keep_keywords` and
+/// `add_keywords` ask for.
+fn output_metadata(source: &Metadata, config: &LowresConfig) -> Metadata {
+    let keep_metadata = config.keep_metadata.unwrap_or(false);
+    let mut metadata = if keep_metadata {
+        source.clone()
+    } else {
+        Metadata {
+            icc_profile: source.icc_profile.clone(),
+            dpi: source.dpi,
+            ..Default::default()
+        }
+    };
+    let keep_keywords = keep_metadata || config.keep_keywords.unwrap_or(false);
+    let mut keywords = Vec::new();
+    if keep_keywords {
+        keywords.extend(source.keywords.iter().cloned());
+    }
+    keywords.extend(
+        config
+            .add_keywords
+            .iter()
+            .flatten()
+            .map(|k| k.trim().to_string()),
+    );
+    let caption = source.caption.as_deref().filter(|_| keep_keywords);
+    if !keywords.is_empty() || caption.is_some() {
+        metadata.tag_keywords(&keywords, caption);
+    }
+    metadata
+}
+
+/// The transform stage of `render_decoded`: match `img`'s colors to
+/// `reference`, resize or pixelate it per `config`, then upscale and fit it.
+/// `dpi` converts print sizes.
+/// Time spent reducing colors is added to `timings.quantize_ms`.
+fn transform(
+    img: &DynamicImage,
+    config: &LowresConfig,
+    quantize: Option<&Quantize>,
+    reference: Option<&color_match::Reference>,
+    dpi: u32,
+    timings: &mut Timings,
+) -> Result<RgbaImage> {
+    let matched = reference.map(|r| DynamicImage::ImageRgba8(r.apply(&img.to_rgba8())));
+    let img = matched.as_ref().unwrap_or(img);
+    let mode = config.mode.unwrap_or(ResizeMode::Auto);
+    let filter = config.filter.unwrap_or(Resample::Nearest);
+    let linear_light = config.linear_light.unwrap_or(false);
+    let keep_size = config.no_resize.unwrap_or(false);
+    let banding_levels = config.banding_levels.unwrap_or(banding::DEFAULT_LEVELS);
+    let block = config
+        .block_size()
+        .filter(|_| !config.no_pixelate.unwrap_or(false));
+    let background = match &config.background {
+        Some(s) => color::parse_color(s)?,
+        None => Rgba([0, 0, 0, 0]),
+    };
+
+    let (out_img, _final_w, _final_h) = if let Some(block) = block {
+        // --- Pixelation path (keeps original WxH unless asked for the small grid) ---
+        let opts = PixelateOptions {
+            block,
+            stat: config.block_stat.unwrap_or(BlockStat::Mean),
+            linear_light,
+            quantize,
+            dither: config.dither,
+            grain: config.grain,
+            output: config.block_output.unwrap_or(BlockOutput::Full),
+            low_memory: config.low_memory.unwrap_or(false),
+            gpu: config.backend == Some(Backend::Gpu),
+        };
+        let mut rgba = match config.regions.as_deref() {
+            Some(regions) => pixelate_regions(img, regions, &opts, timings)?,
+            None => pixelate(img, &opts, timings)?,
+        };
+        rgba = match config.pixelate_channels.unwrap_or(PixelateChannels::All) {
+            PixelateChannels::All => rgba,
+            PixelateChannels::Luma => color::merge_ycbcr(&rgba, &img.to_rgba8()),
+            PixelateChannels::Chroma => color::merge_ycbcr(&img.to_rgba8(), &rgba),
+        };
+        if config.banding == Some(Banding::Posterize) {
+            banding::posterize_luma(&mut rgba, banding_levels);
+        }
+        if let Some(target) = config.auto_mask {
+            rgba = apply_auto_mask(img, rgba, target)?;
+        }
+        let dims = rgba.dimensions();
+        (rgba, dims.0, dims.1)
+    } else if config.regions.is_some() || config.auto_mask.is_some() {
+        return Err(LowresError::InvalidConfig(
+            "Pixelating regions or masks needs a block size".into(),
+        )
+        .into());
+    } else {
+        // --- Plain resize path ---
+        let (width, height) = config.target_dimensions(dpi)?;
+        let (tw, th) = if keep_size {
+            img.dimensions()
+        } else {
+            let default = config.default_size.unwrap_or_default();
+            pick_target_size(img.dimensions(), width, height, config.scale, mode, default)?
+        };
+        let filter_type: FilterType = filter.into();
+        let mut rgba = if keep_size {
+            img.to_rgba8()
+        } else if linear_light {
+            let linear = DynamicImage::ImageRgba32F(color::to_linear(img));
+            let resized = resize_image(&linear, tw, th, filter_type, mode)?;
+            color::from_linear(&resized.to_rgba32f())
+        } else {
+            let resized = resize_image(img, tw, th, filter_type, mode)?;
+            // Convert to RGBA8 for the encoder only once, reusing the buffer if it already is
+            resized.into_rgba8()
+        };
+        match config.banding {
+            Some(Banding::Posterize) => banding::posterize_luma(&mut rgba, banding_levels),
+            // Dither across roughly one palette step, or one level without a palette.
+            Some(Banding::Deband) => {
+                banding::deband(&mut rgba, quantize.map_or(1.0, Quantize::channel_step))
+            }
+            None => {}
+        }
+        if let Some(amount) = config.grain {
+            grain::add_grain(&mut rgba, amount);
+        }
+        if let Some(q) = quantize {
+            let quantize_started = Instant::now();
+            let colors = q.palette_for(rgba.pixels());
+            match config.dither {
+                Some(dither) => palette::dither_image(&mut rgba, &colors, dither, q.channel_step()),
+                None => palette::quantize_image(&mut rgba, &colors),
+            }
+            timings.quantize_ms = elapsed_ms(quantize_started);
+        }
+        // Pad after quantizing so the background stays exactly as requested.
+        if mode == ResizeMode::Pad && !keep_size {
+            rgba = pad_to(&rgba, tw, th, background);
+        }
+        (rgba, tw, th)
+    };
+
+    let out_img = match config.upscale.filter(|_| !keep_size) {
+        Some(factor) => {
+            let upscaler = config.upscaler.unwrap_or(Upscaler::Nearest);
+            upscale::upscale(&out_img, factor, upscaler)?
+        }
+        None => out_img,
+    };
+
+    let out_img = match config.max_edge.filter(|_| !keep_size) {
+        Some(max_edge) => fit_within(out_img, max_edge, filter.into()),
+        None => out_img,
+    };
+    Ok(out_img)
+}
+
+/// The pipeline after decoding: transform `img` per `config` and encode it,
+/// with `metadata` from the source if it is to be kept.
+/// `config` must have had its presets resolved.
+fn render_decoded(
+    img: &DynamicImage,
+    config: &LowresConfig,
+    quantize: Option<&Quantize>,
+    metadata: Option<Metadata>,
+    mut timings: Timings,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<(Vec<u8>, ProcessReport)> {
+    let (orig_w, orig_h) = img.dimensions();
+    // Email-safe output is tagged sRGB instead of carrying the source's
+    // profile, so the profile is applied to the pixels first.
+    let converted = metadata
+        .as_ref()
+        .and_then(|m| m.icc_profile.as_deref())
+        .filter(|_| config.email_safe == Some(true))
+        .and_then(|icc| color::icc_to_srgb(img, icc));
+    let img = converted.as_ref().unwrap_or(img);
+    let filter = config.filter.unwrap_or(Resample::Nearest);
+    // The config's DPI, else the source's, else 300.
+    let dpi = config
+        .dpi
+        .or(metadata.as_ref().and_then(|m| m.dpi))
+        .unwrap_or(300);
+    let keep_size = config.no_resize.unwrap_or(false);
+
+    on_stage(Stage::Transform);
+    let started = Instant::now();
+    let reference = config.color_reference()?;
+    let mut out_img = transform(img, config, quantize, reference.as_ref(), dpi, &mut timings)?;
+    // The config the output is finally rendered with, after the checks.
+    let mut rendered = Cow::Borrowed(config);
+    let banding = match config.banding_check {
+        Some(check) => {
+            let regions = banding::find_banding(&out_img, output_block_side(config));
+            let dither = check == BandingCheck::Dither
+                && !regions.is_empty()
+                && quantize.is_some()
+                && config.dither.is_none();
+            if dither {
+                rendered = Cow::Owned(LowresConfig {
+                    dither: Some(Dither::Ordered),
+                    ..config.clone()
+                });
+                out_img = transform(
+                    img,
+                    &rendered,
+                    quantize,
+                    reference.as_ref(),
+                    dpi,
+                    &mut timings,
+                )?;
+            }
+            Some(banding::BandingReport {
+                regions,
+                dithered: dither,
+            })
+        }
+        None => None,
+    };
+    let legibility = match config.legibility_guard {
+        Some(guard) => Some(legibility::guard(
+            guard,
+            &rendered,
+            img.dimensions(),
+            &mut out_img,
+            legibility::read_text,
+            |c| transform(img, c, quantize, reference.as_ref(), dpi, &mut timings),
+        )?),
+        None => None,
+    };
+    timings.transform_ms = elapsed_ms(started) - timings.quantize_ms;
+
+    let strip = config.strip_metadata.unwrap_or(false);
+    let matte = config.matte.unwrap_or(false);
+    let png_opts = png_options(config, dpi, metadata)?;
+
+    on_stage(Stage::Encode);
+    let started = Instant::now();
+    let (out_img, encoded) = match config.max_bytes {
+        Some(max_bytes) if keep_size => {
+            let encoded = encode_png(&out_img, &png_opts)?;
+            if encoded.len() as u64 > max_bytes {
+                anyhow::bail!(
+                    "Output is {} bytes, over the {} byte limit, and no_resize forbids shrinking it",
+                    encoded.len(),
+                    max_bytes
+                );
+            }
+            (out_img, encoded)
+        }
+        Some(max_bytes) => encode_within_byte_limit(out_img, max_bytes, &png_opts, filter.into())?,
+        None => {
+            let encoded = encode_png(&out_img, &png_opts)?;
+            (out_img, encoded)
+        }
+    };
+    let matte = if matte {
+        Some(encode_matte(&out_img, &png_opts)?)
+    } else {
+        None
+    };
+    timings.encode_ms = elapsed_ms(started);
+    let sidecar = config
+        .sidecar
+        .unwrap_or(false)
+        .then(|| sidecar::Sidecar::new(config, &out_img));
+
+    let report = ProcessReport {
+        original_width: orig_w,
+        original_height: orig_h,
+        width: out_img.width(),
+        height: out_img.height(),
+        bytes: encoded.len() as u64,
+        dpi: (!strip).then_some(dpi),
+        timings,
+        matte,
+        sidecar,
+        banding,
+        legibility,
+    };
+    Ok((encoded, report))
+}
+
+/// The side of a pixelation block in `config`'s output, in pixels: 1 for
+/// resized output and the small grid, before any `upscale`.
+fn output_block_side(config: &LowresConfig) -> u32 {
+    let side = match config.block_size() {
+        Some(block)
+            if !config.no_pixelate.unwrap_or(false)
+                && config.block_output.unwrap_or(BlockOutput::Full) == BlockOutput::Full =>
+        {
+            block.width.max(block.height)
+        }
+        _ => 1,
+    };
+    side * config
+        .upscale
+        .filter(|_| !config.no_resize.unwrap_or(false))
+        .unwrap_or(1)
+}
+
+/// Basic facts about an image file, read from its header and EXIF without
+/// decoding pixels.
+#[derive(Serialize, Debug, Clone)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub format: Option<String>,
+    pub mime: Option<String>,
+    /// Bits per channel.
+    pub bit_depth: u16,
+    pub has_alpha: bool,
+    /// Embedded resolution, when the file records one.
+    pub dpi: Option<u32>,
+    /// EXIF capture time as `YYYY-MM-DDTHH:MM:SS`.
+    pub taken: Option<String>,
+    /// EXIF camera make and model.
+    pub camera: Option<String>,
+}
+
+pub fn probe(path: &PathBuf) -> Result<ImageInfo> {
+    let open = || {
+        std::fs::File::open(path)
+            .map(std::io::BufReader::new)
+            .with_context(|| format!("Failed to read file {:?}", path))
+    };
+    let mut head = [0; 12];
+    // HEIF and RAW files are probed by their own decoders; RAWs are TIFFs
+    // until their first directory is read. AVIF and video are decoded.
+    if open()?.read_exact(&mut head).is_ok()
+        && (is_heif(&head) || is_tiff(&head) || Container::of(&head).is_some())
+    {
+        let data =
+            std::fs::read(path).with_context(|| format!("Failed to read file {:?}", path))?;
+        let probed = if is_heif(&data) {
+            Some((probe_heif(&data)?, "heif", Some("image/heif")))
+        } else if is_raw(&data) {
+            Some((probe_raw(&data)?, "raw", None))
+        } else {
+            match Container::of(&data) {
+                Some(container) => Some((
+                    probe_decoded(&decode_image(&data)?),
+                    container.extension(),
+                    (container == Container::Avif).then_some("image/avif"),
+                )),
+                None => None,
+            }
+        };
+        if let Some(((width, height, bit_depth, has_alpha), format, mime)) = probed {
+            let facts = metadata::read_source_facts(&mut Cursor::new(&data));
+            return Ok(ImageInfo {
+                width,
+                height,
+                format: Some(format.into()),
+                mime: mime.map(Into::into),
+                bit_depth,
+                has_alpha,
+                dpi: facts.dpi,
+                taken: facts.taken,
+                camera: facts.camera,
+            });
+        }
+    }
+    let reader = image::ImageReader::new(open()?)
+        .with_guessed_format()
+        .with_context(|| format!("Failed to read file {:?}", path))?;
+    let format = reader.format();
+    let decoder = reader.into_decoder().context("Failed to decode image")?;
+    let (width, height) = decoder.dimensions();
+    let color = decoder.color_type();
+    let facts = metadata::read_source_facts(&mut open()?);
+
+    Ok(ImageInfo {
+        width,
+        height,
+        format: format.map(|f| format!("{:?}", f).to_lowercase()),
+        mime: format.map(|f| f.to_mime_type().to_string()),
+        bit_depth: color.bits_per_pixel() / color.channel_count() as u16,
+        has_alpha: color.has_alpha(),
+        dpi: facts.dpi,
+        taken: facts.taken,
+        camera: facts.camera,
+    })
+}
+
+/// A small preview of `path` fitting within `max_edge`, for the webview:
+/// JPEG, or PNG when any pixel is transparent. Returns the bytes and MIME type.
+pub fn render_thumbnail(path: &PathBuf, max_edge: u32) -> Result<(Vec<u8>, &'static str)> {
+    let max_edge = max_edge.max(1);
+    let (img, _) = decode_scaled(&read_input(path)?, |(w, h), denominator| {
+        w.max(h).div_ceil(denominator) >= max_edge
+    })?;
+    let img = if img.width() > max_edge || img.height() > max_edge {
+        img.thumbnail(max_edge, max_edge)
+    } else {
+        img
+    };
+
+    let rgba = img.to_rgba8();
+    let mut out = Cursor::new(Vec::new());
+    if rgba.pixels().any(|p| p[3] < 255) {
+        rgba.write_to(&mut out, image::ImageFormat::Png)
+            .context("Failed to encode thumbnail")?;
+        Ok((out.into_inner(), "image/png"))
+    } else {
+        let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 85);
+        DynamicImage::ImageRgba8(rgba)
+            .to_rgb8()
+            .write_with_encoder(encoder)
+            .context("Failed to encode thumbnail")?;
+        Ok((out.into_inner(), "image/jpeg"))
+    }
+}
+
+fn load_image(path: &PathBuf) -> Result<DynamicImage> {
+    decode_image(&read_input(path)?)
+}
+
+/// Read the input file at `path`, unless it is over the file size limit.
+fn read_input(path: &PathBuf) -> Result<Vec<u8>> {
+    limits::check_file(path)?;
+    std::fs::read(path).with_context(|| format!("Failed to read file {:?}", path))
+}
+
+/// Read `path` after checking its size against `limits`.
+fn read_input_within(path: &Path, limits: &limits::SizeLimits) -> Result<Vec<u8>> {
+    limits.check_file(path)?;
+    std::fs::read(path).with_context(|| format!("Failed to read file {:?}", path))
+}
+
+fn decode_image(data: &[u8]) -> Result<DynamicImage> {
+    let limits = limits::current();
+    limits.check_file_bytes(data.len() as u64)?;
+    // Decoders with their own formats are checked once done; only the image
+    // crate's formats can be measured from the header alone.
+    let decoded = |img: DynamicImage| {
+        limits.check_input(img.width(), img.height())?;
+        Ok(img)
+    };
+    // libheif applies HEIF's own rotation and mirroring, which EXIF only repeats.
+    if is_heif(data) {
+        return decoded(decode_heif(data)?);
+    }
+    // Likewise the RAW developer for the camera's orientation.
+    if is_raw(data) {
+        return decoded(decode_raw(data)?);
+    }
+    // And ffmpeg for AVIF's and video's.
+    match Container::of(data) {
+        Some(Container::Avif) => return decoded(codecs::decode_avif(data)?),
+        Some(_) => return decoded(codecs::decode_video_frame(data)?),
+        None => {}
+    }
+
+    let header = image::ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    if let Ok((width, height)) = header.into_dimensions() {
+        limits.check_input(width, height)?;
+    }
+    let orientation = exif_orientation(&mut Cursor::new(data));
+    let img = image::load_from_memory(data).context("Failed to decode image")?;
+    Ok(orient(img, orientation))
+}
+
+/// `img` turned upright for its EXIF `orientation`.
+fn orient(img: DynamicImage, orientation: Option<u32>) -> DynamicImage {
+    match orientation {
+        Some(2) => img.fliph(),
+        Some(3) => img.rotate180(),
+        Some(4) => img.flipv(),
+        Some(5) => img.rotate90().fliph(),
+        Some(6) => img.rotate90(),
+        Some(7) => img.rotate270().fliph(),
+        Some(8) => img.rotate270(),
+        _ => img,
+    }
+}
+
+/// Decode `data` like `decode_image`, but a JPEG at the smallest of 1/8,
+/// 1/4 and 1/2 scale that `enough` accepts, given the upright full size and
+/// the scale's denominator. Returns the image and the full size.
+fn decode_scaled(
+    data: &[u8],
+    enough: impl Fn((u32, u32), u32) -> bool,
+) -> Result<(DynamicImage, (u32, u32))> {
+    if cfg!(feature = "dct-scaling") && data.starts_with(&[0xff, 0xd8, 0xff]) {
+        let limits = limits::current();
+        limits.check_file_bytes(data.len() as u64)?;
+        let header = image::ImageReader::with_format(Cursor::new(data), image::ImageFormat::Jpeg);
+        let (w, h) = header.into_dimensions()?;
+        limits.check_input(w, h)?;
+        let orientation = exif_orientation(&mut Cursor::new(data));
+        let size = match orientation {
+            Some(5..=8) => (h, w),
+            _ => (w, h),
+        };
+        if let Some(denominator) = [8, 4, 2].into_iter().find(|&d| enough(size, d)) {
+            if let Some(img) = decode_jpeg_scaled(data, denominator)? {
+                return Ok((orient(img, orientation), size));
+            }
+        }
+    }
+    let img = decode_image(data)?;
+    let size = img.dimensions();
+    Ok((img, size))
+}
+
+/// The EXIF orientation of an image, 1 to 8, if it records one.
+fn exif_orientation(container: &mut (impl std::io::BufRead + std::io::Seek)) -> Option<u32> {
+    Reader::new()
+        .read_from_container(container)
+        .ok()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY).cloned())
+        .and_then(|field| field.value.get_uint(0))
+}
+
+/// Output size for the resize path. Explicit `width`/`height` win over `scale`.
+fn pick_target_size(
+    (w0, h0): (u32, u32),
+    width: Option<u32>,
+    height: Option<u32>,
+    scale: Option<f32>,
+    mode: ResizeMode,
+    default: DefaultSize,
+) -> Result<(u32, u32)> {
+    if let (None, None, Some(s)) = (width, height, scale) {
+        if !(s.is_finite() && s > 0.0) {
+            return Err(
+                LowresError::InvalidConfig(format!("Scale must be positive, got {}", s)).into(),
+            );
+        }
+        let w = ((w0 as f64) * s as f64).round().max(1.0) as u32;
+        let h = ((h0 as f64) * s as f64).round().max(1.0) as u32;
+        return Ok((w, h));
+    }
+
+    match (width, height, mode) {
+        (Some(w), Some(h), _) => Ok((w, h)),
+
+        (Some(w), None, _) => {
+            let h = ((h0 as f64) * (w as f64) / (w0 as f64)).round().max(1.0) as u32;
+            Ok((w, h))
+        }
+        (None, Some(h), _) => {
+            let w = ((w0 as f64) * (h as f64) / (h0 as f64)).round().max(1.0) as u32;
+            Ok((w, h))
+        }
+        (None, None, _) => match default {
+            DefaultSize::Fixed { width, height } => Ok((width, height)),
+            DefaultSize::Source => Ok((w0, h0)),
+            DefaultSize::Error => Err(LowresError::InvalidConfig(
+                "No output size given: set width, height, print_width, print_height or scale"
+                    .into(),
+            )
+            .into()),
+        },
+    }
+}
+
+fn resize_image(
+    img: &DynamicImage,
+    w: u32,
+    h: u32,
+    filter: FilterType,
+    mode: ResizeMode,
+) -> Result<DynamicImage> {
+    // Keep as DynamicImage so we can call to_rgba8()
+    Ok(match mode {
+        ResizeMode::Exact => img.resize_exact(w, h, filter),
+        ResizeMode::Cover => img.resize_to_fill(w, h, filter),
+        // Pad letterboxes the contained image afterwards, see `pad_to`.
+        ResizeMode::Auto | ResizeMode::Contain | ResizeMode::Pad => img.resize(w, h, filter),
+    })
+}
+
+/// Center `img` on a `w`×`h` canvas filled with `background`.
+fn pad_to(img: &RgbaImage, w: u32, h: u32, background: Rgba<u8>) -> RgbaImage {
+    let mut canvas = RgbaImage::from_pixel(w, h, background);
+    let x = (w.saturating_sub(img.width()) / 2) as i64;
+    let y = (h.saturating_sub(img.height()) / 2) as i64;
+    image::imageops::overlay(&mut canvas, img, x, y);
+    canvas
+}
+
+/// Pixelate the parts of `img` inside `regions` (clipped to the image) and
+/// composite them over the untouched original.
+fn pixelate_regions(
+    img: &DynamicImage,
+    regions: &[Region],
+    opts: &PixelateOptions,
+    timings: &mut Timings,
+) -> Result<RgbaImage> {
+    if opts.output == BlockOutput::Small {
+        anyhow::bail!("Pixelating regions keeps the full image size; use block_output full");
+    }
+    let (w, h) = img.dimensions();
+    let mut out = img.to_rgba8();
+    for region in regions.iter().filter_map(|r| r.clip(w, h)) {
+        let part = pixelate(&region.crop(img)?, opts, timings)?;
+        image::imageops::replace(&mut out, &part, region.x as i64, region.y as i64);
+    }
+    Ok(out)
+}
+
+/// Whether `data` starts like an HEIF file, such as an iPhone's HEIC photos,
+/// by the major brand of its `ftyp` box.
+fn is_heif(data: &[u8]) -> bool {
+    const BRANDS: [&[u8]; 8] = [
+        b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+    ];
+    data.get(4..8) == Some(b"ftyp") && data.get(8..12).is_some_and(|brand| BRANDS.contains(&brand))
+}
+
+#[cfg(feature = "heif")]
+use heif::{decode_heif, probe_heif};
+
+#[cfg(not(feature = "heif"))]
+use codecs::decode_heif;
+
+#[cfg(not(feature = "heif"))]
+fn probe_heif(data: &[u8]) -> Result<(u32, u32, u16, bool)> {
+    decode_heif(data).map(|img| probe_decoded(&img))
+}
+
+/// Width, height, bits per channel and alpha of an image already decoded,
+/// for formats probed by decoding them.
+fn probe_decoded(img: &DynamicImage) -> (u32, u32, u16, bool) {
+    let color = img.color();
+    (
+        img.width(),
+        img.height(),
+        color.bits_per_pixel() / color.channel_count() as u16,
+        color.has_alpha(),
+    )
+}
+
+/// File extensions of the camera RAW formats the `raw` feature reads.
+const RAW_EXTENSIONS: [&str; 4] = ["dng", "cr2", "nef", "arw"];
+
+/// Extensions of the files this build reads as input, for file dialogs.
+/// Formats read through ffmpeg are listed only when it is found.
+pub fn input_extensions() -> Vec<&'static str> {
+    let mut extensions = vec![
+        "png", "apng", "jpg", "jpeg", "webp", "gif", "bmp", "tif", "tiff",
+    ];
+    let ffmpeg = codecs::ffmpeg().is_some();
+    if cfg!(feature = "heif") || ffmpeg {
+        extensions.extend(["heic", "heif"]);
+    }
+    if codecs::avif_built_in() || ffmpeg {
+        extensions.push("avif");
+    }
+    if ffmpeg {
+        extensions.extend(codecs::VIDEO_EXTENSIONS);
+    }
+    if cfg!(feature = "raw") {
+        extensions.extend(RAW_EXTENSIONS);
+    }
+    extensions
+}
+
+fn is_tiff(data: &[u8]) -> bool {
+    data.starts_with(b"II*\0") || data.starts_with(b"MM\0*")
+}
+
+/// Whether `data` is a camera RAW file rather than an ordinary TIFF: a Canon
+/// CR2, a DNG, or a TIFF-based RAW such as NEF or ARW, whose first directory
+/// holds only a reduced-resolution preview of the sensor data.
+fn is_raw(data: &[u8]) -> bool {
+    const NEW_SUBFILE_TYPE: u16 = 0x00fe;
+    const DNG_VERSION: u16 = 0xc612;
+    if !is_tiff(data) {
+        return false;
+    }
+    if data.get(8..10) == Some(b"CR") {
+        return true;
+    }
+    let big_endian = data[0] == b'M';
+    let u16_at = |i: usize| {
+        let b: [u8; 2] = data.get(i..i + 2)?.try_into().ok()?;
+        Some(if big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    };
+    let u32_at = |i: usize| {
+        let b: [u8; 4] = data.get(i..i + 4)?.try_into().ok()?;
+        Some(if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    };
+    let Some(ifd) = u32_at(4).map(|offset| offset as usize) else {
+        return false;
+    };
+    let entries = u16_at(ifd).unwrap_or(0) as usize;
+    (0..entries).any(|i| {
+        let entry = ifd + 2 + i * 12;
+        match u16_at(entry) {
+            Some(DNG_VERSION) => true,
+            Some(NEW_SUBFILE_TYPE) => u32_at(entry + 8).is_some_and(|kind| kind & 1 == 1),
+            _ => false,
+        }
+    })
+}
+
+#[cfg(feature = "raw")]
+use raw::{decode_raw, probe_raw};
+
+#[cfg(feature = "gpu")]
+pub use gpu::gpu_available;
+#[cfg(feature = "gpu")]
+use gpu::{gpu_block_means, gpu_expand};
+
+/// Without the `gpu` feature there is no adapter, and pixelation stays on
+/// the CPU.
+#[cfg(not(feature = "gpu"))]
+pub fn gpu_available() -> bool {
+    false
+}
+
+#[cfg(not(feature = "gpu"))]
+fn gpu_block_means(_: &RgbaImage, _: BlockSize) -> Option<Vec<Rgba<u8>>> {
+    None
+}
+
+#[cfg(not(feature = "gpu"))]
+fn gpu_expand(_: &[Rgba<u8>], _: u32, _: BlockSize, _: (u32, u32)) -> Option<Vec<u8>> {
+    None
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(_: &[u8]) -> Result<DynamicImage> {
+    Err(LowresError::UnsupportedFormat(
+        "Camera RAW files need lowres built with the `raw` feature".into(),
+    )
+    .into())
+}
+
+#[cfg(feature = "dct-scaling")]
+use dct_scale::decode_jpeg_scaled;
+
+/// Without the scaled decoder every JPEG is decoded in full.
+#[cfg(not(feature = "dct-scaling"))]
+fn decode_jpeg_scaled(_: &[u8], _: u32) -> Result<Option<DynamicImage>> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "raw"))]
+fn probe_raw(data: &[u8]) -> Result<(u32, u32, u16, bool)> {
+    decode_raw(data).map(|_| (0, 0, 0, false))
+}
+
+/// Keep `pixelated` only on the `target` side of the automatic subject mask,
+/// and the original `img` everywhere else.
+#[cfg(feature = "segmentation")]
+fn apply_auto_mask(
+    img: &DynamicImage,
+    mut pixelated: RgbaImage,
+    target: AutoMask,
+) -> Result<RgbaImage> {
+    if pixelated.dimensions() != img.dimensions() {
+        anyhow::bail!("Masked pixelation keeps the full image size; use block_output full");
+    }
+    let original = img.to_rgba8();
+    let mask = segment::subject_mask(&original);
+    for ((px, orig), subject) in pixelated.pixels_mut().zip(original.pixels()).zip(mask) {
+        if subject != (target == AutoMask::Subject) {
+            *px = *orig;
+        }
+    }
+    Ok(pixelated)
+}
+
+#[cfg(not(feature = "segmentation"))]
+fn apply_auto_mask(_: &DynamicImage, _: RgbaImage, _: AutoMask) -> Result<RgbaImage> {
+    Err(LowresError::InvalidConfig(
+        "auto_mask needs lowres built with the `segmentation` feature".into(),
+    )
+    .into())
+}
+
+struct PixelateOptions<'a> {
+    block: BlockSize,
+    /// How a block's pixels are reduced to one color.
+    stat: BlockStat,
+    /// Average in linear light instead of on sRGB values.
+    linear_light: bool,
+    /// Snap each block's color to the nearest palette entry.
+    quantize: Option<&'a Quantize>,
+    /// Dither between palette entries across blocks; needs `quantize`.
+    dither: Option<Dither>,
+    /// Film grain added to the block colors before they are quantized.
+    grain: Option<f32>,
+    /// `Small` skips the upscale and returns the blocks_x × blocks_y grid.
+    output: BlockOutput,
+    /// Convert one row of blocks at a time instead of copying the whole image.
+    low_memory: bool,
+    /// Average blocks and expand them on the GPU when one is available.
+    gpu: bool,
+}
+
+/// Pixelate by downscaling to a coarse grid, then upscaling back with Nearest.
+/// `block` is the desired block size in source pixels; blocks may be rectangular.
+/// Optimized version using direct pixel manipulation with parallel processing.
+fn pixelate(
+    img: &DynamicImage,
+    opts: &PixelateOptions,
+    timings: &mut Timings,
+) -> Result<RgbaImage> {
+    let (w, h) = img.dimensions();
+    let bw = opts.block.width.max(1) as usize;
+    let bh = opts.block.height.max(1) as usize;
+    let (stat, linear) = (opts.stat, opts.linear_light);
+
+    // Calculate block grid dimensions
+    let blocks_x = (w as usize).div_ceil(bw);
+    let blocks_y = (h as usize).div_ceil(bh);
+    let grid_block = BlockSize {
+        width: bw as u32,
+        height: bh as u32,
+    };
+
+    let mut block_colors: Vec<Rgba<u8>> = if opts.low_memory {
+        // Convert a strip of one block row at a time rather than the whole image.
+        let mut colors = Vec::with_capacity(blocks_x * blocks_y);
+        for block_y in 0..blocks_y {
+            let y_start = (block_y * bh) as u32;
+            let strip_h = (bh as u32).min(h - y_start);
+            let strip = img.view(0, y_start, w, strip_h).to_image();
+            colors.extend((0..blocks_x).map(|block_x| {
+                let x_start = (block_x * bw) as u32;
+                let x_end = (x_start + bw as u32).min(w);
+                block_color(&strip, x_start..x_end, 0..strip_h, stat, linear)
+            }));
+        }
+        colors
+    } else {
+        // Convert to RGBA once at the start
+        let rgba = img.to_rgba8();
+        let on_gpu = opts.gpu && stat == BlockStat::Mean && !linear;
+        if let Some(colors) = on_gpu.then(|| gpu_block_means(&rgba, grid_block)).flatten() {
+            colors
+        } else {
+            // Pre-compute the color of each block in parallel
+            (0..blocks_y * blocks_x)
+                .into_par_iter()
+                .map(|idx| {
+                    let block_y = idx / blocks_x;
+                    let block_x = idx % blocks_x;
+
+                    let x_start = block_x * bw;
+                    let y_start = block_y * bh;
+                    let x_end = ((x_start + bw).min(w as usize)) as u32;
+                    let y_end = ((y_start + bh).min(h as usize)) as u32;
+
+                    block_color(
+                        &rgba,
+                        x_start as u32..x_end,
+                        y_start as u32..y_end,
+                        stat,
+                        linear,
+                    )
+                })
+                .collect()
+        }
+    };
+
+    finish_blocks(&mut block_colors, blocks_x, opts, timings);
+
+    if opts.output == BlockOutput::Small {
+        let grid: Vec<u8> = block_colors.iter().flat_map(|c| c.0).collect();
+        return RgbaImage::from_raw(blocks_x as u32, blocks_y as u32, grid)
+            .ok_or_else(|| anyhow::anyhow!("Failed to create output buffer"));
+    }
+
+    // The GPU's staging buffers would be another copy of the output.
+    if opts.gpu && !opts.low_memory {
+        if let Some(buffer) = gpu_expand(&block_colors, blocks_x as u32, grid_block, (w, h)) {
+            return RgbaImage::from_raw(w, h, buffer)
+                .ok_or_else(|| anyhow::anyhow!("Failed to create output buffer"));
+        }
+    }
+
+    // Create output image by filling each block with its average color
+    // Optimized: Use parallel iterator over rows instead of par_bridge on pixels
+    let mut buffer = vec![0u8; (w * h * 4) as usize];
+
+    buffer
+        .par_chunks_exact_mut((w * 4) as usize)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let block_y = y / bh;
+            let row_block_start = block_y * blocks_x;
+
+            for x in 0..w as usize {
+                let block_x = x / bw;
+                let color = block_colors[row_block_start + block_x];
+
+                let i = x * 4;
+                row[i] = color[0];
+                row[i + 1] = color[1];
+                row[i + 2] = color[2];
+                row[i + 3] = color[3];
+            }
+        });
+
+    let output = RgbaImage::from_raw(w, h, buffer)
+        .ok_or_else(|| anyhow::anyhow!("Failed to create output buffer"))?;
+
+    Ok(output)
+}
+
+/// Add `opts`' grain to the block colors of a grid `blocks_x` wide, then
+/// reduce them to its palette.
+fn finish_blocks(
+    block_colors: &mut [Rgba<u8>],
+    blocks_x: usize,
+    opts: &PixelateOptions,
+    timings: &mut Timings,
+) {
+    if let Some(amount) = opts.grain {
+        grain::add_grain_to_blocks(block_colors, blocks_x, amount);
+    }
+    if let Some(q) = opts.quantize {
+        let started = Instant::now();
+        let colors = q.palette_for(block_colors.iter());
+        match opts.dither {
+            Some(dither) => {
+                palette::dither_pixels(block_colors, blocks_x, &colors, dither, q.channel_step())
+            }
+            None => {
+                let lookup = kernels::NearestColor::new(&colors);
+                block_colors
+                    .par_iter_mut()
+                    .for_each(|c| *c = lookup.nearest(*c));
+            }
+        }
+        timings.quantize_ms = elapsed_ms(started);
+    }
+}
+
+/// Color of one block under `stat`. `xs`/`ys` are the block's pixel ranges.
+/// `linear` only matters for `Mean`; the other statistics pick existing values.
+fn block_color(
+    rgba: &RgbaImage,
+    xs: Range<u32>,
+    ys: Range<u32>,
+    stat: BlockStat,
+    linear: bool,
+) -> Rgba<u8> {
+    let pixels = || {
+        let xs = xs.clone();
+        ys.clone()
+            .flat_map(move |y| xs.clone().map(move |x| *rgba.get_pixel(x, y)))
+    };
+
+    match stat {
+        BlockStat::Mean if linear => {
+            let mut sums = [0f32; 4];
+            let mut count = 0u32;
+            for p in pixels() {
+                for ch in 0..3 {
+                    sums[ch] += color::srgb_to_linear(p[ch]);
+                }
+                sums[3] += p[3] as f32;
+                count += 1;
+            }
+            if count == 0 {
+                return Rgba([0, 0, 0, 255]);
+            }
+            let n = count as f32;
+            Rgba([
+                color::linear_to_srgb(sums[0] / n),
+                color::linear_to_srgb(sums[1] / n),
+                color::linear_to_srgb(sums[2] / n),
+                (sums[3] / n).round() as u8,
+            ])
+        }
+        BlockStat::Mean => {
+            // Average the pixels in this block, a row at a time
+            let width = rgba.width() as usize;
+            let raw = rgba.as_raw();
+            let mut sums = [0u64; 4];
+            for y in ys.clone() {
+                let row = y as usize * width;
+                let run = &raw[(row + xs.start as usize) * 4..(row + xs.end as usize) * 4];
+                for (s, v) in sums.iter_mut().zip(kernels::sum_rgba(run)) {
+                    *s += v as u64;
+                }
+            }
+
+            let count = xs.len() as u64 * ys.len() as u64;
+            if count > 0 {
+                Rgba(sums.map(|s| (s / count) as u8))
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        }
+        BlockStat::Median => {
+            let mut channels: [Vec<u8>; 4] = Default::default();
+            for p in pixels() {
+                for (c, v) in channels.iter_mut().zip(p.0) {
+                    c.push(v);
+                }
+            }
+            if channels[0].is_empty() {
+                return Rgba([0, 0, 0, 255]);
+            }
+            let mid = channels[0].len() / 2;
+            Rgba(channels.map(|mut c| *c.select_nth_unstable(mid).1))
+        }
+        BlockStat::Dominant => {
+            let mut counts: HashMap<[u8; 4], u32> = HashMap::new();
+            let mut best = ([0, 0, 0, 255], 0);
+            for p in pixels() {
+                let n = counts.entry(p.0).or_insert(0);
+                *n += 1;
+                if *n > best.1 {
+                    best = (p.0, *n);
+                }
+            }
+            Rgba(best.0)
+        }
+        BlockStat::Darkest => pixels().min_by_key(luma).unwrap_or(Rgba([0, 0, 0, 255])),
+        BlockStat::Lightest => pixels().max_by_key(luma).unwrap_or(Rgba([0, 0, 0, 255])),
+    }
+}
+
+/// Rec. 601 luma, scaled by 1000.
+fn luma(p: &Rgba<u8>) -> u32 {
+    299 * p[0] as u32 + 587 * p[1] as u32 + 114 * p[2] as u32
+}
+
+fn dpi_to_ppm(dpi: u32) -> u32 {
+    // PNG pHYs uses pixels-per-meter. 1 inch = 0.0254 m.
+    ((dpi as f64) / 0.0254).round() as u32
+}
+
+/// Scale `rgba` down (never up) so that its longest edge is at most `max_edge`.
+fn fit_within(rgba: RgbaImage, max_edge: u32, filter: FilterType) -> RgbaImage {
+    let (nw, nh) = fit_size(rgba.dimensions(), max_edge);
+    if (nw, nh) == rgba.dimensions() {
+        return rgba;
+    }
+    image::imageops::resize(&rgba, nw, nh, filter)
+}
+
+/// The size `fit_within` scales a `w`×`h` image to.
+fn fit_size((w, h): (u32, u32), max_edge: u32) -> (u32, u32) {
+    let max_edge = max_edge.max(1);
+    if w <= max_edge && h <= max_edge {
+        return (w, h);
+    }
+    let scale = (max_edge as f64) / (w.max(h) as f64);
+    let nw = ((w as f64) * scale).round().clamp(1.0, max_edge as f64) as u32;
+    let nh = ((h as f64) * scale).round().clamp(1.0, max_edge as f64) as u32;
+    (nw, nh)
+}
+
+struct PngOptions {
+    /// Write RGB without the alpha channel.
+    drop_alpha: bool,
+    /// Written as pHYs; `None` leaves the chunk out.
+    dpi: Option<u32>,
+    srgb: bool,
+    /// Source metadata to carry over.
+    metadata: Option<Metadata>,
+    /// `LowresConfig` JSON for the `lowres:settings` chunk.
+    settings: Option<String>,
+    compression: png::Compression,
+    adaptive_filter: bool,
+    /// Write Adam7-interlaced image data; still images only.
+    interlace: bool,
+}
+
+/// The PNG options `config` asks for, at `dpi`, with `metadata` from the source.
+fn png_options(config: &LowresConfig, dpi: u32, metadata: Option<Metadata>) -> Result<PngOptions> {
+    // Descriptive source metadata is only copied with keep_metadata; otherwise the
+    // encoder writes nothing but pHYs and the color space, and not even that when stripping.
+    let strip = config.strip_metadata.unwrap_or(false);
+    Ok(PngOptions {
+        drop_alpha: config.matte.unwrap_or(false),
+        dpi: (!strip).then_some(dpi),
+        // The sRGB tag is a statement about the pixels, not source
+        // metadata, so email-safe output keeps it while stripping the rest.
+        srgb: (!strip || config.email_safe == Some(true)) && config.srgb.unwrap_or(true),
+        metadata: metadata.filter(|_| !strip),
+        settings: if strip {
+            None
+        } else {
+            Some(metadata::settings_json(config)?)
+        },
+        compression: match config.png_compression {
+            Some(compression) => compression.into(),
+            None if config.max_bytes.is_some() => png::Compression::Best,
+            None => png::Compression::Fast,
+        },
+        adaptive_filter: config.png_adaptive_filter.unwrap_or(false),
+        interlace: config.png_interlace.unwrap_or(false),
+    })
+}
+
+fn encode_png(rgba: &RgbaImage, opts: &PngOptions) -> Result<Vec<u8>> {
+    if opts.drop_alpha {
+        let rgb: Vec<u8> = rgba.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        encode_png_data(rgba.dimensions(), png::ColorType::Rgb, &rgb, opts)
+    } else {
+        encode_png_data(rgba.dimensions(), png::ColorType::Rgba, rgba, opts)
+    }
+}
+
+/// `rgba`'s alpha channel as a grayscale PNG, tagged with the output's DPI.
+fn encode_matte(rgba: &RgbaImage, opts: &PngOptions) -> Result<Vec<u8>> {
+    let alpha: Vec<u8> = rgba.pixels().map(|p| p[3]).collect();
+    let opts = PngOptions {
+        drop_alpha: false,
+        dpi: opts.dpi,
+        srgb: false,
+        metadata: None,
+        settings: None,
+        compression: opts.compression,
+        adaptive_filter: opts.adaptive_filter,
+        interlace: opts.interlace,
+    };
+    encode_png_data(rgba.dimensions(), png::ColorType::Grayscale, &alpha, &opts)
+}
+
+fn encode_png_data(
+    size: (u32, u32),
+    color: png::ColorType,
+    data: &[u8],
+    opts: &PngOptions,
+) -> Result<Vec<u8>> {
+    encode_png_frames(size, color, &[(data, (0, 1))], None, opts)
+}
+
+/// Encode `frames`, each with its delay in seconds as a fraction. With
+/// `plays` (0 for forever) they make an APNG; without it, only the first
+/// frame is written, as a still PNG.
+fn encode_png_frames(
+    (w, h): (u32, u32),
+    color: png::ColorType,
+    frames: &[(&[u8], (u16, u16))],
+    plays: Option<u32>,
+    opts: &PngOptions,
+) -> Result<Vec<u8>> {
+    let frames = match plays {
+        Some(_) => frames,
+        None => &frames[..1],
+    };
+    if opts.interlace && plays.is_some() {
+        return Err(LowresError::InvalidConfig(
+            "png_interlace applies to still PNG output only".into(),
+        )
+        .into());
+    }
+
+    let mut out = Vec::new();
+    let mut writer = png_writer(
+        &mut out,
+        (w, h),
+        color,
+        plays.map(|p| (frames.len(), p)),
+        opts,
+    )?;
+
+    if opts.interlace {
+        let idat = adam7::interlaced_idat(
+            (w, h),
+            color.samples(),
+            frames[0].0,
+            opts.adaptive_filter,
+            flate_level(opts.compression),
+        )?;
+        writer
+            .write_chunk(png::chunk::IDAT, &idat)
+            .map_err(|e| anyhow::anyhow!("PNG write error: {}", e))?;
+        return writer
+            .finish()
+            .map(|()| out)
+            .map_err(|e| anyhow::anyhow!("PNG write error: {}", e));
+    }
+
+    let write_error = |e: png::EncodingError| anyhow::anyhow!("PNG write error: {}", e);
+    let bpp = color.samples();
+    let mut prev: Option<&[u8]> = None;
+    for &(data, (numer, denom)) in frames {
+        if plays.is_some() {
+            writer.set_frame_delay(numer, denom).map_err(write_error)?;
+        }
+        let Some(prev) = prev.replace(data) else {
+            writer.write_image_data(data).map_err(write_error)?;
+            continue;
+        };
+        // After the first, a frame is the box that changed, kept over the
+        // frames before it. Over blending lets the box's unchanged pixels be
+        // transparent, which compresses, as long as its changed ones are opaque.
+        let area = animation::changed_box(prev, data, w, bpp).unwrap_or(Region {
+            x: 0,
+            y: 0,
+            width: 1,
+            height: 1,
+        });
+        let mut pixels = animation::crop_rows(data, w, bpp, area);
+        let before = animation::crop_rows(prev, w, bpp, area);
+        let over = bpp == 4
+            && pixels
+                .chunks_exact(4)
+                .zip(before.chunks_exact(4))
+                .all(|(p, b)| p == b || p[3] == 255);
+        if over {
+            for (p, b) in pixels.chunks_exact_mut(4).zip(before.chunks_exact(4)) {
+                if p == b {
+                    p.fill(0);
+                }
+            }
+        }
+        // Bounds are checked against the current offset, so move it out of
+        // the way before resizing.
+        writer.set_frame_position(0, 0).map_err(write_error)?;
+        writer
+            .set_frame_dimension(area.width, area.height)
+            .map_err(write_error)?;
+        writer
+            .set_frame_position(area.x, area.y)
+            .map_err(write_error)?;
+        writer
+            .set_blend_op(if over {
+                png::BlendOp::Over
+            } else {
+                png::BlendOp::Source
+            })
+            .map_err(write_error)?;
+        writer.write_image_data(&pixels).map_err(write_error)?;
+    }
+
+    writer
+        .finish()
+        .map_err(|e| anyhow::anyhow!("PNG write error: {}", e))?;
+
+    Ok(out)
+}
+
+/// A PNG writer for `w`×`h` 8-bit `color` pixels with the chunks `opts`
+/// asks for already written, ready for image data. With `animation`, the
+/// frame count and plays of an APNG.
+fn png_writer<W: std::io::Write>(
+    out: W,
+    (w, h): (u32, u32),
+    color: png::ColorType,
+    animation: Option<(usize, u32)>,
+    opts: &PngOptions,
+) -> Result<png::Writer<W>> {
+    use png::{BitDepth, Encoder, PixelDimensions, SrgbRenderingIntent, Unit};
+
+    // A source profile describes the pixels better than a generic sRGB tag.
+    let icc_profile = opts
+        .metadata
+        .as_ref()
+        .and_then(|m| m.icc_profile.as_deref());
+    let mut info = png::Info::with_size(w, h);
+    info.icc_profile = icc_profile.map(Cow::Borrowed);
+    info.interlaced = opts.interlace;
+    let mut encoder =
+        Encoder::with_info(out, info).map_err(|e| anyhow::anyhow!("PNG header error: {}", e))?;
+    encoder.set_color(color);
+    encoder.set_depth(BitDepth::Eight);
+    encoder.set_compression(opts.compression);
+    if opts.adaptive_filter {
+        encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    }
+
+    encoder.set_pixel_dims(opts.dpi.map(|dpi| {
+        let ppm = dpi_to_ppm(dpi);
+        PixelDimensions {
+            xppu: ppm,
+            yppu: ppm,
+            unit: Unit::Meter,
+        }
+    }));
+
+    if opts.srgb && icc_profile.is_none() {
+        encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+    }
+    if let Some(meta) = &opts.metadata {
+        meta.add_text_chunks(&mut encoder)
+            .map_err(|e| anyhow::anyhow!("PNG metadata error: {}", e))?;
+    }
+    if let Some(settings) = &opts.settings {
+        encoder
+            .add_itxt_chunk(metadata::SETTINGS_KEYWORD.to_string(), settings.clone())
+            .map_err(|e| anyhow::anyhow!("PNG metadata error: {}", e))?;
+    }
+    if let Some((frames, plays)) = animation {
+        encoder
+            .set_animated(frames as u32, plays)
+            .map_err(|e| anyhow::anyhow!("PNG header error: {}", e))?;
+    }
+
+    let mut writer = encoder
+        .write_header()
+        .map_err(|e| anyhow::anyhow!("PNG header error: {}", e))?;
+
+    if let Some(exif) = opts.metadata.as_ref().and_then(|m| m.exif.as_ref()) {
+        writer
+            .write_chunk(png::chunk::eXIf, exif)
+            .map_err(|e| anyhow::anyhow!("PNG metadata error: {}", e))?;
+    }
+    Ok(writer)
+}
+
+/// The deflate level the png crate uses for `compression`.
+fn flate_level(compression: png::Compression) -> flate2::Compression {
+    match compression {
+        png::Compression::Best => flate2::Compression::best(),
+        png::Compression::Default => flate2::Compression::default(),
+        _ => flate2::Compression::fast(),
+    }
+}
+
+/// Encode `rgba`, shrinking it until the PNG fits in `max_bytes`.
+/// Returns the image that was finally encoded alongside its bytes.
+fn encode_within_byte_limit(
+    mut rgba: RgbaImage,
+    max_bytes: u64,
+    opts: &PngOptions,
+    filter: FilterType,
+) -> Result<(RgbaImage, Vec<u8>)> {
+    loop {
+        let encoded = encode_png(&rgba, opts)?;
+        let size = encoded.len() as u64;
+        if size <= max_bytes {
+            return Ok((rgba, encoded));
+        }
+
+        let (w, h) = rgba.dimensions();
+        if w <= 1 && h <= 1 {
+            anyhow::bail!(
+                "Cannot fit output within {} bytes (smallest encoding is {} bytes)",
+                max_bytes,
+                size
+            );
+        }
+
+        // Encoded size scales roughly with pixel count; aim a little under the cap.
+        let scale = ((max_bytes as f64) / (size as f64)).sqrt() * 0.9;
+        let nw = ((w as f64) * scale)
+            .floor()
+            .clamp(1.0, (w - 1).max(1) as f64) as u32;
+        let nh = ((h as f64) * scale)
+            .floor()
+            .clamp(1.0, (h - 1).max(1) as f64) as u32;
+        rgba = image::imageops::resize(&rgba, nw, nh, filter);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dpi_conversion_is_reasonable() {
+        assert_eq!(dpi_to_ppm(300), 11811);
+        assert_eq!(dpi_to_ppm(72), 2835);
+    }
+
+    #[test]
+    fn print_lengths_convert_to_pixels() {
+        assert_eq!("4in".parse::<Length>().unwrap().to_pixels(300), 1200);
+        assert_eq!("2.54 cm".parse::<Length>().unwrap().to_pixels(300), 300);
+        assert_eq!("90mm".parse::<Length>().unwrap().to_pixels(254), 900);
+        assert!("4ft".parse::<Length>().is_err());
+
+        let config: LowresConfig = serde_json::from_str(r#"{"print_width": "10cm"}"#).unwrap();
+        assert_eq!(config.print_width.unwrap().to_string(), "10cm");
+    }
+
+    #[test]
+    fn regions_parse_crop_and_clip() {
+        let crop: Region = "2,1,4x3".parse().unwrap();
+        assert_eq!(
+            crop,
+            Region {
+                x: 2,
+                y: 1,
+                width: 4,
+                height: 3
+            }
+        );
+        assert_eq!(crop.to_string(), "2,1,4x3");
+        assert!("2,1".parse::<Region>().is_err());
+
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(6, 4));
+        assert_eq!(crop.crop(&img).unwrap().dimensions(), (4, 3));
+        assert!("3,1,4x3".parse::<Region>().unwrap().crop(&img).is_err());
+        assert_eq!(
+            "3,1,4x9".parse::<Region>().unwrap().clip(6, 4),
+            Some("3,1,3x3".parse().unwrap())
+        );
+        assert_eq!(crop.clip(2, 4), None);
+    }
+
+    #[test]
+    fn percent_regions_resolve_against_the_crop() {
+        let spec: RegionSpec = "10%,10%,30%,20%".parse().unwrap();
+        let region = spec.percent().unwrap();
+        assert_eq!(region.to_string(), "10%,10%,30%,20%");
+        assert_eq!(
+            region.to_pixels(200, 100),
+            "20,10,60x20".parse::<Region>().unwrap()
+        );
+        assert_eq!(
+            "2,1,4x3".parse::<RegionSpec>().unwrap().pixels(),
+            Some("2,1,4x3".parse().unwrap())
+        );
+        assert!("10%,10%,30%".parse::<RegionSpec>().is_err());
+
+        let config = LowresConfig {
+            percent_crop: Some("50%,0%,50%,100%".parse().unwrap()),
+            percent_regions: Some(vec![region]),
+            ..Default::default()
+        }
+        .resolve_percentages((400, 100));
+        assert_eq!(config.crop, Some("200,0,200x100".parse().unwrap()));
+        assert_eq!(config.regions, Some(vec!["20,10,60x20".parse().unwrap()]));
+
+        let outside = LowresConfig {
+            percent_regions: Some(vec!["80%,0%,30%,10%".parse().unwrap()]),
+            block_width: Some(4),
+            ..Default::default()
+        };
+        assert!(outside.validate().is_err());
+    }
+
+    #[test]
+    fn regions_leave_the_rest_untouched() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 4, |x, _| {
+            Rgba([x as u8 * 30, 0, 0, 255])
+        }));
+        let opts = PixelateOptions {
+            output: BlockOutput::Full,
+            ..small("2".parse().unwrap())
+        };
+        let regions = ["0,0,2x2".parse().unwrap(), "6,2,9x9".parse().unwrap()];
+        let out = pixelate_regions(&img, &regions, &opts, &mut Timings::default()).unwrap();
+        assert_eq!(out.dimensions(), (8, 4));
+        assert_eq!(out.get_pixel(0, 0)[0], 15);
+        assert_eq!(out.get_pixel(7, 3)[0], 195);
+        assert_eq!(out.get_pixel(3, 0)[0], 90);
+        assert_eq!(out.get_pixel(7, 0)[0], 210);
+    }
+
+    #[test]
+    fn low_memory_pixelation_matches() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(23, 17, |x, y| {
+            image::Rgb([(x * 11) as u8, (y * 13) as u8, (x * y) as u8])
+        }));
+        for output in [BlockOutput::Full, BlockOutput::Small] {
+            let opts = PixelateOptions {
+                output,
+                ..small("4x3".parse().unwrap())
+            };
+            let strips = PixelateOptions {
+                output,
+                low_memory: true,
+                ..small("4x3".parse().unwrap())
+            };
+            assert_eq!(
+                pixelate(&img, &opts, &mut Timings::default()).unwrap(),
+                pixelate(&img, &strips, &mut Timings::default()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn scale_resizes_relative_to_the_source() {
+        assert_eq!(parse_scale("25%").unwrap(), 0.25);
+        assert_eq!(parse_scale("1.5").unwrap(), 1.5);
+        assert!(parse_scale("-10%").is_err());
+
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(40, 20));
+        let size = |w, s| {
+            pick_target_size(
+                img.dimensions(),
+                w,
+                None,
+                s,
+                ResizeMode::Auto,
+                DefaultSize::default(),
+            )
+            .unwrap()
+        };
+        assert_eq!(size(None, Some(0.25)), (10, 5));
+        assert_eq!(size(Some(8), Some(0.25)), (8, 4));
+        assert_eq!(size(None, None), (64, 64));
+        let fallback = |default| {
+            pick_target_size(
+                img.dimensions(),
+                None,
+                None,
+                None,
+                ResizeMode::Auto,
+                default,
+            )
+        };
+        assert_eq!(fallback(DefaultSize::Source).unwrap(), (40, 20));
+        assert!(fallback(DefaultSize::Error).is_err());
+        assert_eq!(
+            "32x16".parse::<DefaultSize>().unwrap(),
+            DefaultSize::Fixed {
+                width: 32,
+                height: 16
+            }
+        );
+        assert!("32".parse::<DefaultSize>().is_err());
+    }
+
+    #[test]
+    fn fit_modes_produce_exact_sizes() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(40, 20, Rgba([9, 9, 9, 255])));
+        let size = |mode| {
+            let (w, h) = pick_target_size(
+                img.dimensions(),
+                Some(10),
+                Some(10),
+                None,
+                mode,
+                DefaultSize::default(),
+            )
+            .unwrap();
+            resize_image(&img, w, h, FilterType::Nearest, mode)
+                .unwrap()
+                .dimensions()
+        };
+        assert_eq!(size(ResizeMode::Contain), (10, 5));
+        assert_eq!(size(ResizeMode::Cover), (10, 10));
+        assert_eq!(size(ResizeMode::Exact), (10, 10));
+
+        let fitted = resize_image(&img, 10, 10, FilterType::Nearest, ResizeMode::Pad).unwrap();
+        let padded = pad_to(&fitted.to_rgba8(), 10, 10, Rgba([0, 0, 0, 0]));
+        assert_eq!(padded.dimensions(), (10, 10));
+        assert_eq!(padded.get_pixel(5, 0)[3], 0);
+        assert_eq!(*padded.get_pixel(5, 5), Rgba([9, 9, 9, 255]));
+    }
+
+    fn small(block: BlockSize) -> PixelateOptions<'static> {
+        PixelateOptions {
+            block,
+            stat: BlockStat::Mean,
+            linear_light: false,
+            quantize: None,
+            dither: None,
+            grain: None,
+            output: BlockOutput::Small,
+            low_memory: false,
+            gpu: false,
+        }
+    }
+
+    #[test]
+    fn small_block_output_is_the_block_grid() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 7, |x, _| {
+            if x < 4 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        }));
+        let block: BlockSize = "4".parse().unwrap();
+        let grid = pixelate(&img, &small(block), &mut Timings::default()).unwrap();
+        assert_eq!(grid.dimensions(), (3, 2));
+        assert_eq!(*grid.get_pixel(0, 1), Rgba([255, 0, 0, 255]));
+        assert_eq!(*grid.get_pixel(2, 0), Rgba([0, 0, 255, 255]));
+
+        let block: BlockSize = "4x1".parse().unwrap();
+        let grid = pixelate(&img, &small(block), &mut Timings::default()).unwrap();
+        assert_eq!(grid.dimensions(), (3, 7));
+    }
+
+    #[test]
+    fn block_stats_pick_expected_colors() {
+        let k = Rgba([0, 0, 0, 255]);
+        let w = Rgba([255, 255, 255, 255]);
+        let g = Rgba([100, 100, 100, 255]);
+        // Two white pixels, one black, one gray.
+        let img =
+            RgbaImage::from_raw(2, 2, [w, w, k, g].iter().flat_map(|p| p.0).collect()).unwrap();
+
+        let pick = |stat| block_color(&img, 0..2, 0..2, stat, false);
+        assert_eq!(pick(BlockStat::Mean), Rgba([152, 152, 152, 255]));
+        assert_eq!(pick(BlockStat::Median), w);
+        assert_eq!(pick(BlockStat::Dominant), w);
+        assert_eq!(pick(BlockStat::Darkest), k);
+        assert_eq!(pick(BlockStat::Lightest), w);
+
+        // Linear-light mixing keeps the block brighter than the naive mean.
+        assert_eq!(
+            block_color(&img, 0..2, 0..2, BlockStat::Mean, true),
+            Rgba([193, 193, 193, 255])
+        );
+    }
+
+    #[test]
+    fn config_schema_lists_options() {
+        let schema = serde_json::to_value(config_schema()).unwrap();
+        assert!(schema["properties"]["block"].is_object());
+        assert!(schema["definitions"]["Palette"].is_object());
+    }
+
+    #[test]
+    fn email_safe_fits_edge_and_byte_limits() {
+        let config = LowresConfig {
+            email_safe: Some(true),
+            ..Default::default()
+        }
+        .resolve_presets();
+        assert_eq!(config.max_edge, Some(EMAIL_SAFE_MAX_EDGE));
+
+        let noisy = RgbaImage::from_fn(2400, 1200, |x, y| {
+            let v = (x.wrapping_mul(7919) ^ y.wrapping_mul(104729)) as u8;
+            Rgba([v, v.wrapping_mul(3), v.wrapping_add(y as u8), 255])
+        });
+        let fitted = fit_within(noisy, EMAIL_SAFE_MAX_EDGE, FilterType::Triangle);
+        assert_eq!(fitted.dimensions(), (1600, 800));
+
+        let opts = PngOptions {
+            drop_alpha: false,
+            dpi: Some(300),
+            srgb: true,
+            metadata: None,
+            settings: None,
+            compression: png::Compression::Best,
+            adaptive_filter: false,
+            interlace: false,
+        };
+        let (_, encoded) =
+            encode_within_byte_limit(fitted, EMAIL_SAFE_MAX_BYTES, &opts, FilterType::Triangle)
+                .unwrap();
+        assert!(encoded.len() as u64 <= EMAIL_SAFE_MAX_BYTES);
+    }
+
+    #[test]
+    fn pass_through_keeps_size_and_pixels() {
+        let config = LowresConfig {
+            width: Some(2),
+            block: Some(4),
+            max_edge: Some(3),
+            no_resize: Some(true),
+            no_pixelate: Some(true),
+            ..Default::default()
+        };
+        let src = RgbaImage::from_fn(5, 4, |x, y| Rgba([x as u8 * 40, y as u8 * 60, 7, 255]));
+        let img = DynamicImage::ImageRgba8(src.clone());
+        let (png, report) =
+            render_decoded(&img, &config, None, None, Timings::default(), &mut |_| {}).unwrap();
+        assert_eq!((report.width, report.height), (5, 4));
+        assert_eq!(decode_image(&png).unwrap().to_rgba8(), src);
+    }
+
+    #[test]
+    fn validate_rejects_inconsistent_configs() {
+        let check = |json: &str| {
+            serde_json::from_str::<LowresConfig>(json)
+                .unwrap()
+                .validate()
+                .map_err(|e| e.to_string())
+        };
+        assert!(check(r#"{"width": 800, "mode": "Auto"}"#).is_ok());
+        assert!(check(r#"{"width": 800, "height": 600, "mode": "Exact"}"#).is_ok());
+        assert!(check(r#"{"print_width": "4in", "height": 600, "mode": "Pad"}"#).is_ok());
+        assert!(check(r#"{"width": 800, "mode": "Exact"}"#)
+            .unwrap_err()
+            .contains("needs both width and height"));
+        assert!(check(r#"{"block": 0}"#).unwrap_err().contains("block"));
+        assert!(check(r#"{"width": 100000}"#).unwrap_err().contains("limit"));
+        assert!(check(r#"{"print_width": "200in", "dpi": 300}"#).is_err());
+        assert!(
+            check(r#"{"regions": [{"x": 0, "y": 0, "width": 4, "height": 4}]}"#)
+                .unwrap_err()
+                .contains("need a block size")
+        );
+        assert!(check(r#"{"upscale": 8, "upscaler": "Scale2x"}"#).is_err());
+        assert!(check(r#"{"default_size": "error"}"#)
+            .unwrap_err()
+            .contains("No output size"));
+        assert!(check(r#"{"default_size": "error", "block": 8}"#).is_ok());
+        assert!(check(r#"{"sidecar": true, "strip_metadata": true}"#)
+            .unwrap_err()
+            .contains("sidecar"));
+        assert!(check(r#"{"sidecar": true, "email_safe": true}"#).is_err());
+    }
+
+    #[test]
+    fn settings_round_trip_through_the_output() {
+        let config = LowresConfig {
+            block: Some(3),
+            palette: Some(Palette::GameBoy),
+            ..Default::default()
+        };
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(6, 6, Rgba([9, 9, 9, 255])));
+        let (png, _) =
+            render_decoded(&img, &config, None, None, Timings::default(), &mut |_| {}).unwrap();
+        let path = std::env::temp_dir().join(format!("lowres_settings_{}.png", std::process::id()));
+        std::fs::write(&path, png).unwrap();
+        let read = read_embedded_settings(&path).unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(read.version, Some(migrate::CONFIG_VERSION));
+        assert_eq!(read.block, Some(3));
+        assert_eq!(read.palette, Some(Palette::GameBoy));
+        assert_eq!(read.dpi, None);
+    }
+
+    #[test]
+    fn processes_images_held_in_memory() {
+        use base64::Engine;
+
+        let mut png = Vec::new();
+        RgbaImage::from_fn(8, 8, |x, _| Rgba([x as u8 * 30, 0, 0, 255]))
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let data_url = format!(
+            "data:image/png;base64,{}",
+            base64::engine::general_purpose::STANDARD.encode(&png)
+        );
+        assert_eq!(decode_data_url(&data_url).unwrap(), png);
+        assert!(decode_data_url("data:text/plain,hello").is_err());
+
+        let config = LowresConfig {
+            block: Some(4),
+            ..Default::default()
+        };
+        let (encoded, report) = process_image_bytes(&png, config).unwrap();
+        let out = image::load_from_memory(&encoded).unwrap();
+        assert_eq!(out.dimensions(), (report.width, report.height));
+        assert_eq!((report.original_width, report.original_height), (8, 8));
+        assert!(process_image_bytes(b"not an image", LowresConfig::default()).is_err());
+    }
+
+    #[test]
+    fn decodes_reduced_only_when_the_output_is_unchanged() {
+        let photo = (6000, 4000);
+        let config = |json: &str| serde_json::from_str::<LowresConfig>(json).unwrap();
+        let thumbnail = config(r#"{"width": 256}"#);
+        assert!(decodes_reduced(&thumbnail, photo, None, 8));
+        // 1/8 of a 1500 pixel edge is narrower than the output.
+        assert!(!decodes_reduced(&thumbnail, (1500, 1000), None, 8));
+        assert!(decodes_reduced(&thumbnail, (1500, 1000), None, 4));
+        assert!(decodes_reduced(
+            &config(r#"{"scale": 0.1}"#),
+            photo,
+            None,
+            8
+        ));
+
+        let small_grid = config(r#"{"block": 16, "block_output": "Small"}"#);
+        assert!(decodes_reduced(&small_grid, photo, None, 8));
+        assert!(!decodes_reduced(
+            &config(r#"{"block": 12, "block_output": "Small"}"#),
+            photo,
+            None,
+            8
+        ));
+        assert!(!decodes_reduced(
+            &config(r#"{"block": 16}"#),
+            photo,
+            None,
+            2
+        ));
+        assert!(!decodes_reduced(
+            &config(r#"{"no_resize": true}"#),
+            photo,
+            None,
+            2
+        ));
+        let crop =
+            config(r#"{"crop": {"x": 0, "y": 0, "width": 800, "height": 800}, "width": 64}"#);
+        assert!(!decodes_reduced(&crop, photo, None, 8));
+    }
+
+    #[test]
+    fn proxy_previews_scale_source_sizes() {
+        let source = Source {
+            img: DynamicImage::ImageRgba8(RgbaImage::from_fn(3000, 2000, |x, _| {
+                Rgba([(x / 100 % 2 * 255) as u8, 0, 0, 255])
+            })),
+            size: (3000, 2000),
+            metadata: Metadata::default(),
+            proxy: OnceLock::new(),
+        };
+        let config = LowresConfig {
+            block: Some(100),
+            crop: Some(Region {
+                x: 0,
+                y: 0,
+                width: 3000,
+                height: 1999,
+            }),
+            ..Default::default()
+        };
+        let (png, report) =
+            render_source(&source, config, PreviewQuality::Proxy, &mut |_| {}).unwrap();
+        assert_eq!(
+            (report.original_width, report.original_height),
+            (3000, 2000)
+        );
+        let img = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(img.dimensions(), (1500, 1000));
+        // 100px source blocks are 50px on the half-size proxy.
+        assert_eq!(img.get_pixel(49, 0)[0], 0);
+        assert_eq!(img.get_pixel(50, 0)[0], 255);
+    }
+
+    #[test]
+    fn thumbnails_are_small_and_keep_transparency() {
+        let path = std::env::temp_dir().join(format!("lowres_thumb_{}.png", std::process::id()));
+        let thumbnail = |pixel: Rgba<u8>| {
+            RgbaImage::from_pixel(3000, 1500, pixel)
+                .save(&path)
+                .unwrap();
+            let (data, mime) = render_thumbnail(&path, 1024).unwrap();
+            (image::load_from_memory(&data).unwrap().dimensions(), mime)
+        };
+        assert_eq!(thumbnail(Rgba([9, 9, 9, 255])).1, "image/jpeg");
+        let (dims, mime) = thumbnail(Rgba([9, 9, 9, 0]));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(dims, (1024, 512));
+        assert_eq!(mime, "image/png");
+    }
+
+    #[test]
+    fn passes_icc_profiles_through_and_tags_the_rest_srgb() {
+        let img = RgbaImage::from_pixel(2, 2, Rgba([200, 10, 10, 255]));
+        let encode = |icc_profile: Option<Vec<u8>>| {
+            let opts = PngOptions {
+                drop_alpha: false,
+                dpi: Some(300),
+                srgb: true,
+                metadata: Some(Metadata {
+                    icc_profile,
+                    ..Default::default()
+                }),
+                settings: None,
+                compression: png::Compression::Fast,
+                adaptive_filter: false,
+                interlace: false,
+            };
+            let png = encode_png(&img, &opts).unwrap();
+            let reader = png::Decoder::new(Cursor::new(png)).read_info().unwrap();
+            let info = reader.info();
+            (info.icc_profile.as_ref().map(|p| p.to_vec()), info.srgb)
+        };
+
+        let profile = b"not really a profile, but opaque to the encoder".to_vec();
+        assert_eq!(encode(Some(profile.clone())), (Some(profile), None));
+        let (icc, srgb) = encode(None);
+        assert!(icc.is_none() && srgb.is_some());
+    }
+
+    #[test]
+    fn interlaced_and_adaptive_pngs_decode_to_the_same_pixels() {
+        // Odd sizes leave some Adam7 passes short or empty.
+        let img = RgbaImage::from_fn(13, 7, |x, y| {
+            Rgba([(x * 19) as u8, (y * 37) as u8, (x * y * 5) as u8, 200])
+        });
+        for (adaptive_filter, interlace) in [(true, false), (false, true), (true, true)] {
+            let opts = PngOptions {
+                drop_alpha: false,
+                dpi: None,
+                srgb: false,
+                metadata: None,
+                settings: None,
+                compression: png::Compression::Best,
+                adaptive_filter,
+                interlace,
+            };
+            let png = encode_png(&img, &opts).unwrap();
+            let reader = png::Decoder::new(Cursor::new(&png)).read_info().unwrap();
+            assert_eq!(reader.info().interlaced, interlace);
+            let decoded = image::load_from_memory(&png).unwrap().to_rgba8();
+            assert_eq!(decoded, img);
+        }
+        assert_eq!(
+            "Best".parse::<PngCompression>().unwrap(),
+            PngCompression::Best
+        );
+    }
+
+    #[test]
+    fn source_dpi_is_the_default() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])));
+        let render = |dpi: Option<u32>, source_dpi: Option<u32>| {
+            let config = LowresConfig {
+                dpi,
+                no_resize: Some(true),
+                ..Default::default()
+            };
+            let metadata = Metadata {
+                dpi: source_dpi,
+                ..Default::default()
+            };
+            let (png, report) = render_decoded(
+                &img,
+                &config,
+                None,
+                Some(metadata),
+                Timings::default(),
+                &mut |_| {},
+            )
+            .unwrap();
+            let reader = png::Decoder::new(Cursor::new(png)).read_info().unwrap();
+            let ppm = reader.info().pixel_dims.map(|d| d.xppu);
+            (report.dpi, ppm)
+        };
+        assert_eq!(render(None, Some(72)), (Some(72), Some(dpi_to_ppm(72))));
+        assert_eq!(
+            render(Some(150), Some(72)),
+            (Some(150), Some(dpi_to_ppm(150)))
+        );
+        assert_eq!(render(None, None), (Some(300), Some(dpi_to_ppm(300))));
+    }
+
+    #[test]
+    fn print_sizes_are_checked_at_the_source_dpi() {
+        let config = LowresConfig {
+            print_width: Some("100in".parse().unwrap()),
+            ..Default::default()
+        };
+        // 30000px at the default 300 DPI, under the limit; twice that at 600.
+        config.validate().unwrap();
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(4, 4));
+        let metadata = Metadata {
+            dpi: Some(600),
+            ..Default::default()
+        };
+        let err = render_decoded(
+            &img,
+            &config,
+            None,
+            Some(metadata),
+            Timings::default(),
+            &mut |_| {},
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(LowresError::InvalidConfig(m)) if m.contains("60000px")),
+            "{:#}",
+            err
+        );
+    }
+
+    /// The chunk types of `png`, in order, up to its IEND.
+    pub fn png_chunks(png: &[u8]) -> Vec<String> {
+        // Walk the chunk stream; critical chunk types start with an uppercase letter.
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+        while pos + 8 <= png.len() {
+            let len = u32::from_be_bytes(png[pos..pos + 4].try_into().unwrap()) as usize;
+            chunks.push(String::from_utf8_lossy(&png[pos + 4..pos + 8]).to_string());
+            if chunks.last().unwrap() == "IEND" {
+                break;
+            }
+            pos += 12 + len;
+        }
+        chunks
+    }
+
+    /// Marks the text, XMP and EXIF of `tagged_png`.
+    pub const SOURCE_MARK: &[u8] = b"source-only";
+
+    /// An 8×8 PNG of `frames` frames, animated when there's more than one,
+    /// carrying an ICC profile and text, XMP and EXIF holding `SOURCE_MARK`.
+    pub fn tagged_png(frames: u32) -> Vec<u8> {
+        let mark = String::from_utf8(SOURCE_MARK.to_vec()).unwrap();
+        let mut exif = b"MM\0*\0\0\0\x08".to_vec();
+        exif.extend(SOURCE_MARK);
+        let mut png = Vec::new();
+        {
+            let mut info = png::Info::with_size(8, 8);
+            info.icc_profile = Some(Cow::Owned(
+                moxcms::ColorProfile::new_display_p3().encode().unwrap(),
+            ));
+            info.exif_metadata = Some(Cow::Owned(exif));
+            let mut encoder = png::Encoder::with_info(&mut png, info).unwrap();
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .add_text_chunk("Author".into(), mark.clone())
+                .unwrap();
+            encoder
+                .add_itxt_chunk(
+                    "XML:com.adobe.xmp".into(),
+                    format!("<x:xmpmeta>{}</x:xmpmeta>", mark),
+                )
+                .unwrap();
+            if frames > 1 {
+                encoder.set_animated(frames, 0).unwrap();
+            }
+            let mut writer = encoder.write_header().unwrap();
+            for i in 0..frames {
+                let v = (i * 200 / frames) as u8;
+                writer.write_image_data(&[v, v, v, 255].repeat(64)).unwrap();
+            }
+        }
+        png
+    }
+
+    /// Whether `out` holds any of `tagged_png`'s metadata: its marked text,
+    /// XMP or EXIF, or an uncompressed ICC profile.
+    pub fn carries_source_metadata(out: &[u8]) -> bool {
+        out.windows(SOURCE_MARK.len()).any(|w| w == SOURCE_MARK)
+            || out.windows(4).any(|w| w == b"acsp")
+    }
+
+    #[test]
+    fn email_safe_bakes_the_profile_into_srgb_and_strips_the_rest() {
+        let p3 = moxcms::ColorProfile::new_display_p3().encode().unwrap();
+        let mut source = Vec::new();
+        {
+            let mut info = png::Info::with_size(8, 8);
+            info.icc_profile = Some(Cow::Owned(p3));
+            let mut encoder = png::Encoder::with_info(&mut source, info).unwrap();
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .add_text_chunk("Author".into(), "someone".into())
+                .unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer
+                .write_image_data(&[40, 160, 60, 255].repeat(64))
+                .unwrap();
+        }
+        let config = LowresConfig {
+            email_safe: Some(true),
+            no_resize: Some(true),
+            ..Default::default()
+        };
+        let (png, report) = process_image_bytes(&source, config).unwrap();
+
+        assert_eq!(png_chunks(&png), ["IHDR", "sRGB", "IDAT", "IEND"]);
+        assert_eq!(report.dpi, None);
+        // Display P3 green is more saturated than sRGB's.
+        let pixel = image::load_from_memory(&png).unwrap().to_rgba8()[(0, 0)];
+        assert_ne!(pixel, Rgba([40, 160, 60, 255]));
+        assert!(pixel[1] > 160 && pixel[0] < 40);
+    }
+
+    #[test]
+    fn strip_metadata_writes_only_critical_chunks() {
+        let config = LowresConfig {
+            srgb: Some(true),
+            dpi: Some(600),
+            strip_metadata: Some(true),
+            ..Default::default()
+        };
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([1, 2, 3, 255])));
+        let metadata = Metadata {
+            exif: Some(vec![0; 8]),
+            xmp: Some("<x:xmpmeta/>".into()),
+            text: vec![("Copyright".into(), "someone".into())],
+            icc_profile: Some(vec![0; 16]),
+            dpi: Some(72),
+            keywords: vec!["harbor".into()],
+            caption: Some("Boats".into()),
+        };
+        let (png, _) = render_decoded(
+            &img,
+            &config,
+            None,
+            Some(metadata),
+            Timings::default(),
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(png_chunks(&png), ["IHDR", "IDAT", "IEND"]);
+
+        let config = LowresConfig {
+            strip_metadata: Some(true),
+            width: Some(4),
+            ..Default::default()
+        };
+        let (png, _) = process_image_bytes(&tagged_png(1), config).unwrap();
+        assert_eq!(png_chunks(&png), ["IHDR", "IDAT", "IEND"]);
+        assert!(!carries_source_metadata(&png));
+    }
+
+    #[test]
+    fn recognizes_heif_by_brand() {
+        let heic = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+        assert!(is_heif(heic));
+        assert!(!is_heif(b"\0\0\0\x1cftypavif\0\0\0\0"));
+        assert!(!is_heif(b"\x89PNG\r\n\x1a\n"));
+        #[cfg(not(feature = "heif"))]
+        if codecs::ffmpeg().is_none() {
+            assert!(matches!(
+                decode_image(heic).map_err(LowresError::from),
+                Err(LowresError::UnsupportedFormat(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tells_raw_files_from_tiffs() {
+        // A little-endian TIFF whose first directory has one entry.
+        let tiff = |tag: u16, value: u32| {
+            let mut data = b"II*\0\x08\0\0\0\x01\0".to_vec();
+            data.extend(tag.to_le_bytes());
+            data.extend([4, 0, 1, 0, 0, 0]);
+            data.extend(value.to_le_bytes());
+            data.extend([0; 4]);
+            data
+        };
+        assert!(is_raw(&tiff(0x00fe, 1)));
+        assert!(is_raw(&tiff(0xc612, 0x0104_0000)));
+        assert!(!is_raw(&tiff(0x00fe, 0)));
+        assert!(!is_raw(&tiff(0x0100, 640)));
+        assert!(is_raw(b"II*\0\x10\0\0\0CR\x02\0"));
+        assert!(!is_raw(b"\x89PNG\r\n\x1a\n"));
+        #[cfg(not(feature = "raw"))]
+        assert!(matches!(
+            decode_image(&tiff(0x00fe, 1)).map_err(LowresError::from),
+            Err(LowresError::UnsupportedFormat(_))
+        ));
+
+        let real = std::env::temp_dir().join("lowres_not_raw.tif");
+        RgbaImage::from_pixel(2, 2, Rgba([1, 2, 3, 255]))
+            .save(&real)
+            .unwrap();
+        let data = std::fs::read(&real).unwrap();
+        std::fs::remove_file(&real).unwrap();
+        assert!(is_tiff(&data) && !is_raw(&data));
+    }
+
+    #[test]
+    fn matte_splits_alpha_from_the_fill() {
+        let config = LowresConfig {
+            no_resize: Some(true),
+            matte: Some(true),
+            ..Default::default()
+        };
+        let src = RgbaImage::from_fn(3, 2, |x, y| Rgba([200, 100, 50, (x * 100 + y) as u8]));
+        let img = DynamicImage::ImageRgba8(src.clone());
+        let (png, report) =
+            render_decoded(&img, &config, None, None, Timings::default(), &mut |_| {}).unwrap();
+
+        let fill = decode_image(&png).unwrap();
+        assert_eq!(fill.color(), image::ColorType::Rgb8);
+        assert_eq!(fill.to_rgb8().get_pixel(2, 1).0, [200, 100, 50]);
+        let matte = decode_image(report.matte.as_deref().unwrap()).unwrap();
+        assert_eq!(matte.color(), image::ColorType::L8);
+        let alpha: Vec<u8> = src.pixels().map(|p| p[3]).collect();
+        assert_eq!(matte.to_luma8().into_raw(), alpha);
+        assert_eq!(
+            matte_path(Path::new("out/shot.0001.png")),
+            Path::new("out/shot.0001_matte.png")
+        );
+    }
+}
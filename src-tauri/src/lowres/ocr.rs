@@ -0,0 +1,40 @@
+//! Text recognition for `legibility_guard`, with Tesseract. Built with the
+//! `ocr` feature, which needs libtesseract and its English data installed.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use tesseract::Tesseract;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Mean word confidence, out of 100, from which a reading counts.
+const MIN_CONFIDENCE: i32 = 60;
+/// Shorter side recognition runs at, at least; smaller crops are enlarged,
+/// as Tesseract misses text only a few pixels high.
+const MIN_SIDE: u32 = 64;
+
+/// The text Tesseract reads in `img`, with runs of whitespace collapsed, if
+/// it is confident of any.
+pub fn read_text(img: &RgbaImage) -> Result<Option<String>> {
+    let (w, h) = img.dimensions();
+    let factor = MIN_SIDE.div_ceil(w.min(h).max(1));
+    let rgb = if factor > 1 {
+        DynamicImage::ImageRgba8(imageops::resize(
+            img,
+            w * factor,
+            h * factor,
+            FilterType::Nearest,
+        ))
+        .to_rgb8()
+    } else {
+        DynamicImage::ImageRgba8(img.clone()).to_rgb8()
+    };
+    let (w, h) = rgb.dimensions();
+    let mut tess = Tesseract::new(None, Some("eng"))?
+        .set_frame(rgb.as_raw(), w as i32, h as i32, 3, (w * 3) as i32)?
+        .recognize()?;
+    let confidence = tess.mean_text_conf();
+    let text = tess.get_text()?;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    Ok((confidence >= MIN_CONFIDENCE && !text.is_empty()).then_some(text))
+}
@@ -0,0 +1,520 @@
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::kernels::NearestColor;
+use super::load_image;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Built-in retro palettes.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Palette {
+    /// Original Game Boy, 4 greens.
+    GameBoy,
+    /// NES PPU, 55 distinct colors.
+    Nes,
+    /// IBM CGA, full 16-color set.
+    Cga,
+    /// PICO-8 fantasy console, 16 colors.
+    Pico8,
+    /// Commodore 64 (Pepto), 16 colors.
+    C64,
+    /// 1-bit black and white, as on early Macs.
+    Mono,
+    /// Risograph inks on off-white paper: fluorescent pink, blue, yellow and black.
+    Riso,
+    /// Black ink on newsprint.
+    Newsprint,
+}
+
+const GAME_BOY: &[[u8; 3]] = &[
+    [0x0f, 0x38, 0x0f],
+    [0x30, 0x62, 0x30],
+    [0x8b, 0xac, 0x0f],
+    [0x9b, 0xbc, 0x0f],
+];
+
+#[rustfmt::skip]
+const NES: &[[u8; 3]] = &[
+    [0x7c, 0x7c, 0x7c], [0x00, 0x00, 0xfc], [0x00, 0x00, 0xbc], [0x44, 0x28, 0xbc],
+    [0x94, 0x00, 0x84], [0xa8, 0x00, 0x20], [0xa8, 0x10, 0x00], [0x88, 0x14, 0x00],
+    [0x50, 0x30, 0x00], [0x00, 0x78, 0x00], [0x00, 0x68, 0x00], [0x00, 0x58, 0x00],
+    [0x00, 0x40, 0x58], [0x00, 0x00, 0x00], [0xbc, 0xbc, 0xbc], [0x00, 0x78, 0xf8],
+    [0x00, 0x58, 0xf8], [0x68, 0x44, 0xfc], [0xd8, 0x00, 0xcc], [0xe4, 0x00, 0x58],
+    [0xf8, 0x38, 0x00], [0xe4, 0x5c, 0x10], [0xac, 0x7c, 0x00], [0x00, 0xb8, 0x00],
+    [0x00, 0xa8, 0x00], [0x00, 0xa8, 0x44], [0x00, 0x88, 0x88], [0xf8, 0xf8, 0xf8],
+    [0x3c, 0xbc, 0xfc], [0x68, 0x88, 0xfc], [0x98, 0x78, 0xf8], [0xf8, 0x78, 0xf8],
+    [0xf8, 0x58, 0x98], [0xf8, 0x78, 0x58], [0xfc, 0xa0, 0x44], [0xf8, 0xb8, 0x00],
+    [0xb8, 0xf8, 0x18], [0x58, 0xd8, 0x54], [0x58, 0xf8, 0x98], [0x00, 0xe8, 0xd8],
+    [0x78, 0x78, 0x78], [0xfc, 0xfc, 0xfc], [0xa4, 0xe4, 0xfc], [0xb8, 0xb8, 0xf8],
+    [0xd8, 0xb8, 0xf8], [0xf8, 0xb8, 0xf8], [0xf8, 0xa4, 0xc0], [0xf0, 0xd0, 0xb0],
+    [0xfc, 0xe0, 0xa8], [0xf8, 0xd8, 0x78], [0xd8, 0xf8, 0x78], [0xb8, 0xf8, 0xb8],
+    [0xb8, 0xf8, 0xd8], [0x00, 0xfc, 0xfc], [0xf8, 0xd8, 0xf8],
+];
+
+#[rustfmt::skip]
+const CGA: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x00], [0x00, 0x00, 0xaa], [0x00, 0xaa, 0x00], [0x00, 0xaa, 0xaa],
+    [0xaa, 0x00, 0x00], [0xaa, 0x00, 0xaa], [0xaa, 0x55, 0x00], [0xaa, 0xaa, 0xaa],
+    [0x55, 0x55, 0x55], [0x55, 0x55, 0xff], [0x55, 0xff, 0x55], [0x55, 0xff, 0xff],
+    [0xff, 0x55, 0x55], [0xff, 0x55, 0xff], [0xff, 0xff, 0x55], [0xff, 0xff, 0xff],
+];
+
+#[rustfmt::skip]
+const PICO_8: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x00], [0x1d, 0x2b, 0x53], [0x7e, 0x25, 0x53], [0x00, 0x87, 0x51],
+    [0xab, 0x52, 0x36], [0x5f, 0x57, 0x4f], [0xc2, 0xc3, 0xc7], [0xff, 0xf1, 0xe8],
+    [0xff, 0x00, 0x4d], [0xff, 0xa3, 0x00], [0xff, 0xec, 0x27], [0x00, 0xe4, 0x36],
+    [0x29, 0xad, 0xff], [0x83, 0x76, 0x9c], [0xff, 0x77, 0xa8], [0xff, 0xcc, 0xaa],
+];
+
+#[rustfmt::skip]
+const C64: &[[u8; 3]] = &[
+    [0x00, 0x00, 0x00], [0xff, 0xff, 0xff], [0x68, 0x37, 0x2b], [0x70, 0xa4, 0xb2],
+    [0x6f, 0x3d, 0x86], [0x58, 0x8d, 0x43], [0x35, 0x28, 0x79], [0xb8, 0xc7, 0x6f],
+    [0x6f, 0x4f, 0x25], [0x43, 0x39, 0x00], [0x9a, 0x67, 0x59], [0x44, 0x44, 0x44],
+    [0x6c, 0x6c, 0x6c], [0x9a, 0xd2, 0x84], [0x6c, 0x5e, 0xb5], [0x95, 0x95, 0x95],
+];
+
+const MONO: &[[u8; 3]] = &[[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]];
+
+#[rustfmt::skip]
+const RISO: &[[u8; 3]] = &[
+    [0xf2, 0xee, 0xe3], [0xff, 0x48, 0xb0], [0x00, 0x78, 0xbf], [0xff, 0xe8, 0x00],
+    [0x23, 0x1f, 0x20],
+];
+
+const NEWSPRINT: &[[u8; 3]] = &[[0xe8, 0xe4, 0xd6], [0x28, 0x26, 0x24]];
+
+impl Palette {
+    pub const ALL: [Palette; 8] = [
+        Palette::GameBoy,
+        Palette::Nes,
+        Palette::Cga,
+        Palette::Pico8,
+        Palette::C64,
+        Palette::Mono,
+        Palette::Riso,
+        Palette::Newsprint,
+    ];
+
+    pub fn colors(self) -> &'static [[u8; 3]] {
+        match self {
+            Palette::GameBoy => GAME_BOY,
+            Palette::Nes => NES,
+            Palette::Cga => CGA,
+            Palette::Pico8 => PICO_8,
+            Palette::C64 => C64,
+            Palette::Mono => MONO,
+            Palette::Riso => RISO,
+            Palette::Newsprint => NEWSPRINT,
+        }
+    }
+}
+
+impl Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Palette::GameBoy => "gameboy",
+            Palette::Nes => "nes",
+            Palette::Cga => "cga",
+            Palette::Pico8 => "pico8",
+            Palette::C64 => "c64",
+            Palette::Mono => "mono",
+            Palette::Riso => "riso",
+            Palette::Newsprint => "newsprint",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "gameboy" | "game-boy" | "gb" => Ok(Palette::GameBoy),
+            "nes" => Ok(Palette::Nes),
+            "cga" => Ok(Palette::Cga),
+            "pico8" | "pico-8" => Ok(Palette::Pico8),
+            "c64" => Ok(Palette::C64),
+            "mono" | "1bit" | "1-bit" => Ok(Palette::Mono),
+            "riso" => Ok(Palette::Riso),
+            "newsprint" => Ok(Palette::Newsprint),
+            other => Err(anyhow::anyhow!("Unknown palette {:?}", other)),
+        }
+    }
+}
+
+/// How colors that fall between palette entries are rendered.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Dither {
+    /// 4×4 Bayer matrix: a regular crosshatch that stays put across frames.
+    Ordered,
+    /// Floyd–Steinberg error diffusion: smooth, organic gradients.
+    FloydSteinberg,
+    /// Atkinson error diffusion, as in MacPaint: spreads only 3/4 of the
+    /// error, keeping highlights and shadows crisp.
+    Atkinson,
+}
+
+impl Display for Dither {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Dither::Ordered => "ordered",
+            Dither::FloydSteinberg => "floyd-steinberg",
+            Dither::Atkinson => "atkinson",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "ordered" | "bayer" => Ok(Dither::Ordered),
+            "floyd-steinberg" | "floydsteinberg" | "fs" => Ok(Dither::FloydSteinberg),
+            "atkinson" => Ok(Dither::Atkinson),
+            other => Err(anyhow::anyhow!("Unknown dither {:?}", other)),
+        }
+    }
+}
+
+/// Load a palette from disk. The format follows the extension: GIMP `.gpl`,
+/// Photoshop `.act`, otherwise a plain list of hex colors (one per line).
+pub fn load_palette_file(path: &Path) -> Result<Vec<[u8; 3]>> {
+    let data = std::fs::read(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read palette {:?}: {}", path, e))?;
+
+    let ext = path
+        .extension()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let colors = match ext.as_str() {
+        "gpl" => parse_gpl(&String::from_utf8_lossy(&data))?,
+        "act" => parse_act(&data)?,
+        _ => parse_hex_list(&String::from_utf8_lossy(&data))?,
+    };
+
+    if colors.is_empty() {
+        anyhow::bail!("Palette {:?} contains no colors", path);
+    }
+    Ok(colors)
+}
+
+fn parse_gpl(text: &str) -> Result<Vec<[u8; 3]>> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        anyhow::bail!("Not a GIMP palette (missing \"GIMP Palette\" header)");
+    }
+
+    let mut colors = Vec::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.contains(':') {
+            // Comments and `Name:` / `Columns:` headers
+            continue;
+        }
+        let rgb: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(|v| v.parse::<u8>())
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("Bad GIMP palette entry {:?}: {}", line, e))?;
+        if rgb.len() != 3 {
+            anyhow::bail!("Bad GIMP palette entry {:?}", line);
+        }
+        colors.push([rgb[0], rgb[1], rgb[2]]);
+    }
+    Ok(colors)
+}
+
+fn parse_act(data: &[u8]) -> Result<Vec<[u8; 3]>> {
+    if data.len() < 768 {
+        anyhow::bail!("ACT palette must be at least 768 bytes, got {}", data.len());
+    }
+    // Optional trailer: big-endian color count, then the transparent index.
+    let count = if data.len() >= 772 {
+        (u16::from_be_bytes([data[768], data[769]]) as usize).clamp(1, 256)
+    } else {
+        256
+    };
+    Ok(data[..count * 3]
+        .chunks_exact(3)
+        .map(|c| [c[0], c[1], c[2]])
+        .collect())
+}
+
+fn parse_hex_list(text: &str) -> Result<Vec<[u8; 3]>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with(';') && !l.starts_with("//"))
+        .map(parse_hex_color)
+        .collect()
+}
+
+fn parse_hex_color(s: &str) -> Result<[u8; 3]> {
+    let hex = s.trim_start_matches('#');
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("Bad hex color {:?}", s);
+    }
+    let v = u32::from_str_radix(hex, 16)?;
+    Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8])
+}
+
+/// Write `colors` as a hex list that `load_palette_file` reads back.
+pub fn write_hex_list(path: &Path, colors: &[[u8; 3]]) -> Result<()> {
+    let text: String = colors
+        .iter()
+        .map(|[r, g, b]| format!("#{:02x}{:02x}{:02x}\n", r, g, b))
+        .collect();
+    std::fs::write(path, text).map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))
+}
+
+/// Longest edge `shared_palette` samples each input at, so that every input
+/// weighs about the same however large it is.
+const SHARED_SAMPLE_EDGE: u32 = 256;
+
+/// One median-cut palette of at most `n` colors for all of `inputs`. Inputs
+/// that fail to load are left out; they fail again when processed.
+pub fn shared_palette(inputs: &[PathBuf], n: usize) -> Vec<[u8; 3]> {
+    let thumbnails: Vec<RgbaImage> = inputs
+        .par_iter()
+        .filter_map(|input| load_image(input).ok())
+        .map(|img| {
+            img.thumbnail(SHARED_SAMPLE_EDGE, SHARED_SAMPLE_EDGE)
+                .to_rgba8()
+        })
+        .collect();
+    let pixels: Vec<Rgba<u8>> = thumbnails
+        .iter()
+        .flat_map(|t| t.pixels().copied())
+        .collect();
+    median_cut(pixels.iter(), n)
+}
+
+/// Upper bound on how many pixels `median_cut` looks at; larger inputs are strided.
+const MEDIAN_CUT_SAMPLES: usize = 1 << 16;
+
+/// Build an adaptive palette of at most `n` colors with median cut.
+/// Fully transparent pixels are ignored.
+pub fn median_cut<'a>(
+    pixels: impl ExactSizeIterator<Item = &'a Rgba<u8>>,
+    n: usize,
+) -> Vec<[u8; 3]> {
+    let stride = (pixels.len() / MEDIAN_CUT_SAMPLES).max(1);
+    let samples: Vec<[u8; 3]> = pixels
+        .step_by(stride)
+        .filter(|p| p[3] > 0)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+
+    if samples.is_empty() {
+        return vec![[0, 0, 0]];
+    }
+
+    let mut boxes = vec![samples];
+    while boxes.len() < n.max(1) {
+        // Split the box with the widest channel range.
+        let (idx, channel, range) = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let (channel, range) = widest_channel(b);
+                (i, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range)
+            .unwrap();
+        if range == 0 {
+            break;
+        }
+
+        let mut bx = boxes.swap_remove(idx);
+        bx.sort_unstable_by_key(|c| c[channel]);
+        let upper = bx.split_off(bx.len() / 2);
+        boxes.push(bx);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|b| average(b)).collect()
+}
+
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    let mut lo = [u8::MAX; 3];
+    let mut hi = [u8::MIN; 3];
+    for c in colors {
+        for ch in 0..3 {
+            lo[ch] = lo[ch].min(c[ch]);
+            hi[ch] = hi[ch].max(c[ch]);
+        }
+    }
+    (0..3)
+        .map(|ch| (ch, hi[ch] - lo[ch]))
+        .max_by_key(|&(_, range)| range)
+        .unwrap()
+}
+
+fn average(colors: &[[u8; 3]]) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+    for c in colors {
+        for ch in 0..3 {
+            sum[ch] += c[ch] as u64;
+        }
+    }
+    let n = colors.len().max(1) as u64;
+    [(sum[0] / n) as u8, (sum[1] / n) as u8, (sum[2] / n) as u8]
+}
+
+/// Closest palette entry by squared RGB distance. Alpha is kept as is.
+pub fn nearest(palette: &[[u8; 3]], color: Rgba<u8>) -> Rgba<u8> {
+    let best = palette
+        .iter()
+        .min_by_key(|p| {
+            let dr = p[0] as i32 - color[0] as i32;
+            let dg = p[1] as i32 - color[1] as i32;
+            let db = p[2] as i32 - color[2] as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .copied()
+        .unwrap_or([color[0], color[1], color[2]]);
+    Rgba([best[0], best[1], best[2], color[3]])
+}
+
+/// Snap every pixel of `img` to its nearest palette entry.
+pub fn quantize_image(img: &mut RgbaImage, palette: &[[u8; 3]]) {
+    let lookup = NearestColor::new(palette);
+    img.par_chunks_exact_mut(4).for_each(|px| {
+        let snapped = lookup.nearest(Rgba([px[0], px[1], px[2], px[3]]));
+        px.copy_from_slice(&snapped.0);
+    });
+}
+
+#[rustfmt::skip]
+const BAYER_4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+/// Snap a `width`-wide grid of `pixels` to `palette`, dithering with `dither`.
+/// `step` is the rough spacing between palette colors along one channel,
+/// which sizes the ordered pattern. Fully transparent pixels neither take
+/// nor pass on error.
+pub fn dither_pixels(
+    pixels: &mut [Rgba<u8>],
+    width: usize,
+    palette: &[[u8; 3]],
+    dither: Dither,
+    step: f32,
+) {
+    let lookup = NearestColor::new(palette);
+    let width = width.max(1);
+    let nudge = |p: Rgba<u8>, delta: [f32; 3]| {
+        let c = |i: usize| (p[i] as f32 + delta[i]).round().clamp(0.0, 255.0) as u8;
+        Rgba([c(0), c(1), c(2), p[3]])
+    };
+    // (dx, dy, weight) of each neighbor that takes a share of the error.
+    let kernel: &[(isize, usize, f32)] = match dither {
+        Dither::Ordered => {
+            pixels.par_iter_mut().enumerate().for_each(|(i, px)| {
+                let threshold = BAYER_4[(i / width) % 4][(i % width) % 4] as f32;
+                let offset = ((threshold + 0.5) / 16.0 - 0.5) * step;
+                *px = lookup.nearest(nudge(*px, [offset; 3]));
+            });
+            return;
+        }
+        Dither::FloydSteinberg => &[
+            (1, 0, 7.0 / 16.0),
+            (-1, 1, 3.0 / 16.0),
+            (0, 1, 5.0 / 16.0),
+            (1, 1, 1.0 / 16.0),
+        ],
+        Dither::Atkinson => &[
+            (1, 0, 1.0 / 8.0),
+            (2, 0, 1.0 / 8.0),
+            (-1, 1, 1.0 / 8.0),
+            (0, 1, 1.0 / 8.0),
+            (1, 1, 1.0 / 8.0),
+            (0, 2, 1.0 / 8.0),
+        ],
+    };
+
+    let mut error = vec![[0f32; 3]; pixels.len()];
+    for i in 0..pixels.len() {
+        if pixels[i][3] == 0 {
+            continue;
+        }
+        let wanted = nudge(pixels[i], error[i]);
+        let got = lookup.nearest(wanted);
+        pixels[i] = got;
+        let (x, y) = ((i % width) as isize, i / width);
+        for &(dx, dy, weight) in kernel {
+            let nx = x + dx;
+            if nx < 0 || nx >= width as isize {
+                continue;
+            }
+            let j = (y + dy) * width + nx as usize;
+            if let Some(e) = error.get_mut(j) {
+                for c in 0..3 {
+                    e[c] += (wanted[c] as f32 - got[c] as f32) * weight;
+                }
+            }
+        }
+    }
+}
+
+/// `quantize_image` with dithering.
+pub fn dither_image(img: &mut RgbaImage, palette: &[[u8; 3]], dither: Dither, step: f32) {
+    let width = img.width() as usize;
+    let mut pixels: Vec<Rgba<u8>> = img.pixels().copied().collect();
+    dither_pixels(&mut pixels, width, palette, dither, step);
+    for (px, dithered) in img.pixels_mut().zip(pixels) {
+        *px = dithered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_keeps_alpha() {
+        let c = nearest(GAME_BOY, Rgba([0x90, 0xb0, 0x10, 42]));
+        assert_eq!(c, Rgba([0x8b, 0xac, 0x0f, 42]));
+    }
+
+    #[test]
+    fn parses_palette_files() {
+        let gpl = "GIMP Palette\nName: Test\nColumns: 2\n#\n255   0   0\tRed\n  0 128 255\tBlue\n";
+        assert_eq!(parse_gpl(gpl).unwrap(), vec![[255, 0, 0], [0, 128, 255]]);
+
+        let hex = "; brand\n#FF8800\n00ff00\n";
+        assert_eq!(
+            parse_hex_list(hex).unwrap(),
+            vec![[255, 136, 0], [0, 255, 0]]
+        );
+
+        let mut act = vec![0u8; 772];
+        act[3..6].copy_from_slice(&[1, 2, 3]);
+        act[768..770].copy_from_slice(&2u16.to_be_bytes());
+        assert_eq!(parse_act(&act).unwrap(), vec![[0, 0, 0], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn median_cut_separates_distinct_colors() {
+        let mut pixels = vec![Rgba([255, 0, 0, 255]); 100];
+        pixels.extend(vec![Rgba([0, 0, 255, 255]); 100]);
+        let mut pal = median_cut(pixels.iter(), 2);
+        pal.sort();
+        assert_eq!(pal, vec![[0, 0, 255], [255, 0, 0]]);
+    }
+}
@@ -0,0 +1,184 @@
+//! A minimal PDF writer: pages of text in the standard Helvetica fonts and
+//! RGB images, which is all a proof sheet needs. Images are stored losslessly
+//! as the zlib stream of a PNG encoding, which PDF's Flate filter reads with
+//! the PNG predictors.
+
+use image::RgbImage;
+use std::fmt::Write;
+
+type Result<T> = anyhow::Result<T>;
+
+const CATALOG: usize = 1;
+const PAGES: usize = 2;
+/// Font resource names, as content streams refer to them.
+pub const REGULAR: &str = "F1";
+pub const BOLD: &str = "F2";
+
+/// A document being built; objects are numbered from 1 in the order they
+/// are added.
+pub struct Pdf {
+    objects: Vec<Vec<u8>>,
+    pages: Vec<usize>,
+}
+
+impl Pdf {
+    pub fn new() -> Self {
+        let mut pdf = Pdf {
+            objects: Vec::new(),
+            pages: Vec::new(),
+        };
+        // The catalog and page tree are written by `finish`, once the pages
+        // are known.
+        pdf.add(Vec::new());
+        pdf.add(Vec::new());
+        // The standard fonts every reader has, so nothing is embedded.
+        for font in ["Helvetica", "Helvetica-Bold"] {
+            let dict = format!(
+                "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+                font
+            );
+            pdf.add(dict.into_bytes());
+        }
+        pdf
+    }
+
+    fn add(&mut self, body: Vec<u8>) -> usize {
+        self.objects.push(body);
+        self.objects.len()
+    }
+
+    fn add_stream(&mut self, dict: &str, data: &[u8]) -> usize {
+        let mut body = format!("<< {} /Length {} >>\nstream\n", dict, data.len()).into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\nendstream");
+        self.add(body)
+    }
+
+    /// Add `img` and return its object number, for `page`.
+    pub fn image(&mut self, img: &RgbImage) -> Result<usize> {
+        let (w, h) = img.dimensions();
+        let data = png_zlib_stream(img)?;
+        let dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+/BitsPerComponent 8 /Filter /FlateDecode \
+/DecodeParms << /Predictor 15 /Colors 3 /BitsPerComponent 8 /Columns {} >>",
+            w, h, w
+        );
+        Ok(self.add_stream(&dict, &data))
+    }
+
+    /// Add a page of `size` in points drawn by `content`, which refers to
+    /// `images` as `/Im0`, `/Im1`, … in order.
+    pub fn page(&mut self, (w, h): (f32, f32), content: &str, images: &[usize]) {
+        let contents = self.add_stream("", content.as_bytes());
+        let mut xobjects = String::new();
+        for (i, id) in images.iter().enumerate() {
+            let _ = write!(xobjects, "/Im{} {} 0 R ", i, id);
+        }
+        let page = format!(
+            "<< /Type /Page /Parent {} 0 R /MediaBox [0 0 {:.2} {:.2}] /Contents {} 0 R \
+/Resources << /Font << /{} 3 0 R /{} 4 0 R >> /XObject << {}>> >> >>",
+            PAGES, w, h, contents, REGULAR, BOLD, xobjects
+        );
+        let id = self.add(page.into_bytes());
+        self.pages.push(id);
+    }
+
+    /// The finished file.
+    pub fn finish(mut self) -> Vec<u8> {
+        let kids: Vec<String> = self.pages.iter().map(|id| format!("{} 0 R", id)).collect();
+        self.objects[CATALOG - 1] =
+            format!("<< /Type /Catalog /Pages {} 0 R >>", PAGES).into_bytes();
+        self.objects[PAGES - 1] = format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            kids.len()
+        )
+        .into_bytes();
+
+        // The binary comment marks the file as binary for transfer tools.
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, body) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            out.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            out.extend_from_slice(body);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+        let xref = out.len();
+        let mut table = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+        for offset in offsets {
+            let _ = writeln!(table, "{:010} 00000 n ", offset);
+        }
+        let _ = write!(
+            table,
+            "trailer\n<< /Size {} /Root {} 0 R >>\nstartxref\n{}\n%%EOF\n",
+            self.objects.len() + 1,
+            CATALOG,
+            xref
+        );
+        out.extend_from_slice(table.as_bytes());
+        out
+    }
+}
+
+/// The image data of `img` encoded as a PNG: the concatenated IDAT chunks.
+fn png_zlib_stream(img: &RgbImage) -> Result<Vec<u8>> {
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, img.width(), img.height());
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(img.as_raw())?;
+    }
+    // After the signature, each chunk is its length, type, data and CRC.
+    let mut data = Vec::new();
+    let mut at = 8;
+    while at + 8 <= png.len() {
+        let len = u32::from_be_bytes([png[at], png[at + 1], png[at + 2], png[at + 3]]) as usize;
+        if &png[at + 4..at + 8] == b"IDAT" {
+            data.extend_from_slice(&png[at + 8..at + 8 + len]);
+        }
+        at += 12 + len;
+    }
+    Ok(data)
+}
+
+/// `text` as a PDF string literal in WinAnsi, which matches Latin-1 for the
+/// characters kept; others become `?`.
+pub fn literal(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            ' '..='~' => out.push(c),
+            '\u{a0}'..='\u{ff}' => {
+                let _ = write!(out, "\\{:03o}", c as u32);
+            }
+            _ => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// Roughly how wide `text` is in Helvetica at `size` points. Generous, so
+/// text cut to fit does fit.
+pub fn text_width(text: &str, size: f32) -> f32 {
+    text.chars().count() as f32 * size * 0.56
+}
+
+/// `text` cut short with "..." to be at most `width` points wide.
+pub fn fit_text(text: &str, size: f32, width: f32) -> String {
+    if text_width(text, size) <= width {
+        return text.to_string();
+    }
+    let keep = ((width / (size * 0.56)) as usize).saturating_sub(3);
+    let mut cut: String = text.chars().take(keep).collect();
+    cut.push_str("...");
+    cut
+}
@@ -0,0 +1,332 @@
+//! The fully resolved processing pipeline, for reproducing a run: the config
+//! with every default filled in, plus the stages it runs in order. Saved as
+//! JSON, it reads back with `read_pipeline` and renders the same pixels.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::{
+    banding, migrate, AutoMask, Backend, Banding, BandingCheck, BlockOutput, BlockStat, ColorMatch,
+    DefaultSize, LowresConfig, LowresError, PixelateChannels, Resample, ResizeMode, Upscaler,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Pipeline {
+    /// Version of the lowres build that resolved it.
+    pub lowres: String,
+    pub config: LowresConfig,
+    /// What `config` does, in order. Informational: ignored when read back.
+    #[serde(default)]
+    pub stages: Vec<String>,
+}
+
+/// Resolve `config` and describe the stages it runs.
+pub fn explain(config: &LowresConfig) -> Pipeline {
+    let config = resolved(config);
+    Pipeline {
+        lowres: env!("CARGO_PKG_VERSION").to_string(),
+        stages: stages(&config),
+        config,
+    }
+}
+
+/// The config of a pipeline file written from `explain`. A bare config is
+/// accepted too, so any saved config can stand in for a pipeline.
+pub fn read_pipeline(path: &Path) -> Result<LowresConfig> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+    let mut value: serde_json::Value = serde_json::from_str(&text).map_err(|e| {
+        LowresError::InvalidConfig(format!("Invalid pipeline file {:?}: {}", path, e))
+    })?;
+    if let Some(config) = value.get_mut("config") {
+        value = config.take();
+    }
+    let config = migrate::upgrade(value)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// `config` with presets applied and every unset option that has a default
+/// set to it, so the result no longer depends on this build's defaults.
+/// `dpi` stays unset when it is, meaning the source's DPI.
+fn resolved(config: &LowresConfig) -> LowresConfig {
+    let mut c = config.clone().resolve_presets();
+    c.version = Some(migrate::CONFIG_VERSION);
+    if let Some(block) = c.block_size() {
+        c.block = None;
+        c.block_width = Some(block.width);
+        c.block_height = Some(block.height);
+    }
+    c.mode.get_or_insert(ResizeMode::Auto);
+    c.default_size.get_or_insert(DefaultSize::default());
+    c.background.get_or_insert_with(|| "transparent".into());
+    c.filter.get_or_insert(Resample::Nearest);
+    c.no_resize.get_or_insert(false);
+    c.no_pixelate.get_or_insert(false);
+    c.block_stat.get_or_insert(BlockStat::Mean);
+    c.linear_light.get_or_insert(false);
+    c.block_output.get_or_insert(BlockOutput::Full);
+    c.pixelate_channels.get_or_insert(PixelateChannels::All);
+    c.banding_levels.get_or_insert(banding::DEFAULT_LEVELS);
+    c.pixel_down_filter.get_or_insert(Resample::Triangle);
+    c.upscaler.get_or_insert(Upscaler::Nearest);
+    c.srgb.get_or_insert(true);
+    c.keep_metadata.get_or_insert(false);
+    c.strip_metadata.get_or_insert(false);
+    c.keep_keywords.get_or_insert(false);
+    c.email_safe.get_or_insert(false);
+    c.low_memory.get_or_insert(false);
+    c.tiled.get_or_insert(false);
+    c.backend.get_or_insert(Backend::Cpu);
+    c.matte.get_or_insert(false);
+    c.sidecar.get_or_insert(false);
+    c.shared_palette.get_or_insert(false);
+    if c.match_colors.is_some() {
+        c.match_method.get_or_insert(ColorMatch::Histogram);
+    }
+    c
+}
+
+/// One line per stage of `render_source` for a resolved config.
+fn stages(c: &LowresConfig) -> Vec<String> {
+    let mut stages = vec!["decode, applying EXIF orientation".to_string()];
+    if let Some(crop) = &c.crop {
+        stages.push(format!("crop to {}", crop));
+    } else if let Some(crop) = &c.percent_crop {
+        stages.push(format!("crop to {} of the source", crop));
+    }
+    if let Some(path) = &c.match_colors {
+        stages.push(format!(
+            "match colors to {:?} by {}",
+            path,
+            c.match_method.unwrap_or(ColorMatch::Histogram)
+        ));
+    }
+    let keep_size = c.no_resize == Some(true);
+    let quantize = match (&c.palette_file, c.palette, c.colors) {
+        (Some(path), _, _) => Some(format!("snap colors to the palette in {:?}", path)),
+        (None, Some(palette), _) => Some(format!("snap colors to the {} palette", palette)),
+        (None, None, Some(n)) if c.shared_palette == Some(true) => Some(format!(
+            "snap colors to one {}-color palette built from every input (median cut)",
+            n
+        )),
+        (None, None, Some(n)) => Some(format!("reduce to {} colors (median cut)", n)),
+        (None, None, None) => None,
+    }
+    .map(|q| match c.dither {
+        Some(dither) => format!("{}, dithering {}", q, dither),
+        None => q,
+    });
+    let grain = c.grain.map(|g| format!("add film grain of {}", g));
+    let light = if c.linear_light == Some(true) {
+        "linear light"
+    } else {
+        "sRGB"
+    };
+
+    match c.block_size().filter(|_| c.no_pixelate != Some(true)) {
+        Some(block) => {
+            let regions =
+                c.regions.iter().flatten().count() + c.percent_regions.iter().flatten().count();
+            let within = match (regions, c.auto_mask) {
+                (1.., _) => format!(" inside {} regions", regions),
+                (0, Some(AutoMask::Subject)) => " on the detected subject".into(),
+                (0, Some(AutoMask::Background)) => " on the detected background".into(),
+                (0, None) => String::new(),
+            };
+            stages.push(format!(
+                "pixelate into {}x{} blocks{} by {} color in {}, writing {} size \
+                 (filter and mode don't apply)",
+                block.width,
+                block.height,
+                within,
+                c.block_stat.unwrap_or(BlockStat::Mean),
+                light,
+                c.block_output.unwrap_or(BlockOutput::Full),
+            ));
+            stages.extend(grain);
+            stages.extend(quantize);
+            match c.pixelate_channels.unwrap_or(PixelateChannels::All) {
+                PixelateChannels::All => {}
+                PixelateChannels::Luma => stages.push("keep each source pixel's color".into()),
+                PixelateChannels::Chroma => {
+                    stages.push("keep each source pixel's brightness".into())
+                }
+            }
+            if c.banding == Some(Banding::Posterize) {
+                stages.push(posterize(c));
+            }
+        }
+        None => {
+            if !keep_size {
+                let size = match &c.sizes {
+                    Some(sizes) => {
+                        let sizes: Vec<String> = sizes
+                            .iter()
+                            .map(|s| format!("{}x{}", s.width, s.height))
+                            .collect();
+                        format!("each of {}, one output per size", sizes.join(", "))
+                    }
+                    None => match (c.width, c.height, c.print_width, c.print_height, c.scale) {
+                        (None, None, None, None, Some(scale)) => {
+                            format!("{}% of the source", scale * 100.0)
+                        }
+                        (None, None, None, None, None) => {
+                            format!("the default size {}", c.default_size.unwrap_or_default())
+                        }
+                        (w, h, pw, ph, _) => {
+                            // Print sizes stay physical when the DPI comes from the source.
+                            let side = |px: Option<u32>, len: Option<super::Length>| match (px, len)
+                            {
+                                (Some(px), _) => px.to_string(),
+                                (None, Some(len)) => match c.dpi {
+                                    Some(dpi) => len.to_pixels(dpi).to_string(),
+                                    None => len.to_string(),
+                                },
+                                (None, None) => "auto".to_string(),
+                            };
+                            format!("{}x{}", side(w, pw), side(h, ph))
+                        }
+                    },
+                };
+                stages.push(format!(
+                    "resize to {} ({} mode, {} filter, in {})",
+                    size,
+                    c.mode.unwrap_or(ResizeMode::Auto),
+                    c.filter.unwrap_or(Resample::Nearest),
+                    light
+                ));
+            }
+            match c.banding {
+                Some(Banding::Posterize) => stages.push(posterize(c)),
+                Some(Banding::Deband) => stages.push("deband gradients".into()),
+                None => {}
+            }
+            stages.extend(grain);
+            stages.extend(quantize);
+            if c.mode == Some(ResizeMode::Pad) && !keep_size {
+                stages.push(format!(
+                    "pad onto {}",
+                    c.background.as_deref().unwrap_or("transparent")
+                ));
+            }
+        }
+    }
+
+    if !keep_size {
+        if let Some(factor) = c.upscale {
+            stages.push(format!(
+                "upscale {}x with {}",
+                factor,
+                c.upscaler.unwrap_or(Upscaler::Nearest)
+            ));
+        }
+        if let Some(max_edge) = c.max_edge {
+            stages.push(format!(
+                "fit within {}px with the {} filter",
+                max_edge,
+                c.filter.unwrap_or(Resample::Nearest)
+            ));
+        }
+    }
+
+    match c.banding_check {
+        Some(BandingCheck::Report) => stages.push("report where the output shows banding".into()),
+        Some(BandingCheck::Dither) => stages.push(
+            "report where the output shows banding, and render it again with ordered dithering \
+             if colors are reduced without"
+                .into(),
+        ),
+        None => {}
+    }
+
+    let tags = if c.strip_metadata == Some(true) {
+        "no metadata".to_string()
+    } else {
+        let mut tags = match c.dpi {
+            Some(dpi) => format!("{} DPI", dpi),
+            None => "the source's DPI (300 if it has none)".to_string(),
+        };
+        if c.srgb != Some(false) {
+            tags.push_str(", sRGB unless the source has an ICC profile");
+        }
+        if c.keep_metadata == Some(true) {
+            tags.push_str(", source EXIF/XMP/text");
+        } else if c.keep_keywords == Some(true) {
+            tags.push_str(", source keywords and caption");
+        }
+        if let Some(keywords) = c.add_keywords.as_ref().filter(|k| !k.is_empty()) {
+            tags.push_str(&format!(", keywords {}", keywords.join(", ")));
+        }
+        tags
+    };
+    let tags = if c.matte == Some(true) {
+        format!("{}, without alpha", tags)
+    } else {
+        tags
+    };
+    stages.push(match c.max_bytes {
+        Some(max) if keep_size => format!("encode PNG with {}, failing over {} bytes", tags, max),
+        Some(max) => format!(
+            "encode PNG with {}, shrinking until at most {} bytes",
+            tags, max
+        ),
+        None => format!("encode PNG with {}", tags),
+    });
+    if c.matte == Some(true) {
+        stages.push("encode the alpha channel as a grayscale <stem>_matte.png".into());
+    }
+    if c.sidecar == Some(true) {
+        stages.push(
+            "write <stem>.json with the source, its SHA-256, this config, timings and color \
+             statistics"
+                .into(),
+        );
+    }
+    stages
+}
+
+fn posterize(c: &LowresConfig) -> String {
+    format!(
+        "posterize brightness into {} levels",
+        c.banding_levels.unwrap_or(banding::DEFAULT_LEVELS)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolved_pipelines_read_back_unchanged() {
+        let config = LowresConfig {
+            block: Some(8),
+            palette: Some(super::super::Palette::GameBoy),
+            email_safe: Some(true),
+            ..Default::default()
+        };
+        let pipeline = explain(&config);
+        let c = &pipeline.config;
+        assert_eq!(
+            (c.block_width, c.block_height, c.block),
+            (Some(8), Some(8), None)
+        );
+        assert_eq!(c.max_edge, Some(super::super::EMAIL_SAFE_MAX_EDGE));
+        assert_eq!(c.dpi, None);
+        assert!(pipeline.stages[1].starts_with("pixelate into 8x8 blocks"));
+        assert!(pipeline.stages.last().unwrap().contains("shrinking"));
+
+        let path = std::env::temp_dir().join("lowres_pipeline_test.json");
+        std::fs::write(&path, serde_json::to_string(&pipeline).unwrap()).unwrap();
+        let read = read_pipeline(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            serde_json::to_value(&read).unwrap(),
+            serde_json::to_value(c).unwrap()
+        );
+        // Resolving is idempotent, so an explained pipeline explains the same.
+        assert_eq!(explain(&read).stages, pipeline.stages);
+    }
+}
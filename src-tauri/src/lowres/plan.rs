@@ -0,0 +1,203 @@
+//! Dry runs: what processing an image would produce, worked out from its
+//! headers and the config alone, so a long batch can be checked before any
+//! image is decoded or encoded.
+
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use super::{
+    exif_orientation, fit_size, is_heif, is_tiff, limits, pick_target_size, probe, BlockOutput,
+    Container, LowresConfig, LowresError, ResizeMode,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// The source's size once turned upright.
+    pub source_width: u32,
+    pub source_height: u32,
+    /// Blocks across and down, when pixelating.
+    pub block_grid: Option<(u32, u32)>,
+    /// The output's size. `max_bytes` may shrink it further.
+    pub width: u32,
+    pub height: u32,
+    pub megapixels: f64,
+    /// DPI tagged in the output; `None` when metadata is stripped.
+    pub dpi: Option<u32>,
+}
+
+/// Plan the output of processing `path` with `config`, reading only the
+/// file's headers. Fails as processing would for sizes over the limits.
+pub fn plan(path: &Path, config: &LowresConfig) -> Result<Plan> {
+    limits::check_file(path)?;
+    let info = probe(&path.to_path_buf())?;
+    let (width, height) = match upright_orientation(path) {
+        // Rotated a quarter turn, so the sides swap.
+        Some(5..=8) => (info.height, info.width),
+        _ => (info.width, info.height),
+    };
+    let limits = limits::current();
+    limits.check_input(width, height)?;
+    let plan = plan_for((width, height), info.dpi, config)?;
+    limits.check_output(plan.width, plan.height)?;
+    Ok(plan)
+}
+
+/// The EXIF orientation decoding applies to `path`. HEIF, RAW and video
+/// decoders turn images upright themselves, and their probes report the
+/// upright size already.
+fn upright_orientation(path: &Path) -> Option<u32> {
+    let mut file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    let mut head = [0; 12];
+    file.read_exact(&mut head).ok()?;
+    if is_heif(&head) || is_tiff(&head) || Container::of(&head).is_some() {
+        return None;
+    }
+    let mut file = std::io::BufReader::new(std::fs::File::open(path).ok()?);
+    exif_orientation(&mut file)
+}
+
+/// Plan the output of processing a `source`-sized image whose metadata
+/// records `source_dpi`, as `render_source` would produce it.
+pub fn plan_for(
+    source: (u32, u32),
+    source_dpi: Option<u32>,
+    config: &LowresConfig,
+) -> Result<Plan> {
+    config.validate()?;
+    let config = config.clone().resolve_presets().resolve_percentages(source);
+    let keep_size = config.no_resize.unwrap_or(false);
+    let dpi = config.dpi.or(source_dpi).unwrap_or(300);
+    let cropped = match &config.crop {
+        Some(crop) => {
+            crop.check_fits(source.0, source.1)?;
+            (crop.width, crop.height)
+        }
+        None => source,
+    };
+    let block = config
+        .block_size()
+        .filter(|_| !config.no_pixelate.unwrap_or(false));
+
+    let mut block_grid = None;
+    let mut size = if let Some(block) = block {
+        let grid = (
+            cropped.0.div_ceil(block.width.max(1)),
+            cropped.1.div_ceil(block.height.max(1)),
+        );
+        block_grid = Some(grid);
+        match config.block_output.unwrap_or(BlockOutput::Full) {
+            BlockOutput::Small if config.regions.is_some() => {
+                anyhow::bail!("Pixelating regions keeps the full image size; use block_output full")
+            }
+            BlockOutput::Small => grid,
+            BlockOutput::Full => cropped,
+        }
+    } else if config.regions.is_some() || config.auto_mask.is_some() {
+        return Err(LowresError::InvalidConfig(
+            "Pixelating regions or masks needs a block size".into(),
+        )
+        .into());
+    } else if keep_size {
+        cropped
+    } else {
+        let (width, height) = config.target_dimensions(dpi)?;
+        let mode = config.mode.unwrap_or(ResizeMode::Auto);
+        let default = config.default_size.unwrap_or_default();
+        let target = pick_target_size(cropped, width, height, config.scale, mode, default)?;
+        match mode {
+            // Fit inside the target, keeping the aspect ratio, as the image
+            // crate's `resize` does.
+            ResizeMode::Auto | ResizeMode::Contain => contain(cropped, target),
+            ResizeMode::Exact | ResizeMode::Cover | ResizeMode::Pad => target,
+        }
+    };
+    if !keep_size {
+        if let Some(factor) = config.upscale {
+            size = (size.0 * factor, size.1 * factor);
+        }
+        if let Some(max_edge) = config.max_edge {
+            size = fit_size(size, max_edge);
+        }
+    }
+
+    Ok(Plan {
+        source_width: source.0,
+        source_height: source.1,
+        block_grid,
+        width: size.0,
+        height: size.1,
+        megapixels: size.0 as f64 * size.1 as f64 / 1e6,
+        dpi: Some(dpi).filter(|_| !config.strip_metadata.unwrap_or(false)),
+    })
+}
+
+/// The largest size with the aspect ratio of `(w, h)` inside `target`.
+fn contain((w, h): (u32, u32), (tw, th): (u32, u32)) -> (u32, u32) {
+    let ratio = (tw as f64 / w as f64).min(th as f64 / h as f64);
+    let side = |n: u32| ((n as f64 * ratio).round() as u32).max(1);
+    (side(w), side(h))
+}
+
+/// Where a batch would write the output of `input`, now that its size is
+/// known: `out_dir`, else the config's `output_dir`, else next to `input`.
+pub fn output_path(
+    input: &Path,
+    out_dir: Option<&Path>,
+    config: &LowresConfig,
+    plan: &Plan,
+) -> PathBuf {
+    let out_dir = out_dir.or(config.output_dir.as_deref());
+    super::batch::planned_output(input, out_dir, config, Some((plan.width, plan.height)))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn plans_the_sizes_processing_produces() {
+        let mut png = Vec::new();
+        RgbaImage::from_pixel(40, 30, Rgba([200, 80, 40, 255]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let configs = [
+            r#"{"block": 8}"#,
+            r#"{"block": 8, "block_output": "Small"}"#,
+            r#"{"block": 8, "block_output": "Small", "upscale": 3}"#,
+            r#"{"width": 25}"#,
+            r#"{"width": 10, "height": 10, "mode": "Contain"}"#,
+            r#"{"width": 10, "height": 10, "mode": "Cover"}"#,
+            r#"{"scale": 0.5, "max_edge": 12}"#,
+            r#"{"crop": {"x": 5, "y": 5, "width": 20, "height": 12}, "width": 10}"#,
+            r#"{"no_resize": true, "max_edge": 12}"#,
+            r#"{"style": "Mac", "block_output": "Small", "dpi": 72}"#,
+        ];
+        for json in configs {
+            let config: LowresConfig = serde_json::from_str(json).unwrap();
+            let plan = plan_for((40, 30), None, &config).unwrap();
+            let (_, report) = super::super::process_image_bytes(&png, config).unwrap();
+            assert_eq!(
+                (plan.width, plan.height),
+                (report.width, report.height),
+                "{}",
+                json
+            );
+            assert_eq!(plan.dpi, report.dpi, "{}", json);
+        }
+
+        let config: LowresConfig =
+            serde_json::from_str(r#"{"block_width": 8, "block_height": 4}"#).unwrap();
+        let plan = plan_for((40, 30), Some(72), &config).unwrap();
+        assert_eq!(plan.block_grid, Some((5, 8)));
+        assert_eq!(plan.dpi, Some(72));
+        let config: LowresConfig =
+            serde_json::from_str(r#"{"crop": {"x": 30, "y": 0, "width": 20, "height": 10}}"#)
+                .unwrap();
+        assert!(plan_for((40, 30), None, &config).is_err());
+    }
+}
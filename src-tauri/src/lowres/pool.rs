@@ -0,0 +1,124 @@
+//! Thread pools for processing. A config's `threads` runs its jobs on a pool
+//! of that many workers rather than rayon's global one, which has one per
+//! core; `low_memory` runs them on a single worker, whatever the caller's
+//! pool, and `low_priority` on one that leaves the machine responsive
+//! during long background batches: half the cores, at background priority
+//! where the OS allows.
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::{Arc, Mutex};
+
+use super::LowresConfig;
+
+type Result<T> = anyhow::Result<T>;
+
+/// The pools built so far, by worker count and priority.
+type Pools = Vec<((usize, bool), Arc<ThreadPool>)>;
+
+/// Run `job` on the pool `config` asks for, or on the global pool when it
+/// asks for none. Pools are kept for the next job with the same settings.
+pub fn in_pool<T: Send>(config: &LowresConfig, job: impl FnOnce() -> T + Send) -> Result<T> {
+    static POOLS: Mutex<Pools> = Mutex::new(Vec::new());
+
+    let low_priority = config.low_priority.unwrap_or(false);
+    let low_memory = config.low_memory.unwrap_or(false);
+    let Some(threads) = pool_threads(config.threads, low_priority, low_memory) else {
+        return Ok(job());
+    };
+    let key = (threads, low_priority);
+    let pool = {
+        let mut pools = POOLS.lock().unwrap();
+        match pools.iter().find(|(k, _)| *k == key) {
+            Some((_, pool)) => pool.clone(),
+            None => {
+                let pool = Arc::new(pool_builder(Some(threads), low_priority, false).build()?);
+                pools.push((key, pool.clone()));
+                pool
+            }
+        }
+    };
+    Ok(pool.install(job))
+}
+
+/// A builder for a pool of `threads` workers, or of the size `low_memory`
+/// or `low_priority` implies, whose workers lower their own priority with
+/// the latter.
+pub fn pool_builder(
+    threads: Option<usize>,
+    low_priority: bool,
+    low_memory: bool,
+) -> ThreadPoolBuilder {
+    let mut builder = ThreadPoolBuilder::new();
+    if let Some(threads) = pool_threads(threads, low_priority, low_memory) {
+        builder = builder.num_threads(threads);
+    }
+    if low_priority {
+        builder = builder.start_handler(|_| lower_priority());
+    }
+    builder
+}
+
+/// Workers for `threads`, else one when `low_memory`, since work spread over
+/// more workers, such as an animation's frames, holds more buffers at once,
+/// else half the cores when `low_priority`; `None` for rayon's default.
+fn pool_threads(threads: Option<usize>, low_priority: bool, low_memory: bool) -> Option<usize> {
+    threads.or(low_memory.then_some(1)).or_else(|| {
+        low_priority.then(|| {
+            let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+            cores.div_ceil(2)
+        })
+    })
+}
+
+/// Drop the calling thread to background priority.
+#[cfg(target_os = "linux")]
+fn lower_priority() {
+    // On Linux a nice value belongs to the thread, not the whole process.
+    unsafe {
+        libc::setpriority(libc::PRIO_PROCESS, 0, 10);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn lower_priority() {
+    unsafe {
+        libc::pthread_set_qos_class_self_np(libc::qos_class_t::QOS_CLASS_BACKGROUND, 0);
+    }
+}
+
+#[cfg(windows)]
+fn lower_priority() {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL,
+    };
+    unsafe {
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn lower_priority() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_jobs_on_the_configured_pool() {
+        let config = LowresConfig {
+            threads: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(in_pool(&config, rayon::current_num_threads).unwrap(), 3);
+        assert!(pool_threads(None, true, false).is_some_and(|n| n >= 1));
+        assert_eq!(pool_threads(None, false, false), None);
+
+        let config = LowresConfig {
+            low_memory: Some(true),
+            low_priority: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(in_pool(&config, rayon::current_num_threads).unwrap(), 1);
+        assert_eq!(pool_threads(Some(2), false, true), Some(2));
+    }
+}
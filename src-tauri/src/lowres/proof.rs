@@ -0,0 +1,365 @@
+//! Contact proofs: the outputs of a batch laid out N-up on PDF pages, each
+//! labelled with its file name, pixel size and DPI, under a header with the
+//! settings they were made with, for sending to a client to sign off.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::batch::{BatchItem, BatchReport};
+use super::pdf::{self, Pdf};
+use super::{decode_image, LowresConfig, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Page margin, in points.
+const MARGIN: f32 = 36.0;
+/// Height of the title and settings above the grid.
+const HEADER: f32 = 50.0;
+/// Height of the two label lines under each image.
+const LABEL: f32 = 22.0;
+/// Space between cells.
+const GAP: f32 = 12.0;
+/// Resolution images are embedded at, at the size they are drawn.
+const IMAGE_DPI: f32 = 300.0;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PageSize {
+    A4,
+    Letter,
+}
+
+impl PageSize {
+    /// Width and height in points, portrait.
+    fn points(self) -> (f32, f32) {
+        match self {
+            PageSize::A4 => (595.28, 841.89),
+            PageSize::Letter => (612.0, 792.0),
+        }
+    }
+}
+
+impl Display for PageSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PageSize::A4 => "a4",
+            PageSize::Letter => "letter",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "a4" => Ok(PageSize::A4),
+            "letter" => Ok(PageSize::Letter),
+            other => Err(anyhow::anyhow!("Unknown page size {:?}", other)),
+        }
+    }
+}
+
+/// How images are laid out on the pages.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofLayout {
+    pub columns: u32,
+    pub rows: u32,
+    pub page: PageSize,
+}
+
+impl Default for ProofLayout {
+    fn default() -> Self {
+        ProofLayout {
+            columns: 3,
+            rows: 4,
+            page: PageSize::A4,
+        }
+    }
+}
+
+/// Parse a grid given as `COLUMNSxROWS`, such as `3x4`.
+pub fn parse_grid(s: &str) -> Result<(u32, u32)> {
+    let (columns, rows) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| anyhow::anyhow!("Expected COLUMNSxROWS, got {:?}", s))?;
+    let count = |n: &str| match n.trim().parse::<u32>() {
+        Ok(n @ 1..=10) => Ok(n),
+        _ => Err(anyhow::anyhow!("Grid sides must be 1 to 10, got {:?}", s)),
+    };
+    Ok((count(columns)?, count(rows)?))
+}
+
+/// Lay the outputs `items` produced onto proof pages under `title`, with
+/// the settings of `config`. Skipped and failed items are left out.
+pub fn render_proof(
+    items: &[BatchItem],
+    config: &LowresConfig,
+    layout: &ProofLayout,
+    title: &str,
+) -> Result<Vec<u8>> {
+    if !(1..=10).contains(&layout.columns) || !(1..=10).contains(&layout.rows) {
+        return Err(LowresError::InvalidConfig("Grid sides must be 1 to 10".into()).into());
+    }
+    let items: Vec<&BatchItem> = items.iter().filter(|i| i.report.is_some()).collect();
+    if items.is_empty() {
+        anyhow::bail!("No outputs to proof");
+    }
+
+    let (page_w, page_h) = layout.page.points();
+    let columns = layout.columns as f32;
+    let rows = layout.rows as f32;
+    let cell_w = (page_w - 2.0 * MARGIN - GAP * (columns - 1.0)) / columns;
+    let cell_h = (page_h - 2.0 * MARGIN - HEADER - GAP * (rows - 1.0)) / rows;
+    let frame = (cell_w, cell_h - LABEL);
+
+    let images = items
+        .par_iter()
+        .map(|item| {
+            let data = std::fs::read(&item.output)
+                .map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", item.output, e))?;
+            Ok(embedded(decode_image(&data)?, frame))
+        })
+        .collect::<Result<Vec<RgbImage>>>()?;
+
+    let settings = wrap(&describe(config), 8.0, page_w - 2.0 * MARGIN, 2);
+    let per_page = (layout.columns * layout.rows) as usize;
+    let page_count = items.len().div_ceil(per_page);
+    let mut doc = Pdf::new();
+    for (n, chunk) in items.chunks(per_page).enumerate() {
+        let mut content = String::new();
+        let top = page_h - MARGIN;
+        text(&mut content, pdf::BOLD, 14.0, MARGIN, top - 14.0, title);
+        for (line, y) in settings.iter().zip([top - 28.0, top - 38.0]) {
+            text(&mut content, pdf::REGULAR, 8.0, MARGIN, y, line);
+        }
+        let footer = format!("Page {} of {}", n + 1, page_count);
+        text(
+            &mut content,
+            pdf::REGULAR,
+            8.0,
+            MARGIN,
+            MARGIN - 16.0,
+            &footer,
+        );
+
+        let mut ids = Vec::with_capacity(chunk.len());
+        for (i, item) in chunk.iter().enumerate() {
+            let image = &images[n * per_page + i];
+            let x = MARGIN + (i % layout.columns as usize) as f32 * (cell_w + GAP);
+            let cell_top = top - HEADER - (i / layout.columns as usize) as f32 * (cell_h + GAP);
+
+            // Scale to the frame, centered, and outlined in light gray.
+            let (w, h) = image.dimensions();
+            let scale = (frame.0 / w as f32).min(frame.1 / h as f32);
+            let (draw_w, draw_h) = (w as f32 * scale, h as f32 * scale);
+            let draw_x = x + (frame.0 - draw_w) / 2.0;
+            let draw_y = cell_top - frame.1 + (frame.1 - draw_h) / 2.0;
+            let _ = writeln!(
+                content,
+                "q {:.2} 0 0 {:.2} {:.2} {:.2} cm /Im{} Do Q",
+                draw_w,
+                draw_h,
+                draw_x,
+                draw_y,
+                ids.len()
+            );
+            let _ = writeln!(
+                content,
+                "q 0.8 G 0.5 w {:.2} {:.2} {:.2} {:.2} re S Q",
+                draw_x, draw_y, draw_w, draw_h
+            );
+            ids.push(doc.image(image)?);
+
+            let (name, size) = label(item);
+            let bottom = cell_top - cell_h;
+            let name = pdf::fit_text(&name, 8.0, cell_w);
+            text(&mut content, pdf::BOLD, 8.0, x, bottom + 11.0, &name);
+            let size = pdf::fit_text(&size, 7.0, cell_w);
+            text(&mut content, pdf::REGULAR, 7.0, x, bottom + 2.0, &size);
+        }
+        doc.page((page_w, page_h), &content, &ids);
+    }
+    Ok(doc.finish())
+}
+
+/// Write a proof of the outputs `report` produced to `path`, named after
+/// the file. Returns the number of pages.
+pub fn write_proof(
+    path: &Path,
+    report: &BatchReport,
+    config: &LowresConfig,
+    layout: &ProofLayout,
+) -> Result<usize> {
+    let title = path.file_stem().unwrap_or_default().to_string_lossy();
+    let pdf = render_proof(&report.items, config, layout, &title)?;
+    std::fs::write(path, pdf).map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+    let per_page = (layout.columns * layout.rows) as usize;
+    Ok(report.produced().len().div_ceil(per_page))
+}
+
+fn text(content: &mut String, font: &str, size: f32, x: f32, y: f32, s: &str) {
+    let _ = writeln!(
+        content,
+        "BT /{} {} Tf {:.2} {:.2} Td {} Tj ET",
+        font,
+        size,
+        x,
+        y,
+        pdf::literal(s)
+    );
+}
+
+/// `img` as embedded in a frame of `(w, h)` points: flattened onto white, as
+/// paper shows it, and at `IMAGE_DPI` when drawn to fill the frame. Smaller
+/// images are enlarged by a whole factor without smoothing, so block edges
+/// stay sharp however the viewer scales them.
+fn embedded(img: DynamicImage, (w, h): (f32, f32)) -> RgbImage {
+    let max_w = (w / 72.0 * IMAGE_DPI) as u32;
+    let max_h = (h / 72.0 * IMAGE_DPI) as u32;
+    let img = if img.width() > max_w || img.height() > max_h {
+        img.resize(max_w, max_h, FilterType::Triangle)
+    } else {
+        let factor = (max_w / img.width()).min(max_h / img.height()).max(1);
+        img.resize_exact(
+            img.width() * factor,
+            img.height() * factor,
+            FilterType::Nearest,
+        )
+    };
+    let rgba = img.to_rgba8();
+    RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        let a = p[3] as u32;
+        image::Rgb([0, 1, 2].map(|c| ((p[c] as u32 * a + 255 * (255 - a)) / 255) as u8))
+    })
+}
+
+/// The label lines of an output: its file name, then its size in pixels
+/// and, with a DPI, in inches as it prints.
+fn label(item: &BatchItem) -> (String, String) {
+    let name = item
+        .output
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let Some(report) = &item.report else {
+        return (name, String::new());
+    };
+    let size = match report.dpi {
+        Some(dpi) => format!(
+            "{}x{} px \u{b7} {:.2}x{:.2} in at {} DPI",
+            report.width,
+            report.height,
+            report.width as f32 / dpi as f32,
+            report.height as f32 / dpi as f32,
+            dpi
+        ),
+        None => format!("{}x{} px \u{b7} no DPI", report.width, report.height),
+    };
+    (name, size)
+}
+
+/// The settings given in `config`, as `name=value` pairs.
+fn describe(config: &LowresConfig) -> String {
+    let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(config) else {
+        return String::new();
+    };
+    let parts: Vec<String> = fields
+        .into_iter()
+        .filter(|(_, value)| !value.is_null() && *value != serde_json::Value::Bool(false))
+        .map(|(name, value)| match value {
+            serde_json::Value::String(s) => format!("{}={}", name, s),
+            other => format!("{}={}", name, other),
+        })
+        .collect();
+    if parts.is_empty() {
+        "Default settings".into()
+    } else {
+        parts.join("  ")
+    }
+}
+
+/// `text` broken between its `name=value` pairs into at most `lines` lines
+/// of `width` points, the last cut short if it still doesn't fit.
+fn wrap(text: &str, size: f32, width: f32, lines: usize) -> Vec<String> {
+    let mut out: Vec<String> = Vec::new();
+    for part in text.split("  ") {
+        let full = out.len() == lines;
+        match out.last_mut() {
+            Some(line)
+                if full || pdf::text_width(&format!("{}  {}", line, part), size) <= width =>
+            {
+                line.push_str("  ");
+                line.push_str(part);
+            }
+            _ => out.push(part.to_string()),
+        }
+    }
+    out.into_iter()
+        .map(|line| pdf::fit_text(&line, size, width))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn lays_outputs_out_on_labelled_pages() {
+        let dir = std::env::temp_dir().join("lowres_proof");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let inputs: Vec<_> = (0..5)
+            .map(|i| {
+                let input = dir.join(format!("shot ({}).png", i));
+                RgbaImage::from_pixel(40, 30, Rgba([200, 80, 40 * i as u8, 128]))
+                    .save(&input)
+                    .unwrap();
+                input
+            })
+            .collect();
+        let config = LowresConfig {
+            block: Some(8),
+            ..Default::default()
+        };
+        let out_dir = dir.join("out");
+        let report = super::super::process_batch(
+            &inputs,
+            Some(&out_dir),
+            &config,
+            super::super::OnCollision::Overwrite,
+        )
+        .unwrap();
+        let layout = ProofLayout {
+            columns: 2,
+            rows: 2,
+            page: PageSize::Letter,
+        };
+        let proof = dir.join("proof.pdf");
+        assert_eq!(write_proof(&proof, &report, &config, &layout).unwrap(), 2);
+
+        let pdf = std::fs::read(&proof).unwrap();
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.starts_with("%PDF-1.4"));
+        assert!(text.contains("/Type /Pages /Kids [") && text.contains("/Count 2 >>"));
+        assert_eq!(text.matches("/Subtype /Image").count(), 5);
+        assert!(text.contains("(shot \\(3\\)_lowres.png) Tj"));
+        assert!(text.contains("(40x30 px \\267 0.13x0.10 in at 300 DPI) Tj"));
+        assert!(text.contains("block=8"));
+        assert!(text.contains("(Page 2 of 2) Tj"));
+        assert!(text.trim_end().ends_with("%%EOF"));
+
+        assert_eq!(parse_grid("3x4").unwrap(), (3, 4));
+        assert!(parse_grid("0x4").is_err());
+        assert!(render_proof(&[], &config, &layout, "empty").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! Camera RAW input (DNG, CR2, NEF, ARW), decoded with rawloader and
+//! developed to sRGB with imagepipe's default demosaic, white balance and
+//! tone curve. Built with the `raw` feature.
+
+use image::{DynamicImage, RgbImage};
+use imagepipe::{ImageSource, Pipeline};
+use rawloader::Orientation;
+use std::io::Cursor;
+
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+fn decode_error(message: impl std::fmt::Display) -> anyhow::Error {
+    LowresError::Decode(format!("Failed to decode RAW image: {}", message)).into()
+}
+
+/// Develop a RAW file to an 8-bit sRGB image. imagepipe applies the camera's
+/// orientation, so the result is upright.
+pub fn decode_raw(data: &[u8]) -> Result<DynamicImage> {
+    let raw = rawloader::decode(&mut Cursor::new(data)).map_err(decode_error)?;
+    let mut pipeline = Pipeline::new_from_source(ImageSource::Raw(raw)).map_err(decode_error)?;
+    let developed = pipeline.output_8bit(None).map_err(decode_error)?;
+    let rgb = RgbImage::from_raw(
+        developed.width as u32,
+        developed.height as u32,
+        developed.data,
+    )
+    .ok_or_else(|| decode_error("developed image data is truncated"))?;
+    Ok(DynamicImage::ImageRgb8(rgb))
+}
+
+/// Width and height (after orientation), bits per sample and alpha of a RAW
+/// file, without demosaicing it.
+pub fn probe_raw(data: &[u8]) -> Result<(u32, u32, u16, bool)> {
+    let raw = rawloader::decode(&mut Cursor::new(data)).map_err(decode_error)?;
+    let (w, h) = (raw.width as u32, raw.height as u32);
+    let (width, height) = match raw.orientation {
+        Orientation::Transpose
+        | Orientation::Rotate90
+        | Orientation::Transverse
+        | Orientation::Rotate270 => (h, w),
+        _ => (w, h),
+    };
+    let bits = 16 - raw.whitelevels[0].leading_zeros() as u16;
+    Ok((width, height, bits, false))
+}
@@ -0,0 +1,109 @@
+//! Named region sets: redaction templates such as "bottom-right timestamp"
+//! or "ID card layout", saved once and applied to every screenshot of the
+//! same program. A file holds the sets as a JSON object mapping each name to
+//! its regions, so it can be written by hand, kept beside pipeline files and
+//! shared between the command line and the app.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::{LowresError, Region};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Region sets by name.
+pub type RegionSets = BTreeMap<String, Vec<Region>>;
+
+/// Read the region sets in `path`.
+pub fn read_region_sets(path: &Path) -> Result<RegionSets> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+    serde_json::from_str(&text)
+        .map_err(|e| LowresError::InvalidConfig(format!("Region sets {:?}: {}", path, e)).into())
+}
+
+/// Save `regions` as the set `name` in `path`, replacing a set of that name
+/// and keeping the others; the file is created if missing. No regions
+/// removes the set.
+pub fn save_region_set(path: &Path, name: &str, regions: Vec<Region>) -> Result<RegionSets> {
+    if name.trim().is_empty() {
+        return Err(LowresError::InvalidConfig("A region set needs a name".into()).into());
+    }
+    let mut sets = if path.exists() {
+        read_region_sets(path)?
+    } else {
+        RegionSets::new()
+    };
+    if regions.is_empty() {
+        sets.remove(name);
+    } else {
+        sets.insert(name.to_string(), regions);
+    }
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&sets)? + "\n")
+        .map_err(|e| anyhow::anyhow!("Failed to write {:?}: {}", path, e))?;
+    Ok(sets)
+}
+
+/// The regions of the sets `names` in `sets`, in that order; those of every
+/// set when `names` is empty.
+pub fn select_regions(sets: &RegionSets, names: &[String]) -> Result<Vec<Region>> {
+    if names.is_empty() {
+        return Ok(sets.values().flatten().copied().collect());
+    }
+    let mut regions = Vec::new();
+    for name in names {
+        let set = sets.get(name).ok_or_else(|| {
+            let known: Vec<&str> = sets.keys().map(String::as_str).collect();
+            LowresError::InvalidConfig(format!(
+                "No region set {:?}; the sets are {}",
+                name,
+                if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                }
+            ))
+        })?;
+        regions.extend_from_slice(set);
+    }
+    Ok(regions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_selects_named_sets() {
+        let dir = std::env::temp_dir().join(format!("lowres_region_sets_{}", std::process::id()));
+        let path = dir.join("sets.json");
+        let timestamp: Region = "1800,1040,120x40".parse().unwrap();
+        let card: Vec<Region> = vec![
+            "10,10,200x30".parse().unwrap(),
+            "10,60,80x80".parse().unwrap(),
+        ];
+
+        save_region_set(&path, "timestamp", vec![timestamp]).unwrap();
+        save_region_set(&path, "id card", card.clone()).unwrap();
+        let sets = read_region_sets(&path).unwrap();
+        assert_eq!(sets.len(), 2);
+        assert_eq!(
+            select_regions(&sets, &["timestamp".into()]).unwrap(),
+            vec![timestamp]
+        );
+        // Every set, in name order, when none is named.
+        assert_eq!(
+            select_regions(&sets, &[]).unwrap(),
+            [card.clone(), vec![timestamp]].concat()
+        );
+        assert!(select_regions(&sets, &["badge".into()]).is_err());
+
+        let sets = save_region_set(&path, "timestamp", Vec::new()).unwrap();
+        assert_eq!(sets.keys().collect::<Vec<_>>(), ["id card"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+//! Inputs given as http(s) URLs, downloaded to a local file first so the rest
+//! of the pipeline can treat them like any other input, and other inputs that
+//! arrive as bytes rather than files.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{is_heif, is_raw, Container, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Largest download accepted, so a wrong link can't fill the disk.
+pub const MAX_DOWNLOAD_BYTES: u64 = 100 * 1024 * 1024;
+/// How long a download may take in all, including connecting.
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Tries at a request that fails for reasons that may pass, such as a
+/// dropped connection or a busy server, before giving up.
+pub const ATTEMPTS: u32 = 3;
+/// The pause before the first retry, doubled before each one after.
+const RETRY_PAUSE: Duration = Duration::from_millis(500);
+
+/// Whether `input` is an http or https URL rather than a path.
+pub fn is_url(input: &str) -> bool {
+    let lower = input.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Download the image at `url` into `dir` with `save_image`, named after the
+/// last segment of the URL's path.
+pub fn download(url: &str, dir: &Path) -> Result<PathBuf> {
+    let agent = ureq::AgentBuilder::new().timeout(DOWNLOAD_TIMEOUT).build();
+    let response = agent.get(url).call().map_err(|e| match e {
+        ureq::Error::Status(code, response) => LowresError::Io(format!(
+            "Failed to download {}: {} {}",
+            url,
+            code,
+            response.status_text()
+        )),
+        e => LowresError::Io(format!("Failed to download {}: {}", url, e)),
+    })?;
+    let too_large = || {
+        LowresError::Io(format!(
+            "{} is larger than the {} MB download limit",
+            url,
+            MAX_DOWNLOAD_BYTES / (1024 * 1024)
+        ))
+    };
+    if response
+        .header("Content-Length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .is_some_and(|len| len > MAX_DOWNLOAD_BYTES)
+    {
+        return Err(too_large().into());
+    }
+
+    // The header may be missing or wrong, so count what actually arrives.
+    let mut data = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD_BYTES + 1)
+        .read_to_end(&mut data)
+        .map_err(|e| LowresError::Io(format!("Failed to download {}: {}", url, e)))?;
+    if data.len() as u64 > MAX_DOWNLOAD_BYTES {
+        return Err(too_large().into());
+    }
+
+    save_image(&data, dir, &file_stem(url)).map_err(|e| e.context(url.to_string()))
+}
+
+/// Send `request` with `body` up to `ATTEMPTS` times, retrying transport
+/// errors, 429 and 5xx responses. Other failures, and the last one, are an
+/// `Io` error about `what`.
+pub fn with_retries(what: &str, request: &ureq::Request, body: &[u8]) -> Result<ureq::Response> {
+    let mut pause = RETRY_PAUSE;
+    for attempt in 1.. {
+        let error = match request.clone().send_bytes(body) {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        let transient = match &error {
+            ureq::Error::Status(code, _) => *code == 429 || *code >= 500,
+            ureq::Error::Transport(_) => true,
+        };
+        if !transient || attempt == ATTEMPTS {
+            let message = match error {
+                ureq::Error::Status(code, response) => {
+                    format!("{} {}", code, response.status_text())
+                }
+                // Leave out the URL, which `what` names if it matters.
+                ureq::Error::Transport(transport) => {
+                    let source = std::error::Error::source(&transport).map(|e| e.to_string());
+                    std::iter::once(transport.kind().to_string())
+                        .chain(transport.message().map(str::to_string))
+                        .chain(source)
+                        .collect::<Vec<_>>()
+                        .join(": ")
+                }
+            };
+            return Err(LowresError::Io(format!("Failed to {}: {}", what, message)).into());
+        }
+        std::thread::sleep(pause);
+        pause *= 2;
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// Write encoded image `data` into `dir` as `<stem>.<ext>`, the extension
+/// being that of the format found in the data, so that inputs that never
+/// had a file name, such as downloads or standard input, can be processed
+/// like files. Fails for anything that isn't an image lowres can identify.
+pub fn save_image(data: &[u8], dir: &Path, stem: &str) -> Result<PathBuf> {
+    let extension = extension_of(data)
+        .ok_or_else(|| LowresError::UnsupportedFormat("Not an image lowres reads".into()))?;
+    let path = dir.join(format!("{}.{}", stem, extension));
+    std::fs::write(&path, data)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+    Ok(path)
+}
+
+/// A file extension for the image format of `data`, found from its contents.
+fn extension_of(data: &[u8]) -> Option<&'static str> {
+    if is_heif(data) {
+        return Some("heic");
+    }
+    if is_raw(data) {
+        return Some("dng");
+    }
+    if let Some(container) = Container::of(data) {
+        return Some(container.extension());
+    }
+    image::guess_format(data)
+        .ok()?
+        .extensions_str()
+        .first()
+        .copied()
+}
+
+/// The file name of `url` without its extension, for naming outputs; the
+/// host if the path has no file name.
+fn file_stem(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    let name = path
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or("download");
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    // Keep names portable: no percent-escapes or characters paths reject.
+    let clean: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if clean.is_empty() {
+        "download".into()
+    } else {
+        clean
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    /// A request a `serve` server received: its method and path, headers
+    /// with lowercase names, and body.
+    pub struct Received {
+        pub request_line: String,
+        pub headers: Vec<(String, String)>,
+        pub body: Vec<u8>,
+    }
+
+    /// Serve one request per status in `statuses` on a local port, answering
+    /// each with that status; the base URL and, once all are answered, what
+    /// was received.
+    pub fn serve(statuses: &[u16]) -> (String, JoinHandle<Vec<Received>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let statuses = statuses.to_vec();
+        let server = std::thread::spawn(move || {
+            statuses
+                .into_iter()
+                .map(|status| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream);
+                    let mut request_line = String::new();
+                    reader.read_line(&mut request_line).unwrap();
+                    let mut headers = Vec::new();
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header).unwrap();
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = header.split_once(':') {
+                            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+                        }
+                    }
+                    let length = headers
+                        .iter()
+                        .find(|(name, _)| name == "content-length")
+                        .map_or(0, |(_, value)| value.parse().unwrap());
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body).unwrap();
+                    write!(
+                        reader.get_mut(),
+                        "HTTP/1.1 {} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        status
+                    )
+                    .unwrap();
+                    Received {
+                        request_line: request_line.trim().to_string(),
+                        headers,
+                        body,
+                    }
+                })
+                .collect()
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn retries_only_transient_failures() {
+        let (url, server) = serve(&[503, 500, 200]);
+        let agent = ureq::AgentBuilder::new().build();
+        let response = with_retries("send", &agent.get(&url), &[]).unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(server.join().unwrap().len(), 3);
+
+        let (url, server) = serve(&[404]);
+        let error = with_retries("send", &agent.get(&url), &[]).unwrap_err();
+        assert_eq!(
+            LowresError::from(error),
+            LowresError::Io("Failed to send: 404 Status".into())
+        );
+        assert_eq!(server.join().unwrap()[0].request_line, "GET / HTTP/1.1");
+    }
+
+    #[test]
+    fn names_and_identifies_downloads() {
+        assert!(is_url("https://example.com/a.png"));
+        assert!(is_url("HTTP://example.com"));
+        assert!(!is_url("photos/https.png"));
+
+        assert_eq!(
+            file_stem("https://example.com/refs/cat%20pic.jpg?w=800#top"),
+            "cat_20pic"
+        );
+        assert_eq!(file_stem("https://example.com/gallery/"), "gallery");
+        assert_eq!(file_stem("https://example.com"), "example");
+
+        let mut png = Vec::new();
+        image::RgbaImage::new(1, 1)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(extension_of(&png), Some("png"));
+        assert_eq!(extension_of(b"<!DOCTYPE html><html>"), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Changing a PNG's DPI without decoding it: the chunk stream is copied as
+//! is, with only the pHYs chunk replaced, so pixels and compression are
+//! untouched and retagging costs little more than a file copy.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::dpi_to_ppm;
+
+type Result<T> = anyhow::Result<T>;
+
+const SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+/// Copy the PNG at `input` to `output` with its DPI set to `dpi`. `output`
+/// may be `input`; the file is replaced only once the copy is complete.
+pub fn retag_dpi(input: &Path, output: &Path, dpi: u32) -> Result<()> {
+    let name = output.file_name().unwrap_or_default().to_string_lossy();
+    let temp = output.with_file_name(format!(".{}.retag", name));
+    let result = (|| {
+        let mut reader = BufReader::new(
+            File::open(input)
+                .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", input, e))?,
+        );
+        let mut writer = BufWriter::new(
+            File::create(&temp)
+                .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", output, e))?,
+        );
+        replace_phys(&mut reader, &mut writer, dpi)
+            .map_err(|e| anyhow::anyhow!("{:?}: {}", input, e))?;
+        writer.flush()?;
+        std::fs::rename(&temp, output)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", output, e))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Stream chunks from `r` to `w`, dropping any pHYs and writing a new one
+/// right after IHDR.
+fn replace_phys(r: &mut impl Read, w: &mut impl Write, dpi: u32) -> Result<()> {
+    let mut signature = [0u8; 8];
+    r.read_exact(&mut signature)?;
+    if signature != SIGNATURE {
+        anyhow::bail!("Not a PNG file");
+    }
+    w.write_all(&signature)?;
+
+    loop {
+        let mut header = [0u8; 8];
+        r.read_exact(&mut header)
+            .map_err(|_| anyhow::anyhow!("Truncated PNG: no IEND chunk"))?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+        let kind = &header[4..8];
+        // Chunk data plus its CRC.
+        let mut body = r.by_ref().take(len + 4);
+
+        if kind == b"pHYs" {
+            io::copy(&mut body, &mut io::sink())?;
+            continue;
+        }
+        w.write_all(&header)?;
+        if io::copy(&mut body, w)? != len + 4 {
+            anyhow::bail!("Truncated PNG chunk");
+        }
+        match kind {
+            b"IHDR" => write_chunk(w, b"pHYs", &phys(dpi))?,
+            b"IEND" => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+/// pHYs data: pixels per meter on both axes, unit = meter.
+fn phys(dpi: u32) -> [u8; 9] {
+    let ppm = dpi_to_ppm(dpi).to_be_bytes();
+    let mut data = [0u8; 9];
+    data[..4].copy_from_slice(&ppm);
+    data[4..8].copy_from_slice(&ppm);
+    data[8] = 1;
+    data
+}
+
+fn write_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc.finalize().to_be_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{encode_png, PngOptions};
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn replaces_phys_and_keeps_image_data() {
+        let img = RgbaImage::from_fn(7, 5, |x, y| Rgba([x as u8 * 30, y as u8 * 50, 0, 255]));
+        let original = encode_png(
+            &img,
+            &PngOptions {
+                drop_alpha: false,
+                dpi: Some(72),
+                srgb: false,
+                metadata: None,
+                settings: None,
+                compression: png::Compression::Best,
+                adaptive_filter: false,
+                interlace: false,
+            },
+        )
+        .unwrap();
+
+        let mut retagged = Vec::new();
+        replace_phys(&mut original.as_slice(), &mut retagged, 300).unwrap();
+
+        let reader = png::Decoder::new(retagged.as_slice()).read_info().unwrap();
+        let dims = reader.info().pixel_dims.unwrap();
+        assert_eq!(dims.xppu, dpi_to_ppm(300));
+        // Same chunks, same size: only the pHYs contents differ.
+        assert_eq!(retagged.len(), original.len());
+        let idat = |png: &[u8]| png.windows(4).position(|w| w == b"IDAT").unwrap();
+        assert_eq!(retagged[idat(&retagged)..], original[idat(&original)..]);
+
+        assert!(replace_phys(&mut &b"GIF89a.."[..], &mut Vec::new(), 300).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Coarse automatic subject/background masks, built with the `segmentation`
+//! feature. The border of the image is taken as a sample of the background;
+//! everything reachable from the border through background-like colors is
+//! background and the rest is the subject.
+
+use image::{imageops::FilterType, Rgba, RgbaImage};
+
+use super::palette;
+
+/// Masks are computed on a copy no larger than this along its longest edge.
+const WORK_EDGE: u32 = 256;
+/// Colors in the background model sampled from the border.
+const BACKGROUND_COLORS: usize = 8;
+/// RGB distance within which a pixel still counts as background-like.
+const BACKGROUND_DISTANCE: u32 = 40;
+
+/// One flag per pixel of `img` in row-major order, `true` for the subject.
+pub fn subject_mask(img: &RgbaImage) -> Vec<bool> {
+    let (w, h) = img.dimensions();
+    let scale = (WORK_EDGE as f64 / w.max(h) as f64).min(1.0);
+    let sw = ((w as f64 * scale).round() as u32).max(1);
+    let sh = ((h as f64 * scale).round() as u32).max(1);
+    let small = image::imageops::resize(img, sw, sh, FilterType::Triangle);
+
+    let border: Vec<Rgba<u8>> = small
+        .enumerate_pixels()
+        .filter(|&(x, y, _)| x == 0 || y == 0 || x == sw - 1 || y == sh - 1)
+        .map(|(_, _, p)| *p)
+        .collect();
+    let model = palette::median_cut(border.iter(), BACKGROUND_COLORS);
+    let background_like = |p: &Rgba<u8>| {
+        p[3] < 128
+            || model.iter().any(|c| {
+                let d: u32 = (0..3)
+                    .map(|i| (c[i] as i32 - p[i] as i32).pow(2) as u32)
+                    .sum();
+                d <= BACKGROUND_DISTANCE * BACKGROUND_DISTANCE
+            })
+    };
+
+    // Flood the background in from the border.
+    let mut background = vec![false; (sw * sh) as usize];
+    let mut stack: Vec<(u32, u32)> = (0..sw)
+        .flat_map(|x| [(x, 0), (x, sh - 1)])
+        .chain((0..sh).flat_map(|y| [(0, y), (sw - 1, y)]))
+        .collect();
+    while let Some((x, y)) = stack.pop() {
+        let idx = (y * sw + x) as usize;
+        if background[idx] || !background_like(small.get_pixel(x, y)) {
+            continue;
+        }
+        background[idx] = true;
+        if x > 0 {
+            stack.push((x - 1, y));
+        }
+        if y > 0 {
+            stack.push((x, y - 1));
+        }
+        if x + 1 < sw {
+            stack.push((x + 1, y));
+        }
+        if y + 1 < sh {
+            stack.push((x, y + 1));
+        }
+    }
+
+    let subject = smooth(&background, sw, sh)
+        .into_iter()
+        .map(|bg| !bg)
+        .collect::<Vec<_>>();
+
+    // Back to full size, nearest neighbor.
+    (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let sx = (x as u64 * sw as u64 / w as u64) as u32;
+            let sy = (y as u64 * sh as u64 / h as u64) as u32;
+            subject[(sy * sw + sx) as usize]
+        })
+        .collect()
+}
+
+/// 3×3 majority vote, to drop isolated specks and fill pinholes.
+fn smooth(mask: &[bool], w: u32, h: u32) -> Vec<bool> {
+    (0..h)
+        .flat_map(|y| (0..w).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let (mut set, mut total) = (0, 0);
+            for ny in y.saturating_sub(1)..=(y + 1).min(h - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(w - 1) {
+                    set += mask[(ny * w + nx) as usize] as u32;
+                    total += 1;
+                }
+            }
+            set * 2 > total
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn separates_a_subject_from_a_plain_background() {
+        // A red square on white, with a white hole in the middle that is not background.
+        let img = RgbaImage::from_fn(40, 40, |x, y| {
+            let inside = (10..30).contains(&x) && (10..30).contains(&y);
+            let hole = (18..22).contains(&x) && (18..22).contains(&y);
+            if inside && !hole {
+                Rgba([200, 20, 20, 255])
+            } else {
+                Rgba([250, 250, 250, 255])
+            }
+        });
+        let mask = subject_mask(&img);
+        assert!(!mask[0]);
+        assert!(!mask[5 * 40 + 35]);
+        assert!(mask[12 * 40 + 12]);
+        assert!(mask[20 * 40 + 20]);
+    }
+}
@@ -0,0 +1,357 @@
+//! A self-test of the running build: small synthetic images are round-tripped
+//! through every input decoder, output encoder and optional feature, to show
+//! what a feature-flagged binary supports when an "unsupported format" error
+//! needs explaining.
+
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+use serde::Serialize;
+use std::io::Cursor;
+
+use super::codecs::{self, FFMPEG_ENV};
+use super::icons::{self, IconFormat};
+use super::{
+    decode_image, gpu_available, process_image, process_image_bytes, AutoMask, Backend,
+    LegibilityGuard, LowresConfig, LowresError, OnCollision, Region,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Works in this build.
+    Ok,
+    /// Left out of this build; the detail names the feature that adds it.
+    Disabled,
+    /// Part of this build but broken.
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Check {
+    /// What was checked, e.g. "decode webp".
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Side of the synthetic test image; a multiple of `BLOCK`.
+const SIZE: u32 = 16;
+const BLOCK: u32 = 4;
+
+/// Run every check. Outputs are written to a temporary directory that is
+/// removed afterwards.
+pub fn run_selftest() -> Vec<Check> {
+    let mut checks = Vec::new();
+    let img = test_image();
+
+    for format in [
+        ImageFormat::Png,
+        ImageFormat::Jpeg,
+        ImageFormat::Gif,
+        ImageFormat::WebP,
+        ImageFormat::Bmp,
+        ImageFormat::Tiff,
+    ] {
+        let name = format!("decode {}", format.extensions_str()[0]);
+        checks.push(check(name, || {
+            let data = encode(&img, format)?;
+            let (png, report) = process_image_bytes(&data, pixelate())?;
+            image::load_from_memory(&png)?;
+            expect_size((report.width, report.height), (SIZE, SIZE))?;
+            Ok("round-tripped through the pipeline".into())
+        }));
+    }
+    let ffmpeg = codecs::ffmpeg();
+    checks.push(optional_check(
+        "decode heic",
+        cfg!(feature = "heif") || ffmpeg.is_some(),
+        "needs a build with the `heif` feature, or ffmpeg".into(),
+        || {
+            if !cfg!(feature = "heif") {
+                return Ok("read through ffmpeg; no sample to decode".into());
+            }
+            // No encoder to make a real sample, so make sure the decoder is the
+            // one that rejects a bare header.
+            decoder_present(&heif_header(), "libheif")
+        },
+    ));
+    checks.push(feature_check(
+        "decode raw",
+        "raw",
+        cfg!(feature = "raw"),
+        || decoder_present(&dng_header(), "rawloader"),
+    ));
+    checks.push(optional_check(
+        "decode avif",
+        codecs::avif_built_in() || ffmpeg.is_some(),
+        "needs ffmpeg, or a build with the image crate's `avif-native` feature".into(),
+        || {
+            let data = encode(&img, ImageFormat::Avif)?;
+            let (_, report) = process_image_bytes(&data, pixelate())?;
+            expect_size((report.width, report.height), (SIZE, SIZE))?;
+            Ok(if codecs::avif_built_in() {
+                "round-tripped through the pipeline".into()
+            } else {
+                "round-tripped through ffmpeg and the pipeline".into()
+            })
+        },
+    ));
+    checks.push(optional_check(
+        "decode video",
+        ffmpeg.is_some(),
+        format!("needs ffmpeg on the PATH or named by {}", FFMPEG_ENV),
+        || Ok(format!("{:?} runs; no sample to decode", ffmpeg.unwrap())),
+    ));
+    checks.push(feature_check(
+        "auto mask",
+        "segmentation",
+        cfg!(feature = "segmentation"),
+        || {
+            let data = encode(&img, ImageFormat::Png)?;
+            let config = LowresConfig {
+                auto_mask: Some(AutoMask::Subject),
+                ..pixelate()
+            };
+            process_image_bytes(&data, config)?;
+            Ok("segmented and pixelated".into())
+        },
+    ));
+    checks.push(feature_check(
+        "legibility guard",
+        "ocr",
+        cfg!(feature = "ocr"),
+        || {
+            let data = encode(&img, ImageFormat::Png)?;
+            let config = LowresConfig {
+                regions: Some(vec![Region {
+                    x: 0,
+                    y: 0,
+                    width: img.width() / 2,
+                    height: img.height() / 2,
+                }]),
+                legibility_guard: Some(LegibilityGuard::Warn),
+                ..pixelate()
+            };
+            process_image_bytes(&data, config)?;
+            Ok("ran text recognition over a pixelated region".into())
+        },
+    ));
+    checks.push(optional_check(
+        "gpu pixelation",
+        gpu_available(),
+        "needs lowres built with the `gpu` feature and a GPU adapter".into(),
+        || compare_backends(&encode(&img, ImageFormat::Png)?),
+    ));
+
+    let dir = std::env::temp_dir().join(format!("lowres_selftest_{}", std::process::id()));
+    let input = dir.join("input.png");
+    let prepared = std::fs::create_dir_all(&dir)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(img.save(&input)?));
+    for ext in ["png", "gif", "apng", "webp"] {
+        checks.push(check(format!("encode {}", ext), || {
+            prepared.as_ref().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+            let output = dir.join(format!("output.{}", ext));
+            process_image(input.clone(), output.clone(), pixelate())?;
+            let data = std::fs::read(&output)?;
+            let format = if ext == "apng" {
+                ImageFormat::Png
+            } else {
+                ImageFormat::from_extension(ext).unwrap_or(ImageFormat::Png)
+            };
+            let decoded = image::load_from_memory_with_format(&data, format)?;
+            expect_size((decoded.width(), decoded.height()), (SIZE, SIZE))?;
+            Ok("written and read back".into())
+        }));
+    }
+    for (ext, format) in [("ico", IconFormat::Ico), ("icns", IconFormat::Icns)] {
+        checks.push(check(format!("encode {}", ext), || {
+            prepared.as_ref().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+            let output = dir.join(format!("output.{}", ext));
+            let config = LowresConfig {
+                sizes: Some(vec!["16".parse()?, "32".parse()?]),
+                ..Default::default()
+            };
+            let item =
+                icons::process_icon(&input, &output, format, &config, OnCollision::Overwrite);
+            if let Some(error) = item.error {
+                return Err(error.into());
+            }
+            let data = std::fs::read(&output)?;
+            let readable = match format {
+                IconFormat::Ico => image::load_from_memory_with_format(&data, ImageFormat::Ico)
+                    .is_ok_and(|icon| icon.width() == 32),
+                IconFormat::Icns => data.starts_with(b"icns"),
+            };
+            if !readable {
+                anyhow::bail!("the written icon doesn't read back");
+            }
+            Ok("written and read back".into())
+        }));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+
+    checks
+}
+
+/// Run `test`, recording its error as a failure.
+fn check(name: String, test: impl FnOnce() -> Result<String>) -> Check {
+    match test() {
+        Ok(detail) => Check {
+            name,
+            status: Status::Ok,
+            detail,
+        },
+        Err(e) => Check {
+            name,
+            status: Status::Failed,
+            detail: format!("{:#}", e),
+        },
+    }
+}
+
+/// `check` for something only built with `feature`.
+fn feature_check(
+    name: &str,
+    feature: &str,
+    enabled: bool,
+    test: impl FnOnce() -> Result<String>,
+) -> Check {
+    let missing = format!("needs a build with the `{}` feature", feature);
+    optional_check(name, enabled, missing, test)
+}
+
+/// `check` for something that may be missing, for the reason `missing`.
+fn optional_check(
+    name: &str,
+    available: bool,
+    missing: String,
+    test: impl FnOnce() -> Result<String>,
+) -> Check {
+    if !available {
+        return Check {
+            name: name.into(),
+            status: Status::Disabled,
+            detail: missing,
+        };
+    }
+    check(name.into(), test)
+}
+
+/// Pass if pixelating `data` on the GPU gives the same pixels as on the CPU.
+/// The decoded pixels are compared, as the encoded files differ in the
+/// settings they record.
+fn compare_backends(data: &[u8]) -> Result<String> {
+    let on = |backend| -> Result<RgbaImage> {
+        let config = LowresConfig {
+            backend: Some(backend),
+            ..pixelate()
+        };
+        let (png, _) = process_image_bytes(data, config)?;
+        Ok(image::load_from_memory(&png)?.to_rgba8())
+    };
+    if on(Backend::Gpu)? != on(Backend::Cpu)? {
+        anyhow::bail!("the GPU and CPU pixelated differently");
+    }
+    Ok("matched the CPU pixel for pixel".into())
+}
+
+/// Pass if decoding `header`, which is too short to be an image, fails as
+/// damaged data rather than as an unsupported format.
+fn decoder_present(header: &[u8], decoder: &str) -> Result<String> {
+    match decode_image(header).map_err(LowresError::from) {
+        Err(LowresError::Decode(_)) => Ok(format!("{} is linked; no sample to decode", decoder)),
+        Err(e) => Err(e.into()),
+        Ok(_) => anyhow::bail!("a bare header decoded as an image"),
+    }
+}
+
+fn pixelate() -> LowresConfig {
+    LowresConfig {
+        block: Some(BLOCK),
+        ..Default::default()
+    }
+}
+
+/// Quadrants of four colors, so a wrong decode shows as a wrong size or error.
+fn test_image() -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(SIZE, SIZE, |x, y| {
+        let half = SIZE / 2;
+        match (x < half, y < half) {
+            (true, true) => Rgba([220, 40, 40, 255]),
+            (false, true) => Rgba([40, 200, 60, 255]),
+            (true, false) => Rgba([40, 60, 220, 255]),
+            (false, false) => Rgba([240, 240, 240, 255]),
+        }
+    }))
+}
+
+fn encode(img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    // Formats without alpha get RGB.
+    let img = match format {
+        ImageFormat::Jpeg | ImageFormat::Bmp => DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => img.clone(),
+    };
+    let mut data = Vec::new();
+    img.write_to(&mut Cursor::new(&mut data), format)?;
+    Ok(data)
+}
+
+fn expect_size(got: (u32, u32), want: (u32, u32)) -> Result<()> {
+    if got != want {
+        anyhow::bail!(
+            "came back {}x{} instead of {}x{}",
+            got.0,
+            got.1,
+            want.0,
+            want.1
+        );
+    }
+    Ok(())
+}
+
+/// An HEIC `ftyp` box and nothing else.
+fn heif_header() -> Vec<u8> {
+    let mut data = vec![0, 0, 0, 24];
+    data.extend_from_slice(b"ftypheic\0\0\0\0mif1heic");
+    data
+}
+
+/// A little-endian TIFF whose only directory holds a DNG version tag.
+fn dng_header() -> Vec<u8> {
+    let mut data = b"II*\0\x08\0\0\0".to_vec();
+    data.extend_from_slice(&1u16.to_le_bytes());
+    // DNGVersion, BYTE x4, 1.4.0.0 inline.
+    data.extend_from_slice(&0xc612u16.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&4u32.to_le_bytes());
+    data.extend_from_slice(&[1, 4, 0, 0]);
+    data.extend_from_slice(&0u32.to_le_bytes());
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selftest_passes() {
+        let checks = run_selftest();
+        let failed: Vec<_> = checks
+            .iter()
+            .filter(|c| c.status == Status::Failed)
+            .collect();
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert!(checks.iter().any(|c| c.name == "decode webp"));
+        assert!(super::super::is_raw(&dng_header()));
+        assert!(super::super::is_heif(&heif_header()));
+    }
+
+    #[test]
+    fn compares_backends_by_pixels() {
+        // Without an adapter the GPU backend falls back to the CPU, leaving
+        // only the recorded settings to tell the two outputs apart.
+        let data = encode(&test_image(), ImageFormat::Png).unwrap();
+        compare_backends(&data).unwrap();
+    }
+}
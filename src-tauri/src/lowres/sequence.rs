@@ -0,0 +1,233 @@
+//! Numbered image sequences (`frame_%04d.png`, `shot.####.png`), as rendered
+//! by VFX and animation tools: every frame is processed into a matching
+//! output sequence that keeps the frame numbers.
+
+use anyhow::Context;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use super::batch::{process_pairs, BatchReport, OnCollision};
+use super::{LowresConfig, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// A path with one frame-number placeholder: printf-style `%d` / `%04d`, or a
+/// run of `#` with one digit per `#`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequencePattern {
+    prefix: String,
+    /// Zero-padded width of the frame number; 0 for none.
+    width: usize,
+    suffix: String,
+}
+
+impl SequencePattern {
+    /// The pattern in `path`, or `None` if it has no placeholder.
+    pub fn parse(path: &Path) -> Result<Option<Self>> {
+        let s = path.to_string_lossy();
+        let printf = s.find('%').and_then(|start| {
+            let rest = &s[start + 1..];
+            let digits = rest.find('d')?;
+            let spec = &rest[..digits];
+            if !spec.is_empty() && !spec.starts_with('0') {
+                return None;
+            }
+            let width = if spec.is_empty() {
+                0
+            } else {
+                spec.parse().ok()?
+            };
+            Some((start, start + 1 + digits + 1, width))
+        });
+        let hashes = s.find('#').map(|start| {
+            let len = s[start..].chars().take_while(|&c| c == '#').count();
+            (start, start + len, len)
+        });
+        let Some((start, end, width)) = printf.or(hashes) else {
+            return Ok(None);
+        };
+        let (prefix, suffix) = (&s[..start], &s[end..]);
+        if suffix.contains('#') || suffix.contains("%0") || suffix.contains("%d") {
+            return Err(LowresError::InvalidConfig(format!(
+                "Sequence pattern {:?} has more than one frame placeholder",
+                s
+            ))
+            .into());
+        }
+        Ok(Some(SequencePattern {
+            prefix: prefix.to_string(),
+            width,
+            suffix: suffix.to_string(),
+        }))
+    }
+
+    /// The file of frame `n`.
+    pub fn path(&self, n: u32) -> PathBuf {
+        PathBuf::from(format!(
+            "{}{:0width$}{}",
+            self.prefix,
+            n,
+            self.suffix,
+            width = self.width
+        ))
+    }
+
+    /// The directory holding the frames, and the file-name part of the prefix.
+    fn split_prefix(&self) -> (PathBuf, &str) {
+        match self.prefix.rfind(std::path::is_separator) {
+            Some(i) => (PathBuf::from(&self.prefix[..=i]), &self.prefix[i + 1..]),
+            None => (PathBuf::from("."), self.prefix.as_str()),
+        }
+    }
+
+    /// The frame number of `name`, a file name in the pattern's directory.
+    fn frame_of(&self, name: &str) -> Option<u32> {
+        let digits = name
+            .strip_prefix(self.split_prefix().1)?
+            .strip_suffix(self.suffix.as_str())?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let n: u32 = digits.parse().ok()?;
+        // Only names the pattern itself would produce, so 7 and 007 don't both count.
+        (format!("{:0width$}", n, width = self.width) == digits).then_some(n)
+    }
+
+    /// The frames of this sequence on disk, in order.
+    pub fn frames_on_disk(&self) -> Result<Vec<u32>> {
+        let dir = self.split_prefix().0;
+        let entries = std::fs::read_dir(&dir)
+            .map_err(|e| anyhow::anyhow!("Failed to read directory {:?}: {}", dir, e))?;
+        let mut frames: Vec<u32> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| self.frame_of(&entry.file_name().to_string_lossy()))
+            .collect();
+        frames.sort_unstable();
+        Ok(frames)
+    }
+}
+
+impl Display for SequencePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.width {
+            0 => write!(f, "{}%d{}", self.prefix, self.suffix),
+            w => write!(f, "{}%0{}d{}", self.prefix, w, self.suffix),
+        }
+    }
+}
+
+/// An inclusive range of frame numbers: `START-END`, or a single frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameRange {
+    pub first: u32,
+    pub last: u32,
+}
+
+impl Display for FrameRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+impl FromStr for FrameRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let parse = |v: &str| {
+            v.trim()
+                .parse::<u32>()
+                .map_err(|_| anyhow::anyhow!("Invalid frame range {:?}, expected START-END", s))
+        };
+        let (first, last) = match s.split_once('-') {
+            Some((a, b)) => (parse(a)?, parse(b)?),
+            None => (parse(s)?, parse(s)?),
+        };
+        if first > last {
+            anyhow::bail!("Frame range {:?} ends before it starts", s);
+        }
+        Ok(FrameRange { first, last })
+    }
+}
+
+/// Process the frames of `input` into the same frame numbers of `output`:
+/// every frame in `frames`, or every frame found on disk. Missing frames in
+/// an explicit range are reported as failed items. Each frame renders with
+/// `config_at` its number, so keyframes and shot lists can vary the settings;
+/// every frame's config is checked before any is rendered.
+pub fn process_sequence(
+    input: &SequencePattern,
+    output: &SequencePattern,
+    frames: Option<FrameRange>,
+    config_at: impl Fn(u32) -> Result<LowresConfig>,
+    on_collision: OnCollision,
+) -> Result<BatchReport> {
+    let frames: Vec<u32> = match frames {
+        Some(range) => (range.first..=range.last).collect(),
+        None => input.frames_on_disk()?,
+    };
+    if frames.is_empty() {
+        anyhow::bail!("No frames of {} found", input);
+    }
+    if let Some(dir) = output
+        .path(0)
+        .parent()
+        .filter(|d| !d.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", dir, e))?;
+    }
+    let configs = frames
+        .iter()
+        .map(|&n| config_at(n).with_context(|| format!("Frame {}", n)))
+        .collect::<Result<Vec<_>>>()?;
+    let pairs = frames
+        .into_iter()
+        .zip(configs)
+        .map(|(n, config)| (input.path(n), output.path(n), config));
+    Ok(process_pairs(pairs, on_collision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_patterns_and_matches_frames() {
+        let p = SequencePattern::parse(Path::new("renders/frame_%04d.png"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(p.path(7), Path::new("renders/frame_0007.png"));
+        assert_eq!(p.path(12345), Path::new("renders/frame_12345.png"));
+        assert_eq!(p.frame_of("frame_0042.png"), Some(42));
+        assert_eq!(p.frame_of("frame_42.png"), None);
+        assert_eq!(p.frame_of("frame_0042.jpg"), None);
+
+        let hashes = SequencePattern::parse(Path::new("shot.###.png"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(hashes.to_string(), "shot.%03d.png");
+        assert_eq!(hashes.frame_of("shot.100.png"), Some(100));
+
+        let plain = SequencePattern::parse(Path::new("f%d.png"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(plain.frame_of("f7.png"), Some(7));
+        assert_eq!(plain.frame_of("f007.png"), None);
+
+        assert_eq!(
+            SequencePattern::parse(Path::new("photo.png")).unwrap(),
+            None
+        );
+        assert!(SequencePattern::parse(Path::new("a_%02d_%02d.png")).is_err());
+
+        assert_eq!(
+            "1-120".parse::<FrameRange>().unwrap(),
+            FrameRange {
+                first: 1,
+                last: 120
+            }
+        );
+        assert!("9-3".parse::<FrameRange>().is_err());
+    }
+}
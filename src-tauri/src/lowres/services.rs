@@ -0,0 +1,304 @@
+//! macOS Finder integration: a Quick Action, on the context menu and in the
+//! Services menu of images, that hands the selected files to the desktop app
+//! or processes them with the command line and a chosen preset. Quick
+//! Actions are Automator workflows in the user's Library, so no administrator
+//! rights are needed.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Name of the Quick Action when none is given.
+pub const DEFAULT_NAME: &str = "Pixelate with lowres";
+
+/// What a Quick Action does with the selected files.
+#[derive(Debug, Clone, Copy)]
+pub enum Target<'a> {
+    /// Open them in the desktop app (the `.app` bundle), which takes them
+    /// over whether it is running or not.
+    App(&'a Path),
+    /// Process each with the lowres command line and these flags, writing
+    /// `<stem>_lowres.png` next to it without replacing anything.
+    Cli { cli: &'a Path, flags: &'a [String] },
+}
+
+/// `s` quoted for `sh`: a ' inside '…' has to end the quote to be escaped.
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The shell script the Quick Action runs, with the files as its arguments.
+pub fn script(target: Target) -> String {
+    match target {
+        Target::App(app) => format!("open -a {} \"$@\"\n", sh_quote(&app.display().to_string())),
+        Target::Cli { cli, flags } => {
+            let mut command = sh_quote(&cli.display().to_string());
+            for flag in flags {
+                command.push(' ');
+                command.push_str(&sh_quote(flag));
+            }
+            // One run per file, so every output lands beside its own input
+            // and one bad file doesn't stop the rest.
+            format!(
+                "status=0\n\
+for f in \"$@\"; do\n  \
+{} -i \"$f\" --out-dir \"$(dirname \"$f\")\" --on-collision rename-increment || status=1\n\
+done\n\
+exit $status\n",
+                command
+            )
+        }
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const PLIST_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+"#;
+
+/// The files of a Quick Action bundle named `name` that runs `script`, as
+/// paths inside the `.workflow` directory and their contents.
+pub fn workflow_files(name: &str, script: &str) -> [(&'static str, String); 2] {
+    // Finder offers the action for images only, and Automator passes the
+    // files to the script as arguments (inputMethod 1).
+    let info = format!(
+        r#"{}<dict>
+	<key>NSServices</key>
+	<array>
+		<dict>
+			<key>NSMenuItem</key>
+			<dict>
+				<key>default</key>
+				<string>{}</string>
+			</dict>
+			<key>NSMessage</key>
+			<string>runWorkflowAsService</string>
+			<key>NSRequiredContext</key>
+			<dict>
+				<key>NSApplicationIdentifier</key>
+				<string>com.apple.finder</string>
+			</dict>
+			<key>NSSendFileTypes</key>
+			<array>
+				<string>public.image</string>
+			</array>
+		</dict>
+	</array>
+</dict>
+</plist>
+"#,
+        PLIST_HEADER,
+        xml_escape(name)
+    );
+    let document = format!(
+        r#"{}<dict>
+	<key>AMApplicationBuild</key>
+	<string>523</string>
+	<key>AMApplicationVersion</key>
+	<string>2.10</string>
+	<key>AMDocumentVersion</key>
+	<string>2</string>
+	<key>actions</key>
+	<array>
+		<dict>
+			<key>action</key>
+			<dict>
+				<key>AMAccepts</key>
+				<dict>
+					<key>Container</key>
+					<string>List</string>
+					<key>Optional</key>
+					<true/>
+					<key>Types</key>
+					<array>
+						<string>com.apple.cocoa.path</string>
+					</array>
+				</dict>
+				<key>AMActionVersion</key>
+				<string>2.0.3</string>
+				<key>AMProvides</key>
+				<dict>
+					<key>Container</key>
+					<string>List</string>
+					<key>Types</key>
+					<array>
+						<string>com.apple.cocoa.string</string>
+					</array>
+				</dict>
+				<key>ActionBundlePath</key>
+				<string>/System/Library/Automator/Run Shell Script.action</string>
+				<key>ActionName</key>
+				<string>Run Shell Script</string>
+				<key>ActionParameters</key>
+				<dict>
+					<key>COMMAND_STRING</key>
+					<string>{}</string>
+					<key>CheckedForUserDefaultShell</key>
+					<true/>
+					<key>inputMethod</key>
+					<integer>1</integer>
+					<key>shell</key>
+					<string>/bin/sh</string>
+					<key>source</key>
+					<string></string>
+				</dict>
+				<key>BundleIdentifier</key>
+				<string>com.apple.RunShellScript</string>
+				<key>CFBundleVersion</key>
+				<string>2.0.3</string>
+				<key>Class Name</key>
+				<string>RunShellScriptAction</string>
+				<key>InputUUID</key>
+				<string>5A4E1B1C-6C0E-4C5B-9E55-1F0F3C1D2A01</string>
+				<key>OutputUUID</key>
+				<string>5A4E1B1C-6C0E-4C5B-9E55-1F0F3C1D2A02</string>
+				<key>UUID</key>
+				<string>5A4E1B1C-6C0E-4C5B-9E55-1F0F3C1D2A03</string>
+			</dict>
+			<key>isViewVisible</key>
+			<integer>1</integer>
+		</dict>
+	</array>
+	<key>connectors</key>
+	<dict/>
+	<key>workflowMetaData</key>
+	<dict>
+		<key>serviceApplicationBundleID</key>
+		<string>com.apple.finder</string>
+		<key>serviceApplicationPath</key>
+		<string>/System/Library/CoreServices/Finder.app</string>
+		<key>serviceInputTypeIdentifier</key>
+		<string>com.apple.Automator.fileSystemObject.image</string>
+		<key>serviceOutputTypeIdentifier</key>
+		<string>com.apple.Automator.nothing</string>
+		<key>serviceProcessesInput</key>
+		<integer>0</integer>
+		<key>workflowTypeIdentifier</key>
+		<string>com.apple.Automator.servicesMenu</string>
+	</dict>
+</dict>
+</plist>
+"#,
+        PLIST_HEADER,
+        xml_escape(script)
+    );
+    [
+        ("Contents/Info.plist", info),
+        ("Contents/document.wflow", document),
+    ]
+}
+
+/// Write the Quick Action `name` for `target` into `dir` as
+/// `<name>.workflow`, replacing one already there. Installers can ship the
+/// result and copy it into `~/Library/Services`.
+pub fn write_workflow(dir: &Path, name: &str, target: Target) -> Result<PathBuf> {
+    if name.is_empty() || name.contains('/') {
+        return Err(
+            LowresError::InvalidConfig(format!("{:?} can't name a Quick Action", name)).into(),
+        );
+    }
+    let bundle = dir.join(format!("{}.workflow", name));
+    if bundle.exists() {
+        std::fs::remove_dir_all(&bundle)
+            .map_err(|e| anyhow::anyhow!("Failed to replace {:?}: {}", bundle, e))?;
+    }
+    for (file, contents) in workflow_files(name, &script(target)) {
+        let path = bundle.join(file);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", parent, e))?;
+        }
+        std::fs::write(&path, contents)
+            .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+    }
+    Ok(bundle)
+}
+
+/// The user's Services folder, where Quick Actions are installed.
+pub fn services_dir() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(Path::new(&home).join("Library/Services"))
+}
+
+fn only_on_macos() -> Result<PathBuf> {
+    if !cfg!(target_os = "macos") {
+        return Err(
+            LowresError::InvalidConfig("Quick Actions are only available on macOS".into()).into(),
+        );
+    }
+    services_dir().ok_or_else(|| LowresError::Other("HOME is not set".into()).into())
+}
+
+/// Install the Quick Action `name` for `target` for the current user.
+/// Returns the workflow written.
+pub fn install(name: &str, target: Target) -> Result<PathBuf> {
+    let dir = only_on_macos()?;
+    let bundle = write_workflow(&dir, name, target)?;
+    refresh_services();
+    Ok(bundle)
+}
+
+/// Remove the Quick Action `name`. Returns the workflow removed, if there
+/// was one.
+pub fn remove(name: &str) -> Result<Option<PathBuf>> {
+    let bundle = only_on_macos()?.join(format!("{}.workflow", name));
+    if !bundle.exists() {
+        return Ok(None);
+    }
+    std::fs::remove_dir_all(&bundle)
+        .map_err(|e| anyhow::anyhow!("Failed to remove {:?}: {}", bundle, e))?;
+    refresh_services();
+    Ok(Some(bundle))
+}
+
+/// Have the Services menu pick up the change now rather than at next login.
+fn refresh_services() {
+    // Only speeds things up, so a failure is ignored.
+    let _ = Command::new("/System/Library/CoreServices/pbs")
+        .arg("-update")
+        .output();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_quick_action_workflow() {
+        let flags = ["--block".to_string(), "8".to_string()];
+        let cli = Path::new("/usr/local/bin/lowres");
+        let script = script(Target::Cli { cli, flags: &flags });
+        assert!(script.contains(
+            "'/usr/local/bin/lowres' '--block' '8' -i \"$f\" --out-dir \"$(dirname \"$f\")\""
+        ));
+        assert_eq!(
+            super::script(Target::App(Path::new("/Applications/it's lowres.app"))),
+            "open -a '/Applications/it'\\''s lowres.app' \"$@\"\n"
+        );
+
+        let dir = std::env::temp_dir().join("lowres_quick_action");
+        let _ = std::fs::remove_dir_all(&dir);
+        let bundle =
+            write_workflow(&dir, DEFAULT_NAME, Target::Cli { cli, flags: &flags }).unwrap();
+        assert_eq!(bundle, dir.join("Pixelate with lowres.workflow"));
+        let info = std::fs::read_to_string(bundle.join("Contents/Info.plist")).unwrap();
+        assert!(info.contains("<string>Pixelate with lowres</string>"));
+        let document = std::fs::read_to_string(bundle.join("Contents/document.wflow")).unwrap();
+        assert!(document.contains("for f in &quot;$@&quot;; do"));
+        assert!(write_workflow(&dir, "a/b", Target::App(cli)).is_err());
+        if !cfg!(target_os = "macos") {
+            assert!(install(DEFAULT_NAME, Target::App(cli)).is_err());
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
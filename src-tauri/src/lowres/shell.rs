@@ -0,0 +1,215 @@
+//! Windows Explorer integration: a "Send to lowres" entry on the context menu
+//! of the images lowres reads, and in the Send To menu, that launches the
+//! desktop app with the selected files. Everything is registered for the
+//! current user, so no administrator rights are needed.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::{input_extensions, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Text of the context menu entry and name of the Send To shortcut.
+pub const MENU_TEXT: &str = "Send to lowres";
+/// Name of the context menu verb under each extension's `shell` key.
+const VERB: &str = "lowres";
+
+/// A value under HKEY_CURRENT_USER.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegistryValue {
+    pub key: String,
+    /// `None` for the key's default value.
+    pub name: Option<&'static str>,
+    pub data: String,
+}
+
+/// The key of the context menu verb for files with `extension`.
+fn verb_key(extension: &str) -> String {
+    format!(
+        r"Software\Classes\SystemFileAssociations\.{}\shell\{}",
+        extension, VERB
+    )
+}
+
+/// The registry values adding the context menu entry that opens images in
+/// `app`. Explorer starts one `app` per selected file; the running app takes
+/// each one's file over.
+pub fn context_menu_values(app: &Path) -> Vec<RegistryValue> {
+    let app = app.display();
+    input_extensions()
+        .into_iter()
+        .flat_map(|extension| {
+            let key = verb_key(extension);
+            [
+                RegistryValue {
+                    key: key.clone(),
+                    name: None,
+                    data: MENU_TEXT.into(),
+                },
+                RegistryValue {
+                    key: key.clone(),
+                    name: Some("Icon"),
+                    data: format!("\"{}\",0", app),
+                },
+                // Keep the entry when more than 15 files are selected.
+                RegistryValue {
+                    key: key.clone(),
+                    name: Some("MultiSelectModel"),
+                    data: "Player".into(),
+                },
+                RegistryValue {
+                    key: format!(r"{}\command", key),
+                    name: None,
+                    data: format!("\"{}\" \"%1\"", app),
+                },
+            ]
+        })
+        .collect()
+}
+
+/// `values` as a `.reg` file, for installers that import one.
+pub fn reg_file(values: &[RegistryValue]) -> String {
+    let escape = |s: &str| s.replace('\\', r"\\").replace('"', "\\\"");
+    let mut out = String::from("Windows Registry Editor Version 5.00\r\n");
+    let mut key = None;
+    for value in values {
+        if key != Some(&value.key) {
+            out.push_str(&format!("\r\n[HKEY_CURRENT_USER\\{}]\r\n", value.key));
+            key = Some(&value.key);
+        }
+        let name = value
+            .name
+            .map_or("@".to_string(), |name| format!("\"{}\"", name));
+        out.push_str(&format!("{}=\"{}\"\r\n", name, escape(&value.data)));
+    }
+    out
+}
+
+/// The user's Send To shortcut for lowres. Send To starts the app once with
+/// every selected file.
+pub fn send_to_shortcut() -> Option<PathBuf> {
+    let appdata = std::env::var_os("APPDATA")?;
+    Some(
+        Path::new(&appdata)
+            .join(r"Microsoft\Windows\SendTo")
+            .join(format!("{}.lnk", MENU_TEXT)),
+    )
+}
+
+fn only_on_windows() -> Result<()> {
+    if !cfg!(windows) {
+        return Err(LowresError::InvalidConfig(
+            "Explorer integration is only available on Windows".into(),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Add the context menu entry and the Send To shortcut for `app`. Returns
+/// what was added, for the installer's log.
+pub fn integrate(app: &Path) -> Result<Vec<String>> {
+    only_on_windows()?;
+    if !app.is_file() {
+        return Err(
+            LowresError::InvalidConfig(format!("{:?} is not the app's executable", app)).into(),
+        );
+    }
+    let values = context_menu_values(app);
+    for value in &values {
+        let key = format!(r"HKCU\{}", value.key);
+        let mut reg = Command::new("reg");
+        reg.args(["add", &key]);
+        match value.name {
+            Some(name) => reg.args(["/v", name]),
+            None => reg.arg("/ve"),
+        };
+        run(reg.args(["/d", &value.data, "/f"]), "reg add")?;
+    }
+    let mut added = vec![format!(
+        "context menu entry for {} extensions",
+        input_extensions().len()
+    )];
+
+    if let Some(shortcut) = send_to_shortcut() {
+        // PowerShell quotes a ' inside '…' by doubling it.
+        let quote = |path: &Path| {
+            let path = path.display().to_string();
+            format!("'{}'", path.replace('\'', "''"))
+        };
+        let script = format!(
+            "$s = (New-Object -ComObject WScript.Shell).CreateShortcut({}); \
+$s.TargetPath = {}; $s.Save()",
+            quote(&shortcut),
+            quote(app)
+        );
+        let mut powershell = Command::new("powershell");
+        powershell.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+        run(&mut powershell, "powershell")?;
+        added.push(format!("Send To shortcut {:?}", shortcut));
+    }
+    Ok(added)
+}
+
+/// Undo `integrate`. Entries that aren't there are ignored.
+pub fn remove() -> Result<Vec<String>> {
+    only_on_windows()?;
+    for extension in input_extensions() {
+        let key = format!(r"HKCU\{}", verb_key(extension));
+        // Fails when the key is already gone, which is fine.
+        let _ = Command::new("reg").args(["delete", &key, "/f"]).output();
+    }
+    let mut removed = vec!["context menu entries".to_string()];
+    if let Some(shortcut) = send_to_shortcut().filter(|s| s.exists()) {
+        std::fs::remove_file(&shortcut)
+            .map_err(|e| anyhow::anyhow!("Failed to remove {:?}: {}", shortcut, e))?;
+        removed.push(format!("Send To shortcut {:?}", shortcut));
+    }
+    Ok(removed)
+}
+
+fn run(command: &mut Command, name: &str) -> Result<()> {
+    let output = command
+        .output()
+        .map_err(|e| LowresError::Io(format!("Failed to run {}: {}", name, e)))?;
+    if !output.status.success() {
+        return Err(LowresError::Other(format!(
+            "{} failed: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_context_menu_entry_per_extension() {
+        let app = Path::new(r"C:\Program Files\lowres\lowres.exe");
+        let values = context_menu_values(app);
+        assert_eq!(values.len(), input_extensions().len() * 4);
+        let command = &values[3];
+        assert_eq!(
+            command.key,
+            r"Software\Classes\SystemFileAssociations\.png\shell\lowres\command"
+        );
+        assert_eq!(command.data, r#""C:\Program Files\lowres\lowres.exe" "%1""#);
+
+        let reg = reg_file(&values[..4]);
+        assert!(reg.starts_with("Windows Registry Editor Version 5.00\r\n"));
+        assert!(reg.contains(
+            "[HKEY_CURRENT_USER\\Software\\Classes\\SystemFileAssociations\\.png\\shell\\lowres]\r\n\
+@=\"Send to lowres\"\r\n"
+        ));
+        assert!(reg
+            .contains("@=\"\\\"C:\\\\Program Files\\\\lowres\\\\lowres.exe\\\" \\\"%1\\\"\"\r\n"));
+        if !cfg!(windows) {
+            assert!(integrate(app).is_err());
+        }
+    }
+}
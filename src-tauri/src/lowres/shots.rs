@@ -0,0 +1,292 @@
+//! Shot lists: frame ranges of a sequence mapped to their own settings, so
+//! different shots get different treatments in one run (redact shots 10–14,
+//! stylize 20–23). Read from CSV or an OpenTimelineIO timeline.
+//!
+//! A CSV has a header row naming its columns: `frames` (`START-END` or one
+//! frame), `config` (a config or pipeline file, relative to the list) and an
+//! optional `name`. In a `.otio` timeline, each clip of the first video track
+//! whose metadata has a `lowres` entry, an inline config or a file path, is a
+//! shot covering the frames the clip occupies.
+
+use anyhow::Context;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use super::sequence::FrameRange;
+use super::{migrate, pipeline, LowresConfig, LowresError};
+
+type Result<T> = anyhow::Result<T>;
+
+pub struct Shot {
+    pub name: String,
+    pub frames: FrameRange,
+    /// The config fields set for the shot, applied over the run's config.
+    settings: Map<String, Value>,
+}
+
+pub struct ShotList {
+    /// In frame order, without overlaps.
+    pub shots: Vec<Shot>,
+}
+
+impl ShotList {
+    /// Read a `.csv` or `.otio` shot list.
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read file {:?}: {}", path, e))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase());
+        let shots = match extension.as_deref() {
+            Some("csv") => parse_csv(&text, dir),
+            Some("otio") => parse_otio(&text, dir),
+            _ => Err(invalid("expected a .csv or .otio file".into())),
+        };
+        let mut shots = shots.with_context(|| format!("Shot list {:?}", path))?;
+        shots.sort_by_key(|s| s.frames.first);
+        if let Some(w) = shots
+            .windows(2)
+            .find(|w| w[1].frames.first <= w[0].frames.last)
+        {
+            return Err(invalid(format!(
+                "Shot list {:?}: shots {:?} ({}) and {:?} ({}) overlap",
+                path, w[0].name, w[0].frames, w[1].name, w[1].frames
+            )));
+        }
+        Ok(ShotList { shots })
+    }
+
+    /// `base` with the settings of the shot holding `frame` applied over it;
+    /// frames outside every shot render with `base` as is.
+    pub fn config_at(&self, base: &LowresConfig, frame: u32) -> Result<LowresConfig> {
+        let shot = self
+            .shots
+            .iter()
+            .find(|s| (s.frames.first..=s.frames.last).contains(&frame));
+        match shot {
+            Some(shot) => migrate::overlay(base, &shot.settings)
+                .with_context(|| format!("Shot {:?}", shot.name)),
+            None => Ok(base.clone()),
+        }
+    }
+}
+
+fn invalid(message: String) -> anyhow::Error {
+    LowresError::InvalidConfig(message).into()
+}
+
+/// The fields `config` sets, to apply over another config.
+fn settings_of(config: LowresConfig) -> Result<Map<String, Value>> {
+    let Value::Object(mut fields) = serde_json::to_value(config)? else {
+        return Ok(Map::new());
+    };
+    fields.retain(|key, value| !value.is_null() && key != "version");
+    Ok(fields)
+}
+
+fn parse_csv(text: &str, dir: &Path) -> Result<Vec<Shot>> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let header: Vec<String> = match lines.next() {
+        Some((_, line)) => split_csv(line)
+            .into_iter()
+            .map(|c| c.to_ascii_lowercase())
+            .collect(),
+        None => return Err(invalid("the file is empty".into())),
+    };
+    let column = |name: &str| header.iter().position(|c| c == name);
+    let (Some(frames_col), Some(config_col)) = (column("frames"), column("config")) else {
+        return Err(invalid(
+            "the header must name a frames and a config column".into(),
+        ));
+    };
+    let name_col = column("name");
+
+    lines
+        .map(|(i, line)| {
+            let cells = split_csv(line);
+            let cell = |col: usize| cells.get(col).map(String::as_str).unwrap_or("");
+            let frames: FrameRange = cell(frames_col)
+                .parse()
+                .with_context(|| format!("line {}", i + 1))?;
+            let config = pipeline::read_pipeline(&dir.join(cell(config_col)))
+                .with_context(|| format!("line {}", i + 1))?;
+            let name = name_col
+                .map(cell)
+                .filter(|n| !n.is_empty())
+                .map_or_else(|| frames.to_string(), str::to_string);
+            Ok(Shot {
+                name,
+                frames,
+                settings: settings_of(config)?,
+            })
+        })
+        .collect()
+}
+
+/// The cells of a CSV line, unquoting `"..."` cells (with `""` for a quote).
+fn split_csv(line: &str) -> Vec<String> {
+    let mut cells = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let cell = cells.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => cells.push(String::new()),
+            c => cell.push(c),
+        }
+    }
+    cells.iter().map(|c| c.trim().to_string()).collect()
+}
+
+fn parse_otio(text: &str, dir: &Path) -> Result<Vec<Shot>> {
+    let timeline: Value = serde_json::from_str(text)
+        .map_err(|e| invalid(format!("invalid OpenTimelineIO JSON: {}", e)))?;
+    let schema = |v: &Value| {
+        v.get("OTIO_SCHEMA")
+            .and_then(Value::as_str)
+            .and_then(|s| s.split('.').next())
+            .unwrap_or("")
+            .to_string()
+    };
+    if schema(&timeline) != "Timeline" {
+        return Err(invalid("the file is not an OpenTimelineIO timeline".into()));
+    }
+    let tracks = timeline["tracks"]["children"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let track = tracks
+        .iter()
+        .find(|t| t["kind"] == "Video")
+        .ok_or_else(|| invalid("the timeline has no video track".into()))?;
+
+    // Timeline frames count from the global start time, in its rate.
+    let start = &timeline["global_start_time"];
+    let rate = start["rate"]
+        .as_f64()
+        .or_else(|| track["children"][0]["source_range"]["duration"]["rate"].as_f64())
+        .unwrap_or(24.0);
+    let mut position = start["value"].as_f64().unwrap_or(0.0).round() as u32;
+
+    let mut shots = Vec::new();
+    for item in track["children"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        let kind = schema(item);
+        // Transitions overlap their neighbors and take no time of their own.
+        if kind != "Clip" && kind != "Gap" {
+            continue;
+        }
+        let name = item["name"].as_str().unwrap_or("").to_string();
+        let duration = &item["source_range"]["duration"];
+        let frames = match (duration["value"].as_f64(), duration["rate"].as_f64()) {
+            (Some(value), Some(r)) if r > 0.0 => (value * rate / r).round() as u32,
+            (Some(value), None) => value.round() as u32,
+            _ => {
+                return Err(invalid(format!(
+                    "{} {:?} has no source_range duration",
+                    kind, name
+                )))
+            }
+        };
+        let settings = &item["metadata"]["lowres"];
+        if kind == "Clip" && frames > 0 && !settings.is_null() {
+            let config = match settings {
+                Value::String(file) => pipeline::read_pipeline(&dir.join(file)),
+                inline => migrate::upgrade(inline.clone()),
+            }
+            .with_context(|| format!("clip {:?}", name))?;
+            shots.push(Shot {
+                name,
+                frames: FrameRange {
+                    first: position,
+                    last: position + frames - 1,
+                },
+                settings: settings_of(config)?,
+            });
+        }
+        position += frames;
+    }
+    Ok(shots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reads_csv_and_otio_shot_lists() {
+        let dir = std::env::temp_dir().join("lowres_shots_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("redact.json"), r#"{ "block": 24 }"#).unwrap();
+        std::fs::write(
+            dir.join("shots.csv"),
+            "name,frames,config\n\"shot 10, redact\",10-14,redact.json\n,20,redact.json\n",
+        )
+        .unwrap();
+        let clip = |name: &str, frames: u32, lowres: Value| {
+            json!({
+                "OTIO_SCHEMA": "Clip.2",
+                "name": name,
+                "source_range": { "duration": { "value": frames, "rate": 24 } },
+                "metadata": { "lowres": lowres },
+            })
+        };
+        let timeline = json!({
+            "OTIO_SCHEMA": "Timeline.1",
+            "global_start_time": { "value": 1001, "rate": 24 },
+            "tracks": { "children": [{ "kind": "Video", "children": [
+                clip("sh010", 10, Value::Null),
+                { "OTIO_SCHEMA": "Gap.1", "source_range": { "duration": { "value": 5 } } },
+                clip("sh020", 4, json!({ "palette": "GameBoy" })),
+                clip("sh030", 2, json!("redact.json")),
+            ] }] },
+        });
+        std::fs::write(dir.join("edit.otio"), timeline.to_string()).unwrap();
+        std::fs::write(
+            dir.join("bad.csv"),
+            "frames,config\n1-5,redact.json\n5,redact.json\n",
+        )
+        .unwrap();
+
+        let csv = ShotList::load(&dir.join("shots.csv")).unwrap();
+        let otio = ShotList::load(&dir.join("edit.otio")).unwrap();
+        let overlapping = ShotList::load(&dir.join("bad.csv"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(csv.shots[0].name, "shot 10, redact");
+        assert_eq!(csv.shots[1].name, "20-20");
+        let base = LowresConfig {
+            dpi: Some(72),
+            ..Default::default()
+        };
+        let at = |frame| csv.config_at(&base, frame).unwrap();
+        assert_eq!((at(12).block, at(12).dpi), (Some(24), Some(72)));
+        assert_eq!(at(15).block, None);
+
+        let frames: Vec<_> = otio
+            .shots
+            .iter()
+            .map(|s| (s.frames.first, s.frames.last))
+            .collect();
+        assert_eq!(frames, [(1016, 1019), (1020, 1021)]);
+        assert_eq!(
+            otio.config_at(&base, 1017).unwrap().palette,
+            Some(super::super::Palette::GameBoy)
+        );
+
+        assert!(overlapping.is_err());
+    }
+}
@@ -0,0 +1,190 @@
+//! Provenance sidecars: a JSON file next to each output recording where it
+//! came from and how it was made, for asset managers that ingest derivatives.
+
+use image::RgbaImage;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use super::{manifest, pipeline, LowresConfig, ProcessReport};
+
+type Result<T> = anyhow::Result<T>;
+
+/// How many of the most common colors are listed.
+const TOP_COLORS: usize = 8;
+
+/// What a sidecar records beyond the `ProcessReport` it is written with.
+#[derive(Serialize, Debug, Clone)]
+pub struct Sidecar {
+    /// Version of the lowres build that made the output.
+    pub lowres: String,
+    pub source: Option<PathBuf>,
+    /// SHA-256 of the source file, as hex.
+    pub source_sha256: Option<String>,
+    pub colors: ColorStats,
+    /// The config with every default filled in, as in a pipeline file; the
+    /// sidecar itself can be passed to `--pipeline-file`.
+    pub config: LowresConfig,
+}
+
+/// Color statistics of an output's visible pixels.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ColorStats {
+    pub unique_colors: usize,
+    /// Mean color as `#rrggbb`; `None` when every pixel is transparent.
+    pub mean: Option<String>,
+    /// Share of fully transparent pixels.
+    pub transparent: f64,
+    /// The most common colors, most common first.
+    pub top: Vec<ColorShare>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ColorShare {
+    pub color: String,
+    /// Share of visible pixels.
+    pub share: f64,
+}
+
+impl Sidecar {
+    /// The sidecar of `output` rendered with `config`; the source is filled
+    /// in by `attach_source`.
+    pub fn new(config: &LowresConfig, output: &RgbaImage) -> Self {
+        let pipeline = pipeline::explain(config);
+        Sidecar {
+            lowres: pipeline.lowres,
+            source: None,
+            source_sha256: None,
+            colors: color_stats(output),
+            config: pipeline.config,
+        }
+    }
+}
+
+/// Record `input` as the source of `report`'s sidecar, if it has one.
+pub fn attach_source(report: &mut ProcessReport, input: &Path) -> Result<()> {
+    if let Some(sidecar) = &mut report.sidecar {
+        sidecar.source_sha256 = Some(manifest::sha256_file(input)?);
+        sidecar.source = Some(input.canonicalize().unwrap_or_else(|_| input.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// Where the sidecar of `output` is written: `<stem>.json` beside it.
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    output.with_extension("json")
+}
+
+/// `sidecar` and `report` as one pretty-printed JSON object.
+pub fn sidecar_json(sidecar: &Sidecar, report: &ProcessReport) -> Result<Vec<u8>> {
+    #[derive(Serialize)]
+    struct File<'a> {
+        #[serde(flatten)]
+        report: &'a ProcessReport,
+        #[serde(flatten)]
+        sidecar: &'a Sidecar,
+    }
+    Ok(serde_json::to_vec_pretty(&File { report, sidecar })?)
+}
+
+fn hex([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn color_stats(img: &RgbaImage) -> ColorStats {
+    let mut counts: HashMap<[u8; 3], u64> = HashMap::new();
+    let mut sum = [0u64; 3];
+    let mut visible = 0u64;
+    for p in img.pixels().filter(|p| p[3] > 0) {
+        *counts.entry([p[0], p[1], p[2]]).or_default() += 1;
+        for c in 0..3 {
+            sum[c] += p[c] as u64;
+        }
+        visible += 1;
+    }
+    let total = img.pixels().len() as f64;
+    let mut by_count: Vec<([u8; 3], u64)> = counts.iter().map(|(&c, &n)| (c, n)).collect();
+    // Ties break by color so the list is stable.
+    by_count.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    ColorStats {
+        unique_colors: counts.len(),
+        mean: (visible > 0).then(|| hex(sum.map(|s| ((s as f64) / visible as f64).round() as u8))),
+        transparent: if total > 0.0 {
+            (total - visible as f64) / total
+        } else {
+            0.0
+        },
+        top: by_count
+            .into_iter()
+            .take(TOP_COLORS)
+            .map(|(color, n)| ColorShare {
+                color: hex(color),
+                share: n as f64 / visible as f64,
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn counts_colors_of_visible_pixels() {
+        let img = RgbaImage::from_fn(4, 1, |x, _| match x {
+            0 | 1 => Rgba([255, 0, 0, 255]),
+            2 => Rgba([0, 0, 255, 255]),
+            _ => Rgba([9, 9, 9, 0]),
+        });
+        let stats = color_stats(&img);
+        assert_eq!(stats.unique_colors, 2);
+        assert_eq!(stats.mean.as_deref(), Some("#aa0055"));
+        assert_eq!(stats.transparent, 0.25);
+        assert_eq!(
+            stats.top,
+            vec![
+                ColorShare {
+                    color: "#ff0000".into(),
+                    share: 2.0 / 3.0
+                },
+                ColorShare {
+                    color: "#0000ff".into(),
+                    share: 1.0 / 3.0
+                },
+            ]
+        );
+        assert_eq!(color_stats(&RgbaImage::new(2, 2)).mean, None);
+    }
+
+    #[test]
+    fn writes_a_sidecar_that_reads_back_as_a_pipeline() {
+        let dir = std::env::temp_dir().join("lowres_sidecar_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in.png"), dir.join("out.png"));
+        RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]))
+            .save(&input)
+            .unwrap();
+        let config = LowresConfig {
+            block: Some(2),
+            sidecar: Some(true),
+            ..Default::default()
+        };
+        super::super::process_image(input.clone(), output.clone(), config).unwrap();
+
+        let path = sidecar_path(&output);
+        assert_eq!(path, dir.join("out.json"));
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            json["source_sha256"],
+            manifest::sha256_file(&input).unwrap().as_str()
+        );
+        assert_eq!(json["width"], 4);
+        assert_eq!(json["colors"]["mean"], "#0a141e");
+        assert!(json["timings"]["encode_ms"].is_number());
+        let config = pipeline::read_pipeline(&path).unwrap();
+        assert_eq!(config.block_width, Some(2));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
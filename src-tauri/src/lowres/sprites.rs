@@ -0,0 +1,167 @@
+//! Splits a sprite sheet into its sprites: every 8-connected region of
+//! non-transparent pixels becomes its own PNG, listed with its bounding box
+//! in a JSON index next to them.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use super::{load_image, render_decoded, LowresConfig, Region, Timings};
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Sprite {
+    /// File name within the output directory.
+    pub file: String,
+    /// Bounding box in sheet pixels.
+    #[serde(flatten)]
+    pub bounds: Region,
+}
+
+/// Index of an extracted sheet; also written as `<stem>_sprites.json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct SpriteSheet {
+    pub source: PathBuf,
+    pub sprites: Vec<Sprite>,
+}
+
+/// Cut every sprite out of the sheet at `input` into `out_dir`. With a
+/// `config`, each sprite is run through the normal pipeline before saving.
+pub fn extract_sprites(
+    input: &PathBuf,
+    out_dir: &Path,
+    config: Option<LowresConfig>,
+) -> Result<SpriteSheet> {
+    let img = load_image(input)?;
+    let config = config.map(|c| c.resolve_percentages((img.width(), img.height())));
+    let img = match config.as_ref().and_then(|c| c.crop) {
+        Some(crop) => crop.crop(&img)?,
+        None => img,
+    };
+    let rgba = img.to_rgba8();
+    let config = config.map(LowresConfig::resolve_presets);
+    let quantize = match &config {
+        Some(c) => c.quantize()?,
+        None => None,
+    };
+
+    std::fs::create_dir_all(out_dir)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", out_dir, e))?;
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+
+    let (labels, bounds) = label_components(&rgba);
+    let mut sprites = Vec::with_capacity(bounds.len());
+    for (i, rect) in bounds.into_iter().enumerate() {
+        // Only this component's pixels; neighbors reaching into the box stay transparent.
+        let sprite = RgbaImage::from_fn(rect.width, rect.height, |x, y| {
+            let (sx, sy) = (rect.x + x, rect.y + y);
+            if labels[(sy * rgba.width() + sx) as usize] == i as u32 + 1 {
+                *rgba.get_pixel(sx, sy)
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+
+        let file = format!("{}_{}.png", stem, i);
+        let path = out_dir.join(&file);
+        match &config {
+            Some(config) => {
+                let img = DynamicImage::ImageRgba8(sprite);
+                let (png, _) = render_decoded(
+                    &img,
+                    config,
+                    quantize.as_ref(),
+                    None,
+                    Timings::default(),
+                    &mut |_| {},
+                )?;
+                std::fs::write(&path, png)
+            }
+            None => sprite.save(&path).map_err(std::io::Error::other),
+        }
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", path, e))?;
+
+        sprites.push(Sprite { file, bounds: rect });
+    }
+
+    let sheet = SpriteSheet {
+        source: input.clone(),
+        sprites,
+    };
+    let index = out_dir.join(format!("{}_sprites.json", stem));
+    let json = serde_json::to_vec_pretty(&sheet)?;
+    std::fs::write(&index, json)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", index, e))?;
+
+    Ok(sheet)
+}
+
+/// Label 8-connected regions of pixels with nonzero alpha. Returns one label
+/// per pixel (0 = transparent, `n + 1` = component `n`) and each component's
+/// bounds, ordered by where the component is first met in row-major order.
+fn label_components(img: &RgbaImage) -> (Vec<u32>, Vec<Region>) {
+    let (w, h) = img.dimensions();
+    let mut labels = vec![0u32; (w as usize) * (h as usize)];
+    let mut bounds = Vec::new();
+    let mut stack = Vec::new();
+
+    for start in 0..labels.len() {
+        let (x0, y0) = ((start % w as usize) as u32, (start / w as usize) as u32);
+        if labels[start] != 0 || img.get_pixel(x0, y0)[3] == 0 {
+            continue;
+        }
+        let label = bounds.len() as u32 + 1;
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (x0, y0, x0, y0);
+        labels[start] = label;
+        stack.push((x0, y0));
+
+        while let Some((x, y)) = stack.pop() {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            for ny in y.saturating_sub(1)..=(y + 1).min(h - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(w - 1) {
+                    let idx = (ny * w + nx) as usize;
+                    if labels[idx] == 0 && img.get_pixel(nx, ny)[3] != 0 {
+                        labels[idx] = label;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+        }
+
+        bounds.push(Region {
+            x: min_x,
+            y: min_y,
+            width: max_x - min_x + 1,
+            height: max_y - min_y + 1,
+        });
+    }
+
+    (labels, bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_diagonally_connected_sprites() {
+        let opaque = [(0, 0), (1, 1), (4, 0), (4, 1), (5, 1)];
+        let img = RgbaImage::from_fn(6, 3, |x, y| {
+            if opaque.contains(&(x, y)) {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let (labels, bounds) = label_components(&img);
+        let expected: Vec<Region> = vec!["0,0,2x2".parse().unwrap(), "4,0,2x2".parse().unwrap()];
+        assert_eq!(bounds, expected);
+        assert_eq!(labels[6 + 1], 1);
+        assert_eq!(labels[6 + 5], 2);
+        assert_eq!(labels[2], 0);
+    }
+}
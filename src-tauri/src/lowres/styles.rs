@@ -0,0 +1,138 @@
+//! Named looks that bundle a palette, dithering, block size and grain, so a
+//! classic style takes one setting instead of four.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use super::LowresConfig;
+
+type Result<T> = anyhow::Result<T>;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Style {
+    /// 1-bit black and white with Atkinson dithering, as in MacPaint.
+    Mac,
+    /// The 16 CGA colors with an ordered dither on chunky pixels.
+    Cga,
+    /// Risograph print: a few fluorescent inks on paper, with grain.
+    Riso,
+    /// Halftone-like black ink on newsprint.
+    Newspaper,
+}
+
+impl Style {
+    /// The config fields this style sets.
+    fn settings(self) -> Value {
+        match self {
+            Style::Mac => json!({ "palette": "Mono", "dither": "Atkinson", "block": 2 }),
+            Style::Cga => json!({ "palette": "Cga", "dither": "Ordered", "block": 4 }),
+            Style::Riso => json!({
+                "palette": "Riso", "dither": "Ordered", "block": 3, "grain": 0.12
+            }),
+            Style::Newspaper => json!({
+                "palette": "Newsprint", "dither": "Ordered", "block": 2, "grain": 0.06
+            }),
+        }
+    }
+
+    /// `config` with this style's settings filled into the fields it leaves
+    /// unset. An explicit palette, palette file or color count replaces the
+    /// style's palette.
+    pub fn fill(self, config: LowresConfig) -> LowresConfig {
+        let own_colors =
+            config.palette.is_some() || config.palette_file.is_some() || config.colors.is_some();
+        let (Ok(Value::Object(mut fields)), Value::Object(settings)) =
+            (serde_json::to_value(&config), self.settings())
+        else {
+            return config;
+        };
+        for (key, value) in settings {
+            if key == "palette" && own_colors {
+                continue;
+            }
+            let field = fields.entry(key).or_insert(Value::Null);
+            if field.is_null() {
+                *field = value;
+            }
+        }
+        serde_json::from_value(Value::Object(fields)).unwrap_or(config)
+    }
+}
+
+impl Display for Style {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Style::Mac => "mac",
+            Style::Cga => "cga",
+            Style::Riso => "riso",
+            Style::Newspaper => "newspaper",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Style {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "mac" | "1-bit-mac" | "1bit-mac" => Ok(Style::Mac),
+            "cga" => Ok(Style::Cga),
+            "riso" | "risograph" => Ok(Style::Riso),
+            "newspaper" | "newsprint" => Ok(Style::Newspaper),
+            other => Err(anyhow::anyhow!("Unknown style {:?}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{process_image, Dither, Palette};
+    use super::*;
+
+    #[test]
+    fn every_style_renders() {
+        let dir = std::env::temp_dir().join("lowres_styles_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("ramp.png");
+        image::RgbaImage::from_fn(24, 16, |x, y| {
+            image::Rgba([x as u8 * 10, y as u8 * 15, 128, 255])
+        })
+        .save(&input)
+        .unwrap();
+
+        for style in [Style::Mac, Style::Cga, Style::Riso, Style::Newspaper] {
+            let config = LowresConfig {
+                style: Some(style),
+                ..Default::default()
+            };
+            config.validate().unwrap();
+            let filled = style.fill(config.clone());
+            assert!(filled.palette.is_some() && filled.dither.is_some() && filled.block.is_some());
+            let output = dir.join(format!("{}.png", style));
+            process_image(input.clone(), output.clone(), config).unwrap();
+            let palette = filled.palette.unwrap().colors();
+            let out = image::open(&output).unwrap().to_rgb8();
+            assert!(out.pixels().all(|p| palette.contains(&p.0)), "{}", style);
+        }
+
+        // Explicit settings win over the style's.
+        let config = LowresConfig {
+            block: Some(7),
+            colors: Some(3),
+            ..Default::default()
+        };
+        let filled = Style::Mac.fill(config);
+        assert_eq!(filled.block, Some(7));
+        assert_eq!(filled.palette, None);
+        assert_eq!(filled.dither, Some(Dither::Atkinson));
+        assert_eq!(
+            Style::Cga.fill(LowresConfig::default()).palette,
+            Some(Palette::Cga)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+//! Tiled processing for very large images: pixelation a strip of block rows
+//! at a time, with the output's rows streamed to the PNG encoder, so neither
+//! the source's pixels nor the output's are ever held whole. Strips line up
+//! with block rows, so the output matches an untiled run's exactly.
+
+use anyhow::Context;
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+use rayon::prelude::*;
+use std::io::{Cursor, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use super::{
+    block_color, decode_image, elapsed_ms, exif_orientation, finish_blocks, limits,
+    output_metadata, png_options, png_writer, read_input, source_metadata, BlockOutput, BlockStat,
+    LowresConfig, LowresError, PixelateChannels, PixelateOptions, ProcessReport, Stage, Timings,
+};
+
+type Result<T> = anyhow::Result<T>;
+
+/// Fail unless `config`, with its presets resolved, is one tiled processing
+/// handles: plain pixelation, with any palette, dithering and grain.
+pub fn check_tiled(config: &LowresConfig) -> Result<()> {
+    let keep_size = config.no_resize.unwrap_or(false);
+    let pixelates = config.block_size().is_some() && !config.no_pixelate.unwrap_or(false);
+    if !pixelates {
+        return Err(
+            LowresError::InvalidConfig("Tiled processing needs a block size".into()).into(),
+        );
+    }
+    let unsupported = [
+        (config.crop.is_some(), "crop"),
+        (config.percent_crop.is_some(), "percent_crop"),
+        (config.regions.is_some(), "regions"),
+        (config.percent_regions.is_some(), "percent_regions"),
+        (config.auto_mask.is_some(), "auto_mask"),
+        (
+            config.pixelate_channels.unwrap_or(PixelateChannels::All) != PixelateChannels::All,
+            "pixelate_channels",
+        ),
+        (config.banding.is_some(), "banding"),
+        (config.banding_check.is_some(), "banding_check"),
+        (config.match_colors.is_some(), "match_colors"),
+        (config.upscale.is_some() && !keep_size, "upscale"),
+        (config.max_edge.is_some() && !keep_size, "max_edge"),
+        (config.max_bytes.is_some(), "max_bytes"),
+        (config.png_interlace == Some(true), "png_interlace"),
+        (config.matte == Some(true), "matte"),
+        (config.sidecar == Some(true), "sidecar"),
+        (config.sizes.is_some(), "sizes"),
+    ];
+    let unsupported: Vec<&str> = unsupported
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+    if !unsupported.is_empty() {
+        return Err(LowresError::InvalidConfig(format!(
+            "Tiled processing only pixelates; it can't apply {}",
+            unsupported.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Process `input` with `config` tiled, writing the PNG to `output`.
+pub fn process_tiled(
+    input: &Path,
+    output: &Path,
+    config: &LowresConfig,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<ProcessReport> {
+    let data = read_input(&input.to_path_buf())?;
+    let file =
+        std::fs::File::create(output).with_context(|| format!("Failed to create {:?}", output))?;
+    let mut out = std::io::BufWriter::new(file);
+    let report = render_tiled(&data, config, &mut out, on_stage)?;
+    out.flush()?;
+    Ok(report)
+}
+
+/// Process the encoded image `data` with `config` tiled, streaming the PNG
+/// to `out`.
+pub fn render_tiled<W: Write>(
+    data: &[u8],
+    config: &LowresConfig,
+    out: W,
+    on_stage: &mut dyn FnMut(Stage),
+) -> Result<ProcessReport> {
+    let config = config.clone().resolve_presets();
+    check_tiled(&config)?;
+    let quantize = config.quantize()?;
+    let mut timings = Timings::default();
+
+    on_stage(Stage::Decode);
+    let started = Instant::now();
+    let mut strips = Strips::open(data)?;
+    let (w, h) = strips.size();
+    timings.decode_ms = elapsed_ms(started);
+
+    let block = config
+        .block_size()
+        .ok_or_else(|| anyhow::anyhow!("Tiled processing needs a block size"))?;
+    let opts = PixelateOptions {
+        block,
+        stat: config.block_stat.unwrap_or(BlockStat::Mean),
+        linear_light: config.linear_light.unwrap_or(false),
+        quantize: quantize.as_ref(),
+        dither: config.dither,
+        grain: config.grain,
+        output: config.block_output.unwrap_or(BlockOutput::Full),
+        low_memory: true,
+        gpu: false,
+    };
+    let bw = opts.block.width.max(1);
+    let bh = opts.block.height.max(1);
+    let blocks_x = w.div_ceil(bw);
+    let blocks_y = h.div_ceil(bh);
+    let (out_w, out_h) = match opts.output {
+        BlockOutput::Full => (w, h),
+        BlockOutput::Small => (blocks_x, blocks_y),
+    };
+    limits::current().check_output(out_w, out_h)?;
+
+    // The block colors are all that is kept of the source: one per block.
+    on_stage(Stage::Transform);
+    let started = Instant::now();
+    let mut block_colors = Vec::with_capacity((blocks_x * blocks_y) as usize);
+    let mut decode_ms = 0.0;
+    for block_y in 0..blocks_y {
+        let strip_h = bh.min(h - block_y * bh);
+        let read = Instant::now();
+        let strip = strips.next(strip_h)?;
+        decode_ms += elapsed_ms(read);
+        block_colors.par_extend((0..blocks_x).into_par_iter().map(|block_x| {
+            let x_start = block_x * bw;
+            let x_end = (x_start + bw).min(w);
+            block_color(
+                &strip,
+                x_start..x_end,
+                0..strip_h,
+                opts.stat,
+                opts.linear_light,
+            )
+        }));
+    }
+    drop(strips);
+    finish_blocks(&mut block_colors, blocks_x as usize, &opts, &mut timings);
+    timings.decode_ms += decode_ms;
+    timings.transform_ms = elapsed_ms(started) - decode_ms - timings.quantize_ms;
+
+    on_stage(Stage::Encode);
+    let started = Instant::now();
+    let metadata = output_metadata(&source_metadata(data), &config);
+    let dpi = config.dpi.or(metadata.dpi).unwrap_or(300);
+    let png_opts = png_options(&config, dpi, Some(metadata))?;
+    let mut counted = Counted { out, bytes: 0 };
+    let mut writer = png_writer(
+        &mut counted,
+        (out_w, out_h),
+        png::ColorType::Rgba,
+        None,
+        &png_opts,
+    )?;
+    let mut stream = writer.stream_writer()?;
+    let (cell_w, rows_per_block) = match opts.output {
+        BlockOutput::Full => (bw, bh),
+        BlockOutput::Small => (1, 1),
+    };
+    let mut row = vec![0u8; out_w as usize * 4];
+    for (block_y, colors) in block_colors.chunks(blocks_x as usize).enumerate() {
+        for (x, px) in row.chunks_exact_mut(4).enumerate() {
+            px.copy_from_slice(&colors[x / cell_w as usize].0);
+        }
+        let rows = rows_per_block.min(out_h - block_y as u32 * rows_per_block);
+        for _ in 0..rows {
+            stream.write_all(&row)?;
+        }
+    }
+    stream.finish()?;
+    writer.finish()?;
+    timings.encode_ms = elapsed_ms(started);
+
+    Ok(ProcessReport {
+        original_width: w,
+        original_height: h,
+        width: out_w,
+        height: out_h,
+        bytes: counted.bytes,
+        dpi: (!config.strip_metadata.unwrap_or(false)).then_some(dpi),
+        timings,
+        matte: None,
+        sidecar: None,
+        banding: None,
+        legibility: None,
+    })
+}
+
+/// A source read from the top a strip of rows at a time.
+enum Strips<'a> {
+    /// An upright, non-interlaced 8-bit PNG, decoded a row at a time.
+    Png(Box<png::Reader<Cursor<&'a [u8]>>>),
+    /// Any other image, decoded whole, as only the PNG decoder streams.
+    Decoded { img: DynamicImage, y: u32 },
+}
+
+impl<'a> Strips<'a> {
+    fn open(data: &'a [u8]) -> Result<Self> {
+        let png = image::guess_format(data).ok() == Some(ImageFormat::Png);
+        if png && exif_orientation(&mut Cursor::new(data)).unwrap_or(1) == 1 {
+            limits::current().check_file_bytes(data.len() as u64)?;
+            let mut decoder = png::Decoder::new(Cursor::new(data));
+            // Palettes, low bit depths and transparency chunks expand as the
+            // image crate expands them.
+            decoder.set_transformations(png::Transformations::EXPAND);
+            let reader = decoder.read_info()?;
+            let info = reader.info();
+            limits::current().check_input(info.width, info.height)?;
+            if !info.interlaced && reader.output_color_type().1 == png::BitDepth::Eight {
+                return Ok(Strips::Png(Box::new(reader)));
+            }
+        }
+        Ok(Strips::Decoded {
+            img: decode_image(data)?,
+            y: 0,
+        })
+    }
+
+    fn size(&self) -> (u32, u32) {
+        match self {
+            Strips::Png(reader) => (reader.info().width, reader.info().height),
+            Strips::Decoded { img, .. } => img.dimensions(),
+        }
+    }
+
+    /// The next `rows` rows, as RGBA.
+    fn next(&mut self, rows: u32) -> Result<RgbaImage> {
+        match self {
+            Strips::Png(reader) => {
+                let width = reader.info().width;
+                let color = reader.output_color_type().0;
+                let mut strip = Vec::with_capacity((width * rows * 4) as usize);
+                for _ in 0..rows {
+                    let row = reader
+                        .next_row()?
+                        .ok_or_else(|| anyhow::anyhow!("PNG ended early"))?;
+                    expand_row(row.data(), color, &mut strip)?;
+                }
+                RgbaImage::from_raw(width, rows, strip)
+                    .ok_or_else(|| anyhow::anyhow!("Failed to create strip buffer"))
+            }
+            Strips::Decoded { img, y } => {
+                let strip = img.view(0, *y, img.width(), rows).to_image();
+                *y += rows;
+                Ok(strip)
+            }
+        }
+    }
+}
+
+/// Append the 8-bit `color` pixels of `row` to `out` as RGBA.
+fn expand_row(row: &[u8], color: png::ColorType, out: &mut Vec<u8>) -> Result<()> {
+    use png::ColorType;
+    match color {
+        ColorType::Rgba => out.extend_from_slice(row),
+        ColorType::Rgb => out.extend(row.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255])),
+        ColorType::GrayscaleAlpha => {
+            out.extend(row.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]))
+        }
+        ColorType::Grayscale => out.extend(row.iter().flat_map(|&v| [v, v, v, 255])),
+        ColorType::Indexed => anyhow::bail!("PNG palette was not expanded"),
+    }
+    Ok(())
+}
+
+/// A writer that counts the bytes written through it.
+struct Counted<W> {
+    out: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn encoded(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    #[test]
+    fn tiled_output_matches_untiled() {
+        let img = RgbaImage::from_fn(45, 31, |x, y| {
+            Rgba([(x * 5) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 255])
+        });
+        let sources = [
+            encoded(&DynamicImage::ImageRgba8(img.clone()), ImageFormat::Png),
+            encoded(
+                &DynamicImage::ImageLuma8(DynamicImage::ImageRgba8(img.clone()).to_luma8()),
+                ImageFormat::Png,
+            ),
+            encoded(&DynamicImage::ImageRgba8(img), ImageFormat::Bmp),
+        ];
+        let configs = [
+            r#"{"block": 8}"#,
+            r#"{"block_width": 6, "block_height": 4, "colors": 6, "dither": "FloydSteinberg"}"#,
+            r#"{"block": 5, "block_output": "Small", "block_stat": "Median"}"#,
+        ];
+        for data in &sources {
+            for json in configs {
+                let config: LowresConfig = serde_json::from_str(json).unwrap();
+                let mut tiled = Vec::new();
+                let report = render_tiled(data, &config, &mut tiled, &mut |_| {}).unwrap();
+                let (untiled, _) = super::super::process_image_bytes(data, config).unwrap();
+                let pixels = |png: &[u8]| image::load_from_memory(png).unwrap().to_rgba8();
+                assert_eq!(pixels(&tiled), pixels(&untiled), "{}", json);
+                assert_eq!(report.bytes, tiled.len() as u64);
+            }
+        }
+
+        let config: LowresConfig = serde_json::from_str(r#"{"block": 8, "upscale": 2}"#).unwrap();
+        assert!(check_tiled(&config).is_err());
+        assert!(check_tiled(&LowresConfig::default()).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use image::{Rgba, RgbaImage};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+type Result<T> = anyhow::Result<T>;
+
+/// Algorithms for integer upscaling of pixel art.
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub enum Upscaler {
+    /// Plain pixel duplication; any integer factor.
+    Nearest,
+    /// Scale2x/EPX family: smooths diagonal edges without adding colors.
+    /// Supports factors 2, 3 (Scale3x) and 4 (Scale2x twice).
+    Scale2x,
+}
+
+impl Display for Upscaler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Upscaler::Nearest => "nearest",
+            Upscaler::Scale2x => "scale2x",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Upscaler {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "nearest" => Ok(Upscaler::Nearest),
+            "scale2x" | "epx" => Ok(Upscaler::Scale2x),
+            other => Err(anyhow::anyhow!("Unknown upscaler {:?}", other)),
+        }
+    }
+}
+
+/// Largest supported upscale factor.
+pub const MAX_FACTOR: u32 = 16;
+
+/// Enlarge `img` by an integer `factor`.
+pub fn upscale(img: &RgbaImage, factor: u32, upscaler: Upscaler) -> Result<RgbaImage> {
+    if factor == 0 || factor > MAX_FACTOR {
+        anyhow::bail!(
+            "Upscale factor must be between 1 and {}, got {}",
+            MAX_FACTOR,
+            factor
+        );
+    }
+    if factor == 1 {
+        return Ok(img.clone());
+    }
+
+    match (upscaler, factor) {
+        (Upscaler::Nearest, _) => Ok(nearest(img, factor)),
+        (Upscaler::Scale2x, 2) => Ok(scale2x(img)),
+        (Upscaler::Scale2x, 3) => Ok(scale3x(img)),
+        (Upscaler::Scale2x, 4) => Ok(scale2x(&scale2x(img))),
+        (Upscaler::Scale2x, f) => anyhow::bail!("Scale2x supports factors 2, 3 and 4, got {}", f),
+    }
+}
+
+fn nearest(img: &RgbaImage, factor: u32) -> RgbaImage {
+    RgbaImage::from_fn(img.width() * factor, img.height() * factor, |x, y| {
+        *img.get_pixel(x / factor, y / factor)
+    })
+}
+
+/// Neighbor lookup that clamps at the image border.
+fn at(img: &RgbaImage, x: u32, y: u32, dx: i64, dy: i64) -> Rgba<u8> {
+    let nx = (x as i64 + dx).clamp(0, img.width() as i64 - 1) as u32;
+    let ny = (y as i64 + dy).clamp(0, img.height() as i64 - 1) as u32;
+    *img.get_pixel(nx, ny)
+}
+
+fn scale2x(img: &RgbaImage) -> RgbaImage {
+    let mut out = RgbaImage::new(img.width() * 2, img.height() * 2);
+    for y in 0..img.height() {
+        for x in 0..img.width() {
+            let p = *img.get_pixel(x, y);
+            let a = at(img, x, y, 0, -1);
+            let b = at(img, x, y, 1, 0);
+            let c = at(img, x, y, -1, 0);
+            let d = at(img, x, y, 0, 1);
+
+            let e0 = if c == a && c != d && a != b { a } else { p };
+            let e1 = if a == b && a != c && b != d { b } else { p };
+            let e2 = if d == c && d != b && c != a { c } else { p };
+            let e3 = if b == d && b != a && d != c { d } else { p };
+
+            out.put_pixel(2 * x, 2 * y, e0);
+            out.put_pixel(2 * x + 1, 2 * y, e1);
+            out.put_pixel(2 * x, 2 * y + 1, e2);
+            out.put_pixel(2 * x + 1, 2 * y + 1, e3);
+        }
+    }
+    out
+}
+
+fn scale3x(img: &RgbaImage) -> RgbaImage {
+    let mut out = RgbaImage::new(img.width() * 3, img.height() * 3);
+    for y in 0..img.height() {
+        for x in 0..img.width() {
+            // A B C
+            // D E F
+            // G H I
+            let a = at(img, x, y, -1, -1);
+            let b = at(img, x, y, 0, -1);
+            let c = at(img, x, y, 1, -1);
+            let d = at(img, x, y, -1, 0);
+            let e = *img.get_pixel(x, y);
+            let f = at(img, x, y, 1, 0);
+            let g = at(img, x, y, -1, 1);
+            let h = at(img, x, y, 0, 1);
+            let i = at(img, x, y, 1, 1);
+
+            let db = d == b && b != f && d != h;
+            let bf = b == f && b != d && f != h;
+            let dh = d == h && d != b && h != f;
+            let hf = h == f && d != h && b != f;
+
+            let pick = |cond: bool, px: Rgba<u8>| if cond { px } else { e };
+            let block = [
+                pick(db, d),
+                pick((db && e != c) || (bf && e != a), b),
+                pick(bf, f),
+                pick((db && e != g) || (dh && e != a), d),
+                e,
+                pick((bf && e != i) || (hf && e != c), f),
+                pick(dh, d),
+                pick((dh && e != i) || (hf && e != g), h),
+                pick(hf, f),
+            ];
+            for (k, px) in block.into_iter().enumerate() {
+                out.put_pixel(3 * x + k as u32 % 3, 3 * y + k as u32 / 3, px);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const K: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    #[test]
+    fn scale2x_rounds_diagonals() {
+        // A black diagonal on white.
+        let img = RgbaImage::from_fn(2, 2, |x, y| if x == y { K } else { W });
+        let out = upscale(&img, 2, Upscaler::Scale2x).unwrap();
+        assert_eq!(out.dimensions(), (4, 4));
+        // The corners between the two black pixels are filled in.
+        assert_eq!(*out.get_pixel(3, 0), W);
+        assert_eq!(*out.get_pixel(2, 1), K);
+        assert_eq!(*out.get_pixel(1, 2), K);
+    }
+
+    #[test]
+    fn nearest_duplicates_pixels() {
+        let img = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { K } else { W });
+        let out = upscale(&img, 3, Upscaler::Nearest).unwrap();
+        assert_eq!(out.dimensions(), (6, 3));
+        assert_eq!(*out.get_pixel(2, 2), K);
+        assert_eq!(*out.get_pixel(3, 0), W);
+        assert!(upscale(&img, 5, Upscaler::Scale2x).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+//! Hot folders: every image that appears or changes in a directory is
+//! processed into an output directory with one config, as it arrives.
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use super::batch::{planned_output, process_into, BatchItem, OnCollision};
+use super::{input_extensions, LowresConfig};
+
+type Result<T> = anyhow::Result<T>;
+
+/// How long a file must go without changing before it is processed, so that
+/// one still being copied or saved isn't read half-written.
+pub const DEFAULT_QUIET: Duration = Duration::from_secs(1);
+
+/// Watch `dir` until the watcher fails, processing each image in it into
+/// `out_dir` with `config` once it has been quiet for `quiet`, and handing
+/// the result to `on_item`. Images already there are processed first.
+///
+/// An output at least as new as its input was made from this version of it
+/// and is skipped; one older than its input is replaced.
+pub fn watch(
+    dir: &Path,
+    out_dir: &Path,
+    config: &LowresConfig,
+    quiet: Duration,
+    mut on_item: impl FnMut(BatchItem),
+) -> Result<()> {
+    std::fs::create_dir_all(out_dir)
+        .map_err(|e| anyhow::anyhow!("Failed to create {:?}: {}", out_dir, e))?;
+    let same = |a: &Path, b: &Path| a.canonicalize().ok() == b.canonicalize().ok();
+    if same(dir, out_dir) {
+        anyhow::bail!("The output directory must not be the watched one");
+    }
+
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher
+        .watch(dir, RecursiveMode::NonRecursive)
+        .map_err(|e| anyhow::anyhow!("Failed to watch {:?}: {}", dir, e))?;
+
+    let mut pending = Pending::new(quiet);
+    let existing =
+        std::fs::read_dir(dir).map_err(|e| anyhow::anyhow!("Failed to read {:?}: {}", dir, e))?;
+    for entry in existing.flatten() {
+        pending.touch(entry.path(), Instant::now());
+    }
+    loop {
+        // With nothing pending, only an event can end the wait.
+        let wait = pending.next_due().map_or(Duration::MAX, |due| {
+            due.saturating_duration_since(Instant::now())
+        });
+        match events.recv_timeout(wait) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        pending.touch(path, Instant::now());
+                    }
+                }
+            }
+            Ok(Err(e)) => anyhow::bail!("Watching {:?} failed: {}", dir, e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        for input in pending.settled(Instant::now()) {
+            if is_image(&input) {
+                let policy = policy_for(&input, out_dir, config);
+                on_item(process_into(&input, Some(out_dir), config, policy));
+            }
+        }
+    }
+}
+
+/// Whether `path` is a file lowres reads, judged by its extension. Hidden
+/// files, such as the ones editors save through, are left alone.
+fn is_image(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+    !hidden
+        && path.is_file()
+        && extension.is_some_and(|ext| input_extensions().contains(&ext.as_str()))
+}
+
+/// Skip `input` if its output is up to date, else replace the output.
+fn policy_for(input: &Path, out_dir: &Path, config: &LowresConfig) -> OnCollision {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    // Without a name known up front there is no output to compare against,
+    // so keep whatever is there.
+    let Some(output) = planned_output(input, Some(out_dir), config, None) else {
+        return OnCollision::Skip;
+    };
+    match (modified(input), modified(&output)) {
+        (Some(input), Some(output)) if output < input => OnCollision::Overwrite,
+        _ => OnCollision::Skip,
+    }
+}
+
+/// Paths seen changing, held back until they have been quiet for a while.
+struct Pending {
+    quiet: Duration,
+    last_change: HashMap<PathBuf, Instant>,
+}
+
+impl Pending {
+    fn new(quiet: Duration) -> Self {
+        Pending {
+            quiet,
+            last_change: HashMap::new(),
+        }
+    }
+
+    fn touch(&mut self, path: PathBuf, now: Instant) {
+        self.last_change.insert(path, now);
+    }
+
+    /// When the next path will have been quiet long enough.
+    fn next_due(&self) -> Option<Instant> {
+        self.last_change
+            .values()
+            .min()
+            .map(|&last| last + self.quiet)
+    }
+
+    /// Remove and return the paths quiet since `now - quiet`, oldest first.
+    fn settled(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut settled: Vec<(PathBuf, Instant)> = self
+            .last_change
+            .iter()
+            .filter(|(_, &last)| now.duration_since(last) >= self.quiet)
+            .map(|(path, &last)| (path.clone(), last))
+            .collect();
+        settled.sort_by_key(|&(_, last)| last);
+        for (path, _) in &settled {
+            self.last_change.remove(path);
+        }
+        settled.into_iter().map(|(path, _)| path).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn waits_for_files_to_settle() {
+        let start = Instant::now();
+        let quiet = Duration::from_millis(500);
+        let mut pending = Pending::new(quiet);
+        pending.touch("a.png".into(), start);
+        pending.touch("b.png".into(), start + Duration::from_millis(200));
+        assert_eq!(pending.next_due(), Some(start + quiet));
+        assert!(pending
+            .settled(start + Duration::from_millis(400))
+            .is_empty());
+
+        // Still being written: a later change pushes it back.
+        pending.touch("a.png".into(), start + Duration::from_millis(450));
+        assert_eq!(
+            pending.settled(start + Duration::from_millis(700)),
+            [PathBuf::from("b.png")]
+        );
+        assert_eq!(
+            pending.settled(start + Duration::from_millis(950)),
+            [PathBuf::from("a.png")]
+        );
+        assert_eq!(pending.next_due(), None);
+    }
+
+    #[test]
+    fn replaces_only_outputs_older_than_their_input() {
+        let dir = std::env::temp_dir().join("lowres_watch_policy");
+        let _ = std::fs::remove_dir_all(&dir);
+        let out_dir = dir.join("out");
+        std::fs::create_dir_all(&out_dir).unwrap();
+        let input = dir.join("shot.png");
+        RgbaImage::from_pixel(4, 4, Rgba([9, 9, 9, 255]))
+            .save(&input)
+            .unwrap();
+        let config = LowresConfig::default();
+        assert!(is_image(&input));
+        assert!(!is_image(&dir.join("notes.txt")));
+
+        // No output yet: nothing to collide with.
+        assert_eq!(policy_for(&input, &out_dir, &config), OnCollision::Skip);
+        let output = planned_output(&input, Some(&out_dir), &config, None).unwrap();
+        std::fs::write(&output, b"output").unwrap();
+        assert_eq!(policy_for(&input, &out_dir, &config), OnCollision::Skip);
+        let input_file = std::fs::File::options().write(true).open(&input).unwrap();
+        input_file
+            .set_modified(std::time::SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            policy_for(&input, &out_dir, &config),
+            OnCollision::Overwrite
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
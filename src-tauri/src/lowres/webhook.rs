@@ -0,0 +1,132 @@
+//! Webhooks: what a batch, sequence or hot-folder run did, POSTed as JSON to
+//! a URL when it completes or fails, so an asset pipeline can pick up the
+//! outputs without polling for them.
+
+use serde::Serialize;
+use std::time::Duration;
+
+use super::batch::{BatchItem, BatchReport, BatchSummary};
+use super::remote::{is_url, with_retries};
+use super::LowresError;
+
+type Result<T> = anyhow::Result<T>;
+
+/// How long one delivery may take, including connecting.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Event {
+    /// Every input was processed or skipped.
+    Completed,
+    /// An input failed, or the run as a whole.
+    Failed,
+}
+
+/// The body of a webhook request.
+#[derive(Serialize, Debug, Clone)]
+pub struct Notification {
+    pub event: Event,
+    /// Each input's output, action, report or error, as `--json` prints them.
+    pub items: Vec<BatchItem>,
+    pub summary: Option<BatchSummary>,
+    /// Why the run failed before it had results for its inputs.
+    pub error: Option<LowresError>,
+}
+
+impl Notification {
+    pub fn of_report(report: &BatchReport) -> Self {
+        Notification {
+            event: if report.failed() > 0 {
+                Event::Failed
+            } else {
+                Event::Completed
+            },
+            items: report.items.clone(),
+            summary: Some(report.summary()),
+            error: None,
+        }
+    }
+
+    pub fn of_error(error: LowresError) -> Self {
+        Notification {
+            event: Event::Failed,
+            items: Vec::new(),
+            summary: None,
+            error: Some(error),
+        }
+    }
+}
+
+/// Fail unless `url` is an http or https URL.
+pub fn check_url(url: &str) -> Result<()> {
+    if !is_url(url) {
+        return Err(LowresError::InvalidConfig(format!(
+            "Webhook {:?} isn't an http or https URL",
+            url
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// POST `notification` to `url` as JSON, retrying transient failures.
+pub fn post(url: &str, notification: &Notification) -> Result<()> {
+    let body = serde_json::to_string(notification)?;
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let request = agent.post(url).set("Content-Type", "application/json");
+    with_retries("notify the webhook", &request, body.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::batch::CollisionAction;
+    use super::super::remote::tests::serve;
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn posts_per_file_results() {
+        let item = |name: &str, error: Option<LowresError>| BatchItem {
+            input: PathBuf::from(format!("{}.png", name)),
+            output: PathBuf::from(format!("out/{}.png", name)),
+            action: CollisionAction::Created,
+            report: None,
+            error,
+        };
+        let report = BatchReport {
+            items: vec![
+                item("a", None),
+                item("b", Some(LowresError::Decode("truncated".into()))),
+            ],
+            palette: None,
+        };
+        let (url, server) = serve(&[200]);
+        post(
+            &format!("{}/hooks/lowres", url),
+            &Notification::of_report(&report),
+        )
+        .unwrap();
+        let received = server.join().unwrap();
+        assert_eq!(received[0].request_line, "POST /hooks/lowres HTTP/1.1");
+        assert!(received[0]
+            .headers
+            .contains(&("content-type".into(), "application/json".into())));
+
+        let body: serde_json::Value = serde_json::from_slice(&received[0].body).unwrap();
+        assert_eq!(body["event"], "failed");
+        assert_eq!(body["items"][0]["output"], "out/a.png");
+        assert_eq!(body["items"][1]["error"]["kind"], "Decode");
+        assert_eq!(body["summary"]["failed"], 1);
+
+        let body =
+            serde_json::to_value(Notification::of_error(LowresError::Io("disk full".into())))
+                .unwrap();
+        assert_eq!(body["event"], "failed");
+        assert_eq!(body["error"]["message"], "disk full");
+
+        assert!(check_url("https://ci.example.com/hook").is_ok());
+        assert!(check_url("ci.example.com/hook").is_err());
+    }
+}
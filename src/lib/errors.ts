@@ -0,0 +1,34 @@
+/** Mirrors `LowresError` on the Rust side: `{ kind, message }`. */
+export type LowresError = {
+  kind:
+    | "Io"
+    | "UnsupportedFormat"
+    | "Decode"
+    | "InvalidConfig"
+    | "TooLarge"
+    | "Other";
+  message: string;
+};
+
+function isLowresError(e: unknown): e is LowresError {
+  return typeof e === "object" && e !== null && "kind" in e && "message" in e;
+}
+
+/** Turn an error thrown by `invoke` into text for the error toast. */
+export function describeError(e: unknown): string {
+  if (!isLowresError(e)) return String(e);
+  switch (e.kind) {
+    case "UnsupportedFormat":
+      return "This file type isn't supported. Try a PNG, JPEG, GIF or WebP.";
+    case "Decode":
+      return "The image looks damaged and couldn't be read: " + e.message;
+    case "Io":
+      return "Couldn't access the file: " + e.message;
+    case "InvalidConfig":
+      return "Check the settings: " + e.message;
+    case "TooLarge":
+      return "This image is too large to process safely: " + e.message;
+    default:
+      return e.message;
+  }
+}